@@ -0,0 +1,186 @@
+#ifndef POSEIDON_MERKLE_TREE_H
+#define POSEIDON_MERKLE_TREE_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define DEFAULT_LEVELS 20
+
+#define MAX_POSEIDON_INPUTS 12
+
+#define PMT_OK 0
+
+#define PMT_INVALID_LEVELS 1
+
+#define PMT_MERKLE_TREE_FULL 2
+
+#define PMT_INDEX_OUT_OF_BOUNDS 3
+
+#define PMT_KEY_NOT_FOUND 4
+
+#define PMT_KEY_ALREADY_PRESENT 5
+
+#define PMT_INVALID_PROOF 6
+
+#define PMT_INVALID_CIRCOM_JSON 7
+
+#define PMT_INVALID_CALLDATA 8
+
+#define PMT_UNKNOWN_ROOT 9
+
+#define PMT_LEAF_CONFLICT 10
+
+/**
+ * No longer returned, as a tree without a leaf log now reports `PMT_LEAF_LOG_UNSUPPORTED`.
+ * Kept so the code is never given to another error
+ */
+#define PMT_LEAF_LOG_DISABLED 11
+
+#define PMT_INVALID_PAIR_ALIGNMENT 12
+
+#define PMT_MISALIGNED_SUBTREE 13
+
+#define PMT_LEAF_LOG_UNSUPPORTED 14
+
+#define PMT_INVALID_HISTORY_SIZE 15
+
+#define PMT_FILLED_SUBTREES_MISMATCH 16
+
+#define PMT_FILLED_SUBTREE_OUTSIDE_FIELD 17
+
+#define PMT_ROOT_INDEX_OUT_OF_RANGE 18
+
+#define PMT_NEXT_INDEX_BEYOND_CAPACITY 19
+
+#define PMT_LEAF_LOG_MISMATCH 20
+
+#define PMT_INVALID_ENCODING 21
+
+#define PMT_UNSUPPORTED_VERSION 22
+
+#define PMT_INVALID_ACCOUNT_DATA 23
+
+#define PMT_HASH_ERROR 24
+
+/**
+ * No longer returned, as the shared hasher now recovers from a poisoned lock. Kept so the
+ * code is never given to another error
+ */
+#define PMT_POSEIDON_LOCK_ERROR 25
+
+#define PMT_FRONTIER_MISMATCH 26
+
+#define PMT_INVALID_HEX 27
+
+#define PMT_INVALID_LEAF 28
+
+#define PMT_MISSING_EVENT 29
+
+#define PMT_DUPLICATE_EVENT 30
+
+#define PMT_INVALID_DECIMAL 31
+
+#define PMT_CHECKPOINT_MISMATCH 32
+
+#define PMT_SNAPSHOT_MISMATCH 33
+
+#define PMT_DUPLICATE_LEAF 34
+
+/**
+ * No longer returned, as a level out of range now reports `PMT_INVALID_LEVELS`. Kept so the
+ * code is never given to another error
+ */
+#define PMT_INVALID_LEVEL 35
+
+#define PMT_INVALID_POSEIDON_PARAMETERS 36
+
+#define PMT_INVALID_INPUT_COUNT 37
+
+#define PMT_ZERO_CHAIN_MISMATCH 38
+
+#define PMT_DOUBLE_SPEND 39
+
+#define PMT_NULLIFIER_SET_FULL 40
+
+#define PMT_INVALID_NOTE 41
+
+#define PMT_NULL_POINTER -1
+
+#define PMT_BUFFER_TOO_SMALL -2
+
+#define PMT_PANIC -3
+
+#define PoseidonMerkleTreeConst_SIZE ((((32 * LEVELS) + (32 * ROOT_HISTORY_SIZE)) + 4) + 8)
+
+typedef struct PoseidonMerkleTree PoseidonMerkleTree;
+
+/**
+ * Returns a new empty tree to be freed with `pmt_free`, or null if `levels` is not between 1
+ * and 20
+ */
+struct PoseidonMerkleTree *pmt_new(uint32_t levels);
+
+/**
+ * # Safety
+ *
+ * `tree` must be null or a pointer returned by `pmt_new` or `pmt_deserialize` that has not
+ * been freed yet
+ */
+void pmt_free(struct PoseidonMerkleTree *tree);
+
+/**
+ * # Safety
+ *
+ * `tree` must be a live tree and `leaf` must point to 32 readable bytes, the leaf in
+ * big-endian order
+ */
+int32_t pmt_insert(struct PoseidonMerkleTree *tree, const uint8_t *leaf);
+
+/**
+ * # Safety
+ *
+ * `tree` must be a live tree and `out` must point to 32 writable bytes, which receive the
+ * current root
+ */
+int32_t pmt_root(const struct PoseidonMerkleTree *tree, uint8_t *out);
+
+/**
+ * # Safety
+ *
+ * `tree` must be a live tree, `root` must point to 32 readable bytes and `known` to a
+ * writable `bool`
+ */
+int32_t pmt_is_known_root(const struct PoseidonMerkleTree *tree, const uint8_t *root, bool *known);
+
+/**
+ * # Safety
+ *
+ * `tree` must be a live tree, `out` must point to `capacity` writable bytes (it may be null
+ * when `capacity` is zero) and `written` to a writable `size_t`.
+ *
+ * Writes the versioned encoding read back by `pmt_deserialize`. `written` receives its
+ * length, and if that exceeds `capacity` nothing is written and `PMT_BUFFER_TOO_SMALL` is
+ * returned, so a caller can size its buffer with a first call
+ */
+int32_t pmt_serialize(const struct PoseidonMerkleTree *tree,
+                      uint8_t *out,
+                      size_t capacity,
+                      size_t *written);
+
+/**
+ * # Safety
+ *
+ * `bytes` must point to `len` readable bytes (it may be null when `len` is zero) and `out`
+ * to a writable tree pointer.
+ *
+ * Reads any encoding `PoseidonMerkleTree::deserialize_versioned` accepts into a new tree
+ * stored in `out`, to be freed with `pmt_free`. On failure `out` is set to null
+ */
+int32_t pmt_deserialize(const uint8_t *bytes, size_t len, struct PoseidonMerkleTree **out);
+
+#endif  /* POSEIDON_MERKLE_TREE_H */
@@ -0,0 +1,292 @@
+use std::fmt;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use anchor_lang::error::ErrorCode;
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::{AccountDeserialize, AccountSerialize, Discriminator, Id, Owner, Result, Space};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::PoseidonMerkleTree;
+
+// A tree stored in an Anchor account owned by the program `P`, usable as
+// `Account<'info, PoseidonMerkleTreeAccount<MyProgram>>`. The data is laid out as `#[account]`
+// would: the discriminator, then the tree's Borsh encoding
+pub struct PoseidonMerkleTreeAccount<P> {
+    pub tree: PoseidonMerkleTree,
+    program: PhantomData<P>,
+}
+
+impl<P> PoseidonMerkleTreeAccount<P> {
+    pub fn new(tree: PoseidonMerkleTree) -> PoseidonMerkleTreeAccount<P> {
+        PoseidonMerkleTreeAccount {
+            tree,
+            program: PhantomData,
+        }
+    }
+
+    pub fn into_inner(self) -> PoseidonMerkleTree {
+        self.tree
+    }
+
+    // Space for a tree of `levels` keeping `history_size` roots, without the discriminator.
+    // `INIT_SPACE` covers any depth with the default history. A leaf log grows with every
+    // insert, so an account holding one has to be reallocated as it fills
+    pub const fn init_space(levels: u32, history_size: usize) -> usize {
+        PoseidonMerkleTree::stored_size(levels, history_size)
+    }
+}
+
+impl<P> From<PoseidonMerkleTree> for PoseidonMerkleTreeAccount<P> {
+    fn from(tree: PoseidonMerkleTree) -> PoseidonMerkleTreeAccount<P> {
+        PoseidonMerkleTreeAccount::new(tree)
+    }
+}
+
+// Written by hand since deriving would require `P` itself to implement these
+impl<P> Clone for PoseidonMerkleTreeAccount<P> {
+    fn clone(&self) -> PoseidonMerkleTreeAccount<P> {
+        PoseidonMerkleTreeAccount::new(self.tree.clone())
+    }
+}
+
+impl<P> fmt::Debug for PoseidonMerkleTreeAccount<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoseidonMerkleTreeAccount")
+            .field("tree", &self.tree)
+            .finish()
+    }
+}
+
+impl<P> Deref for PoseidonMerkleTreeAccount<P> {
+    type Target = PoseidonMerkleTree;
+
+    fn deref(&self) -> &PoseidonMerkleTree {
+        &self.tree
+    }
+}
+
+impl<P> DerefMut for PoseidonMerkleTreeAccount<P> {
+    fn deref_mut(&mut self) -> &mut PoseidonMerkleTree {
+        &mut self.tree
+    }
+}
+
+// The first 8 bytes of sha256("account:PoseidonMerkleTreeAccount"), as `#[account]` derives
+impl<P> Discriminator for PoseidonMerkleTreeAccount<P> {
+    const DISCRIMINATOR: [u8; 8] = [4, 226, 213, 233, 189, 105, 233, 217];
+}
+
+impl<P: Id> Owner for PoseidonMerkleTreeAccount<P> {
+    fn owner() -> Pubkey {
+        P::id()
+    }
+}
+
+impl<P> Space for PoseidonMerkleTreeAccount<P> {
+    const INIT_SPACE: usize = PoseidonMerkleTree::SIZE;
+}
+
+impl<P> AccountSerialize for PoseidonMerkleTreeAccount<P> {
+    fn try_serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer
+            .write_all(&Self::DISCRIMINATOR)
+            .map_err(|_| ErrorCode::AccountDidNotSerialize)?;
+        self.tree
+            .serialize(writer)
+            .map_err(|_| ErrorCode::AccountDidNotSerialize)?;
+        Ok(())
+    }
+}
+
+impl<P> AccountDeserialize for PoseidonMerkleTreeAccount<P> {
+    fn try_deserialize(buf: &mut &[u8]) -> Result<Self> {
+        if buf.len() < Self::DISCRIMINATOR.len() {
+            return Err(ErrorCode::AccountDiscriminatorNotFound.into());
+        }
+        if buf[..8] != Self::DISCRIMINATOR {
+            return Err(ErrorCode::AccountDiscriminatorMismatch.into());
+        }
+        Self::try_deserialize_unchecked(buf)
+    }
+
+    // The account may be larger than the tree, so the bytes after it are ignored. Account data
+    // can be written by anyone who owned the account before, so the tree is validated too
+    fn try_deserialize_unchecked(buf: &mut &[u8]) -> Result<Self> {
+        let mut data = buf.get(8..).unwrap_or_default();
+        let tree = PoseidonMerkleTree::deserialize_reader(&mut data)
+            .map_err(|_| ErrorCode::AccountDidNotDeserialize)?;
+        tree.validate()
+            .map_err(|_| ErrorCode::AccountDidNotDeserialize)?;
+        Ok(PoseidonMerkleTreeAccount::new(tree))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anchor_lang::prelude::{Account, AccountInfo};
+    use anchor_lang::solana_program::hash::hash;
+    use anchor_lang::AccountsExit;
+
+    use super::*;
+    use crate::{Endianness, PoseidonMerkleTreeError, ZeroPreset, ROOT_HISTORY_SIZE};
+
+    struct TreeProgram;
+
+    impl Id for TreeProgram {
+        fn id() -> Pubkey {
+            Pubkey::new_from_array([7; 32])
+        }
+    }
+
+    type TreeAccount = PoseidonMerkleTreeAccount<TreeProgram>;
+
+    // Runs one instruction against the account: load it the way Anchor does, let `handler`
+    // work on it, then write it back on exit
+    fn instruction<R>(data: &mut [u8], handler: impl FnOnce(&mut Account<TreeAccount>) -> R) -> R {
+        let key = Pubkey::new_unique();
+        let owner = TreeProgram::id();
+        let mut lamports = 1_000_000;
+        let info = AccountInfo::new(&key, false, true, &mut lamports, data, &owner, false, 0);
+
+        let mut account = Account::<TreeAccount>::try_from(&info).unwrap();
+        let result = handler(&mut account);
+        account.exit(&TreeProgram::id()).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_discriminator_matches_anchor() {
+        let digest = hash(b"account:PoseidonMerkleTreeAccount").to_bytes();
+        assert_eq!(TreeAccount::DISCRIMINATOR, digest[..8]);
+    }
+
+    #[test]
+    fn test_tree_account_across_instructions() {
+        let levels = 5;
+        let mut data = vec![0u8; 8 + TreeAccount::init_space(levels, ROOT_HISTORY_SIZE)];
+
+        // Initialize, as `#[account(init, space = 8 + ...)]` would
+        TreeAccount::new(PoseidonMerkleTree::new(levels).unwrap())
+            .try_serialize(&mut data.as_mut_slice())
+            .unwrap();
+
+        let root = instruction(&mut data, |account| {
+            account.insert(&[1u8; 32]).unwrap();
+            account.last_root()
+        });
+        let known = instruction(&mut data, |account| {
+            assert_eq!(account.next_index, 1);
+            account.is_known_root(root)
+        });
+        assert!(known);
+
+        let mut expected = PoseidonMerkleTree::new(levels).unwrap();
+        expected.insert(&[1u8; 32]).unwrap();
+        assert_eq!(
+            TreeAccount::try_deserialize(&mut data.as_slice())
+                .unwrap()
+                .tree,
+            expected
+        );
+        assert!(TreeAccount::init_space(levels, ROOT_HISTORY_SIZE) <= TreeAccount::INIT_SPACE);
+    }
+
+    #[test]
+    fn test_tree_account_keeps_accept_initial_root() {
+        let levels = 4;
+        let tree = PoseidonMerkleTree::builder()
+            .levels(levels)
+            .accept_initial_root(false)
+            .build()
+            .unwrap();
+        let empty_root = tree.last_root();
+        let mut data = vec![0u8; 8 + TreeAccount::INIT_SPACE];
+        TreeAccount::new(tree)
+            .try_serialize(&mut data.as_mut_slice())
+            .unwrap();
+
+        let known = instruction(&mut data, |account| {
+            assert!(!account.accepts_initial_root());
+            account.is_known_root(empty_root)
+        });
+        assert!(!known);
+        let read = TreeAccount::try_deserialize(&mut data.as_slice()).unwrap();
+        assert!(!read.accepts_initial_root());
+    }
+
+    #[test]
+    fn test_tree_account_keeps_zero_preset() {
+        let mut expected =
+            PoseidonMerkleTree::new_with_zero_preset(4, ZeroPreset::Tornado).unwrap();
+        let mut data = vec![0u8; 8 + TreeAccount::INIT_SPACE];
+        TreeAccount::new(expected.clone())
+            .try_serialize(&mut data.as_mut_slice())
+            .unwrap();
+
+        for i in 1..=3u8 {
+            let root = instruction(&mut data, |account| {
+                assert_eq!(account.zero_preset(), ZeroPreset::Tornado);
+                account.insert(&[i; 32]).unwrap();
+                account.last_root()
+            });
+            expected.insert(&[i; 32]).unwrap();
+            assert_eq!(root, expected.last_root());
+        }
+    }
+
+    #[test]
+    fn test_tree_account_keeps_endianness() {
+        let tree = PoseidonMerkleTree::builder()
+            .levels(4)
+            .endianness(Endianness::Little)
+            .build()
+            .unwrap();
+        let mut data = vec![0u8; 8 + TreeAccount::INIT_SPACE];
+        TreeAccount::new(tree)
+            .try_serialize(&mut data.as_mut_slice())
+            .unwrap();
+
+        let root = instruction(&mut data, |account| {
+            account.insert(&[1u8; 32]).unwrap();
+            account.last_root()
+        });
+        let known = instruction(&mut data, |account| {
+            assert_eq!(account.endianness(), Endianness::Little);
+            account.is_known_root(root)
+        });
+        assert!(known);
+    }
+
+    #[test]
+    fn test_tree_account_keeps_reject_duplicates() {
+        let mut tree = PoseidonMerkleTree::with_leaf_log(4).unwrap();
+        tree.set_reject_duplicates(true).unwrap();
+        let mut data = vec![0u8; 8 + TreeAccount::INIT_SPACE];
+        TreeAccount::new(tree)
+            .try_serialize(&mut data.as_mut_slice())
+            .unwrap();
+
+        instruction(&mut data, |account| account.insert(&[1u8; 32]).unwrap());
+        let duplicate = instruction(&mut data, |account| account.insert(&[1u8; 32]));
+        assert_eq!(duplicate, Err(PoseidonMerkleTreeError::DuplicateLeaf));
+    }
+
+    #[test]
+    fn test_rejects_foreign_and_corrupt_data() {
+        let zeroed = vec![0u8; 8 + TreeAccount::INIT_SPACE];
+        assert!(TreeAccount::try_deserialize(&mut zeroed.as_slice()).is_err());
+        assert!(TreeAccount::try_deserialize(&mut &[1u8, 2][..]).is_err());
+
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        tree.levels = 4;
+        assert_eq!(
+            tree.validate(),
+            Err(PoseidonMerkleTreeError::FilledSubtreesMismatch)
+        );
+        let mut data = Vec::new();
+        TreeAccount::new(tree).try_serialize(&mut data).unwrap();
+        assert!(TreeAccount::try_deserialize(&mut data.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tokio::task::{spawn_blocking, JoinError};
+
+use crate::{PoseidonMerkleTree, PoseidonMerkleTreeError};
+
+// Inserts `leaf` on tokio's blocking pool so the hashing does not stall the async runtime, and
+// returns the index the leaf was assigned together with the new root
+pub async fn insert_async(
+    tree: Arc<Mutex<PoseidonMerkleTree>>,
+    leaf: [u8; 32],
+) -> Result<(u64, [u8; 32]), PoseidonMerkleTreeError> {
+    spawn_blocking(move || {
+        let (indices, root) = stage_and_apply(&tree, &[leaf])?;
+        Ok((indices[0], root))
+    })
+    .await
+    .unwrap_or_else(propagate_panic)
+}
+
+// Like `insert_async`, for a whole batch. Either every leaf is inserted or none is
+pub async fn insert_many_async(
+    tree: Arc<Mutex<PoseidonMerkleTree>>,
+    leaves: Vec<[u8; 32]>,
+) -> Result<(Vec<u64>, [u8; 32]), PoseidonMerkleTreeError> {
+    spawn_blocking(move || stage_and_apply(&tree, &leaves))
+        .await
+        .unwrap_or_else(propagate_panic)
+}
+
+// The batch is applied to a copy that only replaces the tree once every insert succeeded.
+// Dropping the future cannot interrupt this either: a blocking task always runs to the end
+fn stage_and_apply(
+    tree: &Mutex<PoseidonMerkleTree>,
+    leaves: &[[u8; 32]],
+) -> Result<(Vec<u64>, [u8; 32]), PoseidonMerkleTreeError> {
+    let mut tree = lock_tree(tree);
+    let mut staged = tree.clone();
+    let indices = staged.insert_many(leaves)?;
+    let root = staged.last_root();
+
+    *tree = staged;
+    Ok((indices, root))
+}
+
+// The tree is only ever replaced whole, so a panic while it was locked cannot have left it
+// half updated
+fn lock_tree(tree: &Mutex<PoseidonMerkleTree>) -> MutexGuard<'_, PoseidonMerkleTree> {
+    tree.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// A blocking task is only cancelled when the runtime shuts down, so the one failure worth
+// reporting is a panic, which is passed on to the caller
+fn propagate_panic<T>(error: JoinError) -> T {
+    std::panic::resume_unwind(error.into_panic())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_async_inserts() {
+        let tree = Arc::new(Mutex::new(PoseidonMerkleTree::new(6).unwrap()));
+
+        let handles: Vec<_> = (0..32u8)
+            .map(|i| tokio::spawn(insert_async(tree.clone(), [i + 1; 32])))
+            .collect();
+        let mut assigned = Vec::new();
+        for (i, handle) in handles.into_iter().enumerate() {
+            let (index, root) = handle.await.unwrap().unwrap();
+            assigned.push((index, [i as u8 + 1; 32], root));
+        }
+
+        // Replaying the leaves in the order they were assigned reproduces every root
+        assigned.sort();
+        let mut expected = PoseidonMerkleTree::new(6).unwrap();
+        for (position, (index, leaf, root)) in assigned.into_iter().enumerate() {
+            assert_eq!(index, position as u64);
+            expected.insert(&leaf).unwrap();
+            assert_eq!(root, expected.last_root());
+        }
+        assert_eq!(*tree.lock().unwrap(), expected);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_insert_many_async_is_all_or_nothing() {
+        let tree = Arc::new(Mutex::new(PoseidonMerkleTree::new(3).unwrap()));
+
+        let leaves: Vec<[u8; 32]> = (0..5u8).map(|i| [i + 1; 32]).collect();
+        let (indices, root) = insert_many_async(tree.clone(), leaves.clone())
+            .await
+            .unwrap();
+        assert_eq!(indices, vec![0, 1, 2, 3, 4]);
+
+        let mut expected = PoseidonMerkleTree::new(3).unwrap();
+        expected.insert_many(&leaves).unwrap();
+        assert_eq!(root, expected.last_root());
+
+        // The second leaf is outside the field, so the first one must not stay inserted either
+        let result = insert_many_async(tree.clone(), vec![[6u8; 32], [0xff; 32]]).await;
+        assert_eq!(
+            result,
+            Err(PoseidonMerkleTreeError::invalid_leaf(&[0xff; 32]))
+        );
+        assert_eq!(*tree.lock().unwrap(), expected);
+    }
+}
@@ -0,0 +1,324 @@
+use crate::zero_preset::seed_leaf;
+use crate::{
+    PoseidonMerkleTree, PoseidonMerkleTreeError, ZeroChain, ZeroPreset, ZeroValues, DEFAULT_LEVELS,
+    MAX_LEVELS, ROOT_HISTORY_SIZE,
+};
+
+// The byte order of the leaves a tree takes and the roots it reports. Circom and Solidity
+// use big-endian; some runtimes hand field elements around little-endian
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endianness {
+    #[default]
+    Big,
+    Little,
+}
+
+impl Endianness {
+    // Between big-endian and this order; the conversion is the same in both directions
+    pub(crate) fn convert(self, bytes: [u8; 32]) -> [u8; 32] {
+        match self {
+            Endianness::Big => bytes,
+            Endianness::Little => {
+                let mut reversed = bytes;
+                reversed.reverse();
+                reversed
+            }
+        }
+    }
+}
+
+// Every option of a `PoseidonMerkleTree`, all checked by `build`. Unset options are those of
+// `PoseidonMerkleTree::default`
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoseidonMerkleTreeBuilder {
+    levels: u32,
+    root_history: usize,
+    zero_preset: ZeroPreset,
+    // Set by `zero_values`, so the chain is not derived again for every tree
+    zero_values: Option<ZeroValues>,
+    endianness: Endianness,
+    accept_initial_root: bool,
+}
+
+impl Default for PoseidonMerkleTreeBuilder {
+    fn default() -> PoseidonMerkleTreeBuilder {
+        PoseidonMerkleTreeBuilder {
+            levels: DEFAULT_LEVELS,
+            root_history: ROOT_HISTORY_SIZE,
+            zero_preset: ZeroPreset::Voidify,
+            zero_values: None,
+            endianness: Endianness::Big,
+            accept_initial_root: true,
+        }
+    }
+}
+
+impl PoseidonMerkleTreeBuilder {
+    pub fn new() -> PoseidonMerkleTreeBuilder {
+        PoseidonMerkleTreeBuilder::default()
+    }
+
+    pub fn levels(mut self, levels: u32) -> PoseidonMerkleTreeBuilder {
+        self.levels = levels;
+        self
+    }
+
+    // How many of the latest roots `is_known_root` accepts
+    pub fn root_history(mut self, size: usize) -> PoseidonMerkleTreeBuilder {
+        self.root_history = size;
+        self
+    }
+
+    // A `Custom` preset whose empty leaf is derived from `seed` the way the `Voidify` one is
+    // from "voidify": its SHA-256, reduced into the field
+    pub fn zero_seed(self, seed: &str) -> PoseidonMerkleTreeBuilder {
+        self.zero_preset(ZeroPreset::Custom(seed_leaf(seed.as_bytes())))
+    }
+
+    pub fn zero_preset(mut self, preset: ZeroPreset) -> PoseidonMerkleTreeBuilder {
+        self.zero_preset = preset;
+        self.zero_values = None;
+        self
+    }
+
+    // Builds on a chain derived beforehand. The tree can be at most as deep as the chain
+    pub fn zero_values(mut self, values: &ZeroValues) -> PoseidonMerkleTreeBuilder {
+        self.zero_preset = values.preset();
+        self.zero_values = Some(values.clone());
+        self
+    }
+
+    pub fn endianness(mut self, endianness: Endianness) -> PoseidonMerkleTreeBuilder {
+        self.endianness = endianness;
+        self
+    }
+
+    // With `false`, `is_known_root` turns down the empty tree's root until the first insert,
+    // for verifiers that must not accept proofs against a tree with no leaves
+    pub fn accept_initial_root(mut self, accept: bool) -> PoseidonMerkleTreeBuilder {
+        self.accept_initial_root = accept;
+        self
+    }
+
+    // Fails with `InvalidLevels` for a depth outside `1..=MAX_LEVELS` or deeper than the
+    // `zero_values`, `InvalidHistorySize` for an empty history and `InvalidLeaf` for a
+    // `Custom` leaf outside the field
+    pub fn build(&self) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        let max_levels = self
+            .zero_values
+            .as_ref()
+            .map_or(MAX_LEVELS as u32, ZeroValues::levels);
+        if self.levels == 0 || self.levels > max_levels {
+            return Err(PoseidonMerkleTreeError::InvalidLevels {
+                got: self.levels,
+                max: max_levels,
+            });
+        }
+        if self.root_history == 0 {
+            return Err(PoseidonMerkleTreeError::InvalidHistorySize);
+        }
+        let zeros = match &self.zero_values {
+            Some(values) => values.chain().clone(),
+            None => ZeroChain::new(self.zero_preset)?,
+        };
+
+        let mut tree =
+            PoseidonMerkleTree::empty(self.levels, self.root_history, zeros, self.endianness);
+        tree.accept_initial_root = self.accept_initial_root;
+        Ok(tree)
+    }
+}
+
+impl PoseidonMerkleTree {
+    pub fn builder() -> PoseidonMerkleTreeBuilder {
+        PoseidonMerkleTreeBuilder::new()
+    }
+
+    // The options this tree was built with, to build an empty one like it
+    pub(crate) fn to_builder(&self) -> PoseidonMerkleTreeBuilder {
+        PoseidonMerkleTreeBuilder {
+            levels: self.levels,
+            root_history: self.roots.len(),
+            zero_preset: self.zero_preset(),
+            zero_values: Some(ZeroValues::from_chain(self.zeros.clone())),
+            endianness: self.endianness,
+            accept_initial_root: self.accept_initial_root,
+        }
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    pub fn accepts_initial_root(&self) -> bool {
+        self.accept_initial_root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zeros;
+
+    fn reversed(bytes: [u8; 32]) -> [u8; 32] {
+        Endianness::Little.convert(bytes)
+    }
+
+    #[test]
+    fn test_new_wraps_the_builder() {
+        assert_eq!(
+            PoseidonMerkleTree::builder().levels(5).build(),
+            PoseidonMerkleTree::new(5)
+        );
+        assert_eq!(
+            PoseidonMerkleTree::builder().build().unwrap(),
+            PoseidonMerkleTree::default()
+        );
+    }
+
+    #[test]
+    fn test_levels_are_bounded() {
+        for levels in [0, MAX_LEVELS as u32 + 1] {
+            assert_eq!(
+                PoseidonMerkleTree::builder().levels(levels).build(),
+                Err(PoseidonMerkleTreeError::invalid_levels(levels))
+            );
+        }
+        let deepest = PoseidonMerkleTree::builder()
+            .levels(MAX_LEVELS as u32)
+            .build()
+            .unwrap();
+        assert_eq!(deepest.levels, MAX_LEVELS as u32);
+    }
+
+    #[test]
+    fn test_root_history() {
+        let tree = PoseidonMerkleTree::builder()
+            .levels(3)
+            .root_history(4)
+            .build()
+            .unwrap();
+        assert_eq!(tree, PoseidonMerkleTree::new_with_history(3, 4).unwrap());
+        assert_eq!(tree.history_size(), 4);
+
+        assert_eq!(
+            PoseidonMerkleTree::builder().root_history(0).build(),
+            Err(PoseidonMerkleTreeError::InvalidHistorySize)
+        );
+    }
+
+    #[test]
+    fn test_zero_seed_hashes_into_the_field() {
+        assert_eq!(seed_leaf(b"voidify"), zeros(0));
+        assert_eq!(
+            PoseidonMerkleTree::builder()
+                .levels(4)
+                .zero_seed("voidify")
+                .build()
+                .unwrap()
+                .last_root(),
+            PoseidonMerkleTree::new(4).unwrap().last_root()
+        );
+
+        let tree = PoseidonMerkleTree::builder()
+            .levels(4)
+            .zero_seed("another seed")
+            .build()
+            .unwrap();
+        assert_eq!(
+            tree.zero_preset(),
+            ZeroPreset::Custom(seed_leaf(b"another seed"))
+        );
+        assert_ne!(
+            tree.last_root(),
+            PoseidonMerkleTree::new(4).unwrap().last_root()
+        );
+    }
+
+    #[test]
+    fn test_zero_preset() {
+        assert_eq!(
+            PoseidonMerkleTree::builder()
+                .levels(4)
+                .zero_preset(ZeroPreset::Tornado)
+                .build(),
+            PoseidonMerkleTree::new_with_zero_preset(4, ZeroPreset::Tornado)
+        );
+        assert_eq!(
+            PoseidonMerkleTree::builder()
+                .zero_preset(ZeroPreset::Custom([0xff; 32]))
+                .build(),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&[0xff; 32]))
+        );
+    }
+
+    #[test]
+    fn test_little_endian_tree_reverses_leaves_and_roots() {
+        let mut big = PoseidonMerkleTree::new(3).unwrap();
+        let mut little = PoseidonMerkleTree::builder()
+            .levels(3)
+            .endianness(Endianness::Little)
+            .build()
+            .unwrap();
+        assert_eq!(little.endianness(), Endianness::Little);
+        assert_eq!(little.last_root(), reversed(big.last_root()));
+
+        let leaves = [[1u8; 32], zeros(2), zeros(4)];
+        for leaf in &leaves {
+            big.insert(leaf).unwrap();
+            little.insert(&reversed(*leaf)).unwrap();
+            assert_eq!(little.last_root(), reversed(big.last_root()));
+        }
+        assert!(little.is_known_root(reversed(big.last_root())));
+        assert!(!little.is_known_root(big.last_root()));
+        assert_eq!(little.root_fr(), big.root_fr());
+
+        // A leaf is only inside the field in the order the tree reads it
+        let mut outside = [0u8; 32];
+        outside[31] = 0xff;
+        assert_eq!(
+            little.insert(&outside),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&outside))
+        );
+        assert!(big.insert(&outside).is_ok());
+
+        little.reset().unwrap();
+        assert_eq!(little.endianness(), Endianness::Little);
+        assert_eq!(little.last_root(), reversed(zeros(2)));
+    }
+
+    #[test]
+    fn test_endianness_kept_across_round_trips() {
+        use borsh::{BorshDeserialize, BorshSerialize};
+
+        let mut tree = PoseidonMerkleTree::builder()
+            .levels(3)
+            .endianness(Endianness::Little)
+            .build()
+            .unwrap();
+        tree.insert(&reversed([1u8; 32])).unwrap();
+        let root = tree.last_root();
+
+        let borsh = PoseidonMerkleTree::try_from_slice(&tree.try_to_vec().unwrap()).unwrap();
+        let versioned =
+            PoseidonMerkleTree::deserialize_versioned(&tree.serialize_versioned().unwrap())
+                .unwrap();
+        for mut read in [borsh, versioned] {
+            assert_eq!(read.endianness(), Endianness::Little);
+            assert!(read.is_known_root(root));
+            assert_eq!(read, tree);
+
+            // Leaves are still read little-endian, so roots keep matching the original tree
+            let mut original = tree.clone();
+            let leaf = reversed(zeros(2));
+            read.insert(&leaf).unwrap();
+            original.insert(&leaf).unwrap();
+            assert_eq!(read.last_root(), original.last_root());
+        }
+
+        // The endianness is the last byte of the options
+        let mut bytes = tree.try_to_vec().unwrap();
+        *bytes.last_mut().unwrap() = 2;
+        assert!(PoseidonMerkleTree::try_from_slice(&bytes).is_err());
+    }
+}
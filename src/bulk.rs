@@ -0,0 +1,153 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use ark_bn254::Fr;
+use light_poseidon::Poseidon;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+#[cfg(feature = "rayon")]
+use crate::new_poseidon;
+use crate::{
+    fr_from_be_bytes, hash_pair, zero_hash, FullPoseidonMerkleTree, MemoryNodeStore,
+    PoseidonMerkleTree, PoseidonMerkleTreeError,
+};
+
+impl PoseidonMerkleTree {
+    // Builds the tree that inserting `leaves` one by one would produce, hashing each level of
+    // the tree once instead of a full path per leaf. Levels are hashed in parallel with the
+    // `rayon` feature
+    pub fn from_leaves(
+        levels: u32,
+        leaves: &[[u8; 32]],
+    ) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        Ok(FullPoseidonMerkleTree::from_leaves(levels, leaves)?
+            .tree()
+            .clone())
+    }
+}
+
+impl FullPoseidonMerkleTree {
+    pub fn from_leaves(
+        levels: u32,
+        leaves: &[[u8; 32]],
+    ) -> Result<FullPoseidonMerkleTree, PoseidonMerkleTreeError> {
+        let mut tree = PoseidonMerkleTree::new(levels)?;
+        if leaves.len() as u64 > tree.capacity() {
+            return Err(PoseidonMerkleTreeError::MerkleTreeFull {
+                capacity: tree.capacity(),
+            });
+        }
+
+        // The root history holds a root for each of the last `history` inserts, and those can
+        // only come from inserting the final leaves one at a time
+        let history = tree.history_size();
+        let (bulk, rest) = leaves.split_at(leaves.len().saturating_sub(history - 1));
+        let nodes = hash_levels(&mut tree.hasher.checkout(), levels, bulk)?;
+
+        if let Some(last) = bulk.len().checked_sub(1) {
+            // Each level's filled subtree is the last left child written there, which by now
+            // is final
+            for (level, filled) in tree.filled_subtrees.iter_mut().enumerate() {
+                *filled = fr_from_be_bytes(&nodes[level][(last >> level) & !1])?;
+            }
+            tree.next_index = bulk.len() as u64;
+            tree.current_root_index = (bulk.len() % history) as u32;
+            tree.roots = vec![[0; 32]; history];
+            tree.roots[tree.current_root_index as usize] = nodes[levels as usize][0];
+            tree.reindex_roots();
+        }
+
+        let mut full = FullPoseidonMerkleTree::from_parts(tree, MemoryNodeStore { nodes });
+        for leaf in rest {
+            full.insert(leaf)?;
+        }
+
+        Ok(full)
+    }
+}
+
+// Every level of the tree over `leaves`, from the leaves up to the root, each as long as its
+// rightmost non-empty node
+fn hash_levels(
+    poseidon: &mut Poseidon<Fr>,
+    levels: u32,
+    leaves: &[[u8; 32]],
+) -> Result<Vec<Vec<[u8; 32]>>, PoseidonMerkleTreeError> {
+    let mut nodes = vec![leaves.to_vec()];
+    for level in 0..levels {
+        let parents = hash_level(poseidon, level, &nodes[level as usize])?;
+        nodes.push(parents);
+    }
+
+    Ok(nodes)
+}
+
+#[cfg(not(feature = "rayon"))]
+fn hash_level(
+    poseidon: &mut Poseidon<Fr>,
+    level: u32,
+    children: &[[u8; 32]],
+) -> Result<Vec<[u8; 32]>, PoseidonMerkleTreeError> {
+    let zero = zero_hash(level)?;
+    children
+        .chunks(2)
+        .map(|pair| hash_pair(poseidon, &pair[0], pair.get(1).unwrap_or(&zero)))
+        .collect()
+}
+
+// Each rayon worker builds its own hasher rather than sharing the caller's
+#[cfg(feature = "rayon")]
+fn hash_level(
+    _: &mut Poseidon<Fr>,
+    level: u32,
+    children: &[[u8; 32]],
+) -> Result<Vec<[u8; 32]>, PoseidonMerkleTreeError> {
+    let zero = zero_hash(level)?;
+    children
+        .par_chunks(2)
+        .map_init(new_poseidon, |poseidon, pair| {
+            hash_pair(poseidon, &pair[0], pair.get(1).unwrap_or(&zero))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{XorShift, MAX_LEVELS};
+
+    #[test]
+    fn test_from_leaves_matches_sequential_inserts() {
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        let leaves: Vec<[u8; 32]> = (0..300).map(|_| rng.leaf()).collect();
+
+        for count in [0, 1, 2, 19, 20, 21, 64, 255, 300] {
+            let mut sequential = FullPoseidonMerkleTree::new(9).unwrap();
+            for leaf in &leaves[..count] {
+                sequential.insert(leaf).unwrap();
+            }
+
+            let bulk = FullPoseidonMerkleTree::from_leaves(9, &leaves[..count]).unwrap();
+            assert_eq!(bulk, sequential, "{count} leaves");
+            assert_eq!(
+                &PoseidonMerkleTree::from_leaves(9, &leaves[..count]).unwrap(),
+                sequential.tree()
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_leaves_rejects_too_many_leaves() {
+        assert_eq!(
+            PoseidonMerkleTree::from_leaves(2, &[[1u8; 32]; 5]),
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 4 })
+        );
+        assert_eq!(
+            PoseidonMerkleTree::from_leaves(MAX_LEVELS as u32 + 1, &[]),
+            Err(PoseidonMerkleTreeError::invalid_levels(
+                MAX_LEVELS as u32 + 1
+            ))
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use alloc::vec::Vec;
+
+use borsh::maybestd::io::{self, Error, ErrorKind, Read, Write};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{read_bounded, zero_hashes, MerkleProof, PoseidonMerkleTree, PoseidonMerkleTreeError};
+
+// Incremental tree that also caches every node in the `canopy_depth` levels below the root, so
+// callers only have to supply the lower part of a proof
+#[derive(Clone, Debug, PartialEq)]
+pub struct CanopyPoseidonMerkleTree {
+    tree: PoseidonMerkleTree,
+    canopy_depth: u32,
+    // Nodes in breadth-first order starting below the root: the node `depth` levels down at
+    // `position` sits at (2^depth - 2) + position
+    canopy: Vec<[u8; 32]>,
+}
+
+impl CanopyPoseidonMerkleTree {
+    pub fn new(
+        levels: u32,
+        canopy_depth: u32,
+    ) -> Result<CanopyPoseidonMerkleTree, PoseidonMerkleTreeError> {
+        let tree = PoseidonMerkleTree::new(levels)?;
+        if canopy_depth > levels {
+            return Err(PoseidonMerkleTreeError::InvalidLevels {
+                got: canopy_depth,
+                max: levels,
+            });
+        }
+
+        let zeros = zero_hashes(levels)?;
+        let canopy = (1..=canopy_depth)
+            .flat_map(|depth| {
+                let zero = zeros[(levels - depth) as usize];
+                (0..1u64 << depth).map(move |_| zero)
+            })
+            .collect();
+
+        Ok(CanopyPoseidonMerkleTree {
+            tree,
+            canopy_depth,
+            canopy,
+        })
+    }
+
+    // Serialized size budget for a tree with the given canopy, on top of
+    // `PoseidonMerkleTree::SIZE`
+    pub const fn size(canopy_depth: u32) -> usize {
+        PoseidonMerkleTree::SIZE + 4 + 4 + 32 * canopy_len(canopy_depth)
+    }
+
+    pub fn tree(&self) -> &PoseidonMerkleTree {
+        &self.tree
+    }
+
+    pub fn canopy_depth(&self) -> u32 {
+        self.canopy_depth
+    }
+
+    pub fn canopy(&self) -> &[[u8; 32]] {
+        &self.canopy
+    }
+
+    pub fn insert(&mut self, leaf: &[u8; 32]) -> Result<u64, PoseidonMerkleTreeError> {
+        let index = self.tree.next_index as u32;
+        let path = self.tree.append(leaf)?;
+
+        let levels = self.tree.levels;
+        for depth in 1..=self.canopy_depth {
+            let level = levels - depth;
+            self.canopy[canopy_offset(depth, index >> level)] = path[level as usize];
+        }
+
+        Ok(index as u64)
+    }
+
+    pub fn is_known_root(&self, root: [u8; 32]) -> bool {
+        self.tree.is_known_root(root)
+    }
+
+    // Completes a proof that only carries the siblings below the canopy. `partial.index` must
+    // still hold the full path, including the bits for the cached levels
+    pub fn fill_proof_from_canopy(
+        &self,
+        partial: &mut MerkleProof,
+    ) -> Result<(), PoseidonMerkleTreeError> {
+        let levels = self.tree.levels;
+        if partial.siblings.len() != (levels - self.canopy_depth) as usize {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+        if partial.index as u64 >= 1u64 << levels {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: partial.index as u64,
+                len: 1u64 << levels,
+            });
+        }
+
+        for level in levels - self.canopy_depth..levels {
+            let depth = levels - level;
+            let sibling = (partial.index >> level) ^ 1;
+            partial
+                .siblings
+                .push(self.canopy[canopy_offset(depth, sibling)]);
+        }
+
+        Ok(())
+    }
+}
+
+// The tree's options come last, so bytes written before they were stored end at the canopy
+impl BorshSerialize for CanopyPoseidonMerkleTree {
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.tree.serialize_fields(writer)?;
+        self.canopy_depth.serialize(writer)?;
+        self.canopy.serialize(writer)?;
+        self.tree.serialize_options(writer)
+    }
+}
+
+impl BorshDeserialize for CanopyPoseidonMerkleTree {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut tree = PoseidonMerkleTree::deserialize_fields(reader)?;
+        let canopy_depth = u32::deserialize_reader(reader)?;
+        if canopy_depth > tree.levels {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Canopy is deeper than the tree",
+            ));
+        }
+
+        let canopy = read_bounded(
+            reader,
+            |len| len as usize == canopy_len(canopy_depth),
+            "Canopy length does not match its depth",
+        )?;
+        tree.deserialize_options(reader)?;
+
+        Ok(CanopyPoseidonMerkleTree {
+            tree,
+            canopy_depth,
+            canopy,
+        })
+    }
+}
+
+const fn canopy_len(canopy_depth: u32) -> usize {
+    (2 << canopy_depth) - 2
+}
+
+fn canopy_offset(depth: u32, position: u32) -> usize {
+    canopy_len(depth - 1) + position as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zeros;
+    use crate::FullPoseidonMerkleTree;
+
+    #[test]
+    fn test_truncated_proof_plus_canopy_equals_full_proof() {
+        let mut tree = CanopyPoseidonMerkleTree::new(6, 3).unwrap();
+        let mut full = FullPoseidonMerkleTree::new(6).unwrap();
+
+        for i in 0..21u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+            full.insert(&[i + 1; 32]).unwrap();
+        }
+        assert_eq!(tree.tree(), full.tree());
+
+        for index in 0..21 {
+            let proof = full.get_proof(index).unwrap();
+            let mut partial = proof.clone();
+            partial.siblings.truncate(3);
+
+            tree.fill_proof_from_canopy(&mut partial).unwrap();
+            assert_eq!(partial, proof);
+        }
+    }
+
+    #[test]
+    fn test_canopy_rejects_bad_input() {
+        assert_eq!(
+            CanopyPoseidonMerkleTree::new(4, 5),
+            Err(PoseidonMerkleTreeError::InvalidLevels { got: 5, max: 4 })
+        );
+
+        let mut tree = CanopyPoseidonMerkleTree::new(4, 2).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        let mut proof = MerkleProof {
+            leaf: [1u8; 32],
+            index: 0,
+            siblings: vec![zeros(0)],
+        };
+        assert_eq!(
+            tree.fill_proof_from_canopy(&mut proof),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+
+        proof.siblings.push(zeros(1));
+        proof.index = 16;
+        assert_eq!(
+            tree.fill_proof_from_canopy(&mut proof),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 16, len: 16 })
+        );
+    }
+
+    #[test]
+    fn test_canopy_borsh_and_size() {
+        let mut tree = CanopyPoseidonMerkleTree::new(20, 4).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        assert_eq!(tree.canopy().len(), 30);
+
+        let bytes = tree.try_to_vec().unwrap();
+        let decoded = CanopyPoseidonMerkleTree::try_from_slice(&bytes).unwrap();
+        assert_eq!(decoded, tree);
+
+        // The canopy adds its depth, its length prefix and one word per cached node
+        let base = tree.tree().try_to_vec().unwrap().len();
+        assert_eq!(
+            bytes.len() - base,
+            CanopyPoseidonMerkleTree::size(4) - PoseidonMerkleTree::SIZE
+        );
+
+        // Bytes written before the tree's options were stored end at the canopy
+        let legacy = &bytes[..bytes.len() - crate::OPTIONS_SIZE];
+        assert_eq!(
+            CanopyPoseidonMerkleTree::try_from_slice(legacy).unwrap(),
+            tree
+        );
+
+        let mut truncated = tree.clone();
+        truncated.canopy.pop();
+        let bytes = truncated.try_to_vec().unwrap();
+        assert!(CanopyPoseidonMerkleTree::try_from_slice(&bytes).is_err());
+    }
+}
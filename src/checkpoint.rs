@@ -0,0 +1,184 @@
+use alloc::vec::Vec;
+
+use ark_bn254::Fr;
+
+use crate::{PoseidonMerkleTree, PoseidonMerkleTreeError};
+
+// What a single insert overwrote, so `undo` can put it back
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    index: u64,
+    // The frontier entries the insert replaced, by level
+    filled_subtrees: Vec<(usize, Fr)>,
+    previous_root_index: u32,
+    // The root the insert recorded and the one it evicted from that slot
+    root: [u8; 32],
+    evicted_root: [u8; 32],
+}
+
+impl Checkpoint {
+    // Index of the leaf the checkpointed insert placed
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.root
+    }
+}
+
+impl PoseidonMerkleTree {
+    // `insert_with_root`, also returning a checkpoint that `undo` can roll the insert back with,
+    // e.g. when the transaction it was made for is rejected
+    pub fn insert_checkpointed(
+        &mut self,
+        leaf: &[u8; 32],
+    ) -> Result<(u64, Checkpoint), PoseidonMerkleTreeError> {
+        let index = self.next_index;
+        // An insert replaces the frontier at every level where its path goes left
+        let filled_subtrees = (0..self.levels as usize)
+            .filter(|level| (index >> level) & 1 == 0)
+            .map(|level| (level, self.filled_subtrees[level]))
+            .collect();
+        let previous_root_index = self.current_root_index;
+        let evicted_root = self.roots[(previous_root_index as usize + 1) % self.roots.len()];
+
+        let (index, root) = self.insert_with_root(leaf)?;
+        let checkpoint = Checkpoint {
+            index,
+            filled_subtrees,
+            previous_root_index,
+            root,
+            evicted_root,
+        };
+        Ok((index, checkpoint))
+    }
+
+    // Rolls back the insert `checkpoint` was taken for. It must still be the latest insert, so
+    // a checkpoint from another tree, one already undone or one followed by further inserts is
+    // refused with `CheckpointMismatch` and the tree is left as it is
+    pub fn undo(&mut self, checkpoint: &Checkpoint) -> Result<(), PoseidonMerkleTreeError> {
+        let slot = (checkpoint.previous_root_index as usize + 1) % self.roots.len();
+        if self.next_index != checkpoint.index + 1
+            || self.current_root_index as usize != slot
+            || self.roots[slot] != checkpoint.root
+            || checkpoint
+                .filled_subtrees
+                .iter()
+                .any(|(level, _)| *level >= self.filled_subtrees.len())
+        {
+            return Err(PoseidonMerkleTreeError::CheckpointMismatch);
+        }
+
+        for (level, node) in &checkpoint.filled_subtrees {
+            self.filled_subtrees[*level] = *node;
+        }
+        self.unrecord_root(checkpoint.evicted_root, checkpoint.previous_root_index);
+        self.next_index = checkpoint.index;
+        if let Some(leaf) = self.leaf_log.as_mut().and_then(Vec::pop) {
+            self.leaf_positions.remove(&leaf, checkpoint.index);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(i: u8) -> [u8; 32] {
+        [i; 32]
+    }
+
+    #[test]
+    fn test_undo_to_empty_follows_accept_initial_root() {
+        for accept in [true, false] {
+            let mut tree = PoseidonMerkleTree::builder()
+                .levels(3)
+                .accept_initial_root(accept)
+                .build()
+                .unwrap();
+            let empty_root = tree.last_root();
+            let (_, checkpoint) = tree.insert_checkpointed(&leaf(1)).unwrap();
+            assert!(tree.is_known_root(empty_root));
+
+            tree.undo(&checkpoint).unwrap();
+            assert_eq!(tree.last_root(), empty_root);
+            assert_eq!(tree.is_known_root(empty_root), accept);
+            assert_eq!(tree.find_root(&empty_root), accept.then_some(0));
+        }
+    }
+
+    #[test]
+    fn test_undo_restores_the_tree() {
+        for history_size in [3, 20] {
+            let mut tree = PoseidonMerkleTree::new_with_history(4, history_size).unwrap();
+            tree.leaf_log = Some(Vec::new());
+            for i in 1..=6 {
+                tree.insert(&leaf(i)).unwrap();
+            }
+            let before = tree.clone();
+
+            let (index, checkpoint) = tree.insert_checkpointed(&leaf(7)).unwrap();
+            assert_eq!(index, 6);
+            assert_eq!(checkpoint.index(), 6);
+            assert_eq!(checkpoint.root(), tree.last_root());
+            assert!(tree.is_known_root(checkpoint.root()));
+
+            tree.undo(&checkpoint).unwrap();
+            assert_eq!(tree, before);
+            assert!(!tree.is_known_root(checkpoint.root()));
+            assert!(before
+                .valid_roots()
+                .iter()
+                .all(|root| tree.is_known_root(*root)));
+
+            // Reinserting a different leaf ends up where a tree that never speculated would
+            let mut expected = before.clone();
+            expected.insert(&leaf(8)).unwrap();
+            tree.insert(&leaf(8)).unwrap();
+            assert_eq!(tree, expected);
+        }
+    }
+
+    #[test]
+    fn test_undo_refuses_stale_checkpoints() {
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        let (_, checkpoint) = tree.insert_checkpointed(&leaf(1)).unwrap();
+        tree.undo(&checkpoint).unwrap();
+        let before = tree.clone();
+        assert_eq!(
+            tree.undo(&checkpoint),
+            Err(PoseidonMerkleTreeError::CheckpointMismatch)
+        );
+        assert_eq!(tree, before);
+
+        let (_, checkpoint) = tree.insert_checkpointed(&leaf(1)).unwrap();
+        tree.insert(&leaf(2)).unwrap();
+        let before = tree.clone();
+        assert_eq!(
+            tree.undo(&checkpoint),
+            Err(PoseidonMerkleTreeError::CheckpointMismatch)
+        );
+        assert_eq!(tree, before);
+
+        // The same position in a tree holding other leaves
+        let mut other = PoseidonMerkleTree::new(3).unwrap();
+        other.insert(&leaf(9)).unwrap();
+        let (_, checkpoint) = tree.insert_checkpointed(&leaf(3)).unwrap();
+        other.insert(&leaf(9)).unwrap();
+        other.insert(&leaf(9)).unwrap();
+        assert_eq!(
+            other.undo(&checkpoint),
+            Err(PoseidonMerkleTreeError::CheckpointMismatch)
+        );
+
+        let mut full = PoseidonMerkleTree::new(1).unwrap();
+        full.insert(&leaf(1)).unwrap();
+        full.insert(&leaf(2)).unwrap();
+        assert_eq!(
+            full.insert_checkpointed(&leaf(3)),
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 2 })
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use core::str::FromStr;
+
+use ark_bn254::Fr;
+use ark_ff::{BigInteger, PrimeField};
+use serde_json::{json, Value};
+
+use crate::{MerkleProof, PoseidonMerkleTreeError, WithdrawInputs, MAX_LEVELS};
+
+impl MerkleProof {
+    // Renders the proof as circom withdraw inputs, every element as a decimal BN254 field string
+    pub fn to_circom_json(&self) -> Result<String, PoseidonMerkleTreeError> {
+        // Recomputing the root also rejects any leaf or sibling outside the field
+        let root = self.root()?;
+
+        Ok(json!({
+            "root": to_decimal(&root),
+            "leaf": to_decimal(&self.leaf),
+            "pathElements": self.siblings.iter().map(to_decimal).collect::<Vec<_>>(),
+            "pathIndices": self.path_indices_vec(),
+        })
+        .to_string())
+    }
+
+    pub fn from_circom_json(input: &str) -> Result<MerkleProof, PoseidonMerkleTreeError> {
+        let value: Value =
+            serde_json::from_str(input).map_err(|_| PoseidonMerkleTreeError::InvalidCircomJson)?;
+
+        let leaf = from_decimal(&value["leaf"])?;
+        let root = from_decimal(&value["root"])?;
+        let siblings = value["pathElements"]
+            .as_array()
+            .ok_or(PoseidonMerkleTreeError::InvalidCircomJson)?
+            .iter()
+            .map(from_decimal)
+            .collect::<Result<Vec<_>, _>>()?;
+        let path_indices = value["pathIndices"]
+            .as_array()
+            .ok_or(PoseidonMerkleTreeError::InvalidCircomJson)?
+            .iter()
+            .map(|bit| match bit.as_u64() {
+                Some(bit @ (0 | 1)) => Ok(bit as u8),
+                _ => Err(PoseidonMerkleTreeError::InvalidCircomJson),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if siblings.len() > MAX_LEVELS || path_indices.len() != siblings.len() {
+            return Err(PoseidonMerkleTreeError::InvalidCircomJson);
+        }
+        let index = MerkleProof::path_indices_from_vec(&path_indices)?;
+
+        let proof = MerkleProof {
+            leaf,
+            index,
+            siblings,
+        };
+        if proof.root()? != root {
+            return Err(PoseidonMerkleTreeError::InvalidCircomJson);
+        }
+
+        Ok(proof)
+    }
+}
+
+impl WithdrawInputs {
+    // The input file snarkjs reads for the withdraw circuit. Addresses and amounts are numbers
+    // like every other signal, so they go out as decimal strings too; the address bytes are
+    // taken big-endian, as the contract's `uint256(uint160(address))` reads them
+    pub fn to_circom_json(&self) -> String {
+        json!({
+            "root": to_decimal(&self.root),
+            "nullifierHash": to_decimal(&self.nullifier_hash),
+            "recipient": address_to_decimal(&self.recipient),
+            "relayer": address_to_decimal(&self.relayer),
+            "fee": self.fee.to_string(),
+            "refund": self.refund.to_string(),
+            "nullifier": to_decimal(&self.nullifier),
+            "secret": to_decimal(&self.secret),
+            "pathElements": self.path_elements.iter().map(to_decimal).collect::<Vec<_>>(),
+            "pathIndices": self.path_indices,
+        })
+        .to_string()
+    }
+}
+
+fn address_to_decimal(address: &[u8; 20]) -> String {
+    let mut bytes = [0u8; 32];
+    bytes[12..].copy_from_slice(address);
+    to_decimal(&bytes)
+}
+
+fn to_decimal(bytes: &[u8; 32]) -> String {
+    Fr::from_be_bytes_mod_order(bytes).to_string()
+}
+
+fn from_decimal(value: &Value) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+    let decimal = value
+        .as_str()
+        .ok_or(PoseidonMerkleTreeError::InvalidCircomJson)?;
+
+    // `Fr::from_str` silently reduces modulo p, so only accept the canonical rendering
+    let element = Fr::from_str(decimal).map_err(|_| PoseidonMerkleTreeError::InvalidCircomJson)?;
+    if element.to_string() != decimal {
+        return Err(PoseidonMerkleTreeError::InvalidCircomJson);
+    }
+
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&element.into_bigint().to_bytes_be());
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DepositNote, FullPoseidonMerkleTree};
+
+    fn sample_tree() -> FullPoseidonMerkleTree {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        for i in 0..5u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn test_circom_json_round_trip() {
+        let tree = sample_tree();
+        let root = tree.tree().last_root();
+        let proof = tree.get_proof(3).unwrap();
+
+        let json = proof.to_circom_json().unwrap();
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["root"], to_decimal(&root));
+        assert_eq!(value["pathElements"].as_array().unwrap().len(), 3);
+        assert_eq!(value["pathIndices"], json!([1, 1, 0]));
+
+        let parsed = MerkleProof::from_circom_json(&json).unwrap();
+        assert_eq!(parsed, proof);
+        assert_eq!(parsed.root().unwrap(), root);
+    }
+
+    #[test]
+    fn test_circom_json_rejects_invalid_input() {
+        let proof = sample_tree().get_proof(1).unwrap();
+        let mut value: Value = serde_json::from_str(&proof.to_circom_json().unwrap()).unwrap();
+
+        // Index bits that no longer match the path produce a different root
+        let mut flipped = value.clone();
+        flipped["pathIndices"][0] = json!(0);
+        assert_eq!(
+            MerkleProof::from_circom_json(&flipped.to_string()),
+            Err(PoseidonMerkleTreeError::InvalidCircomJson)
+        );
+
+        // The field modulus itself is not a canonical field element
+        value["leaf"] = json!(Fr::MODULUS.to_string());
+        assert_eq!(
+            MerkleProof::from_circom_json(&value.to_string()),
+            Err(PoseidonMerkleTreeError::InvalidCircomJson)
+        );
+
+        assert_eq!(
+            MerkleProof::from_circom_json("not json"),
+            Err(PoseidonMerkleTreeError::InvalidCircomJson)
+        );
+    }
+
+    #[test]
+    fn test_withdraw_inputs_snapshot() {
+        let (mut secret, mut nullifier) = ([0u8; 32], [0u8; 32]);
+        secret[31] = 2;
+        nullifier[31] = 1;
+        let note = DepositNote::from_secrets("voidify", "0.1", 1, secret, nullifier).unwrap();
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        tree.insert(&[1; 32]).unwrap();
+        tree.insert(&note.commitment).unwrap();
+        let proof = tree.get_proof(1).unwrap();
+
+        let mut recipient = [0u8; 20];
+        recipient[19] = 0x01;
+        recipient[0] = 0xab;
+        let inputs = WithdrawInputs::build(&proof, &note, recipient, [0xff; 20], 50, 1).unwrap();
+        assert_eq!(inputs.root, tree.tree().last_root());
+        // The leaf's sibling is the `[1; 32]` leaf, its path bits the binary of index 1
+        assert_eq!(
+            inputs.to_circom_json(),
+            concat!(
+                r#"{"fee":"50","nullifier":"1","#,
+                r#""nullifierHash":"18586133768512220936620570745912940619677854269274689475585506675881198879027","#,
+                r#""pathElements":["454086624460063511464984254936031011189294057512315937409637584344757371137","#,
+                r#""8841286955941674696145474291062801913424347741909660939324677505376782734876","#,
+                r#""167765807172692931679631608059454543888453788995890101478278185547822860343"],"#,
+                r#""pathIndices":[1,0,0],"recipient":"976237421810876558643867603103454673285798690817","#,
+                r#""refund":"1","relayer":"1461501637330902918203684832716283019655932542975","#,
+                r#""root":"21294561316431148135541759425729713561824327719101109951672279412567966137109","#,
+                r#""secret":"2"}"#,
+            )
+        );
+    }
+}
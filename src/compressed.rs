@@ -0,0 +1,191 @@
+use alloc::vec::Vec;
+
+use borsh::maybestd::io::{self, Read};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{
+    hash_pair, lock_poseidon, read_bounded, zero_hash, MerkleProof, PoseidonMerkleTreeError,
+    MAX_LEVELS,
+};
+
+// A `MerkleProof` with every sibling equal to the zero chain left out
+#[derive(Clone, BorshSerialize, Debug, Default, PartialEq)]
+pub struct CompressedProof {
+    pub leaf: [u8; 32],
+    pub index: u32,
+    // Bit `level` is set when the sibling at that level is stored explicitly
+    pub bitmap: u32,
+    // The explicit siblings, in ascending level order
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl MerkleProof {
+    pub fn compress(&self) -> CompressedProof {
+        let mut bitmap = 0;
+        let mut siblings = Vec::new();
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            if zero_hash(level as u32) != Ok(*sibling) {
+                bitmap |= 1 << level;
+                siblings.push(*sibling);
+            }
+        }
+
+        CompressedProof {
+            leaf: self.leaf,
+            index: self.index,
+            bitmap,
+            siblings,
+        }
+    }
+}
+
+impl CompressedProof {
+    pub fn decompress(&self, levels: u32) -> Result<MerkleProof, PoseidonMerkleTreeError> {
+        self.check(levels)?;
+
+        let mut explicit = self.siblings.iter();
+        let siblings = (0..levels)
+            .map(|level| match self.bitmap >> level & 1 {
+                1 => Ok(*explicit.next().unwrap()),
+                _ => zero_hash(level),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(MerkleProof {
+            leaf: self.leaf,
+            index: self.index,
+            siblings,
+        })
+    }
+
+    // Walks the path straight from the compressed form, filling in zero siblings on the fly
+    pub fn root(&self, levels: u32) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        self.check(levels)?;
+
+        let mut poseidon = lock_poseidon();
+
+        let mut explicit = self.siblings.iter();
+        let mut current_level_hash = self.leaf;
+        for level in 0..levels {
+            let zero = zero_hash(level)?;
+            let sibling = match self.bitmap >> level & 1 {
+                1 => explicit.next().unwrap(),
+                _ => &zero,
+            };
+
+            current_level_hash = if self.index >> level & 1 == 0 {
+                hash_pair(&mut poseidon, &current_level_hash, sibling)?
+            } else {
+                hash_pair(&mut poseidon, sibling, &current_level_hash)?
+            };
+        }
+
+        Ok(current_level_hash)
+    }
+
+    pub fn verify(&self, levels: u32, root: &[u8; 32]) -> Result<bool, PoseidonMerkleTreeError> {
+        Ok(self.root(levels)? == *root)
+    }
+
+    fn check(&self, levels: u32) -> Result<(), PoseidonMerkleTreeError> {
+        if levels as usize > MAX_LEVELS
+            || self.bitmap.checked_shr(levels).unwrap_or(0) != 0
+            || self.bitmap.count_ones() as usize != self.siblings.len()
+        {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+        if self.index.checked_shr(levels).unwrap_or(0) != 0 {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: self.index as u64,
+                len: 1u64 << levels,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl BorshDeserialize for CompressedProof {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let leaf = <[u8; 32]>::deserialize_reader(reader)?;
+        let index = u32::deserialize_reader(reader)?;
+        let bitmap = u32::deserialize_reader(reader)?;
+
+        let siblings = read_bounded(
+            reader,
+            |len| len == bitmap.count_ones(),
+            "Compressed proof sibling count does not match its bitmap",
+        )?;
+
+        Ok(CompressedProof {
+            leaf,
+            index,
+            bitmap,
+            siblings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FullPoseidonMerkleTree;
+
+    #[test]
+    fn test_compress_round_trip() {
+        let mut tree = FullPoseidonMerkleTree::new(20).unwrap();
+        for i in 0..3u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let root = tree.tree().last_root();
+
+        for index in 0..3 {
+            let proof = tree.get_proof(index).unwrap();
+            let compressed = proof.compress();
+            assert_eq!(compressed.decompress(20).unwrap(), proof);
+            assert!(compressed.verify(20, &root).unwrap());
+            assert!(!compressed.verify(20, &[1u8; 32]).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_compress_shrinks_sparse_proofs() {
+        let mut tree = FullPoseidonMerkleTree::new(20).unwrap();
+        for i in 0..3u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+
+        let proof = tree.get_proof(0).unwrap();
+        let compressed = proof.compress();
+        assert_eq!(compressed.bitmap, 0b11);
+
+        let full_size = proof.try_to_vec().unwrap().len();
+        let compressed_bytes = compressed.try_to_vec().unwrap();
+        assert_eq!(full_size, 32 + 4 + 4 + 32 * 20);
+        assert_eq!(compressed_bytes.len(), 32 + 4 + 4 + 4 + 32 * 2);
+        assert!(compressed_bytes.len() * 2 < full_size);
+
+        let decoded = CompressedProof::try_from_slice(&compressed_bytes).unwrap();
+        assert_eq!(decoded, compressed);
+    }
+
+    #[test]
+    fn test_compressed_proof_rejects_inconsistent_bitmap() {
+        let mut tree = FullPoseidonMerkleTree::new(4).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        tree.insert(&[2u8; 32]).unwrap();
+
+        let mut compressed = tree.get_proof(1).unwrap().compress();
+        compressed.bitmap |= 1 << 3;
+        assert_eq!(
+            compressed.decompress(4),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+        assert!(CompressedProof::try_from_slice(&compressed.try_to_vec().unwrap()).is_err());
+
+        let compressed = tree.get_proof(1).unwrap().compress();
+        assert_eq!(
+            compressed.root(0),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use ark_bn254::Fr;
+use borsh::maybestd::io::{self, Error, ErrorKind, Read};
+use borsh::{BorshDeserialize, BorshSerialize};
+use light_poseidon::Poseidon;
+
+use crate::{
+    hash_pair, lock_poseidon, read_bounded, zero_hash, zero_hashes, MerkleProof,
+    PoseidonMerkleTreeError, MAX_LEVELS,
+};
+
+// The nodes written by one change, from the leaf (level 0) up to just below the new root
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct ChangeLogEntry {
+    pub root: [u8; 32],
+    pub path: Vec<[u8; 32]>,
+    pub index: u32,
+}
+
+// Tree that only keeps its recent changes, in the style of Solana's concurrent Merkle tree.
+// Writers supply a proof against any root still in the changelog, and the proof is brought up
+// to date with the changes made since before it is applied. Empty slots hold `zero_hash(0)`, so
+// an empty tree has the root `zero_hash(levels)`
+#[derive(Clone, BorshSerialize, Debug, PartialEq)]
+pub struct ConcurrentPoseidonMerkleTree {
+    pub levels: u32,
+    changelog_size: u32,
+    // Oldest first; the last entry describes the current root
+    changelog: Vec<ChangeLogEntry>,
+}
+
+impl ConcurrentPoseidonMerkleTree {
+    pub fn new(
+        levels: u32,
+        changelog_size: u32,
+    ) -> Result<ConcurrentPoseidonMerkleTree, PoseidonMerkleTreeError> {
+        if levels == 0 || levels > MAX_LEVELS as u32 {
+            return Err(PoseidonMerkleTreeError::invalid_levels(levels));
+        }
+        if changelog_size == 0 {
+            return Err(PoseidonMerkleTreeError::InvalidHistorySize);
+        }
+
+        let initial = ChangeLogEntry {
+            root: zero_hash(levels)?,
+            path: zero_hashes(levels - 1)?,
+            index: 0,
+        };
+
+        Ok(ConcurrentPoseidonMerkleTree {
+            levels,
+            changelog_size,
+            changelog: vec![initial],
+        })
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.changelog.last().unwrap().root
+    }
+
+    pub fn changelog_size(&self) -> u32 {
+        self.changelog_size
+    }
+
+    pub fn changelog(&self) -> &[ChangeLogEntry] {
+        &self.changelog
+    }
+
+    pub fn is_known_root(&self, root: [u8; 32]) -> bool {
+        self.changelog.iter().any(|entry| entry.root == root)
+    }
+
+    // Replaces the leaf at `proof.index`, which held `proof.leaf` when the tree had `root`, with
+    // `leaf`. Writes to other slots since `root` are folded into the proof; a write to the same
+    // slot means `proof.leaf` may be stale and is rejected
+    pub fn insert_with_proof(
+        &mut self,
+        leaf: &[u8; 32],
+        proof: &MerkleProof,
+        root: &[u8; 32],
+    ) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        if proof.siblings.len() != self.levels as usize {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+        if proof.index as u64 >= 1u64 << self.levels {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: proof.index as u64,
+                len: 1u64 << self.levels,
+            });
+        }
+        let start = self
+            .changelog
+            .iter()
+            .rposition(|entry| entry.root == *root)
+            .ok_or(PoseidonMerkleTreeError::UnknownRoot)?;
+
+        let mut poseidon = lock_poseidon();
+
+        let (computed, _) = hash_path(&mut poseidon, &proof.leaf, proof.index, &proof.siblings)?;
+        if computed != *root {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+
+        // Each later change touches our path only where the two paths meet, and there it
+        // replaces exactly one of our siblings
+        let mut siblings = proof.siblings.clone();
+        for entry in &self.changelog[start + 1..] {
+            if entry.index == proof.index {
+                return Err(PoseidonMerkleTreeError::LeafConflict);
+            }
+            let level = (entry.index ^ proof.index).ilog2() as usize;
+            siblings[level] = entry.path[level];
+        }
+
+        let (new_root, path) = hash_path(&mut poseidon, leaf, proof.index, &siblings)?;
+        self.changelog.push(ChangeLogEntry {
+            root: new_root,
+            path,
+            index: proof.index,
+        });
+        if self.changelog.len() > self.changelog_size as usize {
+            self.changelog.remove(0);
+        }
+
+        Ok(new_root)
+    }
+}
+
+impl BorshDeserialize for ConcurrentPoseidonMerkleTree {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let levels = u32::deserialize_reader(reader)?;
+        let changelog_size = u32::deserialize_reader(reader)?;
+        if levels == 0 || levels > MAX_LEVELS as u32 || changelog_size == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Invalid tree parameters",
+            ));
+        }
+
+        let changelog: Vec<ChangeLogEntry> = read_bounded(
+            reader,
+            |len| len > 0 && len <= changelog_size,
+            "Changelog length does not fit its size",
+        )?;
+        if changelog.iter().any(|entry| {
+            entry.path.len() != levels as usize || entry.index as u64 >= 1u64 << levels
+        }) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Changelog entry does not match the tree depth",
+            ));
+        }
+
+        Ok(ConcurrentPoseidonMerkleTree {
+            levels,
+            changelog_size,
+            changelog,
+        })
+    }
+}
+
+// Hashes `leaf` up to the root, also returning the nodes on the way (excluding the root)
+fn hash_path(
+    poseidon: &mut Poseidon<Fr>,
+    leaf: &[u8; 32],
+    index: u32,
+    siblings: &[[u8; 32]],
+) -> Result<([u8; 32], Vec<[u8; 32]>), PoseidonMerkleTreeError> {
+    let mut path = Vec::with_capacity(siblings.len());
+    let mut current_level_hash = *leaf;
+    for (level, sibling) in siblings.iter().enumerate() {
+        path.push(current_level_hash);
+        current_level_hash = if (index >> level).is_multiple_of(2) {
+            hash_pair(poseidon, &current_level_hash, sibling)?
+        } else {
+            hash_pair(poseidon, sibling, &current_level_hash)?
+        };
+    }
+
+    Ok((current_level_hash, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zeros;
+    use crate::SparsePoseidonMerkleTree;
+
+    #[test]
+    fn test_concurrent_proofs_from_same_root() {
+        let mut tree = ConcurrentPoseidonMerkleTree::new(4, 8).unwrap();
+        let mut mirror = SparsePoseidonMerkleTree::new(4).unwrap();
+        assert_eq!(tree.root(), mirror.root());
+
+        let root = tree
+            .insert_with_proof(&[1u8; 32], &mirror.prove_exclusion(2).unwrap(), &zeros(4))
+            .unwrap();
+        mirror.insert(2, &[1u8; 32]).unwrap();
+        assert_eq!(root, mirror.root());
+
+        // Both writers build their proofs from the same snapshot
+        let shared_root = tree.root();
+        let first = mirror.prove_exclusion(3).unwrap();
+        let second = mirror.prove_exclusion(12).unwrap();
+
+        tree.insert_with_proof(&[2u8; 32], &first, &shared_root)
+            .unwrap();
+        tree.insert_with_proof(&[3u8; 32], &second, &shared_root)
+            .unwrap();
+
+        mirror.insert(3, &[2u8; 32]).unwrap();
+        mirror.insert(12, &[3u8; 32]).unwrap();
+        assert_eq!(tree.root(), mirror.root());
+        assert!(tree.is_known_root(shared_root));
+    }
+
+    #[test]
+    fn test_concurrent_rejects_conflicts_and_unknown_roots() {
+        let mut tree = ConcurrentPoseidonMerkleTree::new(3, 2).unwrap();
+        let mirror = SparsePoseidonMerkleTree::new(3).unwrap();
+        let empty_root = tree.root();
+
+        let proof = mirror.prove_exclusion(5).unwrap();
+        tree.insert_with_proof(&[1u8; 32], &proof, &empty_root)
+            .unwrap();
+        assert_eq!(
+            tree.insert_with_proof(&[2u8; 32], &proof, &empty_root),
+            Err(PoseidonMerkleTreeError::LeafConflict)
+        );
+
+        let mut forged = mirror.prove_exclusion(1).unwrap();
+        forged.leaf = [9u8; 32];
+        assert_eq!(
+            tree.insert_with_proof(&[2u8; 32], &forged, &empty_root),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+
+        // A second change pushes the empty root out of a two-entry changelog
+        tree.insert_with_proof(&[2u8; 32], &mirror.prove_exclusion(6).unwrap(), &empty_root)
+            .unwrap();
+        assert!(!tree.is_known_root(empty_root));
+        assert_eq!(
+            tree.insert_with_proof(&[3u8; 32], &mirror.prove_exclusion(0).unwrap(), &empty_root),
+            Err(PoseidonMerkleTreeError::UnknownRoot)
+        );
+    }
+
+    #[test]
+    fn test_concurrent_borsh_round_trip() {
+        let mut tree = ConcurrentPoseidonMerkleTree::new(3, 4).unwrap();
+        let mirror = SparsePoseidonMerkleTree::new(3).unwrap();
+        let root = tree.root();
+        tree.insert_with_proof(&[1u8; 32], &mirror.prove_exclusion(1).unwrap(), &root)
+            .unwrap();
+
+        let bytes = tree.try_to_vec().unwrap();
+        let decoded = ConcurrentPoseidonMerkleTree::try_from_slice(&bytes).unwrap();
+        assert_eq!(decoded, tree);
+        assert_eq!(decoded.changelog_size(), 4);
+
+        let mut shrunk = tree.clone();
+        shrunk.changelog_size = 1;
+        let bytes = shrunk.try_to_vec().unwrap();
+        assert!(ConcurrentPoseidonMerkleTree::try_from_slice(&bytes).is_err());
+    }
+}
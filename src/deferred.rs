@@ -0,0 +1,185 @@
+use borsh::maybestd::io::{self, Error, ErrorKind, Read, Write};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{PoseidonMerkleTree, PoseidonMerkleTreeError};
+
+// Incremental tree whose inserts only enter the root history when committed, so a burst of
+// inserts costs one history slot instead of evicting it. `is_known_root` only ever sees
+// committed roots
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeferredPoseidonMerkleTree {
+    tree: PoseidonMerkleTree,
+    // Inserts between automatic commits; 0 leaves every commit to `commit_root`
+    commit_every: u32,
+    // Inserts since the last commit, kept so a reloaded tree knows a commit is outstanding
+    pending: u32,
+    // Root after the last insert, committed or not
+    latest_root: [u8; 32],
+}
+
+impl DeferredPoseidonMerkleTree {
+    pub fn new(
+        levels: u32,
+        commit_every: u32,
+    ) -> Result<DeferredPoseidonMerkleTree, PoseidonMerkleTreeError> {
+        let tree = PoseidonMerkleTree::new(levels)?;
+        let latest_root = tree.last_root();
+
+        Ok(DeferredPoseidonMerkleTree {
+            tree,
+            commit_every,
+            pending: 0,
+            latest_root,
+        })
+    }
+
+    pub fn tree(&self) -> &PoseidonMerkleTree {
+        &self.tree
+    }
+
+    pub fn pending(&self) -> u32 {
+        self.pending
+    }
+
+    // The root including every deferred insert, which may not be known yet
+    pub fn latest_root(&self) -> [u8; 32] {
+        self.latest_root
+    }
+
+    pub fn insert_deferred(&mut self, leaf: &[u8; 32]) -> Result<u64, PoseidonMerkleTreeError> {
+        let index = self.tree.next_index;
+        let path = self.tree.append_unrecorded(leaf)?;
+        self.latest_root = path[self.tree.levels as usize];
+        self.pending += 1;
+
+        if self.pending == self.commit_every {
+            self.commit_root();
+        }
+
+        Ok(index)
+    }
+
+    // Records the latest root in the history. Nothing is recorded when no insert is pending
+    pub fn commit_root(&mut self) -> [u8; 32] {
+        if self.pending > 0 {
+            self.tree.record_root(self.latest_root);
+            self.pending = 0;
+        }
+        self.latest_root
+    }
+
+    pub fn is_known_root(&self, root: [u8; 32]) -> bool {
+        self.tree.is_known_root(root)
+    }
+}
+
+// The tree's options come last, so bytes written before they were stored end at
+// `latest_root`
+impl BorshSerialize for DeferredPoseidonMerkleTree {
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.tree.serialize_fields(writer)?;
+        self.commit_every.serialize(writer)?;
+        self.pending.serialize(writer)?;
+        self.latest_root.serialize(writer)?;
+        self.tree.serialize_options(writer)
+    }
+}
+
+impl BorshDeserialize for DeferredPoseidonMerkleTree {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut tree = PoseidonMerkleTree::deserialize_fields(reader)?;
+        let commit_every = u32::deserialize_reader(reader)?;
+        let pending = u32::deserialize_reader(reader)?;
+        let latest_root = <[u8; 32]>::deserialize_reader(reader)?;
+        tree.deserialize_options(reader)?;
+
+        // An automatic commit fires as soon as `pending` reaches `commit_every`
+        if pending as u64 > tree.next_index || (commit_every > 0 && pending >= commit_every) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Pending insert count is out of range",
+            ));
+        }
+
+        Ok(DeferredPoseidonMerkleTree {
+            tree,
+            commit_every,
+            pending,
+            latest_root,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deferred_commits_every_n_inserts() {
+        let mut tree = DeferredPoseidonMerkleTree::new(6, 5).unwrap();
+        let mut eager = PoseidonMerkleTree::new(6).unwrap();
+
+        let mut committed = Vec::new();
+        for i in 0..25u8 {
+            tree.insert_deferred(&[i + 1; 32]).unwrap();
+            eager.insert(&[i + 1; 32]).unwrap();
+            assert_eq!(tree.latest_root(), eager.last_root());
+            if tree.pending() == 0 {
+                committed.push(tree.latest_root());
+            }
+        }
+
+        // 25 inserts would have cycled the whole history; five commits keep them all
+        assert_eq!(committed.len(), 5);
+        assert_eq!(tree.tree().current_root_index, 5);
+        assert!(committed.iter().all(|root| tree.is_known_root(*root)));
+        assert_eq!(tree.tree().filled_subtrees, eager.filled_subtrees);
+    }
+
+    #[test]
+    fn test_deferred_manual_commit() {
+        let mut tree = DeferredPoseidonMerkleTree::new(4, 0).unwrap();
+        let initial_root = tree.commit_root();
+        assert_eq!(tree.tree().current_root_index, 0);
+
+        for i in 0..3u8 {
+            tree.insert_deferred(&[i + 1; 32]).unwrap();
+        }
+        let latest = tree.latest_root();
+        assert!(!tree.is_known_root(latest));
+        assert!(tree.is_known_root(initial_root));
+
+        assert_eq!(tree.commit_root(), latest);
+        assert!(tree.is_known_root(latest));
+        assert_eq!(tree.pending(), 0);
+        assert_eq!(tree.tree().current_root_index, 1);
+    }
+
+    #[test]
+    fn test_deferred_pending_survives_reload() {
+        let mut tree = DeferredPoseidonMerkleTree::new(4, 10).unwrap();
+        for i in 0..4u8 {
+            tree.insert_deferred(&[i + 1; 32]).unwrap();
+        }
+
+        let bytes = tree.try_to_vec().unwrap();
+        let mut reloaded = DeferredPoseidonMerkleTree::try_from_slice(&bytes).unwrap();
+        assert_eq!(reloaded, tree);
+        assert_eq!(reloaded.pending(), 4);
+        // Bytes written before the tree's options were stored end at `latest_root`
+        let legacy = &bytes[..bytes.len() - crate::OPTIONS_SIZE];
+        assert_eq!(
+            DeferredPoseidonMerkleTree::try_from_slice(legacy).unwrap(),
+            tree
+        );
+
+        let root = reloaded.commit_root();
+        assert!(reloaded.is_known_root(root));
+        assert_eq!(root, tree.commit_root());
+
+        let mut corrupted = tree.clone();
+        corrupted.pending = 10;
+        let bytes = corrupted.try_to_vec().unwrap();
+        assert!(DeferredPoseidonMerkleTree::try_from_slice(&bytes).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use core::borrow::Borrow;
+
+use crate::{PoseidonMerkleTree, PoseidonMerkleTreeError};
+
+impl PoseidonMerkleTree {
+    // Inserts every leaf in order and returns how many were inserted. Stops at the first insert
+    // that fails, such as one past the capacity, and every leaf before it stays inserted; the
+    // error comes with how many that was
+    pub fn try_extend<I>(&mut self, leaves: I) -> Result<u64, (u64, PoseidonMerkleTreeError)>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<[u8; 32]>,
+    {
+        let mut inserted = 0;
+        for leaf in leaves {
+            self.insert(leaf.borrow()).map_err(|err| (inserted, err))?;
+            inserted += 1;
+        }
+        Ok(inserted)
+    }
+}
+
+// `Extend` cannot report an error, so these panic where `try_extend` would fail, after
+// inserting every leaf before the failing one
+impl Extend<[u8; 32]> for PoseidonMerkleTree {
+    fn extend<I: IntoIterator<Item = [u8; 32]>>(&mut self, leaves: I) {
+        if let Err((_, err)) = self.try_extend(leaves) {
+            panic!("failed to extend tree: {err}");
+        }
+    }
+}
+
+impl<'a> Extend<&'a [u8; 32]> for PoseidonMerkleTree {
+    fn extend<I: IntoIterator<Item = &'a [u8; 32]>>(&mut self, leaves: I) {
+        if let Err((_, err)) = self.try_extend(leaves) {
+            panic!("failed to extend tree: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    fn leaves(count: u8) -> Vec<[u8; 32]> {
+        (1..=count).map(|i| [i; 32]).collect()
+    }
+
+    fn reference(levels: u32, leaves: &[[u8; 32]]) -> PoseidonMerkleTree {
+        let mut tree = PoseidonMerkleTree::new(levels).unwrap();
+        for leaf in leaves {
+            tree.insert(leaf).unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn test_extend_matches_sequential_inserts() {
+        let leaves = leaves(6);
+
+        let mut owned = PoseidonMerkleTree::new(4).unwrap();
+        owned.extend(leaves.iter().copied());
+        assert_eq!(owned, reference(4, &leaves));
+
+        let mut borrowed = PoseidonMerkleTree::new(4).unwrap();
+        borrowed.extend(&leaves[..2]);
+        borrowed.extend(leaves[2..].iter());
+        assert_eq!(borrowed, owned);
+
+        let mut tried = PoseidonMerkleTree::new(4).unwrap();
+        assert_eq!(tried.try_extend(&leaves), Ok(6));
+        assert_eq!(tried.try_extend(Vec::<[u8; 32]>::new()), Ok(0));
+        assert_eq!(tried, owned);
+    }
+
+    #[test]
+    fn test_try_extend_keeps_progress_when_the_tree_fills() {
+        let leaves = leaves(6);
+        let mut tree = PoseidonMerkleTree::new(2).unwrap();
+        tree.insert(&leaves[0]).unwrap();
+
+        assert_eq!(
+            tree.try_extend(&leaves[1..]),
+            Err((3, PoseidonMerkleTreeError::MerkleTreeFull { capacity: 4 }))
+        );
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree, reference(2, &leaves[..4]));
+
+        let mut invalid = PoseidonMerkleTree::new(3).unwrap();
+        assert_eq!(
+            invalid.try_extend([[1; 32], [0xff; 32], [2; 32]]),
+            Err((1, PoseidonMerkleTreeError::invalid_leaf(&[0xff; 32])))
+        );
+        assert_eq!(invalid, reference(3, &leaves[..1]));
+    }
+
+    #[test]
+    #[should_panic(expected = "failed to extend tree")]
+    fn test_extend_panics_when_the_tree_fills() {
+        let mut tree = PoseidonMerkleTree::new(1).unwrap();
+        tree.extend(leaves(3));
+    }
+}
@@ -0,0 +1,381 @@
+use core::ptr;
+use core::slice;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::{PoseidonMerkleTree, PoseidonMerkleTreeError};
+
+// C bindings for the `ffi` feature, built into a shared library with
+// `cargo rustc --release --features ffi --crate-type cdylib`. The matching declarations are in
+// `include/poseidon_merkle_tree.h`, generated by `cbindgen --config cbindgen.toml`.
+//
+// Trees are handed out as opaque pointers owned by the caller until `pmt_free`. Functions
+// return `PMT_OK` or one of the codes below; pointers are checked for null, and a panic is
+// caught and reported as `PMT_PANIC` instead of unwinding into the caller
+
+pub const PMT_OK: i32 = 0;
+pub const PMT_INVALID_LEVELS: i32 = 1;
+pub const PMT_MERKLE_TREE_FULL: i32 = 2;
+pub const PMT_INDEX_OUT_OF_BOUNDS: i32 = 3;
+pub const PMT_KEY_NOT_FOUND: i32 = 4;
+pub const PMT_KEY_ALREADY_PRESENT: i32 = 5;
+pub const PMT_INVALID_PROOF: i32 = 6;
+pub const PMT_INVALID_CIRCOM_JSON: i32 = 7;
+pub const PMT_INVALID_CALLDATA: i32 = 8;
+pub const PMT_UNKNOWN_ROOT: i32 = 9;
+pub const PMT_LEAF_CONFLICT: i32 = 10;
+/// No longer returned, as a tree without a leaf log now reports `PMT_LEAF_LOG_UNSUPPORTED`.
+/// Kept so the code is never given to another error
+pub const PMT_LEAF_LOG_DISABLED: i32 = 11;
+pub const PMT_INVALID_PAIR_ALIGNMENT: i32 = 12;
+pub const PMT_MISALIGNED_SUBTREE: i32 = 13;
+pub const PMT_LEAF_LOG_UNSUPPORTED: i32 = 14;
+pub const PMT_INVALID_HISTORY_SIZE: i32 = 15;
+pub const PMT_FILLED_SUBTREES_MISMATCH: i32 = 16;
+pub const PMT_FILLED_SUBTREE_OUTSIDE_FIELD: i32 = 17;
+pub const PMT_ROOT_INDEX_OUT_OF_RANGE: i32 = 18;
+pub const PMT_NEXT_INDEX_BEYOND_CAPACITY: i32 = 19;
+pub const PMT_LEAF_LOG_MISMATCH: i32 = 20;
+pub const PMT_INVALID_ENCODING: i32 = 21;
+pub const PMT_UNSUPPORTED_VERSION: i32 = 22;
+pub const PMT_INVALID_ACCOUNT_DATA: i32 = 23;
+pub const PMT_HASH_ERROR: i32 = 24;
+/// No longer returned, as the shared hasher now recovers from a poisoned lock. Kept so the
+/// code is never given to another error
+pub const PMT_POSEIDON_LOCK_ERROR: i32 = 25;
+pub const PMT_FRONTIER_MISMATCH: i32 = 26;
+pub const PMT_INVALID_HEX: i32 = 27;
+pub const PMT_INVALID_LEAF: i32 = 28;
+pub const PMT_MISSING_EVENT: i32 = 29;
+pub const PMT_DUPLICATE_EVENT: i32 = 30;
+pub const PMT_INVALID_DECIMAL: i32 = 31;
+pub const PMT_CHECKPOINT_MISMATCH: i32 = 32;
+pub const PMT_SNAPSHOT_MISMATCH: i32 = 33;
+pub const PMT_DUPLICATE_LEAF: i32 = 34;
+/// No longer returned, as a level out of range now reports `PMT_INVALID_LEVELS`. Kept so the
+/// code is never given to another error
+pub const PMT_INVALID_LEVEL: i32 = 35;
+pub const PMT_INVALID_POSEIDON_PARAMETERS: i32 = 36;
+pub const PMT_INVALID_INPUT_COUNT: i32 = 37;
+pub const PMT_ZERO_CHAIN_MISMATCH: i32 = 38;
+pub const PMT_DOUBLE_SPEND: i32 = 39;
+pub const PMT_NULLIFIER_SET_FULL: i32 = 40;
+pub const PMT_INVALID_NOTE: i32 = 41;
+
+// Errors of the bindings themselves, kept clear of the range above
+pub const PMT_NULL_POINTER: i32 = -1;
+pub const PMT_BUFFER_TOO_SMALL: i32 = -2;
+pub const PMT_PANIC: i32 = -3;
+
+pub fn error_code(error: &PoseidonMerkleTreeError) -> i32 {
+    match error {
+        PoseidonMerkleTreeError::InvalidLevels { .. } => PMT_INVALID_LEVELS,
+        PoseidonMerkleTreeError::MerkleTreeFull { .. } => PMT_MERKLE_TREE_FULL,
+        PoseidonMerkleTreeError::IndexOutOfBounds { .. } => PMT_INDEX_OUT_OF_BOUNDS,
+        PoseidonMerkleTreeError::KeyNotFound => PMT_KEY_NOT_FOUND,
+        PoseidonMerkleTreeError::KeyAlreadyPresent => PMT_KEY_ALREADY_PRESENT,
+        PoseidonMerkleTreeError::InvalidProof => PMT_INVALID_PROOF,
+        PoseidonMerkleTreeError::InvalidCircomJson => PMT_INVALID_CIRCOM_JSON,
+        PoseidonMerkleTreeError::InvalidCalldata => PMT_INVALID_CALLDATA,
+        PoseidonMerkleTreeError::UnknownRoot => PMT_UNKNOWN_ROOT,
+        PoseidonMerkleTreeError::LeafConflict => PMT_LEAF_CONFLICT,
+        PoseidonMerkleTreeError::InvalidPairAlignment => PMT_INVALID_PAIR_ALIGNMENT,
+        PoseidonMerkleTreeError::MisalignedSubtree => PMT_MISALIGNED_SUBTREE,
+        PoseidonMerkleTreeError::LeafLogUnsupported => PMT_LEAF_LOG_UNSUPPORTED,
+        PoseidonMerkleTreeError::InvalidHistorySize => PMT_INVALID_HISTORY_SIZE,
+        PoseidonMerkleTreeError::FilledSubtreesMismatch => PMT_FILLED_SUBTREES_MISMATCH,
+        PoseidonMerkleTreeError::FilledSubtreeOutsideField => PMT_FILLED_SUBTREE_OUTSIDE_FIELD,
+        PoseidonMerkleTreeError::RootIndexOutOfRange => PMT_ROOT_INDEX_OUT_OF_RANGE,
+        PoseidonMerkleTreeError::NextIndexBeyondCapacity => PMT_NEXT_INDEX_BEYOND_CAPACITY,
+        PoseidonMerkleTreeError::LeafLogMismatch => PMT_LEAF_LOG_MISMATCH,
+        PoseidonMerkleTreeError::InvalidEncoding => PMT_INVALID_ENCODING,
+        PoseidonMerkleTreeError::UnsupportedVersion(_) => PMT_UNSUPPORTED_VERSION,
+        PoseidonMerkleTreeError::InvalidAccountData => PMT_INVALID_ACCOUNT_DATA,
+        PoseidonMerkleTreeError::FrontierMismatch => PMT_FRONTIER_MISMATCH,
+        PoseidonMerkleTreeError::InvalidHex => PMT_INVALID_HEX,
+        PoseidonMerkleTreeError::InvalidDecimal => PMT_INVALID_DECIMAL,
+        PoseidonMerkleTreeError::InvalidLeaf { .. } => PMT_INVALID_LEAF,
+        PoseidonMerkleTreeError::MissingEvent(_) => PMT_MISSING_EVENT,
+        PoseidonMerkleTreeError::DuplicateEvent(_) => PMT_DUPLICATE_EVENT,
+        PoseidonMerkleTreeError::CheckpointMismatch => PMT_CHECKPOINT_MISMATCH,
+        PoseidonMerkleTreeError::SnapshotMismatch => PMT_SNAPSHOT_MISMATCH,
+        PoseidonMerkleTreeError::DuplicateLeaf => PMT_DUPLICATE_LEAF,
+        PoseidonMerkleTreeError::InvalidPoseidonParameters => PMT_INVALID_POSEIDON_PARAMETERS,
+        PoseidonMerkleTreeError::InvalidInputCount => PMT_INVALID_INPUT_COUNT,
+        PoseidonMerkleTreeError::ZeroChainMismatch { .. } => PMT_ZERO_CHAIN_MISMATCH,
+        PoseidonMerkleTreeError::DoubleSpend => PMT_DOUBLE_SPEND,
+        PoseidonMerkleTreeError::NullifierSetFull { .. } => PMT_NULLIFIER_SET_FULL,
+        PoseidonMerkleTreeError::InvalidNote => PMT_INVALID_NOTE,
+        PoseidonMerkleTreeError::HashError(_) => PMT_HASH_ERROR,
+    }
+}
+
+// Runs `body`, turning a panic into `PMT_PANIC`. Nothing the bindings hand out is left half
+// updated by a panic, as every update goes through methods that change the tree only once
+// hashing has succeeded
+fn guarded(body: impl FnOnce() -> Result<(), i32>) -> i32 {
+    match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => PMT_OK,
+        Ok(Err(code)) => code,
+        Err(_) => PMT_PANIC,
+    }
+}
+
+fn check(result: Result<(), PoseidonMerkleTreeError>) -> Result<(), i32> {
+    result.map_err(|error| error_code(&error))
+}
+
+unsafe fn tree_ref<'a>(tree: *const PoseidonMerkleTree) -> Result<&'a PoseidonMerkleTree, i32> {
+    tree.as_ref().ok_or(PMT_NULL_POINTER)
+}
+
+unsafe fn bytes32<'a>(bytes: *const u8) -> Result<&'a [u8; 32], i32> {
+    bytes.cast::<[u8; 32]>().as_ref().ok_or(PMT_NULL_POINTER)
+}
+
+/// Returns a new empty tree to be freed with `pmt_free`, or null if `levels` is not between 1
+/// and 20
+#[no_mangle]
+pub extern "C" fn pmt_new(levels: u32) -> *mut PoseidonMerkleTree {
+    // `PoseidonMerkleTree::new` does not reject an empty tree itself yet
+    if levels == 0 {
+        return ptr::null_mut();
+    }
+    catch_unwind(|| PoseidonMerkleTree::new(levels))
+        .ok()
+        .and_then(Result::ok)
+        .map_or(ptr::null_mut(), |tree| Box::into_raw(Box::new(tree)))
+}
+
+/// # Safety
+///
+/// `tree` must be null or a pointer returned by `pmt_new` or `pmt_deserialize` that has not
+/// been freed yet
+#[no_mangle]
+pub unsafe extern "C" fn pmt_free(tree: *mut PoseidonMerkleTree) {
+    if !tree.is_null() {
+        drop(Box::from_raw(tree));
+    }
+}
+
+/// # Safety
+///
+/// `tree` must be a live tree and `leaf` must point to 32 readable bytes, the leaf in
+/// big-endian order
+#[no_mangle]
+pub unsafe extern "C" fn pmt_insert(tree: *mut PoseidonMerkleTree, leaf: *const u8) -> i32 {
+    guarded(|| {
+        let tree = tree.as_mut().ok_or(PMT_NULL_POINTER)?;
+        let leaf = bytes32(leaf)?;
+        check(tree.insert(leaf).map(drop))
+    })
+}
+
+/// # Safety
+///
+/// `tree` must be a live tree and `out` must point to 32 writable bytes, which receive the
+/// current root
+#[no_mangle]
+pub unsafe extern "C" fn pmt_root(tree: *const PoseidonMerkleTree, out: *mut u8) -> i32 {
+    guarded(|| {
+        let tree = tree_ref(tree)?;
+        let out = out.cast::<[u8; 32]>().as_mut().ok_or(PMT_NULL_POINTER)?;
+        *out = tree.last_root();
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `tree` must be a live tree, `root` must point to 32 readable bytes and `known` to a
+/// writable `bool`
+#[no_mangle]
+pub unsafe extern "C" fn pmt_is_known_root(
+    tree: *const PoseidonMerkleTree,
+    root: *const u8,
+    known: *mut bool,
+) -> i32 {
+    guarded(|| {
+        let tree = tree_ref(tree)?;
+        let root = bytes32(root)?;
+        let known = known.as_mut().ok_or(PMT_NULL_POINTER)?;
+        *known = tree.is_known_root(*root);
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `tree` must be a live tree, `out` must point to `capacity` writable bytes (it may be null
+/// when `capacity` is zero) and `written` to a writable `size_t`.
+///
+/// Writes the versioned encoding read back by `pmt_deserialize`. `written` receives its
+/// length, and if that exceeds `capacity` nothing is written and `PMT_BUFFER_TOO_SMALL` is
+/// returned, so a caller can size its buffer with a first call
+#[no_mangle]
+pub unsafe extern "C" fn pmt_serialize(
+    tree: *const PoseidonMerkleTree,
+    out: *mut u8,
+    capacity: usize,
+    written: *mut usize,
+) -> i32 {
+    guarded(|| {
+        let tree = tree_ref(tree)?;
+        let written = written.as_mut().ok_or(PMT_NULL_POINTER)?;
+        let bytes = tree
+            .serialize_versioned()
+            .map_err(|_| PMT_INVALID_ENCODING)?;
+        *written = bytes.len();
+        if bytes.len() > capacity {
+            return Err(PMT_BUFFER_TOO_SMALL);
+        }
+        if out.is_null() {
+            return Err(PMT_NULL_POINTER);
+        }
+        ptr::copy_nonoverlapping(bytes.as_ptr(), out, bytes.len());
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `bytes` must point to `len` readable bytes (it may be null when `len` is zero) and `out`
+/// to a writable tree pointer.
+///
+/// Reads any encoding `PoseidonMerkleTree::deserialize_versioned` accepts into a new tree
+/// stored in `out`, to be freed with `pmt_free`. On failure `out` is set to null
+#[no_mangle]
+pub unsafe extern "C" fn pmt_deserialize(
+    bytes: *const u8,
+    len: usize,
+    out: *mut *mut PoseidonMerkleTree,
+) -> i32 {
+    guarded(|| {
+        let out = out.as_mut().ok_or(PMT_NULL_POINTER)?;
+        *out = ptr::null_mut();
+        let bytes = match len {
+            0 => &[],
+            _ if bytes.is_null() => return Err(PMT_NULL_POINTER),
+            _ => slice::from_raw_parts(bytes, len),
+        };
+        let tree =
+            PoseidonMerkleTree::deserialize_versioned(bytes).map_err(|error| error_code(&error))?;
+        *out = Box::into_raw(Box::new(tree));
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drive_tree_through_c_api() {
+        unsafe {
+            let tree = pmt_new(4);
+            assert!(!tree.is_null());
+            let mut expected = PoseidonMerkleTree::new(4).unwrap();
+            for i in 1..=5u8 {
+                assert_eq!(pmt_insert(tree, [i; 32].as_ptr()), PMT_OK);
+                expected.insert(&[i; 32]).unwrap();
+            }
+
+            let mut root = [0u8; 32];
+            assert_eq!(pmt_root(tree, root.as_mut_ptr()), PMT_OK);
+            assert_eq!(root, expected.last_root());
+            let mut known = false;
+            assert_eq!(pmt_is_known_root(tree, root.as_ptr(), &mut known), PMT_OK);
+            assert!(known);
+            assert_eq!(
+                pmt_is_known_root(tree, [9; 32].as_ptr(), &mut known),
+                PMT_OK
+            );
+            assert!(!known);
+
+            let mut len = 0;
+            assert_eq!(
+                pmt_serialize(tree, ptr::null_mut(), 0, &mut len),
+                PMT_BUFFER_TOO_SMALL
+            );
+            let mut bytes = vec![0u8; len];
+            assert_eq!(
+                pmt_serialize(tree, bytes.as_mut_ptr(), bytes.len(), &mut len),
+                PMT_OK
+            );
+            assert_eq!(bytes, expected.serialize_versioned().unwrap());
+
+            let mut copy = ptr::null_mut();
+            assert_eq!(pmt_deserialize(bytes.as_ptr(), len, &mut copy), PMT_OK);
+            assert_eq!(*copy, expected);
+
+            pmt_free(copy);
+            pmt_free(tree);
+        }
+    }
+
+    #[test]
+    fn test_reports_errors_as_codes() {
+        unsafe {
+            assert!(pmt_new(0).is_null());
+            assert!(pmt_new(crate::MAX_LEVELS as u32 + 1).is_null());
+
+            let tree = pmt_new(1);
+            assert_eq!(pmt_insert(tree, [0xff; 32].as_ptr()), PMT_INVALID_LEAF);
+            assert_eq!(pmt_insert(tree, [1; 32].as_ptr()), PMT_OK);
+            assert_eq!(pmt_insert(tree, [2; 32].as_ptr()), PMT_OK);
+            assert_eq!(pmt_insert(tree, [3; 32].as_ptr()), PMT_MERKLE_TREE_FULL);
+
+            let mut known = false;
+            assert_eq!(
+                pmt_insert(ptr::null_mut(), [1; 32].as_ptr()),
+                PMT_NULL_POINTER
+            );
+            assert_eq!(pmt_insert(tree, ptr::null()), PMT_NULL_POINTER);
+            assert_eq!(pmt_root(tree, ptr::null_mut()), PMT_NULL_POINTER);
+            assert_eq!(
+                pmt_is_known_root(ptr::null(), [1; 32].as_ptr(), &mut known),
+                PMT_NULL_POINTER
+            );
+            pmt_free(tree);
+            pmt_free(ptr::null_mut());
+
+            let mut out = ptr::null_mut();
+            assert_eq!(
+                pmt_deserialize(b"PMTv\x04".as_ptr(), 5, &mut out),
+                PMT_UNSUPPORTED_VERSION
+            );
+            assert_eq!(
+                pmt_deserialize(ptr::null(), 0, &mut out),
+                PMT_INVALID_ENCODING
+            );
+            assert_eq!(pmt_deserialize(ptr::null(), 3, &mut out), PMT_NULL_POINTER);
+            assert!(out.is_null());
+        }
+    }
+
+    #[test]
+    fn test_header_matches_codes() {
+        let header = include_str!("../include/poseidon_merkle_tree.h");
+        for (name, code) in [
+            ("PMT_OK", PMT_OK),
+            ("PMT_INVALID_LEVELS", PMT_INVALID_LEVELS),
+            ("PMT_MERKLE_TREE_FULL", PMT_MERKLE_TREE_FULL),
+            ("PMT_INVALID_ENCODING", PMT_INVALID_ENCODING),
+            ("PMT_POSEIDON_LOCK_ERROR", PMT_POSEIDON_LOCK_ERROR),
+            ("PMT_NULL_POINTER", PMT_NULL_POINTER),
+            ("PMT_PANIC", PMT_PANIC),
+        ] {
+            assert!(header.contains(&format!("#define {name} {code}")), "{name}");
+        }
+        for function in [
+            "pmt_new",
+            "pmt_free",
+            "pmt_insert",
+            "pmt_root",
+            "pmt_is_known_root",
+            "pmt_serialize",
+            "pmt_deserialize",
+        ] {
+            assert!(header.contains(&format!("{function}(")), "{function}");
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use alloc::collections::BTreeMap;
+
+use ark_bn254::Fr;
+use ark_ff::PrimeField;
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{
+    hash_pair, lock_poseidon, PoseidonMerkleTree, PoseidonMerkleTreeError, TreeHasher, MAX_LEVELS,
+    ROOT_HISTORY_SIZE, ZERO_HASHES,
+};
+
+// `PoseidonMerkleTree` with the depth fixed by the type, so the stored arrays can never drift
+// from it after deserialization
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct PoseidonMerkleTreeConst<const LEVELS: usize> {
+    pub filled_subtrees: [[u8; 32]; LEVELS],
+    pub roots: [[u8; 32]; ROOT_HISTORY_SIZE],
+    pub current_root_index: u32,
+    pub next_index: u64,
+}
+
+impl<const LEVELS: usize> PoseidonMerkleTreeConst<LEVELS> {
+    pub const SIZE: usize = 32 * LEVELS + 32 * ROOT_HISTORY_SIZE + 4 + 8;
+
+    // Evaluated when `new` is instantiated, so an unsupported depth fails to compile
+    const VALID_LEVELS: () = assert!(LEVELS > 0 && LEVELS <= MAX_LEVELS, "Invalid levels");
+
+    pub fn new() -> PoseidonMerkleTreeConst<LEVELS> {
+        let () = Self::VALID_LEVELS;
+
+        let mut roots = [[0; 32]; ROOT_HISTORY_SIZE];
+        roots[0] = ZERO_HASHES[LEVELS - 1];
+
+        PoseidonMerkleTreeConst {
+            filled_subtrees: core::array::from_fn(|level| ZERO_HASHES[level]),
+            roots,
+            current_root_index: 0,
+            next_index: 0,
+        }
+    }
+
+    pub fn insert(&mut self, leaf: &[u8; 32]) -> Result<u64, PoseidonMerkleTreeError> {
+        if self.next_index >= 1u64 << LEVELS {
+            return Err(PoseidonMerkleTreeError::MerkleTreeFull {
+                capacity: 1u64 << LEVELS,
+            });
+        }
+
+        let mut poseidon = lock_poseidon();
+
+        // Hash into a copy so a failure leaves the tree untouched
+        let mut filled_subtrees = self.filled_subtrees;
+        let mut current_index = self.next_index;
+        let mut current_level_hash = *leaf;
+        for (level, filled) in filled_subtrees.iter_mut().enumerate() {
+            let (left, right) = if current_index.is_multiple_of(2) {
+                (current_level_hash, ZERO_HASHES[level])
+            } else {
+                (*filled, current_level_hash)
+            };
+
+            current_level_hash = hash_pair(&mut poseidon, &left, &right)?;
+            *filled = left;
+            current_index /= 2;
+        }
+
+        let new_root_index = (self.current_root_index + 1) % ROOT_HISTORY_SIZE as u32;
+        self.filled_subtrees = filled_subtrees;
+        self.current_root_index = new_root_index;
+        self.roots[new_root_index as usize] = current_level_hash;
+        self.next_index += 1;
+
+        Ok(self.next_index - 1)
+    }
+
+    pub fn is_known_root(&self, root: [u8; 32]) -> bool {
+        if root == [0; 32] {
+            return false;
+        }
+
+        // Slots older than the number of inserts were never written
+        let written = (self.next_index + 1).min(ROOT_HISTORY_SIZE as u64);
+        let mut i = self.current_root_index;
+        for _ in 0..written {
+            if self.roots[i as usize] == root {
+                return true;
+            }
+            i = if i == 0 {
+                ROOT_HISTORY_SIZE as u32 - 1
+            } else {
+                i - 1
+            };
+        }
+
+        false
+    }
+}
+
+impl<const LEVELS: usize> Default for PoseidonMerkleTreeConst<LEVELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const LEVELS: usize> From<PoseidonMerkleTreeConst<LEVELS>> for PoseidonMerkleTree {
+    fn from(tree: PoseidonMerkleTreeConst<LEVELS>) -> PoseidonMerkleTree {
+        let mut converted = PoseidonMerkleTree {
+            levels: LEVELS as u32,
+            // Every node a tree writes is a hash output, and so inside the field
+            filled_subtrees: tree
+                .filled_subtrees
+                .iter()
+                .map(|node| Fr::from_be_bytes_mod_order(node))
+                .collect(),
+            roots: tree.roots.to_vec(),
+            current_root_index: tree.current_root_index,
+            next_index: tree.next_index,
+            leaf_log: None,
+            leaf_positions: Default::default(),
+            reject_duplicates: false,
+            accept_initial_root: true,
+            known_roots: BTreeMap::new(),
+            hasher: TreeHasher::default(),
+            zeros: Default::default(),
+            endianness: Default::default(),
+        };
+        converted.reindex_roots();
+        converted
+    }
+}
+
+// Fails with `InvalidLevels` unless the dynamic tree has exactly `LEVELS` levels, and with
+// `InvalidHistorySize` unless it keeps the default `ROOT_HISTORY_SIZE` roots. A leaf log is
+// not carried over
+impl<const LEVELS: usize> TryFrom<PoseidonMerkleTree> for PoseidonMerkleTreeConst<LEVELS> {
+    type Error = PoseidonMerkleTreeError;
+
+    fn try_from(tree: PoseidonMerkleTree) -> Result<Self, Self::Error> {
+        if tree.levels as usize != LEVELS {
+            return Err(PoseidonMerkleTreeError::InvalidLevels {
+                got: tree.levels,
+                max: LEVELS as u32,
+            });
+        }
+
+        Ok(PoseidonMerkleTreeConst {
+            filled_subtrees: tree
+                .filled_subtrees()
+                .try_into()
+                .map_err(|_| PoseidonMerkleTreeError::invalid_levels(LEVELS as u32))?,
+            roots: tree
+                .roots
+                .try_into()
+                .map_err(|_| PoseidonMerkleTreeError::InvalidHistorySize)?,
+            current_root_index: tree.current_root_index,
+            next_index: tree.next_index,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_const_tree_matches_dynamic_tree() {
+        let mut fixed = PoseidonMerkleTreeConst::<4>::new();
+        let mut dynamic = PoseidonMerkleTree::new(4).unwrap();
+        assert_eq!(PoseidonMerkleTree::from(fixed.clone()), dynamic);
+
+        for i in 0..16u8 {
+            assert_eq!(fixed.insert(&[i + 1; 32]), dynamic.insert(&[i + 1; 32]));
+            assert_eq!(
+                fixed.roots[fixed.current_root_index as usize],
+                dynamic.last_root()
+            );
+        }
+        assert_eq!(PoseidonMerkleTree::from(fixed.clone()), dynamic);
+        assert!(fixed.is_known_root(dynamic.last_root()));
+        assert_eq!(
+            fixed.insert(&[17u8; 32]),
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 16 })
+        );
+
+        // A count past the capacity is refused the same way
+        fixed.next_index = 40;
+        let before = fixed.clone();
+        assert_eq!(
+            fixed.insert(&[17u8; 32]),
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 16 })
+        );
+        assert_eq!(fixed, before);
+    }
+
+    #[test]
+    fn test_const_tree_conversions() {
+        let mut dynamic = PoseidonMerkleTree::new(20).unwrap();
+        for i in 0..3u8 {
+            dynamic.insert(&[i + 1; 32]).unwrap();
+        }
+
+        let mut fixed = PoseidonMerkleTreeConst::<20>::try_from(dynamic.clone()).unwrap();
+        fixed.insert(&[4u8; 32]).unwrap();
+        dynamic.insert(&[4u8; 32]).unwrap();
+        assert_eq!(PoseidonMerkleTree::from(fixed), dynamic);
+
+        assert_eq!(
+            PoseidonMerkleTreeConst::<19>::try_from(dynamic.clone()),
+            Err(PoseidonMerkleTreeError::InvalidLevels { got: 20, max: 19 })
+        );
+        dynamic.filled_subtrees.pop();
+        assert_eq!(
+            PoseidonMerkleTreeConst::<20>::try_from(dynamic),
+            Err(PoseidonMerkleTreeError::invalid_levels(20))
+        );
+        assert_eq!(
+            PoseidonMerkleTreeConst::<20>::try_from(
+                PoseidonMerkleTree::new_with_history(20, 100).unwrap()
+            ),
+            Err(PoseidonMerkleTreeError::InvalidHistorySize)
+        );
+    }
+
+    #[test]
+    fn test_const_tree_size() {
+        let mut tree = PoseidonMerkleTreeConst::<20>::new();
+        tree.insert(&[1u8; 32]).unwrap();
+
+        let bytes = tree.try_to_vec().unwrap();
+        assert_eq!(bytes.len(), PoseidonMerkleTreeConst::<20>::SIZE);
+        assert_eq!(
+            PoseidonMerkleTreeConst::<20>::try_from_slice(&bytes).unwrap(),
+            tree
+        );
+        assert_eq!(PoseidonMerkleTreeConst::<3>::SIZE, 32 * 3 + 32 * 20 + 12);
+    }
+}
@@ -0,0 +1,162 @@
+use serde_json::Value;
+
+use crate::decode_hash;
+
+// Reference vectors for the `fixtures` feature, so a change to the hashing or the zero values
+// fails loudly instead of silently moving every root. `testdata/merkle_vectors.py` generates
+// them with a standalone port of circomlibjs's Poseidon, checked against circomlibjs's own
+// expected output, rather than with this crate
+const VECTORS: &str = include_str!("testdata/merkle_vectors.json");
+
+// A tree of `levels` and the root it must report after each of `leaves` is inserted
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestVector {
+    pub levels: u32,
+    pub leaves: Vec<[u8; 32]>,
+    pub roots: Vec<[u8; 32]>,
+}
+
+// `hash` is Poseidon of `left` and `right`
+#[derive(Clone, Debug, PartialEq)]
+pub struct HashVector {
+    pub left: [u8; 32],
+    pub right: [u8; 32],
+    pub hash: [u8; 32],
+}
+
+impl TestVector {
+    // Trees of depth 2, 5 and 20; the first one is filled completely
+    pub fn load_all() -> Vec<TestVector> {
+        entries("trees")
+            .iter()
+            .map(|entry| TestVector {
+                levels: entry["levels"].as_u64().expect("vector levels") as u32,
+                leaves: hashes(&entry["leaves"]),
+                roots: hashes(&entry["roots"]),
+            })
+            .collect()
+    }
+}
+
+impl HashVector {
+    pub fn load_all() -> Vec<HashVector> {
+        entries("hashes")
+            .iter()
+            .map(|entry| HashVector {
+                left: hash(&entry["left"]),
+                right: hash(&entry["right"]),
+                hash: hash(&entry["hash"]),
+            })
+            .collect()
+    }
+}
+
+// Replays every vector against another tree implementation: `new` builds an empty tree of the
+// given depth and `insert` adds one leaf and returns the new root. Panics at the first root
+// that differs
+pub fn assert_matches_vectors<T>(
+    mut new: impl FnMut(u32) -> T,
+    mut insert: impl FnMut(&mut T, &[u8; 32]) -> [u8; 32],
+) {
+    for vector in TestVector::load_all() {
+        let mut tree = new(vector.levels);
+        for (i, (leaf, root)) in vector.leaves.iter().zip(&vector.roots).enumerate() {
+            assert_eq!(
+                insert(&mut tree, leaf),
+                *root,
+                "root after leaf {i} of the depth {} vector",
+                vector.levels
+            );
+        }
+    }
+}
+
+fn entries(name: &str) -> Vec<Value> {
+    let vectors: Value = serde_json::from_str(VECTORS).expect("bundled vectors are valid JSON");
+    vectors[name].as_array().expect("vector list").clone()
+}
+
+fn hashes(values: &Value) -> Vec<[u8; 32]> {
+    values
+        .as_array()
+        .expect("vector hash list")
+        .iter()
+        .map(hash)
+        .collect()
+}
+
+fn hash(value: &Value) -> [u8; 32] {
+    decode_hash(value.as_str().expect("vector hash")).expect("vector hash is hex")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hash_pair, lock_poseidon, FullPoseidonMerkleTree, PoseidonMerkleTree};
+
+    fn root(tree: &PoseidonMerkleTree) -> [u8; 32] {
+        tree.last_root()
+    }
+
+    #[test]
+    fn test_vectors_cover_the_promised_trees() {
+        let vectors = TestVector::load_all();
+        assert_eq!(
+            vectors
+                .iter()
+                .map(|vector| vector.levels)
+                .collect::<Vec<_>>(),
+            [2, 5, 20]
+        );
+        for vector in &vectors {
+            assert!(!vector.leaves.is_empty());
+            assert_eq!(vector.leaves.len(), vector.roots.len());
+        }
+        assert_eq!(vectors[0].leaves.len(), 4);
+    }
+
+    #[test]
+    fn test_hasher_matches_vectors() {
+        let mut poseidon = lock_poseidon();
+        for vector in HashVector::load_all() {
+            assert_eq!(
+                hash_pair(&mut poseidon, &vector.left, &vector.right),
+                Ok(vector.hash)
+            );
+        }
+    }
+
+    #[test]
+    fn test_trees_match_vectors() {
+        assert_matches_vectors(
+            |levels| PoseidonMerkleTree::new(levels).unwrap(),
+            |tree, leaf| {
+                tree.insert(leaf).unwrap();
+                root(tree)
+            },
+        );
+        assert_matches_vectors(
+            |levels| FullPoseidonMerkleTree::new(levels).unwrap(),
+            |tree, leaf| {
+                tree.insert(leaf).unwrap();
+                root(tree.tree())
+            },
+        );
+    }
+
+    #[test]
+    fn test_harness_catches_a_wrong_root() {
+        let result = std::panic::catch_unwind(|| {
+            assert_matches_vectors(
+                |levels| PoseidonMerkleTree::new(levels).unwrap(),
+                |tree, leaf| {
+                    tree.insert(leaf).unwrap();
+                    let mut root = root(tree);
+                    root[31] ^= 1;
+                    root
+                },
+            )
+        });
+        assert!(result.is_err());
+    }
+}
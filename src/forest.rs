@@ -0,0 +1,183 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use borsh::maybestd::io::{self, Error, ErrorKind, Read, Write};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{PoseidonMerkleTree, PoseidonMerkleTreeError};
+
+// Sequence of equally deep trees that keeps accepting leaves after one fills up by starting
+// the next. Every tree but the last is full, so a global leaf index maps to its tree and local
+// index by splitting off the low `levels` bits
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerkleForest {
+    pub levels: u32,
+    // Oldest first; the last tree receives new leaves
+    trees: Vec<PoseidonMerkleTree>,
+}
+
+impl MerkleForest {
+    pub fn new(levels: u32) -> Result<MerkleForest, PoseidonMerkleTreeError> {
+        Ok(MerkleForest {
+            levels,
+            trees: vec![PoseidonMerkleTree::new(levels)?],
+        })
+    }
+
+    pub fn trees(&self) -> &[PoseidonMerkleTree] {
+        &self.trees
+    }
+
+    pub fn active_tree(&self) -> &PoseidonMerkleTree {
+        self.trees.last().unwrap()
+    }
+
+    pub fn num_leaves(&self) -> u64 {
+        let full_trees = self.trees.len() as u64 - 1;
+        full_trees * self.active_tree().capacity() + self.active_tree().next_index
+    }
+
+    // Returns the leaf's index across the whole forest, as `locate` takes it
+    pub fn insert(&mut self, leaf: &[u8; 32]) -> Result<u64, PoseidonMerkleTreeError> {
+        let index = self.num_leaves();
+        match self.trees.last_mut().unwrap().insert(leaf) {
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { .. }) => {
+                let mut tree = PoseidonMerkleTree::new(self.levels)?;
+                tree.insert(leaf)?;
+                self.trees.push(tree);
+            }
+            result => {
+                result?;
+            }
+        }
+
+        Ok(index)
+    }
+
+    // The tree a global leaf index landed in and its index inside that tree
+    pub fn locate(&self, global_index: u64) -> Result<(u32, u64), PoseidonMerkleTreeError> {
+        if global_index >= self.num_leaves() {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: global_index,
+                len: self.num_leaves(),
+            });
+        }
+        let capacity = self.active_tree().capacity();
+
+        Ok(((global_index / capacity) as u32, global_index % capacity))
+    }
+
+    pub fn is_known_root(&self, root: [u8; 32]) -> bool {
+        self.trees.iter().any(|tree| tree.is_known_root(root))
+    }
+}
+
+// The trees' fields back to back, then the options of each tree in the same order, so bytes
+// written before the options were stored read with the options of `new`
+impl BorshSerialize for MerkleForest {
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.levels.serialize(writer)?;
+        (self.trees.len() as u32).serialize(writer)?;
+        for tree in &self.trees {
+            tree.serialize_fields(writer)?;
+        }
+        for tree in &self.trees {
+            tree.serialize_options(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl BorshDeserialize for MerkleForest {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let levels = u32::deserialize_reader(reader)?;
+
+        // Trees are read one at a time so a bogus length prefix never allocates up front
+        let len = u32::deserialize_reader(reader)?;
+        if len == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "Forest has no trees"));
+        }
+        let mut trees = (0..len)
+            .map(|_| PoseidonMerkleTree::deserialize_fields(reader))
+            .collect::<io::Result<Vec<_>>>()?;
+        for tree in &mut trees {
+            tree.deserialize_options(reader)?;
+        }
+
+        let (active, full) = trees.split_last().unwrap();
+        if trees.iter().any(|tree| tree.levels != levels)
+            || full.iter().any(|tree| tree.next_index != tree.capacity())
+            || active.next_index > active.capacity()
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Forest trees are inconsistent",
+            ));
+        }
+
+        Ok(MerkleForest { levels, trees })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forest_rolls_over_full_tree() {
+        let mut forest = MerkleForest::new(2).unwrap();
+        for i in 0..4u8 {
+            assert_eq!(forest.insert(&[i + 1; 32]).unwrap(), i as u64);
+        }
+        assert_eq!(forest.trees().len(), 1);
+        let first_root = forest.active_tree().last_root();
+
+        assert_eq!(forest.insert(&[5u8; 32]).unwrap(), 4);
+        assert_eq!(forest.trees().len(), 2);
+        assert_eq!(forest.locate(3).unwrap(), (0, 3));
+        assert_eq!(forest.locate(4).unwrap(), (1, 0));
+        assert_eq!(
+            forest.locate(5),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 5, len: 5 })
+        );
+
+        // The fifth leaf is the first leaf of a fresh tree
+        let mut expected = PoseidonMerkleTree::new(2).unwrap();
+        expected.insert(&[5u8; 32]).unwrap();
+        assert_eq!(forest.active_tree(), &expected);
+        assert!(forest.is_known_root(first_root));
+    }
+
+    #[test]
+    fn test_forest_borsh_round_trip() {
+        let mut forest = MerkleForest::new(2).unwrap();
+        for i in 0..6u8 {
+            forest.insert(&[i + 1; 32]).unwrap();
+        }
+
+        let bytes = forest.try_to_vec().unwrap();
+        let decoded = MerkleForest::try_from_slice(&bytes).unwrap();
+        assert_eq!(decoded, forest);
+        assert_eq!(decoded.num_leaves(), 6);
+
+        // A tree that is not full cannot be followed by another one
+        let mut broken = forest.clone();
+        broken.trees.insert(0, PoseidonMerkleTree::new(2).unwrap());
+        let bytes = broken.try_to_vec().unwrap();
+        assert!(MerkleForest::try_from_slice(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_bytes_without_options_still_load() {
+        let mut forest = MerkleForest::new(2).unwrap();
+        for i in 1..=5u8 {
+            forest.insert(&[i; 32]).unwrap();
+        }
+        // Written before the trees' options were stored
+        let stored = include_bytes!("testdata/forest_levels2_5_leaves.bin");
+        assert_eq!(MerkleForest::try_from_slice(stored).unwrap(), forest);
+        let bytes = forest.try_to_vec().unwrap();
+        assert_eq!(bytes.len(), stored.len() + 2 * crate::OPTIONS_SIZE);
+        assert_eq!(bytes[..stored.len()], stored[..]);
+    }
+}
@@ -0,0 +1,309 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use ark_bn254::Fr;
+use borsh::{BorshDeserialize, BorshSerialize};
+use light_poseidon::Poseidon;
+
+use crate::{
+    hash_pair, lock_poseidon, zero_hash, PoseidonMerkleTree, PoseidonMerkleTreeError, MAX_LEVELS,
+    ROOT_HISTORY_SIZE,
+};
+
+// The state a light client copies from an account to keep inserting, bundled with the root
+// it claims to produce
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct FrontierProof {
+    pub frontier: Vec<[u8; 32]>,
+    pub next_index: u64,
+    pub root: [u8; 32],
+}
+
+// The frontier of `PoseidonMerkleTree::default()`
+impl Default for FrontierProof {
+    fn default() -> FrontierProof {
+        PoseidonMerkleTree::default().prove_frontier()
+    }
+}
+
+impl Default for Frontier {
+    fn default() -> Frontier {
+        PoseidonMerkleTree::default().export_frontier()
+    }
+}
+
+impl FrontierProof {
+    pub fn verify(&self) -> bool {
+        verify_frontier(&self.frontier, self.next_index, &self.root)
+    }
+}
+
+// Everything a replica needs to keep inserting where a tree left off, without its root
+// history or leaf log
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct Frontier {
+    pub levels: u32,
+    pub next_index: u64,
+    pub filled_subtrees: Vec<[u8; 32]>,
+    pub root: [u8; 32],
+}
+
+impl PoseidonMerkleTree {
+    pub fn prove_frontier(&self) -> FrontierProof {
+        FrontierProof {
+            frontier: self.filled_subtrees(),
+            next_index: self.next_index,
+            root: self.endianness.convert(self.last_root()),
+        }
+    }
+
+    pub fn export_frontier(&self) -> Frontier {
+        Frontier {
+            levels: self.levels,
+            next_index: self.next_index,
+            filled_subtrees: self.filled_subtrees(),
+            root: self.endianness.convert(self.last_root()),
+        }
+    }
+
+    // Rebuilds a tree from an exported frontier, after checking that it hashes up to its
+    // root. The tree keeps `ROOT_HISTORY_SIZE` roots, of which only the imported one is
+    // known until more are inserted. As with `verify_frontier`, a full tree cannot be
+    // imported, and neither can an empty one that does not match `new`
+    pub fn from_frontier(
+        frontier: Frontier,
+    ) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        if frontier.levels == 0 || frontier.levels as usize > MAX_LEVELS {
+            return Err(PoseidonMerkleTreeError::invalid_levels(frontier.levels));
+        }
+        if frontier.filled_subtrees.len() != frontier.levels as usize {
+            return Err(PoseidonMerkleTreeError::FilledSubtreesMismatch);
+        }
+
+        let mut poseidon = lock_poseidon();
+        let computed = frontier_root(
+            &mut poseidon,
+            &frontier.filled_subtrees,
+            frontier.next_index,
+        )?;
+        if computed != Some(frontier.root) {
+            return Err(PoseidonMerkleTreeError::FrontierMismatch);
+        }
+
+        let mut roots = vec![[0; 32]; ROOT_HISTORY_SIZE];
+        roots[0] = frontier.root;
+        PoseidonMerkleTree::from_stored(
+            frontier.levels,
+            &frontier.filled_subtrees,
+            roots,
+            0,
+            frontier.next_index,
+            None,
+        )
+    }
+}
+
+// Checks that `frontier` (a tree's `filled_subtrees`) and `next_index` hash up to `root`,
+// filling empty right subtrees with the zero chain. Entries below the lowest left ancestor of
+// the last leaf are overwritten by the next insert before they are ever read, so they are not
+// covered. An empty tree is expected to report the initial root set by
+// `PoseidonMerkleTree::new`. A completely full tree no longer holds its rightmost leaf in the
+// frontier and can never be verified this way
+pub fn verify_frontier(frontier: &[[u8; 32]], next_index: u64, root: &[u8; 32]) -> bool {
+    let mut poseidon = lock_poseidon();
+
+    matches!(
+        frontier_root(&mut poseidon, frontier, next_index),
+        Ok(Some(computed)) if computed == *root
+    )
+}
+
+fn frontier_root(
+    poseidon: &mut Poseidon<Fr>,
+    frontier: &[[u8; 32]],
+    next_index: u64,
+) -> Result<Option<[u8; 32]>, PoseidonMerkleTreeError> {
+    let levels = frontier.len() as u32;
+    if levels == 0 || levels as usize > MAX_LEVELS || next_index > 1u64 << levels {
+        return Ok(None);
+    }
+
+    if next_index == 0 {
+        let pristine = (0..levels).all(|level| zero_hash(level) == Ok(frontier[level as usize]));
+        return Ok(pristine.then_some(zero_hash(levels - 1)?));
+    }
+
+    // Below the first level where the last leaf's ancestor is a left child, the frontier holds
+    // left siblings; from that level on it holds the ancestor itself
+    let last_index = next_index - 1;
+    let Some(start) = (0..levels).find(|level| (last_index >> level).is_multiple_of(2)) else {
+        return Ok(None);
+    };
+
+    let mut current_level_hash = frontier[start as usize];
+    for level in start..levels {
+        let sibling = &frontier[level as usize];
+        current_level_hash = if (last_index >> level).is_multiple_of(2) {
+            // Higher left ancestors are stored as well and must agree with the recomputed path
+            if level > start && *sibling != current_level_hash {
+                return Ok(None);
+            }
+            hash_pair(poseidon, &current_level_hash, &zero_hash(level)?)?
+        } else {
+            hash_pair(poseidon, sibling, &current_level_hash)?
+        };
+    }
+
+    Ok(Some(current_level_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fr_from_be_bytes;
+
+    #[test]
+    fn test_frontier_verifies_at_every_size() {
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        assert!(tree.prove_frontier().verify());
+
+        for i in 0..7u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+            let proof = tree.prove_frontier();
+            assert!(proof.verify(), "frontier after {} inserts", i + 1);
+        }
+    }
+
+    #[test]
+    fn test_frontier_detects_corruption() {
+        let mut tree = PoseidonMerkleTree::new(4).unwrap();
+        for i in 0..5u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let proof = tree.prove_frontier();
+
+        // With the last leaf at an even index every frontier entry contributes to the root
+        for level in 0..4 {
+            let mut corrupted = proof.frontier.clone();
+            corrupted[level][31] ^= 1;
+            assert!(
+                !verify_frontier(&corrupted, proof.next_index, &proof.root),
+                "level {level}"
+            );
+        }
+
+        assert!(!verify_frontier(
+            &proof.frontier[..3],
+            proof.next_index,
+            &proof.root
+        ));
+        assert!(!verify_frontier(
+            &proof.frontier,
+            proof.next_index - 1,
+            &proof.root
+        ));
+        assert!(!verify_frontier(
+            &proof.frontier,
+            proof.next_index,
+            &[1u8; 32]
+        ));
+    }
+
+    #[test]
+    fn test_frontier_ignores_entries_about_to_be_overwritten() {
+        let mut tree = PoseidonMerkleTree::new(4).unwrap();
+        for i in 0..6u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let mut proof = tree.prove_frontier();
+
+        // Leaf 4 is only kept as the left sibling of leaf 5; the next insert replaces it
+        proof.frontier[0] = [7u8; 32];
+        assert!(proof.verify());
+
+        let mut expected = tree.clone();
+        tree.filled_subtrees = proof
+            .frontier
+            .iter()
+            .map(|node| fr_from_be_bytes(node).unwrap())
+            .collect();
+        tree.insert(&[7u8; 32]).unwrap();
+        expected.insert(&[7u8; 32]).unwrap();
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn test_frontier_full_tree_is_unverifiable() {
+        let mut tree = PoseidonMerkleTree::new(2).unwrap();
+        for i in 0..4u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        assert!(!tree.prove_frontier().verify());
+    }
+
+    #[test]
+    fn test_frontier_import_continues_inserting() {
+        let mut source = PoseidonMerkleTree::new(5).unwrap();
+        for i in 0..11u8 {
+            source.insert(&[i + 1; 32]).unwrap();
+        }
+
+        let bytes = source.export_frontier().try_to_vec().unwrap();
+        let frontier = Frontier::try_from_slice(&bytes).unwrap();
+        let mut replica = PoseidonMerkleTree::from_frontier(frontier).unwrap();
+        let root = source.last_root();
+        assert!(replica.is_known_root(root));
+        // Only the imported root carries over
+        assert!(source.is_known_root(source.roots[source.current_root_index as usize - 1]));
+        assert!(!replica.is_known_root(source.roots[source.current_root_index as usize - 1]));
+        assert_eq!(replica.next_index, source.next_index);
+
+        for i in 11..20u8 {
+            assert_eq!(replica.insert(&[i + 1; 32]), source.insert(&[i + 1; 32]));
+            assert_eq!(replica.last_root(), source.last_root());
+        }
+    }
+
+    #[test]
+    fn test_default_frontier_is_the_empty_default_tree() {
+        assert!(FrontierProof::default().verify());
+        let frontier = Frontier::default();
+        assert_eq!(frontier.next_index, 0);
+        assert_eq!(
+            PoseidonMerkleTree::from_frontier(frontier),
+            Ok(PoseidonMerkleTree::default())
+        );
+    }
+
+    #[test]
+    fn test_frontier_import_rejects_mismatches() {
+        let mut tree = PoseidonMerkleTree::new(4).unwrap();
+        for i in 0..5u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let frontier = tree.export_frontier();
+
+        let mut wrong_root = frontier.clone();
+        wrong_root.root = [1u8; 32];
+        let mut corrupted = frontier.clone();
+        corrupted.filled_subtrees[2][31] ^= 1;
+        let mut wrong_index = frontier.clone();
+        wrong_index.next_index += 2;
+        for frontier in [wrong_root, corrupted, wrong_index] {
+            assert_eq!(
+                PoseidonMerkleTree::from_frontier(frontier),
+                Err(PoseidonMerkleTreeError::FrontierMismatch)
+            );
+        }
+
+        let mut short = frontier.clone();
+        short.filled_subtrees.pop();
+        assert_eq!(
+            PoseidonMerkleTree::from_frontier(short),
+            Err(PoseidonMerkleTreeError::FilledSubtreesMismatch)
+        );
+        let mut outside = frontier;
+        outside.filled_subtrees[0] = [0xff; 32];
+        assert!(PoseidonMerkleTree::from_frontier(outside).is_err());
+    }
+}
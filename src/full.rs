@@ -0,0 +1,763 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use ark_bn254::Fr;
+use borsh::maybestd::io::{self, Error, ErrorKind, Read, Write};
+use borsh::{BorshDeserialize, BorshSerialize};
+use light_poseidon::Poseidon;
+
+use crate::proof::position_at;
+use crate::{
+    fr_from_be_bytes, hash_pair, read_tag, LeafIndex, MemoryNodeStore, MerkleProof, MultiProof,
+    NodeStore, PoseidonMerkleTree, PoseidonMerkleTreeError, ZeroPreset,
+};
+
+// Incremental tree that additionally keeps every node it has hashed, so that
+// authentication paths can be produced for any leaf inserted so far
+#[derive(Clone, Debug, PartialEq)]
+pub struct FullPoseidonMerkleTree<S: NodeStore = MemoryNodeStore> {
+    tree: PoseidonMerkleTree,
+    // Level 0 holds the leaves and level `levels` the root; absent nodes are empty subtrees
+    store: S,
+    // Rebuilt from the stored leaves when a tree is read back
+    pub(crate) leaves: LeafIndex,
+    pub(crate) reject_duplicates: bool,
+}
+
+impl FullPoseidonMerkleTree {
+    pub fn new(levels: u32) -> Result<FullPoseidonMerkleTree, PoseidonMerkleTreeError> {
+        FullPoseidonMerkleTree::new_with_zero_preset(levels, ZeroPreset::Voidify)
+    }
+
+    // Like `new`, with empty subtrees and proof siblings taken from `preset`
+    pub fn new_with_zero_preset(
+        levels: u32,
+        preset: ZeroPreset,
+    ) -> Result<FullPoseidonMerkleTree, PoseidonMerkleTreeError> {
+        let tree = PoseidonMerkleTree::new_with_zero_preset(levels, preset)?;
+        let store = MemoryNodeStore {
+            nodes: vec![Vec::new(); levels as usize + 1],
+        };
+        Ok(FullPoseidonMerkleTree {
+            tree,
+            store,
+            leaves: LeafIndex::default(),
+            reject_duplicates: false,
+        })
+    }
+}
+
+impl<S: NodeStore> FullPoseidonMerkleTree<S> {
+    // `store` is expected to be empty; use `from_parts` to resume from a populated one
+    pub fn with_store(
+        levels: u32,
+        store: S,
+    ) -> Result<FullPoseidonMerkleTree<S>, PoseidonMerkleTreeError> {
+        let tree = PoseidonMerkleTree::new(levels)?;
+        Ok(FullPoseidonMerkleTree {
+            tree,
+            store,
+            leaves: LeafIndex::default(),
+            reject_duplicates: false,
+        })
+    }
+
+    // Pairs a tree with the store that holds its nodes, e.g. after reopening a persistent
+    // store. Nothing is checked, so the two must come from the same tree. Every stored leaf is
+    // read once to index it
+    pub fn from_parts(tree: PoseidonMerkleTree, store: S) -> FullPoseidonMerkleTree<S> {
+        let leaves = LeafIndex::from_leaves(
+            (0..tree.next_index).map(|index| store.get(0, index as u32).unwrap_or_default()),
+        );
+        FullPoseidonMerkleTree {
+            tree,
+            store,
+            leaves,
+            reject_duplicates: false,
+        }
+    }
+
+    pub fn tree(&self) -> &PoseidonMerkleTree {
+        &self.tree
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn insert(&mut self, leaf: &[u8; 32]) -> Result<u64, PoseidonMerkleTreeError> {
+        if self.reject_duplicates && self.contains_leaf(leaf) {
+            return Err(PoseidonMerkleTreeError::DuplicateLeaf);
+        }
+        let index = self.tree.next_index as u32;
+        let path = self.tree.append(leaf)?;
+
+        self.leaves.insert(path[0], index as u64);
+        for (level, node) in path.into_iter().enumerate() {
+            self.store
+                .put(level as u32, position_at(index, level), node);
+        }
+
+        Ok(index as u64)
+    }
+
+    // Rewrites the leaf at `index`, rehashes the path above it and records the new root in the
+    // history as an insert would, returning that root. Only inserted leaves can be updated;
+    // later positions fail with `IndexOutOfBounds` and are filled with `insert`
+    pub fn update(
+        &mut self,
+        index: u32,
+        new_leaf: [u8; 32],
+    ) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        if index as u64 >= self.tree.next_index {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: index as u64,
+                len: self.tree.next_index,
+            });
+        }
+        self.tree.read_leaf(&new_leaf)?;
+        if self.reject_duplicates
+            && self
+                .leaf_indices(&new_leaf)
+                .iter()
+                .any(|&other| other != index as u64)
+        {
+            return Err(PoseidonMerkleTreeError::DuplicateLeaf);
+        }
+
+        // Read before the tree's own hasher is checked out, which borrows the tree
+        let levels = self.tree.levels;
+        let siblings: Vec<_> = (0..levels)
+            .map(|level| self.node(level, (index >> level) ^ 1))
+            .collect();
+
+        let mut path = Vec::with_capacity(levels as usize + 1);
+        let mut node = new_leaf;
+        path.push(node);
+        {
+            let mut poseidon = self.tree.hasher.checkout();
+            for (level, sibling) in siblings.iter().enumerate() {
+                node = if (index >> level).is_multiple_of(2) {
+                    hash_pair(&mut poseidon, &node, sibling)?
+                } else {
+                    hash_pair(&mut poseidon, sibling, &node)?
+                };
+                path.push(node);
+            }
+        }
+
+        // Nothing is written until every hash succeeded. A frontier entry on the path is the
+        // left subtree the next inserts hash against, so it takes the new node as well
+        let old_leaf = self.node(0, index);
+        self.leaves.remove(&old_leaf, index as u64);
+        self.leaves.insert(new_leaf, index as u64);
+        let last = self.tree.next_index - 1;
+        for (level, node) in path.into_iter().enumerate() {
+            let position = position_at(index, level);
+            if level < levels as usize && position as u64 == (last >> level) & !1 {
+                self.tree.filled_subtrees[level] = fr_from_be_bytes(&node)?;
+            }
+            self.store.put(level as u32, position, node);
+        }
+        self.tree.record_root(node);
+
+        Ok(node)
+    }
+
+    pub fn is_known_root(&self, root: [u8; 32]) -> bool {
+        self.tree.is_known_root(root)
+    }
+
+    pub fn num_leaves(&self) -> u64 {
+        self.tree.next_index
+    }
+
+    pub fn get_leaf(&self, index: u32) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        if index as u64 >= self.tree.next_index {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: index as u64,
+                len: self.tree.next_index,
+            });
+        }
+        Ok(self.node(0, index))
+    }
+
+    // Any position inside the tree can be read; subtrees with no leaves yet report the zero chain
+    pub fn get_node(&self, level: u32, index: u32) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        let width = (self.tree.levels)
+            .checked_sub(level)
+            .map_or(0, |height| 1u64 << height);
+        if index as u64 >= width {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: index as u64,
+                len: width,
+            });
+        }
+
+        Ok(self.node(level, index))
+    }
+
+    pub fn get_proof(&self, index: u32) -> Result<MerkleProof, PoseidonMerkleTreeError> {
+        if index as u64 >= self.tree.next_index {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: index as u64,
+                len: self.tree.next_index,
+            });
+        }
+
+        let levels = self.tree.levels;
+        let siblings = (0..levels)
+            .map(|level| self.node(level, (index >> level) ^ 1))
+            .collect();
+
+        Ok(MerkleProof {
+            leaf: self.node(0, index),
+            index,
+            siblings,
+        })
+    }
+
+    // Builds the proof for `index` as it was when the tree held `version` leaves, so that it
+    // verifies against the root recorded by that insert
+    pub fn get_proof_at_version(
+        &self,
+        index: u32,
+        version: u32,
+    ) -> Result<MerkleProof, PoseidonMerkleTreeError> {
+        if version as u64 > self.tree.next_index || index >= version {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: index as u64,
+                len: self.tree.next_index.min(version as u64),
+            });
+        }
+
+        // A clone of the tree's own hasher, as the tree is only borrowed
+        let mut hasher = self.tree.hasher.clone();
+        let mut poseidon = hasher.checkout();
+
+        let siblings = (0..self.tree.levels)
+            .map(|level| self.node_at_version(&mut poseidon, level, (index >> level) ^ 1, version))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(MerkleProof {
+            leaf: self.node(0, index),
+            index,
+            siblings,
+        })
+    }
+
+    // Subtrees entirely below `version` have not changed since (the tree is append-only) and
+    // subtrees entirely above it were still empty, so only the subtree straddling `version`
+    // at each level has to be rehashed
+    fn node_at_version(
+        &self,
+        poseidon: &mut Poseidon<Fr>,
+        level: u32,
+        position: u32,
+        version: u32,
+    ) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        let first_leaf = (position as u64) << level;
+        let end_leaf = (position as u64 + 1) << level;
+
+        if first_leaf >= version as u64 {
+            Ok(self.tree.zeros.bytes(level))
+        } else if end_leaf <= version as u64 {
+            Ok(self.node(level, position))
+        } else {
+            let left = self.node_at_version(poseidon, level - 1, position * 2, version)?;
+            let right = self.node_at_version(poseidon, level - 1, position * 2 + 1, version)?;
+            hash_pair(poseidon, &left, &right)
+        }
+    }
+
+    pub fn get_multi_proof(&self, indices: &[u32]) -> Result<MultiProof, PoseidonMerkleTreeError> {
+        if indices.is_empty() {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+        if let Some(&index) = indices
+            .iter()
+            .find(|&&index| index as u64 >= self.tree.next_index)
+        {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: index as u64,
+                len: self.tree.next_index,
+            });
+        }
+
+        let mut positions = indices.to_vec();
+        positions.sort_unstable();
+        positions.dedup();
+
+        let leaves = positions
+            .iter()
+            .map(|&index| (index, self.node(0, index)))
+            .collect();
+
+        // Walk the levels the same way `MultiProof::root` does, emitting every sibling
+        // that is not itself one of the known nodes
+        let mut nodes = Vec::new();
+        for level in 0..self.tree.levels {
+            let mut known = positions.iter().peekable();
+            let mut parents = Vec::with_capacity(positions.len());
+            while let Some(&position) = known.next() {
+                if position % 2 == 0 && known.peek() == Some(&&(position + 1)) {
+                    known.next();
+                } else {
+                    nodes.push(self.node(level, position ^ 1));
+                }
+                parents.push(position / 2);
+            }
+            positions = parents;
+        }
+
+        Ok(MultiProof {
+            levels: self.tree.levels,
+            leaves,
+            nodes,
+        })
+    }
+
+    fn node(&self, level: u32, position: u32) -> [u8; 32] {
+        self.store
+            .get(level, position)
+            .unwrap_or_else(|| self.tree.zeros.bytes(level))
+    }
+}
+
+// The tree's fields and the store, then the tree's options and `reject_duplicates`. Bytes
+// written before those were stored end after the store, and read with the options of `new`
+impl<S: NodeStore + BorshSerialize> BorshSerialize for FullPoseidonMerkleTree<S> {
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.tree.serialize_fields(writer)?;
+        self.store.serialize(writer)?;
+        self.tree.serialize_options(writer)?;
+        self.reject_duplicates.serialize(writer)
+    }
+}
+
+impl BorshDeserialize for FullPoseidonMerkleTree<MemoryNodeStore> {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut tree = PoseidonMerkleTree::deserialize_fields(reader)?;
+        let store = MemoryNodeStore::deserialize_reader(reader)?;
+        let nodes = &store.nodes;
+
+        // Every level must hold exactly the positions touched by `next_index` inserts, which is
+        // what the accessors index into without further checks
+        let consistent = nodes.len() == tree.levels as usize + 1
+            && nodes.iter().enumerate().all(|(level, level_nodes)| {
+                let expected = match tree.next_index {
+                    0 => 0,
+                    next_index => ((next_index - 1) >> level) as usize + 1,
+                };
+                level_nodes.len() == expected
+            });
+        if !consistent {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Stored nodes do not match the tree size",
+            ));
+        }
+
+        tree.deserialize_options(reader)?;
+        let reject_duplicates = match read_tag(reader)? {
+            0 => false,
+            1 => true,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Invalid duplicate policy",
+                ))
+            }
+        };
+
+        let leaves = LeafIndex::from_leaves(store.nodes[0].iter().copied());
+        Ok(FullPoseidonMerkleTree {
+            tree,
+            store,
+            leaves,
+            reject_duplicates,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{zeros, XorShift};
+    use ark_bn254::Fr;
+    use light_poseidon::{Poseidon, PoseidonBytesHasher};
+
+    fn root_from_proof(proof: &MerkleProof) -> [u8; 32] {
+        let mut poseidon = Poseidon::<Fr>::new_circom(2).unwrap();
+        let mut current = proof.leaf;
+        for (sibling, bit) in proof.siblings.iter().zip(proof.path_indices_vec()) {
+            current = if bit == 0 {
+                poseidon.hash_bytes_be(&[&current, sibling]).unwrap()
+            } else {
+                poseidon.hash_bytes_be(&[sibling, &current]).unwrap()
+            };
+        }
+        current
+    }
+
+    #[test]
+    fn test_insert_reports_the_proof_index() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        for expected in 0..5u64 {
+            let index = tree.insert(&[expected as u8 + 1; 32]).unwrap();
+            assert_eq!(index, expected);
+
+            let proof = tree.get_proof(index as u32).unwrap();
+            assert_eq!(proof.leaf, [expected as u8 + 1; 32]);
+            assert_eq!(root_from_proof(&proof), tree.tree().last_root());
+        }
+    }
+
+    #[test]
+    fn test_proof_first_leaf_uses_zero_siblings() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        let leaf = [1u8; 32];
+        tree.insert(&leaf).unwrap();
+
+        let proof = tree.get_proof(0).unwrap();
+        assert_eq!(proof.leaf, leaf);
+        assert_eq!(proof.index, 0);
+        assert_eq!(proof.siblings, vec![zeros(0), zeros(1), zeros(2)]);
+        assert_eq!(proof.path_indices_vec(), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn test_proofs_match_incremental_root() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        let mut incremental = PoseidonMerkleTree::new(3).unwrap();
+        for i in 0..5u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+            incremental.insert(&[i + 1; 32]).unwrap();
+        }
+        assert_eq!(tree.tree(), &incremental);
+
+        let root = incremental.last_root();
+        for index in 0..5 {
+            let proof = tree.get_proof(index).unwrap();
+            assert_eq!(proof.leaf, [index as u8 + 1; 32]);
+            assert_eq!(root_from_proof(&proof), root);
+        }
+
+        let proof = tree.get_proof(3).unwrap();
+        assert_eq!(proof.path_indices(), 0b011);
+    }
+
+    #[test]
+    fn test_proof_at_version() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        for i in 0..5u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let historical_root = tree.tree().last_root();
+        let proof = tree.get_proof_at_version(3, 5).unwrap();
+        assert_eq!(proof, tree.get_proof(3).unwrap());
+
+        tree.insert(&[6u8; 32]).unwrap();
+        tree.insert(&[7u8; 32]).unwrap();
+
+        // A proof taken from the same historical state after the tree has moved on
+        let proof = tree.get_proof_at_version(3, 5).unwrap();
+        assert_eq!(proof.root().unwrap(), historical_root);
+        assert!(tree.tree().is_known_root(proof.root().unwrap()));
+
+        let proof = tree.get_proof_at_version(1, 2).unwrap();
+        assert!(tree.tree().is_known_root(proof.root().unwrap()));
+        assert_eq!(proof.siblings[1], zeros(1));
+    }
+
+    #[test]
+    fn test_proof_at_version_out_of_bounds() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        for i in 0..3u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        assert_eq!(
+            tree.get_proof_at_version(2, 2),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 2, len: 2 })
+        );
+        assert_eq!(
+            tree.get_proof_at_version(0, 4),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 0, len: 3 })
+        );
+    }
+
+    #[test]
+    fn test_multi_proof() {
+        let mut tree = FullPoseidonMerkleTree::new(4).unwrap();
+        for i in 0..11u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let root = tree.tree().last_root();
+
+        // Unsorted input with duplicates is normalized
+        let proof = tree.get_multi_proof(&[9, 2, 3, 9, 0]).unwrap();
+        assert_eq!(
+            proof
+                .leaves
+                .iter()
+                .map(|(index, _)| *index)
+                .collect::<Vec<_>>(),
+            vec![0, 2, 3, 9]
+        );
+        assert!(proof.verify(&root).unwrap());
+        assert_eq!(proof, tree.get_multi_proof(&[0, 2, 3, 9]).unwrap());
+
+        let mut tampered = proof.clone();
+        tampered.leaves[1].1 = [42u8; 32];
+        assert!(!tampered.verify(&root).unwrap());
+
+        let mut truncated = proof.clone();
+        truncated.nodes.pop();
+        assert_eq!(
+            truncated.verify(&root),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+    }
+
+    #[test]
+    fn test_multi_proof_smaller_than_single_proofs() {
+        let mut tree = FullPoseidonMerkleTree::new(10).unwrap();
+        for i in 0..64u8 {
+            let mut leaf = [0u8; 32];
+            leaf[31] = i;
+            tree.insert(&leaf).unwrap();
+        }
+        let root = tree.tree().last_root();
+
+        let indices: Vec<u32> = (8..24).collect();
+        let multi = tree.get_multi_proof(&indices).unwrap();
+        assert!(multi.verify(&root).unwrap());
+
+        let multi_size = borsh::to_vec(&multi).unwrap().len();
+        let single_size: usize = indices
+            .iter()
+            .map(|&index| {
+                borsh::to_vec(&tree.get_proof(index).unwrap())
+                    .unwrap()
+                    .len()
+            })
+            .sum();
+        assert!(multi_size * 4 < single_size);
+    }
+
+    #[test]
+    fn test_multi_proof_index_out_of_bounds() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        assert_eq!(
+            tree.get_multi_proof(&[0, 1]),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 1, len: 1 })
+        );
+    }
+
+    #[test]
+    fn test_proof_index_out_of_bounds() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        assert_eq!(
+            tree.get_proof(0),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 0, len: 0 })
+        );
+
+        tree.insert(&[1u8; 32]).unwrap();
+        assert!(tree.get_proof(0).is_ok());
+        assert_eq!(
+            tree.get_proof(1),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 1, len: 1 })
+        );
+    }
+
+    #[test]
+    fn test_node_accessors() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        for i in 0..3u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let root = tree.tree().last_root();
+
+        assert_eq!(tree.num_leaves(), 3);
+        assert_eq!(tree.get_leaf(2), Ok([3u8; 32]));
+        assert_eq!(
+            tree.get_leaf(3),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 3, len: 3 })
+        );
+        assert_eq!(tree.get_node(0, 1), Ok([2u8; 32]));
+        assert_eq!(tree.get_node(0, 7), Ok(zeros(0)));
+        assert_eq!(tree.get_node(2, 1), Ok(zeros(2)));
+        assert_eq!(tree.get_node(3, 0), Ok(root));
+        assert_eq!(
+            tree.get_node(3, 1),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 1, len: 1 })
+        );
+        assert_eq!(
+            tree.get_node(4, 0),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 0, len: 0 })
+        );
+        assert!(tree.is_known_root(root));
+    }
+
+    #[test]
+    fn test_matches_incremental_tree_on_random_leaves() {
+        let mut tree = FullPoseidonMerkleTree::new(10).unwrap();
+        let mut incremental = PoseidonMerkleTree::new(10).unwrap();
+
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        for _ in 0..300 {
+            let leaf = rng.leaf();
+            assert_eq!(tree.insert(&leaf), incremental.insert(&leaf));
+            let root = incremental.last_root();
+            assert_eq!(tree.get_node(10, 0), Ok(root));
+            assert!(tree.is_known_root(root));
+        }
+        assert_eq!(tree.tree(), &incremental);
+    }
+
+    #[test]
+    fn test_update_matches_a_rebuild() {
+        let mut tree = FullPoseidonMerkleTree::new(4).unwrap();
+        let mut leaves = Vec::new();
+        for i in 0..14u8 {
+            if i % 3 == 2 {
+                let index = (i as usize * 7) % leaves.len();
+                leaves[index] = [i + 20; 32];
+                let root = tree.update(index as u32, leaves[index]).unwrap();
+                assert_eq!(root, tree.tree().last_root());
+            } else {
+                leaves.push([i + 1; 32]);
+                tree.insert(&[i + 1; 32]).unwrap();
+            }
+
+            let mut rebuilt = FullPoseidonMerkleTree::new(4).unwrap();
+            for leaf in &leaves {
+                rebuilt.insert(leaf).unwrap();
+            }
+            assert_eq!(
+                tree.tree().last_root(),
+                rebuilt.tree().last_root(),
+                "step {i}"
+            );
+            assert_eq!(
+                tree.tree().filled_subtrees(),
+                rebuilt.tree().filled_subtrees()
+            );
+            assert_eq!(tree.store(), rebuilt.store());
+        }
+
+        let root = tree.tree().last_root();
+        for index in 0..leaves.len() as u32 {
+            let proof = tree.get_proof(index).unwrap();
+            assert_eq!(proof.leaf, leaves[index as usize]);
+            assert_eq!(root_from_proof(&proof), root);
+        }
+    }
+
+    #[test]
+    fn test_update_hashes_with_the_tree_params() {
+        let mut params = crate::circom_params();
+        params.ark[0] += Fr::from(1u64);
+        let custom = || {
+            let params = crate::params::copy_params(&params);
+            let tree = PoseidonMerkleTree::new_with_params(3, params).unwrap();
+            FullPoseidonMerkleTree::from_parts(tree, MemoryNodeStore::new())
+        };
+        let updated = [[1; 32], [2; 32], [9; 32], [4; 32], [5; 32]];
+
+        let mut tree = custom();
+        for i in 1..=5u8 {
+            tree.insert(&[i; 32]).unwrap();
+        }
+        let root = tree.update(2, [9; 32]).unwrap();
+
+        let mut rebuilt = custom();
+        let mut default = FullPoseidonMerkleTree::new(3).unwrap();
+        for leaf in &updated {
+            rebuilt.insert(leaf).unwrap();
+            default.insert(leaf).unwrap();
+        }
+        assert_eq!(root, rebuilt.tree().last_root());
+        assert_ne!(root, default.tree().last_root());
+        assert_eq!(
+            tree.tree().filled_subtrees(),
+            rebuilt.tree().filled_subtrees()
+        );
+        assert_eq!(tree.store(), rebuilt.store());
+    }
+
+    #[test]
+    fn test_update_with_the_same_leaf_records_the_same_root() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        for i in 1..=3u8 {
+            tree.insert(&[i; 32]).unwrap();
+        }
+        let before = tree.tree().valid_roots();
+        let root = tree.tree().last_root();
+
+        assert_eq!(tree.update(1, [2; 32]), Ok(root));
+        assert_eq!(tree.tree().valid_roots()[0], root);
+        assert_eq!(tree.tree().valid_roots()[1..], before[..2]);
+        assert!(before.iter().all(|root| tree.is_known_root(*root)));
+
+        assert_eq!(
+            tree.update(3, [4; 32]),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 3, len: 3 })
+        );
+        assert_eq!(
+            tree.update(0, [0xff; 32]),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&[0xff; 32]))
+        );
+        assert_eq!(tree.tree().last_root(), root);
+    }
+
+    #[test]
+    fn test_borsh_round_trip() {
+        let mut tree = FullPoseidonMerkleTree::new(4).unwrap();
+        let bytes = tree.try_to_vec().unwrap();
+        assert_eq!(
+            FullPoseidonMerkleTree::try_from_slice(&bytes).unwrap(),
+            tree
+        );
+
+        for i in 0..5u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let bytes = tree.try_to_vec().unwrap();
+        let decoded = FullPoseidonMerkleTree::try_from_slice(&bytes).unwrap();
+        assert_eq!(decoded, tree);
+        assert_eq!(decoded.get_proof(4), tree.get_proof(4));
+
+        // Dropping a stored leaf leaves the levels out of step with `next_index`
+        let mut truncated = tree.clone();
+        truncated.store.nodes[0].pop();
+        let bytes = truncated.try_to_vec().unwrap();
+        assert!(FullPoseidonMerkleTree::try_from_slice(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_bytes_without_options_still_load() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        for i in 1..=5u8 {
+            tree.insert(&[i; 32]).unwrap();
+        }
+        // Written before the tree's options and `reject_duplicates` were stored
+        let stored = include_bytes!("testdata/full_tree_levels3_5_leaves.bin");
+        assert_eq!(
+            FullPoseidonMerkleTree::try_from_slice(stored).unwrap(),
+            tree
+        );
+        let bytes = tree.try_to_vec().unwrap();
+        assert_eq!(bytes.len(), stored.len() + crate::OPTIONS_SIZE + 1);
+        assert_eq!(bytes[..stored.len()], stored[..]);
+
+        let mut tornado =
+            FullPoseidonMerkleTree::new_with_zero_preset(3, ZeroPreset::Tornado).unwrap();
+        tornado.insert(&[1; 32]).unwrap();
+        let bytes = tornado.try_to_vec().unwrap();
+        let read = FullPoseidonMerkleTree::try_from_slice(&bytes).unwrap();
+        assert_eq!(read.tree().zero_preset(), ZeroPreset::Tornado);
+        assert_eq!(read.get_proof(0), tornado.get_proof(0));
+    }
+}
@@ -0,0 +1,387 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use crate::{validate_leaf, PoseidonMerkleTree, PoseidonMerkleTreeError};
+
+const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+// A hash as `0x` followed by 64 lowercase hex digits
+pub fn encode_hash(hash: &[u8; 32]) -> String {
+    let mut hex = String::with_capacity(66);
+    hex.push_str("0x");
+    for byte in hash {
+        hex.push(DIGITS[(byte >> 4) as usize] as char);
+        hex.push(DIGITS[(byte & 0xf) as usize] as char);
+    }
+    hex
+}
+
+// Reads 64 hex digits in either case, with or without a `0x` prefix
+pub fn decode_hash(hex: &str) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+    let digits = hex.strip_prefix("0x").unwrap_or(hex).as_bytes();
+    if digits.len() != 64 {
+        return Err(PoseidonMerkleTreeError::InvalidHex);
+    }
+
+    let mut hash = [0u8; 32];
+    for (byte, pair) in hash.iter_mut().zip(digits.chunks_exact(2)) {
+        *byte = digit(pair[0])? << 4 | digit(pair[1])?;
+    }
+    Ok(hash)
+}
+
+fn digit(digit: u8) -> Result<u8, PoseidonMerkleTreeError> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        _ => Err(PoseidonMerkleTreeError::InvalidHex),
+    }
+}
+
+// A hash or leaf read from a string. Parses `0x`-prefixed or bare hex as `decode_hash` does,
+// and otherwise a decimal number below the BN254 modulus, as circom tooling prints field
+// elements. A bare string of 64 hex digits is always read as hex, even if it is all decimal
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Hash32(pub [u8; 32]);
+
+impl FromStr for Hash32 {
+    type Err = PoseidonMerkleTreeError;
+
+    fn from_str(value: &str) -> Result<Hash32, PoseidonMerkleTreeError> {
+        let is_hex = value.starts_with("0x")
+            || value.len() == 64 && value.bytes().all(|byte| byte.is_ascii_hexdigit());
+        if is_hex || value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+            return decode_hash(value).map(Hash32);
+        }
+        decode_decimal(value).map(Hash32)
+    }
+}
+
+impl TryFrom<&str> for Hash32 {
+    type Error = PoseidonMerkleTreeError;
+
+    fn try_from(value: &str) -> Result<Hash32, PoseidonMerkleTreeError> {
+        value.parse()
+    }
+}
+
+impl TryFrom<String> for Hash32 {
+    type Error = PoseidonMerkleTreeError;
+
+    fn try_from(value: String) -> Result<Hash32, PoseidonMerkleTreeError> {
+        value.parse()
+    }
+}
+
+impl From<[u8; 32]> for Hash32 {
+    fn from(hash: [u8; 32]) -> Hash32 {
+        Hash32(hash)
+    }
+}
+
+impl From<Hash32> for [u8; 32] {
+    fn from(hash: Hash32) -> [u8; 32] {
+        hash.0
+    }
+}
+
+impl fmt::Display for Hash32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_hex(f, &self.0, 32)
+    }
+}
+
+impl fmt::Debug for Hash32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Hash32").field(&DebugHash(&self.0)).finish()
+    }
+}
+
+// Accumulates the digits into little-endian limbs, failing as soon as the number no longer
+// fits in 256 bits, and then checks it against the modulus
+fn decode_decimal(digits: &str) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+    let mut limbs = [0u64; 4];
+    for digit in digits.bytes() {
+        let mut carry = (digit - b'0') as u128;
+        for limb in &mut limbs {
+            let product = *limb as u128 * 10 + carry;
+            *limb = product as u64;
+            carry = product >> 64;
+        }
+        if carry != 0 {
+            return Err(PoseidonMerkleTreeError::InvalidDecimal);
+        }
+    }
+
+    let mut hash = [0u8; 32];
+    for (chunk, limb) in hash.chunks_exact_mut(8).zip(limbs.iter().rev()) {
+        chunk.copy_from_slice(&limb.to_be_bytes());
+    }
+    validate_leaf(&hash).map_err(|_| PoseidonMerkleTreeError::InvalidDecimal)?;
+    Ok(hash)
+}
+
+impl PoseidonMerkleTree {
+    // `is_known_root` for a root in any format `Hash32` parses
+    pub fn is_known_root_str(&self, root: &str) -> Result<bool, PoseidonMerkleTreeError> {
+        Ok(self.is_known_root(root.parse::<Hash32>()?.0))
+    }
+
+    pub fn last_root_hex(&self) -> String {
+        encode_hash(&self.last_root())
+    }
+
+    pub fn insert_hex(&mut self, leaf: &str) -> Result<u64, PoseidonMerkleTreeError> {
+        self.insert(&decode_hash(leaf)?)
+    }
+}
+
+// How many roots before the current one `Display` lists
+const DISPLAYED_HISTORY: usize = 3;
+
+// Writes the first `len` bytes of `hash` as `0x`-prefixed hex, without allocating
+fn write_hex(f: &mut fmt::Formatter<'_>, hash: &[u8; 32], len: usize) -> fmt::Result {
+    f.write_str("0x")?;
+    hash[..len]
+        .iter()
+        .try_for_each(|byte| write!(f, "{byte:02x}"))
+}
+
+struct DebugHash<'a>(&'a [u8; 32]);
+
+impl fmt::Debug for DebugHash<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_hex(f, self.0, 32)
+    }
+}
+
+fn debug_hashes(hashes: &[[u8; 32]]) -> Vec<DebugHash<'_>> {
+    hashes.iter().map(DebugHash).collect()
+}
+
+// Every field with hashes as hex, except the root lookup index, which is rebuilt from `roots`
+impl fmt::Debug for PoseidonMerkleTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoseidonMerkleTree")
+            .field("levels", &self.levels)
+            .field("filled_subtrees", &debug_hashes(&self.filled_subtrees()))
+            .field("roots", &debug_hashes(&self.roots))
+            .field("current_root_index", &self.current_root_index)
+            .field("next_index", &self.next_index)
+            .field("leaf_log", &self.leaf_log.as_deref().map(debug_hashes))
+            .field("hasher", &self.hasher)
+            .field("zeros", &self.zeros)
+            .field("endianness", &self.endianness)
+            .field("reject_duplicates", &self.reject_duplicates)
+            .field("accept_initial_root", &self.accept_initial_root)
+            .finish_non_exhaustive()
+    }
+}
+
+// One line for logs: the size, the full current root and 8-byte prefixes of the roots before
+// it, newest first
+impl fmt::Display for PoseidonMerkleTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "levels={} next_index={} capacity={} root=",
+            self.levels,
+            self.next_index,
+            self.capacity()
+        )?;
+        write_hex(f, &self.last_root(), 32)?;
+
+        f.write_str(" history=[")?;
+        for (age, root) in self.roots_iter().skip(1).take(DISPLAYED_HISTORY) {
+            if age > 1 {
+                f.write_str(", ")?;
+            }
+            write_hex(f, &root, 8)?;
+        }
+        f.write_str("]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_round_trip() {
+        let mut hash = [0u8; 32];
+        for (i, byte) in hash.iter_mut().enumerate() {
+            *byte = (i as u8).wrapping_mul(37);
+        }
+
+        let hex = encode_hash(&hash);
+        assert_eq!(hex.len(), 66);
+        assert!(hex.starts_with("0x00254a6f"));
+        assert_eq!(decode_hash(&hex), Ok(hash));
+        assert_eq!(decode_hash(&hex[2..]), Ok(hash));
+        assert_eq!(
+            decode_hash(&hex.to_uppercase().replace("0X", "0x")),
+            Ok(hash)
+        );
+        assert_eq!(
+            decode_hash(&format!("0x{}", "aB".repeat(32))),
+            Ok([0xab; 32])
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects_malformed_input() {
+        for invalid in [
+            String::new(),
+            "0x".into(),
+            "0".repeat(63),
+            "0".repeat(65),
+            format!("0x{}", "0".repeat(62)),
+            format!("0x{}g", "0".repeat(63)),
+            format!("0x0x{}", "0".repeat(62)),
+            format!("0X{}", "0".repeat(64)),
+            format!("0x{}", "\u{e9}".repeat(32)),
+        ] {
+            assert_eq!(
+                decode_hash(&invalid),
+                Err(PoseidonMerkleTreeError::InvalidHex),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_tree_hex_helpers() {
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        let mut expected = tree.clone();
+
+        assert_eq!(tree.insert_hex(&encode_hash(&[1u8; 32])), Ok(0));
+        expected.insert(&[1u8; 32]).unwrap();
+        assert_eq!(tree, expected);
+        assert!(tree.is_known_root(decode_hash(&tree.last_root_hex()).unwrap()));
+
+        assert_eq!(
+            tree.insert_hex("0x1234"),
+            Err(PoseidonMerkleTreeError::InvalidHex)
+        );
+        assert_eq!(tree.next_index, 1);
+    }
+
+    #[test]
+    fn test_display_summary() {
+        let mut tree = PoseidonMerkleTree::new_with_history(2, 3).unwrap();
+        assert_eq!(
+            tree.to_string(),
+            "levels=2 next_index=0 capacity=4 \
+             root=0x138bfdb791d8bad98a50c82ea1ef624feb03ed9b7bbdb348551a6b347ffd561c history=[]"
+        );
+
+        tree.insert(&[1u8; 32]).unwrap();
+        tree.insert(&[2u8; 32]).unwrap();
+        assert_eq!(
+            tree.to_string(),
+            "levels=2 next_index=2 capacity=4 \
+             root=0x1fabc2b3086b2df48ad13ff0a828aa14edc103a71491615b1d1fb6b6c6fd7e7b \
+             history=[0x0523d1ffacad94e6, 0x138bfdb791d8bad9]"
+        );
+
+        // The initial root has been overwritten once the three slots wrap
+        tree.insert(&[3u8; 32]).unwrap();
+        let summary = tree.to_string();
+        assert!(summary.ends_with("history=[0x1fabc2b3086b2df4, 0x0523d1ffacad94e6]"));
+        assert!(!summary.contains('\n'));
+    }
+
+    #[test]
+    fn test_debug_prints_hashes_as_hex() {
+        let mut tree = PoseidonMerkleTree::new_with_history(2, 3).unwrap();
+        tree.insert(&[3u8; 32]).unwrap();
+        assert_eq!(
+            format!("{tree:?}"),
+            format!(
+                "PoseidonMerkleTree {{ levels: 2, filled_subtrees: [0x{}, {}], roots: [{}, {}, {}], \
+                 current_root_index: 1, next_index: 1, leaf_log: None, hasher: Owned, \
+                 zeros: Voidify, endianness: Big, reject_duplicates: false, \
+                 accept_initial_root: true, .. }}",
+                "03".repeat(32),
+                encode_hash(&tree.filled_subtrees()[1]),
+                encode_hash(&tree.roots[0]),
+                encode_hash(&tree.roots[1]),
+                encode_hash(&[0; 32]),
+            )
+        );
+
+        let logged = format!("{:?}", PoseidonMerkleTree::with_leaf_log(1).unwrap());
+        assert!(logged.contains("leaf_log: Some([])"));
+    }
+
+    // The BN254 scalar field modulus
+    const MODULUS: &str =
+        "21888242871839275222246405745257275088548364400416034343698204186575808495617";
+
+    #[test]
+    fn test_hash32_parses_every_format() {
+        let expected =
+            decode_hash("0x2fe54c60d3acabf3343a35b6eba15db4821b340f76e741e2249685ed4899af6c")
+                .unwrap();
+        for input in [
+            "0x2fe54c60d3acabf3343a35b6eba15db4821b340f76e741e2249685ed4899af6c",
+            "2FE54C60D3ACABF3343A35B6EBA15DB4821B340F76E741E2249685ED4899AF6C",
+            "21663839004416932945382355908790599225266501822907911457504978515578255421292",
+        ] {
+            assert_eq!(input.parse::<Hash32>(), Ok(Hash32(expected)), "{input}");
+        }
+
+        assert_eq!(Hash32::try_from("0"), Ok(Hash32([0; 32])));
+        let mut one = [0u8; 32];
+        one[31] = 1;
+        assert_eq!(Hash32::try_from(String::from("0001")), Ok(Hash32(one)));
+        assert_eq!(Hash32::try_from("256").unwrap().0[30..], [1, 0]);
+
+        // 64 digits are hex even when they are all decimal
+        assert_eq!("1".repeat(64).parse(), Ok(Hash32([0x11; 32])));
+
+        let hash = Hash32(expected);
+        assert_eq!(hash.to_string(), encode_hash(&expected));
+        assert_eq!(hash.to_string().parse(), Ok(hash));
+        assert_eq!(<[u8; 32]>::from(hash), expected);
+    }
+
+    #[test]
+    fn test_hash32_rejects_malformed_strings() {
+        let below = "21888242871839275222246405745257275088548364400416034343698204186575808495616";
+        assert!(below.parse::<Hash32>().is_ok());
+
+        for invalid in [MODULUS.into(), "9".repeat(78), "1".repeat(200)] {
+            assert_eq!(
+                invalid.parse::<Hash32>(),
+                Err(PoseidonMerkleTreeError::InvalidDecimal),
+                "{invalid}"
+            );
+        }
+        for invalid in ["", "-1", "+1", " 1", "1.5", "0x12", "12ab", "1e10"] {
+            assert_eq!(
+                invalid.parse::<Hash32>(),
+                Err(PoseidonMerkleTreeError::InvalidHex),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_known_root_str() {
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        let root = tree.last_root_hex();
+
+        assert_eq!(tree.is_known_root_str(&root), Ok(true));
+        assert_eq!(tree.is_known_root_str(&root[2..]), Ok(true));
+        assert_eq!(tree.is_known_root_str("12345"), Ok(false));
+        assert_eq!(
+            tree.is_known_root_str("root"),
+            Err(PoseidonMerkleTreeError::InvalidHex)
+        );
+    }
+}
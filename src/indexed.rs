@@ -0,0 +1,381 @@
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use ark_bn254::Fr;
+use light_poseidon::Poseidon;
+
+use crate::proof::position_at;
+use crate::{
+    hash_pair, lock_poseidon, MerkleProof, PoseidonMerkleTreeError, MAX_LEVELS, ZERO_HASHES,
+};
+
+// One entry of the sorted linked list. A `next_value` of zero marks the largest value, whose
+// range extends to the end of the field
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct IndexedLeaf {
+    pub value: [u8; 32],
+    pub next_value: [u8; 32],
+    pub next_index: u32,
+}
+
+// Everything a circuit needs to replay an insert: the low leaf is proven against the root
+// before the insert, and the new leaf against the root after it
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexedInsertion {
+    pub low_leaf_index: u32,
+    pub low_leaf: IndexedLeaf,
+    pub low_leaf_proof: MerkleProof,
+    pub updated_low_leaf: IndexedLeaf,
+    pub new_leaf_index: u32,
+    pub new_leaf: IndexedLeaf,
+    pub new_leaf_proof: MerkleProof,
+}
+
+// Shows that `value` falls strictly between `low_leaf.value` and `low_leaf.next_value`
+#[derive(Clone, Debug, PartialEq)]
+pub struct NonMembershipProof {
+    pub low_leaf: IndexedLeaf,
+    pub proof: MerkleProof,
+}
+
+// Aztec-style indexed tree: leaves are appended in insertion order but link to each other in
+// value order, so the absence of a value is proven by the leaf whose range covers it. Leaf 0
+// is the all-zero leaf, and slots not yet used hold `zeros(0)`
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexedMerkleTree {
+    pub levels: u32,
+    leaves: Vec<IndexedLeaf>,
+    // Leaf index of every stored value, for finding low leaves
+    positions: BTreeMap<[u8; 32], u32>,
+    // nodes[0] holds the leaf hashes and nodes[levels] the root; absent nodes are empty subtrees
+    nodes: Vec<Vec<[u8; 32]>>,
+}
+
+impl IndexedLeaf {
+    // Width-3 Poseidon takes two inputs, so the three fields are hashed as
+    // H(H(value, next_value), next_index)
+    pub fn hash(&self) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        let mut poseidon = lock_poseidon();
+
+        self.hash_with(&mut poseidon)
+    }
+
+    fn hash_with(&self, poseidon: &mut Poseidon<Fr>) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        let mut next_index = [0u8; 32];
+        next_index[28..].copy_from_slice(&self.next_index.to_be_bytes());
+
+        let values = hash_pair(poseidon, &self.value, &self.next_value)?;
+        hash_pair(poseidon, &values, &next_index)
+    }
+
+    fn covers(&self, value: &[u8; 32]) -> bool {
+        self.value < *value && (self.next_value == [0u8; 32] || *value < self.next_value)
+    }
+}
+
+impl NonMembershipProof {
+    pub fn verify(
+        &self,
+        value: &[u8; 32],
+        root: &[u8; 32],
+    ) -> Result<bool, PoseidonMerkleTreeError> {
+        if !self.low_leaf.covers(value) || self.low_leaf.hash()? != self.proof.leaf {
+            return Ok(false);
+        }
+        self.proof.verify(root)
+    }
+}
+
+impl IndexedMerkleTree {
+    pub fn new(levels: u32) -> Result<IndexedMerkleTree, PoseidonMerkleTreeError> {
+        if levels == 0 || levels > MAX_LEVELS as u32 {
+            return Err(PoseidonMerkleTreeError::invalid_levels(levels));
+        }
+
+        let mut tree = IndexedMerkleTree {
+            levels,
+            leaves: Vec::new(),
+            positions: BTreeMap::new(),
+            nodes: vec![Vec::new(); levels as usize + 1],
+        };
+
+        let mut poseidon = lock_poseidon();
+
+        let leaf = IndexedLeaf::default();
+        let hash = leaf.hash_with(&mut poseidon)?;
+        tree.write_leaf(&mut poseidon, 0, leaf, hash)?;
+
+        Ok(tree)
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.node(self.levels, 0)
+    }
+
+    pub fn num_leaves(&self) -> u32 {
+        self.leaves.len() as u32
+    }
+
+    pub fn get_leaf(&self, index: u32) -> Result<IndexedLeaf, PoseidonMerkleTreeError> {
+        self.leaves
+            .get(index as usize)
+            .copied()
+            .ok_or(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: index as u64,
+                len: self.leaves.len() as u64,
+            })
+    }
+
+    pub fn contains(&self, value: &[u8; 32]) -> bool {
+        self.positions.contains_key(value)
+    }
+
+    pub fn insert(
+        &mut self,
+        value: &[u8; 32],
+    ) -> Result<IndexedInsertion, PoseidonMerkleTreeError> {
+        if self.leaves.len() as u64 >= 1u64 << self.levels {
+            return Err(PoseidonMerkleTreeError::MerkleTreeFull {
+                capacity: 1u64 << self.levels,
+            });
+        }
+        let (low_leaf_index, low_leaf) = self.low_leaf(value)?;
+        let low_leaf_proof = self.get_proof(low_leaf_index)?;
+
+        let new_leaf_index = self.leaves.len() as u32;
+        let new_leaf = IndexedLeaf {
+            value: *value,
+            next_value: low_leaf.next_value,
+            next_index: low_leaf.next_index,
+        };
+        let updated_low_leaf = IndexedLeaf {
+            next_value: *value,
+            next_index: new_leaf_index,
+            ..low_leaf
+        };
+
+        {
+            let mut poseidon = lock_poseidon();
+
+            // Only the leaf hashes can reject a value outside the field, so compute both before
+            // touching the tree
+            let updated_low_hash = updated_low_leaf.hash_with(&mut poseidon)?;
+            let new_hash = new_leaf.hash_with(&mut poseidon)?;
+
+            self.write_leaf(
+                &mut poseidon,
+                low_leaf_index,
+                updated_low_leaf,
+                updated_low_hash,
+            )?;
+            self.write_leaf(&mut poseidon, new_leaf_index, new_leaf, new_hash)?;
+        }
+        self.positions.insert(*value, new_leaf_index);
+
+        Ok(IndexedInsertion {
+            low_leaf_index,
+            low_leaf,
+            low_leaf_proof,
+            updated_low_leaf,
+            new_leaf_index,
+            new_leaf,
+            new_leaf_proof: self.get_proof(new_leaf_index)?,
+        })
+    }
+
+    pub fn prove_non_membership(
+        &self,
+        value: &[u8; 32],
+    ) -> Result<NonMembershipProof, PoseidonMerkleTreeError> {
+        let (low_leaf_index, low_leaf) = self.low_leaf(value)?;
+        Ok(NonMembershipProof {
+            low_leaf,
+            proof: self.get_proof(low_leaf_index)?,
+        })
+    }
+
+    // The proven leaf is the hash of the stored `IndexedLeaf`
+    pub fn get_proof(&self, index: u32) -> Result<MerkleProof, PoseidonMerkleTreeError> {
+        if index as usize >= self.leaves.len() {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: index as u64,
+                len: self.leaves.len() as u64,
+            });
+        }
+
+        let siblings = (0..self.levels)
+            .map(|level| self.node(level, (index >> level) ^ 1))
+            .collect();
+
+        Ok(MerkleProof {
+            leaf: self.nodes[0][index as usize],
+            index,
+            siblings,
+        })
+    }
+
+    // The stored leaf with the largest value below `value`; fails if `value` is already stored
+    fn low_leaf(&self, value: &[u8; 32]) -> Result<(u32, IndexedLeaf), PoseidonMerkleTreeError> {
+        if self.contains(value) || *value == [0u8; 32] {
+            return Err(PoseidonMerkleTreeError::KeyAlreadyPresent);
+        }
+
+        let index = self
+            .positions
+            .range(..*value)
+            .next_back()
+            .map_or(0, |(_, index)| *index);
+        Ok((index, self.leaves[index as usize]))
+    }
+
+    fn write_leaf(
+        &mut self,
+        poseidon: &mut Poseidon<Fr>,
+        index: u32,
+        leaf: IndexedLeaf,
+        hash: [u8; 32],
+    ) -> Result<(), PoseidonMerkleTreeError> {
+        let mut path = Vec::with_capacity(self.levels as usize + 1);
+        let mut current_level_hash = hash;
+        path.push(current_level_hash);
+        for level in 0..self.levels {
+            let position = index >> level;
+            let sibling = self.node(level, position ^ 1);
+            current_level_hash = if position.is_multiple_of(2) {
+                hash_pair(poseidon, &current_level_hash, &sibling)?
+            } else {
+                hash_pair(poseidon, &sibling, &current_level_hash)?
+            };
+            path.push(current_level_hash);
+        }
+
+        for (level, node) in path.into_iter().enumerate() {
+            let position = position_at(index, level) as usize;
+            let level_nodes = &mut self.nodes[level];
+            if position < level_nodes.len() {
+                level_nodes[position] = node;
+            } else {
+                level_nodes.push(node);
+            }
+        }
+        match self.leaves.get_mut(index as usize) {
+            Some(stored) => *stored = leaf,
+            None => self.leaves.push(leaf),
+        }
+
+        Ok(())
+    }
+
+    fn node(&self, level: u32, position: u32) -> [u8; 32] {
+        self.nodes[level as usize]
+            .get(position as usize)
+            .copied()
+            .unwrap_or(ZERO_HASHES[level as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zeros;
+
+    fn value(n: u64) -> [u8; 32] {
+        let mut value = [0u8; 32];
+        value[24..].copy_from_slice(&n.to_be_bytes());
+        value
+    }
+
+    #[test]
+    fn test_indexed_insert_links_in_value_order() {
+        let mut tree = IndexedMerkleTree::new(6).unwrap();
+        let empty_root = tree.root();
+        assert_ne!(empty_root, zeros(6));
+
+        // Values visited in a scrambled order: 37 is coprime to 50
+        let values = (0..40u64).map(|i| (i * 37 % 50) + 1).collect::<Vec<_>>();
+        for &n in &values {
+            let old_root = tree.root();
+            let insertion = tree.insert(&value(n)).unwrap();
+
+            assert!(insertion.low_leaf.covers(&value(n)));
+            assert_eq!(
+                insertion.low_leaf_proof.leaf,
+                insertion.low_leaf.hash().unwrap()
+            );
+            assert!(insertion.low_leaf_proof.verify(&old_root).unwrap());
+            assert_eq!(
+                insertion.new_leaf_proof.leaf,
+                insertion.new_leaf.hash().unwrap()
+            );
+            assert!(insertion.new_leaf_proof.verify(&tree.root()).unwrap());
+            assert_eq!(insertion.updated_low_leaf.next_value, value(n));
+        }
+
+        // Walking the links from leaf 0 visits every value in ascending order
+        let mut sorted = values.clone();
+        sorted.sort_unstable();
+        let mut walked = Vec::new();
+        let mut leaf = tree.get_leaf(0).unwrap();
+        while leaf.next_value != [0u8; 32] {
+            leaf = tree.get_leaf(leaf.next_index).unwrap();
+            walked.push(u64::from_be_bytes(leaf.value[24..].try_into().unwrap()));
+        }
+        assert_eq!(walked, sorted);
+        assert_eq!(tree.num_leaves(), 41);
+    }
+
+    #[test]
+    fn test_indexed_non_membership() {
+        let mut tree = IndexedMerkleTree::new(4).unwrap();
+        for n in [30, 10, 20] {
+            tree.insert(&value(n)).unwrap();
+        }
+        let root = tree.root();
+
+        for n in [5, 15, 25, 1_000] {
+            let proof = tree.prove_non_membership(&value(n)).unwrap();
+            assert!(proof.verify(&value(n), &root).unwrap());
+        }
+        let proof = tree.prove_non_membership(&value(15)).unwrap();
+        assert_eq!(proof.low_leaf.value, value(10));
+        assert!(!proof.verify(&value(20), &root).unwrap());
+        assert!(!proof.verify(&value(25), &root).unwrap());
+
+        assert_eq!(
+            tree.prove_non_membership(&value(20)),
+            Err(PoseidonMerkleTreeError::KeyAlreadyPresent)
+        );
+        assert_eq!(
+            tree.insert(&value(10)),
+            Err(PoseidonMerkleTreeError::KeyAlreadyPresent)
+        );
+    }
+
+    #[test]
+    fn test_indexed_full_and_invalid_values() {
+        let mut tree = IndexedMerkleTree::new(2).unwrap();
+        for n in 1..4 {
+            tree.insert(&value(n)).unwrap();
+        }
+        assert_eq!(
+            tree.insert(&value(9)),
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 4 })
+        );
+
+        // A value outside the field is rejected before either leaf is written
+        let mut tree = IndexedMerkleTree::new(3).unwrap();
+        let before = tree.clone();
+        assert_eq!(
+            tree.insert(&[0xffu8; 32]),
+            Err(PoseidonMerkleTreeError::HashError(
+                "Input is larger than the modulus of the prime field.".into()
+            ))
+        );
+        assert_eq!(tree, before);
+        assert_eq!(
+            IndexedMerkleTree::new(0),
+            Err(PoseidonMerkleTreeError::invalid_levels(0))
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::PoseidonMerkleTree;
+
+// Everything a dashboard shows about a tree, read from its fields without hashing anything
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct TreeInfo {
+    pub levels: u32,
+    pub next_index: u64,
+    pub capacity: u64,
+    pub fill_ratio: f64,
+    pub current_root: [u8; 32],
+    // Roots the history keeps, and how many of them it holds so far
+    pub root_history_size: u32,
+    pub roots_recorded: u32,
+}
+
+impl PoseidonMerkleTree {
+    pub fn info(&self) -> TreeInfo {
+        TreeInfo {
+            levels: self.levels,
+            next_index: self.next_index,
+            capacity: self.capacity(),
+            fill_ratio: self.fill_ratio(),
+            current_root: self.last_root(),
+            root_history_size: self.history_size() as u32,
+            roots_recorded: self.root_history_len() as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_info_after_three_inserts() {
+        let mut tree = PoseidonMerkleTree::new_with_history(3, 5).unwrap();
+        for i in 1..=3u8 {
+            tree.insert(&[i; 32]).unwrap();
+        }
+
+        let info = tree.info();
+        assert_eq!(
+            info,
+            TreeInfo {
+                levels: 3,
+                next_index: 3,
+                capacity: 8,
+                fill_ratio: 0.375,
+                current_root: tree.roots[3],
+                root_history_size: 5,
+                roots_recorded: 4,
+            }
+        );
+        assert_eq!(
+            TreeInfo::try_from_slice(&info.try_to_vec().unwrap()).unwrap(),
+            info
+        );
+    }
+}
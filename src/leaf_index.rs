@@ -0,0 +1,299 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::{FullPoseidonMerkleTree, NodeStore, PoseidonMerkleTree, PoseidonMerkleTreeError};
+
+// Every index each leaf value sits at, in ascending order. Rebuilt from the leaves a tree
+// keeps rather than serialized, like the root index
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct LeafIndex(BTreeMap<[u8; 32], Vec<u64>>);
+
+impl LeafIndex {
+    pub(crate) fn from_leaves(leaves: impl IntoIterator<Item = [u8; 32]>) -> LeafIndex {
+        let mut index = LeafIndex::default();
+        for (position, leaf) in leaves.into_iter().enumerate() {
+            index.insert(leaf, position as u64);
+        }
+        index
+    }
+
+    pub(crate) fn insert(&mut self, leaf: [u8; 32], position: u64) {
+        let positions = self.0.entry(leaf).or_default();
+        if let Err(at) = positions.binary_search(&position) {
+            positions.insert(at, position);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, leaf: &[u8; 32], position: u64) {
+        if let Some(positions) = self.0.get_mut(leaf) {
+            positions.retain(|&other| other != position);
+            if positions.is_empty() {
+                self.0.remove(leaf);
+            }
+        }
+    }
+
+    pub(crate) fn positions(&self, leaf: &[u8; 32]) -> &[u64] {
+        self.0.get(leaf).map_or(&[], Vec::as_slice)
+    }
+}
+
+impl PoseidonMerkleTree {
+    // The lowest index `leaf` was inserted at. Only a tree built with `with_leaf_log` knows its
+    // leaves, so any other one reports `None`
+    pub fn leaf_index(&self, leaf: &[u8; 32]) -> Option<u64> {
+        self.leaf_indices(leaf).first().copied()
+    }
+
+    // Every index `leaf` was inserted at, lowest first
+    pub fn leaf_indices(&self, leaf: &[u8; 32]) -> &[u64] {
+        self.leaf_positions.positions(leaf)
+    }
+
+    // Whether `leaf` was inserted before. Only the leaf log records that, so a tree without one
+    // fails with `LeafLogUnsupported` instead of answering `false`
+    pub fn contains_leaf(&self, leaf: &[u8; 32]) -> Result<bool, PoseidonMerkleTreeError> {
+        if self.leaf_log.is_none() {
+            return Err(PoseidonMerkleTreeError::LeafLogUnsupported);
+        }
+        Ok(self.contains_logged(leaf, leaf))
+    }
+
+    // Makes every insert of a leaf the tree already holds fail with `DuplicateLeaf`, leaving the
+    // tree as it was. Needs the leaf log to tell. The setting is stored with the tree
+    pub fn set_reject_duplicates(&mut self, reject: bool) -> Result<(), PoseidonMerkleTreeError> {
+        if reject && self.leaf_log.is_none() {
+            return Err(PoseidonMerkleTreeError::LeafLogUnsupported);
+        }
+        self.reject_duplicates = reject;
+        Ok(())
+    }
+
+    pub fn rejects_duplicates(&self) -> bool {
+        self.reject_duplicates
+    }
+
+    pub(crate) fn contains_logged(&self, left: &[u8; 32], right: &[u8; 32]) -> bool {
+        !self.leaf_indices(left).is_empty() || !self.leaf_indices(right).is_empty()
+    }
+}
+
+impl<S: NodeStore> FullPoseidonMerkleTree<S> {
+    // The lowest index that holds `leaf`, to build its proof from a commitment alone
+    pub fn leaf_index(&self, leaf: &[u8; 32]) -> Option<u64> {
+        self.leaf_indices(leaf).first().copied()
+    }
+
+    // Every index that holds `leaf`, lowest first
+    pub fn leaf_indices(&self, leaf: &[u8; 32]) -> &[u64] {
+        self.leaves.positions(leaf)
+    }
+
+    pub fn contains_leaf(&self, leaf: &[u8; 32]) -> bool {
+        !self.leaf_indices(leaf).is_empty()
+    }
+
+    // Makes `insert` fail with `DuplicateLeaf` for a leaf the tree already holds, and `update`
+    // for one held at another index. Stored after the nodes
+    pub fn set_reject_duplicates(&mut self, reject: bool) {
+        self.reject_duplicates = reject;
+    }
+
+    pub fn rejects_duplicates(&self) -> bool {
+        self.reject_duplicates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use borsh::{BorshDeserialize, BorshSerialize};
+
+    use super::*;
+    use crate::MemoryNodeStore;
+
+    fn leaves() -> [[u8; 32]; 5] {
+        [[1; 32], [2; 32], [1; 32], [3; 32], [1; 32]]
+    }
+
+    #[test]
+    fn test_leaf_log_finds_every_index() {
+        let mut tree = PoseidonMerkleTree::with_leaf_log(3).unwrap();
+        tree.insert_many(&leaves()).unwrap();
+
+        assert_eq!(tree.leaf_index(&[1; 32]), Some(0));
+        assert_eq!(tree.leaf_indices(&[1; 32]), [0, 2, 4]);
+        assert_eq!(tree.leaf_index(&[3; 32]), Some(3));
+        assert_eq!(tree.leaf_index(&[4; 32]), None);
+        assert!(tree.leaf_indices(&[4; 32]).is_empty());
+
+        let (_, checkpoint) = tree.insert_checkpointed(&[4; 32]).unwrap();
+        assert_eq!(tree.leaf_index(&[4; 32]), Some(5));
+        tree.undo(&checkpoint).unwrap();
+        assert_eq!(tree.leaf_index(&[4; 32]), None);
+
+        let read = PoseidonMerkleTree::try_from_slice(&tree.try_to_vec().unwrap()).unwrap();
+        assert_eq!(read.leaf_indices(&[1; 32]), [0, 2, 4]);
+        assert_eq!(read, tree);
+
+        tree.reset().unwrap();
+        assert_eq!(tree.leaf_index(&[1; 32]), None);
+
+        let mut unlogged = PoseidonMerkleTree::new(3).unwrap();
+        unlogged.insert(&[1; 32]).unwrap();
+        assert_eq!(unlogged.leaf_index(&[1; 32]), None);
+    }
+
+    #[test]
+    fn test_full_tree_finds_every_index() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        for leaf in &leaves() {
+            tree.insert(leaf).unwrap();
+        }
+        assert_eq!(tree.leaf_indices(&[1; 32]), [0, 2, 4]);
+        let index = tree.leaf_index(&[3; 32]).unwrap();
+        assert_eq!(tree.get_proof(index as u32).unwrap().leaf, [3; 32]);
+        assert_eq!(tree.leaf_index(&[4; 32]), None);
+
+        tree.update(2, [4; 32]).unwrap();
+        assert_eq!(tree.leaf_indices(&[1; 32]), [0, 4]);
+        assert_eq!(tree.leaf_index(&[4; 32]), Some(2));
+
+        let read = FullPoseidonMerkleTree::try_from_slice(&tree.try_to_vec().unwrap()).unwrap();
+        assert_eq!(read.leaf_indices(&[1; 32]), [0, 4]);
+        assert_eq!(read.leaves, tree.leaves);
+
+        let parts = FullPoseidonMerkleTree::from_parts(tree.tree().clone(), tree.store().clone());
+        assert_eq!(parts.leaf_index(&[4; 32]), Some(2));
+        assert_eq!(parts.leaf_index(&[2; 32]), Some(1));
+
+        let empty = FullPoseidonMerkleTree::with_store(3, MemoryNodeStore::new()).unwrap();
+        assert_eq!(empty.leaf_index(&[0; 32]), None);
+    }
+
+    #[test]
+    fn test_duplicates_are_allowed_unless_rejected() {
+        let mut allowing = PoseidonMerkleTree::with_leaf_log(3).unwrap();
+        assert_eq!(allowing.contains_leaf(&[1; 32]), Ok(false));
+        allowing.insert(&[1; 32]).unwrap();
+        assert_eq!(allowing.insert(&[1; 32]), Ok(1));
+        assert_eq!(allowing.contains_leaf(&[1; 32]), Ok(true));
+        assert!(!allowing.rejects_duplicates());
+
+        let mut rejecting = PoseidonMerkleTree::with_leaf_log(3).unwrap();
+        rejecting.set_reject_duplicates(true).unwrap();
+        rejecting.insert_many(&[[1; 32], [4; 32]]).unwrap();
+        let before = rejecting.clone();
+        assert_eq!(
+            rejecting.insert(&[1; 32]),
+            Err(PoseidonMerkleTreeError::DuplicateLeaf)
+        );
+        assert_eq!(
+            rejecting.insert_fr(crate::fr_from_be_bytes(&[1; 32]).unwrap()),
+            Err(PoseidonMerkleTreeError::DuplicateLeaf)
+        );
+        assert_eq!(
+            rejecting.insert_batch_atomic(&[[2; 32], [3; 32], [2; 32]]),
+            Err(PoseidonMerkleTreeError::DuplicateLeaf)
+        );
+        assert_eq!(
+            rejecting.insert_pair(&[2; 32], &[2; 32]),
+            Err(PoseidonMerkleTreeError::DuplicateLeaf)
+        );
+        assert_eq!(
+            rejecting.insert_pair(&[2; 32], &[1; 32]),
+            Err(PoseidonMerkleTreeError::DuplicateLeaf)
+        );
+        assert_eq!(rejecting, before);
+        assert_eq!(rejecting.contains_leaf(&[1; 32]), Ok(true));
+        assert_eq!(rejecting.contains_leaf(&[2; 32]), Ok(false));
+
+        rejecting.reset().unwrap();
+        assert!(rejecting.rejects_duplicates());
+        rejecting.set_reject_duplicates(false).unwrap();
+        rejecting.insert_pair(&[1; 32], &[1; 32]).unwrap();
+        assert_eq!(rejecting.leaf_indices(&[1; 32]), [0, 1]);
+    }
+
+    #[test]
+    fn test_contains_leaf_needs_the_leaf_log() {
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        tree.insert(&[1; 32]).unwrap();
+        assert_eq!(
+            tree.contains_leaf(&[1; 32]),
+            Err(PoseidonMerkleTreeError::LeafLogUnsupported)
+        );
+        assert_eq!(
+            tree.set_reject_duplicates(true),
+            Err(PoseidonMerkleTreeError::LeafLogUnsupported)
+        );
+        assert_eq!(tree.set_reject_duplicates(false), Ok(()));
+    }
+
+    #[test]
+    fn test_full_tree_rejects_duplicates() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        tree.insert(&[1; 32]).unwrap();
+        tree.insert(&[2; 32]).unwrap();
+        assert!(tree.contains_leaf(&[2; 32]));
+        assert!(!tree.contains_leaf(&[3; 32]));
+
+        tree.set_reject_duplicates(true);
+        assert_eq!(
+            tree.insert(&[2; 32]),
+            Err(PoseidonMerkleTreeError::DuplicateLeaf)
+        );
+        assert_eq!(
+            tree.update(0, [2; 32]),
+            Err(PoseidonMerkleTreeError::DuplicateLeaf)
+        );
+        assert!(tree.update(1, [2; 32]).is_ok());
+        assert_eq!(tree.num_leaves(), 2);
+
+        tree.set_reject_duplicates(false);
+        assert_eq!(tree.insert(&[2; 32]), Ok(2));
+        assert_eq!(tree.leaf_indices(&[2; 32]), [1, 2]);
+    }
+
+    #[test]
+    fn test_duplicate_policy_survives_round_trips() {
+        let mut tree = PoseidonMerkleTree::with_leaf_log(3).unwrap();
+        tree.set_reject_duplicates(true).unwrap();
+        tree.insert(&[1; 32]).unwrap();
+        let bytes = tree.try_to_vec().unwrap();
+        let versioned = tree.serialize_versioned().unwrap();
+        for mut read in [
+            PoseidonMerkleTree::try_from_slice(&bytes).unwrap(),
+            PoseidonMerkleTree::deserialize_versioned(&versioned).unwrap(),
+        ] {
+            assert!(read.rejects_duplicates());
+            assert_eq!(
+                read.insert(&[1; 32]),
+                Err(PoseidonMerkleTreeError::DuplicateLeaf)
+            );
+        }
+
+        // The policy is the last byte of the options, and needs the leaf log
+        let mut unlogged = PoseidonMerkleTree::new(3).unwrap().try_to_vec().unwrap();
+        *unlogged.last_mut().unwrap() = 1;
+        assert!(PoseidonMerkleTree::try_from_slice(&unlogged).is_err());
+
+        let mut full = FullPoseidonMerkleTree::new(3).unwrap();
+        full.set_reject_duplicates(true);
+        full.insert(&[1; 32]).unwrap();
+        let bytes = full.try_to_vec().unwrap();
+        let mut read = FullPoseidonMerkleTree::try_from_slice(&bytes).unwrap();
+        assert!(read.rejects_duplicates());
+        assert_eq!(read, full);
+        assert_eq!(
+            read.insert(&[1; 32]),
+            Err(PoseidonMerkleTreeError::DuplicateLeaf)
+        );
+
+        // Bytes from before the policy was stored allow duplicates
+        let legacy = FullPoseidonMerkleTree::try_from_slice(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(!legacy.rejects_duplicates());
+        let mut invalid = bytes;
+        *invalid.last_mut().unwrap() = 2;
+        assert!(FullPoseidonMerkleTree::try_from_slice(&invalid).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use alloc::vec::Vec;
+
+use crate::{PoseidonMerkleTree, PoseidonMerkleTreeError};
+
+impl PoseidonMerkleTree {
+    // Like `new`, but every inserted leaf is also recorded so the tree can be audited and
+    // rebuilt later. The log grows by 32 bytes per insert, so it is opt-in
+    pub fn with_leaf_log(levels: u32) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        let mut tree = PoseidonMerkleTree::new(levels)?;
+        tree.leaf_log = Some(Vec::new());
+        Ok(tree)
+    }
+
+    // `None` unless the tree was built with `with_leaf_log`
+    pub fn leaves(&self) -> Option<&[[u8; 32]]> {
+        self.leaf_log.as_deref()
+    }
+
+    // Replays the log into a fresh tree and replaces `filled_subtrees`, `roots` and `next_index`
+    // with the result
+    pub fn rebuild_from_log(&mut self) -> Result<(), PoseidonMerkleTreeError> {
+        let leaves = self
+            .leaf_log
+            .as_ref()
+            .ok_or(PoseidonMerkleTreeError::LeafLogUnsupported)?;
+
+        let mut rebuilt = self.to_builder().build()?;
+        rebuilt.leaf_log = Some(Vec::new());
+        rebuilt.hasher = self.hasher.clone();
+        for leaf in leaves {
+            rebuilt.insert(leaf)?;
+        }
+        rebuilt.reject_duplicates = self.reject_duplicates;
+
+        *self = rebuilt;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_bn254::Fr;
+    use borsh::{BorshDeserialize, BorshSerialize};
+
+    use super::*;
+    use crate::XorShift;
+
+    #[test]
+    fn test_rebuild_from_log_matches_original() {
+        let mut tree = PoseidonMerkleTree::with_leaf_log(8).unwrap();
+
+        let mut rng = XorShift(0x0123_4567_89ab_cdef);
+        let mut inserted = Vec::new();
+        for _ in 0..100 {
+            let leaf = rng.leaf();
+            tree.insert(&leaf).unwrap();
+            inserted.push(leaf);
+        }
+        assert_eq!(tree.leaves(), Some(&inserted[..]));
+
+        let mut rebuilt = tree.clone();
+        rebuilt
+            .filled_subtrees
+            .iter_mut()
+            .for_each(|node| *node = Fr::from(0u64));
+        rebuilt.next_index = 0;
+        rebuilt.rebuild_from_log().unwrap();
+
+        assert_eq!(rebuilt.levels, tree.levels);
+        assert_eq!(rebuilt.filled_subtrees, tree.filled_subtrees);
+        assert_eq!(rebuilt.roots, tree.roots);
+        assert_eq!(rebuilt.current_root_index, tree.current_root_index);
+        assert_eq!(rebuilt.next_index, tree.next_index);
+        assert_eq!(rebuilt.leaves(), tree.leaves());
+    }
+
+    #[test]
+    fn test_leaf_log_is_opt_in() {
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        assert_eq!(tree.leaves(), None);
+        assert_eq!(
+            tree.rebuild_from_log(),
+            Err(PoseidonMerkleTreeError::LeafLogUnsupported)
+        );
+
+        // Without a log the serialized tree only grows by the absent marker
+        let bytes = tree.try_to_vec().unwrap();
+        assert_eq!(PoseidonMerkleTree::try_from_slice(&bytes).unwrap(), tree);
+    }
+
+    #[test]
+    fn test_deserialize_rejects_mismatched_log() {
+        let mut tree = PoseidonMerkleTree::with_leaf_log(3).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        tree.insert(&[2u8; 32]).unwrap();
+
+        let bytes = tree.try_to_vec().unwrap();
+        assert_eq!(PoseidonMerkleTree::try_from_slice(&bytes).unwrap(), tree);
+
+        tree.leaf_log.as_mut().unwrap().pop();
+        let bytes = tree.try_to_vec().unwrap();
+        assert!(PoseidonMerkleTree::try_from_slice(&bytes).is_err());
+    }
+}
@@ -1,331 +1,3242 @@
-use std::sync::Mutex;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "tls-hasher")]
+use core::cell::Cell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+#[cfg(all(feature = "std", not(feature = "tls-hasher")))]
+use std::sync::{Mutex, MutexGuard};
 
 use ark_bn254::Fr;
+use ark_ff::{BigInteger256, PrimeField};
+use borsh::maybestd::io::{self, Error, ErrorKind, Read, Write};
 use borsh::{BorshDeserialize, BorshSerialize};
-use light_poseidon::{Poseidon, PoseidonBytesHasher, PoseidonParameters};
+use light_poseidon::{Poseidon, PoseidonBytesHasher, PoseidonHasher, PoseidonParameters};
+use once_cell::race::OnceBox;
+#[cfg(all(feature = "std", not(feature = "tls-hasher")))]
 use once_cell::sync::Lazy;
-use thiserror::Error;
 
 use circom_t3::{ARK, MDS};
+use leaf_index::LeafIndex;
+use zero_preset::ZeroChain;
 
+#[cfg(feature = "anchor")]
+pub use anchor::PoseidonMerkleTreeAccount;
+#[cfg(feature = "tokio")]
+pub use async_insert::{insert_async, insert_many_async};
+pub use builder::{Endianness, PoseidonMerkleTreeBuilder};
+pub use canopy::CanopyPoseidonMerkleTree;
+pub use checkpoint::Checkpoint;
+pub use compressed::CompressedProof;
+pub use concurrent::{ChangeLogEntry, ConcurrentPoseidonMerkleTree};
+pub use deferred::DeferredPoseidonMerkleTree;
+pub use fixed::PoseidonMerkleTreeConst;
+#[cfg(any(test, feature = "fixtures"))]
+pub use fixtures::{assert_matches_vectors, HashVector, TestVector};
+pub use forest::MerkleForest;
+pub use frontier::{verify_frontier, Frontier, FrontierProof};
+pub use full::FullPoseidonMerkleTree;
+pub use hex::{decode_hash, encode_hash, Hash32};
+pub use indexed::{IndexedInsertion, IndexedLeaf, IndexedMerkleTree, NonMembershipProof};
+pub use info::TreeInfo;
+pub use map::{PoseidonSparseMap, SparseMapProof};
+pub use mmr::{MmrProof, PoseidonMmr};
+pub use note::DepositNote;
+pub use nullifier::NullifierSet;
+#[cfg(feature = "zero-copy")]
+pub use pod::PodTree;
+#[cfg(feature = "std")]
+pub use pool::{HasherPool, PooledHasher};
+pub use proof::{
+    compute_root_from_proof, verify_merkle_proof, verify_partial_proof, verify_proofs, MerkleProof,
+    MultiProof,
+};
+pub use snapshot::TreeSnapshot;
+pub use sparse::SparsePoseidonMerkleTree;
+pub use store::{CachedNodeStore, MemoryNodeStore, NodeStore};
+pub use subtree::compute_subtree_root;
+pub use tracker::ProofTracker;
+#[cfg(feature = "u256")]
+pub use u256::{leaf_from_u256, root_to_u256, U256Proof};
+pub use versioned::{CURRENT_VERSION, VERSION_MAGIC};
+#[cfg(feature = "wasm")]
+pub use wasm::WasmMerkleTree;
+pub use withdraw::WithdrawInputs;
+pub use zero_preset::{verify_zero_constants, ZeroPreset, ZeroValues};
+
+#[cfg(feature = "anchor")]
+mod anchor;
+#[cfg(feature = "tokio")]
+mod async_insert;
+mod builder;
+mod bulk;
+mod canopy;
+mod checkpoint;
+#[cfg(feature = "serde_json")]
+mod circom;
 mod circom_t3;
+mod compressed;
+mod concurrent;
+mod deferred;
+mod extend;
+// Public as a module so the C error codes keep their own namespace
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod fixed;
+// Always built for this crate's own tests
+#[cfg(any(test, feature = "fixtures"))]
+mod fixtures;
+mod forest;
+mod frontier;
+mod full;
+mod hex;
+mod indexed;
+mod info;
+mod leaf_index;
+mod leaf_log;
+mod map;
+mod mmr;
+mod note;
+mod nullifier;
+mod params;
+#[cfg(feature = "zero-copy")]
+mod pod;
+#[cfg(feature = "std")]
+mod pool;
+mod proof;
+mod replay;
+#[cfg(feature = "serde")]
+mod serde_hex;
+mod snapshot;
+mod solidity;
+mod sparse;
+mod store;
+mod subtree;
+mod tracker;
+#[cfg(feature = "u256")]
+mod u256;
+mod versioned;
+#[cfg(feature = "wasm")]
+mod wasm;
+mod withdraw;
+mod zero_preset;
+
+pub const MAX_LEVELS: usize = 32;
+
+// Depth of `PoseidonMerkleTree::default()`, the same as Tornado Cash's pools
+pub const DEFAULT_LEVELS: u32 = 20;
+
+// Roots kept by `PoseidonMerkleTree::new`. `new_with_history` picks any other depth
+pub const ROOT_HISTORY_SIZE: usize = 20;
+
+// Most inputs `poseidon_hash` takes, the widest parameter set circomlib ships
+pub const MAX_POSEIDON_INPUTS: usize = 12;
+
+// Static Poseidon hasher initialized lazily and protected by a Mutex for thread safety. It
+// serves the free functions and the other tree types; `PoseidonMerkleTree` has its own
+#[cfg(all(feature = "std", not(feature = "tls-hasher")))]
+static POSEIDON: Lazy<Mutex<Poseidon<Fr>>> = Lazy::new(|| Mutex::new(new_poseidon()));
+
+// With `tls-hasher` every thread builds its own hasher on first use instead, so threads never
+// contend for the shared one
+#[cfg(feature = "tls-hasher")]
+thread_local! {
+    static THREAD_POSEIDON: Cell<Option<Poseidon<Fr>>> = const { Cell::new(None) };
+}
+
+// A hasher keeps no state between hashes, so one left behind by a panicking thread is still
+// fine to reuse, and a poisoned lock must not fail every later hash in the process
+#[cfg(all(feature = "std", not(feature = "tls-hasher")))]
+fn lock_poseidon() -> MutexGuard<'static, Poseidon<Fr>> {
+    POSEIDON
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(feature = "tls-hasher")]
+fn lock_poseidon() -> ThreadPoseidon {
+    let poseidon = THREAD_POSEIDON
+        .with(Cell::take)
+        .unwrap_or_else(new_poseidon);
+    ThreadPoseidon(Some(poseidon))
+}
+
+// Without `std` there is no lock to share a hasher behind, so every call builds its own. This
+// only costs the free functions and the other tree types; `PoseidonMerkleTree` keeps its hasher
+#[cfg(not(feature = "std"))]
+fn lock_poseidon() -> Poseidon<Fr> {
+    new_poseidon()
+}
 
-pub const MAX_LEVELS: usize = 20;
+// Borrows this thread's hasher and hands it back on drop. A nested borrow finds the slot
+// empty and simply builds another hasher
+#[cfg(feature = "tls-hasher")]
+struct ThreadPoseidon(Option<Poseidon<Fr>>);
 
-// Static Poseidon hasher initialized lazily and protected by a Mutex for thread safety
-static POSEIDON: Lazy<Mutex<Poseidon<Fr>>> = Lazy::new(|| {
-    let params = PoseidonParameters {
+#[cfg(feature = "tls-hasher")]
+impl Deref for ThreadPoseidon {
+    type Target = Poseidon<Fr>;
+
+    fn deref(&self) -> &Poseidon<Fr> {
+        self.0.as_ref().unwrap()
+    }
+}
+
+#[cfg(feature = "tls-hasher")]
+impl DerefMut for ThreadPoseidon {
+    fn deref_mut(&mut self) -> &mut Poseidon<Fr> {
+        self.0.as_mut().unwrap()
+    }
+}
+
+#[cfg(feature = "tls-hasher")]
+impl Drop for ThreadPoseidon {
+    fn drop(&mut self) {
+        // The thread's storage may already be gone while the thread is exiting
+        let _ = THREAD_POSEIDON.try_with(|slot| slot.set(self.0.take()));
+    }
+}
+
+// Two-input circom Poseidon over BN254, built from the bundled constants
+fn new_poseidon() -> Poseidon<Fr> {
+    Poseidon::<Fr>::new(circom_params())
+}
+
+fn circom_params() -> PoseidonParameters<Fr> {
+    PoseidonParameters {
         ark: Vec::from(ARK),
         mds: MDS.iter().map(|row| row.to_vec()).collect(),
         full_rounds: 8,
         partial_rounds: 57,
         width: 3,
         alpha: 5,
-    };
-    Mutex::new(Poseidon::<Fr>::new(params))
-});
+    }
+}
 
-#[derive(Error, Debug, PartialEq)]
+#[derive(Debug, PartialEq)]
 pub enum PoseidonMerkleTreeError {
-    #[error("Invalid levels")]
-    InvalidLevels,
+    InvalidLevels { got: u32, max: u32 },
+    MerkleTreeFull { capacity: u64 },
+    IndexOutOfBounds { index: u64, len: u64 },
+    KeyNotFound,
+    KeyAlreadyPresent,
+    InvalidProof,
+    InvalidCircomJson,
+    InvalidCalldata,
+    UnknownRoot,
+    LeafConflict,
+    InvalidPairAlignment,
+    MisalignedSubtree,
+    LeafLogUnsupported,
+    InvalidHistorySize,
+    FilledSubtreesMismatch,
+    FilledSubtreeOutsideField,
+    RootIndexOutOfRange,
+    NextIndexBeyondCapacity,
+    LeafLogMismatch,
+    InvalidEncoding,
+    UnsupportedVersion(u8),
+    InvalidAccountData,
+    FrontierMismatch,
+    InvalidHex,
+    InvalidDecimal,
+    InvalidLeaf { leaf_hex: String },
+    MissingEvent(u64),
+    DuplicateEvent(u64),
+    CheckpointMismatch,
+    SnapshotMismatch,
+    DuplicateLeaf,
+    InvalidPoseidonParameters,
+    InvalidInputCount,
+    ZeroChainMismatch { level: u32 },
+    DoubleSpend,
+    NullifierSetFull { capacity: u64 },
+    InvalidNote,
+    HashError(String),
+}
+
+impl PoseidonMerkleTreeError {
+    pub(crate) fn invalid_leaf(leaf: &[u8; 32]) -> PoseidonMerkleTreeError {
+        PoseidonMerkleTreeError::InvalidLeaf {
+            leaf_hex: encode_hash(leaf),
+        }
+    }
+
+    pub(crate) fn hash_error(error: impl fmt::Display) -> PoseidonMerkleTreeError {
+        PoseidonMerkleTreeError::HashError(error.to_string())
+    }
 
-    #[error("Merkle tree is full")]
-    MerkleTreeFull,
+    // Any depth over `max`, which is `MAX_LEVELS` unless a structure caps it lower
+    pub(crate) fn invalid_levels(got: u32) -> PoseidonMerkleTreeError {
+        PoseidonMerkleTreeError::InvalidLevels {
+            got,
+            max: MAX_LEVELS as u32,
+        }
+    }
+}
 
-    #[error("Failed to acquire Poseidon hasher lock")]
-    PoseidonLockError,
+// Written out rather than derived so the error type needs nothing beyond `alloc`
+impl fmt::Display for PoseidonMerkleTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoseidonMerkleTreeError::InvalidLevels { got, max } => {
+                write!(f, "Invalid levels {got}, expected 1 to {max}")
+            }
+            PoseidonMerkleTreeError::MerkleTreeFull { capacity } => {
+                write!(f, "Merkle tree is full at {capacity} leaves")
+            }
+            PoseidonMerkleTreeError::IndexOutOfBounds { index, len } => {
+                write!(f, "Leaf index {index} out of bounds for {len} leaves")
+            }
+            PoseidonMerkleTreeError::KeyNotFound => "Key not found".fmt(f),
+            PoseidonMerkleTreeError::KeyAlreadyPresent => "Key already present".fmt(f),
+            PoseidonMerkleTreeError::InvalidProof => "Malformed Merkle proof".fmt(f),
+            PoseidonMerkleTreeError::InvalidCircomJson => "Invalid circom proof JSON".fmt(f),
+            PoseidonMerkleTreeError::InvalidCalldata => "Invalid Solidity calldata".fmt(f),
+            PoseidonMerkleTreeError::UnknownRoot => "Root not found in changelog".fmt(f),
+            PoseidonMerkleTreeError::LeafConflict => {
+                "Leaf was changed after the proof's root".fmt(f)
+            }
+            PoseidonMerkleTreeError::InvalidPairAlignment => {
+                "Leaf pair must start at an even index".fmt(f)
+            }
+            PoseidonMerkleTreeError::MisalignedSubtree => {
+                "Subtree must start at a multiple of its size".fmt(f)
+            }
+            PoseidonMerkleTreeError::LeafLogUnsupported => {
+                "Operation is not supported with the tree's leaf log setting".fmt(f)
+            }
+            PoseidonMerkleTreeError::InvalidHistorySize => {
+                "Root history must hold at least one root".fmt(f)
+            }
+            PoseidonMerkleTreeError::FilledSubtreesMismatch => {
+                "Filled subtrees do not match the tree depth".fmt(f)
+            }
+            PoseidonMerkleTreeError::FilledSubtreeOutsideField => {
+                "Filled subtree is outside the field".fmt(f)
+            }
+            PoseidonMerkleTreeError::RootIndexOutOfRange => {
+                "Current root index is outside the root history".fmt(f)
+            }
+            PoseidonMerkleTreeError::NextIndexBeyondCapacity => {
+                "Next index is beyond the tree capacity".fmt(f)
+            }
+            PoseidonMerkleTreeError::LeafLogMismatch => {
+                "Leaf log length does not match next_index".fmt(f)
+            }
+            PoseidonMerkleTreeError::InvalidEncoding => {
+                "Serialized tree is truncated or malformed".fmt(f)
+            }
+            PoseidonMerkleTreeError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported serialization version {version}")
+            }
+            PoseidonMerkleTreeError::InvalidAccountData => {
+                "Account data does not match the zero-copy layout".fmt(f)
+            }
+            PoseidonMerkleTreeError::FrontierMismatch => {
+                "Frontier does not hash to its root".fmt(f)
+            }
+            PoseidonMerkleTreeError::InvalidHex => "Hash is not 64 hex digits".fmt(f),
+            PoseidonMerkleTreeError::InvalidDecimal => {
+                "Hash is not a decimal BN254 field element".fmt(f)
+            }
+            PoseidonMerkleTreeError::InvalidLeaf { leaf_hex } => {
+                write!(f, "Leaf {leaf_hex} is not a BN254 field element")
+            }
+            PoseidonMerkleTreeError::MissingEvent(index) => {
+                write!(f, "No event for leaf index {index}")
+            }
+            PoseidonMerkleTreeError::DuplicateEvent(index) => {
+                write!(f, "Leaf index {index} was already replayed")
+            }
+            PoseidonMerkleTreeError::CheckpointMismatch => {
+                "Checkpoint is not for the latest insert".fmt(f)
+            }
+            PoseidonMerkleTreeError::SnapshotMismatch => {
+                "Snapshot is from a tree of another depth".fmt(f)
+            }
+            PoseidonMerkleTreeError::DuplicateLeaf => "Leaf was already inserted".fmt(f),
+            PoseidonMerkleTreeError::InvalidPoseidonParameters => {
+                "Poseidon parameters do not describe a two-input hash".fmt(f)
+            }
+            PoseidonMerkleTreeError::InvalidInputCount => {
+                "Poseidon hashes between 1 and 12 inputs".fmt(f)
+            }
+            PoseidonMerkleTreeError::ZeroChainMismatch { level } => {
+                write!(
+                    f,
+                    "Zero value of level {level} is not the hash of the one below"
+                )
+            }
+            PoseidonMerkleTreeError::DoubleSpend => "Nullifier has already been spent".fmt(f),
+            PoseidonMerkleTreeError::NullifierSetFull { capacity } => {
+                write!(f, "Nullifier set is full at {capacity} nullifiers")
+            }
+            PoseidonMerkleTreeError::InvalidNote => {
+                "Note is not protocol-denomination-netid-0xsecret0xnullifier".fmt(f)
+            }
+            PoseidonMerkleTreeError::HashError(reason) => {
+                write!(f, "Poseidon hash failed: {reason}")
+            }
+        }
+    }
 }
 
-#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+impl core::error::Error for PoseidonMerkleTreeError {}
+
+// `Debug` and `Display` are written out in `hex`, so hashes print as hex
+#[derive(Clone, PartialEq)]
 pub struct PoseidonMerkleTree {
     pub levels: u32,
-    pub filled_subtrees: Vec<[u8; 32]>,
+    // Kept as field elements since every insert hashes with them; they are only turned into
+    // bytes for serialization and `filled_subtrees()`
+    pub(crate) filled_subtrees: Vec<Fr>,
+    // The raw ring, with unwritten slots left at zero and no order once it wraps; read it
+    // through `last_root`, `roots_iter` or `valid_roots`, as it is meant to become private
     pub roots: Vec<[u8; 32]>,
     pub current_root_index: u32,
-    pub next_index: u32,
+    pub next_index: u64,
+    // Every inserted leaf in order, kept only by trees built with `with_leaf_log`
+    leaf_log: Option<Vec<[u8; 32]>>,
+    // Where each logged leaf sits; empty without a leaf log
+    leaf_positions: LeafIndex,
+    // See `set_reject_duplicates`
+    reject_duplicates: bool,
+    // Whether the empty tree's root counts as known before the first insert
+    pub(crate) accept_initial_root: bool,
+    // How many slots of `roots` hold each root, so lookups do not scan the history. Rebuilt
+    // from `roots` rather than serialized
+    known_roots: BTreeMap<[u8; 32], u32>,
+    hasher: TreeHasher,
+    pub(crate) zeros: ZeroChain,
+    // The byte order of leaves and of `roots`; `filled_subtrees` and everything stored as a
+    // field element are big-endian regardless
+    pub(crate) endianness: Endianness,
+}
+
+// The hasher a tree inserts with. Either the tree owns one, so inserts into different trees
+// never wait on each other, or it borrows one from a `HasherPool` per operation. A hasher keeps
+// no state between hashes, which is why an owned hasher is rebuilt on clone and any two compare
+// equal. A `Custom` one is an owned hasher on parameters from `new_with_params`, kept so a clone
+// is rebuilt on the same ones
+pub(crate) enum TreeHasher {
+    Owned(Poseidon<Fr>),
+    Custom(Poseidon<Fr>, Arc<PoseidonParameters<Fr>>),
+    #[cfg(feature = "std")]
+    Pooled(HasherPool),
+}
+
+pub(crate) enum HasherGuard<'a> {
+    Owned(&'a mut Poseidon<Fr>),
+    #[cfg(feature = "std")]
+    Pooled(PooledHasher),
+}
+
+impl TreeHasher {
+    pub(crate) fn custom(params: Arc<PoseidonParameters<Fr>>) -> TreeHasher {
+        TreeHasher::Custom(Poseidon::new(params::copy_params(&params)), params)
+    }
+
+    pub(crate) fn checkout(&mut self) -> HasherGuard<'_> {
+        match self {
+            TreeHasher::Owned(poseidon) | TreeHasher::Custom(poseidon, _) => {
+                HasherGuard::Owned(poseidon)
+            }
+            #[cfg(feature = "std")]
+            TreeHasher::Pooled(pool) => HasherGuard::Pooled(pool.checkout()),
+        }
+    }
+}
+
+impl Deref for HasherGuard<'_> {
+    type Target = Poseidon<Fr>;
+
+    fn deref(&self) -> &Poseidon<Fr> {
+        match self {
+            HasherGuard::Owned(poseidon) => poseidon,
+            #[cfg(feature = "std")]
+            HasherGuard::Pooled(pooled) => pooled,
+        }
+    }
+}
+
+impl DerefMut for HasherGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Poseidon<Fr> {
+        match self {
+            HasherGuard::Owned(poseidon) => poseidon,
+            #[cfg(feature = "std")]
+            HasherGuard::Pooled(pooled) => pooled,
+        }
+    }
+}
+
+impl Default for TreeHasher {
+    fn default() -> TreeHasher {
+        TreeHasher::Owned(new_poseidon())
+    }
+}
+
+impl Clone for TreeHasher {
+    fn clone(&self) -> TreeHasher {
+        match self {
+            TreeHasher::Owned(_) => TreeHasher::default(),
+            TreeHasher::Custom(_, params) => TreeHasher::custom(params.clone()),
+            #[cfg(feature = "std")]
+            TreeHasher::Pooled(pool) => TreeHasher::Pooled(pool.clone()),
+        }
+    }
+}
+
+impl fmt::Debug for TreeHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TreeHasher::Owned(_) => f.write_str("Owned"),
+            TreeHasher::Custom(..) => f.write_str("Custom"),
+            #[cfg(feature = "std")]
+            TreeHasher::Pooled(pool) => f.debug_tuple("Pooled").field(pool).finish(),
+        }
+    }
+}
+
+impl PartialEq for TreeHasher {
+    fn eq(&self, _: &TreeHasher) -> bool {
+        true
+    }
+}
+
+/// An empty tree of `DEFAULT_LEVELS`, so the tree can sit in structs deriving `Default`:
+///
+/// ```
+/// use poseidon_merkle_tree::{PoseidonMerkleTree, DEFAULT_LEVELS};
+///
+/// #[derive(Default)]
+/// struct Pool {
+///     deposits: PoseidonMerkleTree,
+///     denomination: u64,
+/// }
+///
+/// let mut pool = Pool::default();
+/// assert_eq!(pool.deposits.levels, DEFAULT_LEVELS);
+/// pool.deposits.insert(&[1u8; 32]).unwrap();
+/// ```
+impl Default for PoseidonMerkleTree {
+    fn default() -> PoseidonMerkleTree {
+        PoseidonMerkleTree::new(DEFAULT_LEVELS).expect("DEFAULT_LEVELS is a valid depth")
+    }
 }
 
 impl PoseidonMerkleTree {
-    pub const SIZE: usize = 4 + 32 * MAX_LEVELS + 32 * MAX_LEVELS + 4 + 4;
+    // Room for any tree built by `new`, whatever its depth
+    pub const SIZE: usize = PoseidonMerkleTree::max_serialized_size(MAX_LEVELS as u32);
+
+    // Largest serialized size of a tree without a leaf log that keeps `history_size` roots
+    pub const fn size(history_size: usize) -> usize {
+        PoseidonMerkleTree::stored_size(MAX_LEVELS as u32, history_size)
+    }
+
+    // Serialized size of a tree built by `new(levels)`. Without a leaf log it never grows, so
+    // this is all the space an account holding it needs
+    pub const fn max_serialized_size(levels: u32) -> usize {
+        PoseidonMerkleTree::stored_size(levels, ROOT_HISTORY_SIZE)
+    }
+
+    // Borsh length of a tree without a leaf log: `levels`, both length-prefixed vectors,
+    // `current_root_index`, `next_index`, the leaf log's `None` tag and the options
+    pub(crate) const fn stored_size(levels: u32, history_size: usize) -> usize {
+        4 + 4 + 32 * levels as usize + 4 + 32 * history_size + 4 + 8 + 1 + OPTIONS_SIZE
+    }
+
+    // Length of this tree's Borsh encoding, counted from its fields
+    pub fn serialized_size(&self) -> usize {
+        let leaf_log = self
+            .leaf_log
+            .as_ref()
+            .map_or(0, |leaf_log| 4 + 32 * leaf_log.len());
+        4 + 4
+            + 32 * self.filled_subtrees.len()
+            + 4
+            + 32 * self.roots.len()
+            + 4
+            + 8
+            + 1
+            + leaf_log
+            + OPTIONS_SIZE
+    }
 
     pub fn new(levels: u32) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
-        if levels > MAX_LEVELS as u32 {
-            return Err(PoseidonMerkleTreeError::InvalidLevels);
-        }
+        PoseidonMerkleTree::builder().levels(levels).build()
+    }
+
+    // Like `new`, but `is_known_root` accepts any of the last `history_size` roots instead of
+    // the last `ROOT_HISTORY_SIZE`
+    pub fn new_with_history(
+        levels: u32,
+        history_size: usize,
+    ) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        PoseidonMerkleTree::builder()
+            .levels(levels)
+            .root_history(history_size)
+            .build()
+    }
 
+    // An empty tree from options `PoseidonMerkleTreeBuilder::build` has already checked
+    pub(crate) fn empty(
+        levels: u32,
+        history_size: usize,
+        zeros: ZeroChain,
+        endianness: Endianness,
+    ) -> PoseidonMerkleTree {
         // Initialize filled_subtrees with the correct zero values
-        let filled_subtrees: Vec<[u8; 32]> = (0..levels).map(zeros).collect();
+        let filled_subtrees = zeros.fr()[..levels as usize].to_vec();
 
         // Initialize roots with zeros and set the first root
-        let mut roots = [[0; 32]; MAX_LEVELS];
-        roots[0] = zeros(levels - 1);
+        let mut roots = vec![[0; 32]; history_size];
+        roots[0] = endianness.convert(zeros.bytes(levels - 1));
 
-        Ok(PoseidonMerkleTree {
+        let mut tree = PoseidonMerkleTree {
             levels,
             filled_subtrees,
-            roots: roots.to_vec(),
+            roots,
             current_root_index: 0,
             next_index: 0,
-        })
+            leaf_log: None,
+            leaf_positions: LeafIndex::default(),
+            reject_duplicates: false,
+            accept_initial_root: true,
+            known_roots: BTreeMap::new(),
+            hasher: TreeHasher::default(),
+            zeros,
+            endianness,
+        };
+        tree.reindex_roots();
+        tree
+    }
+
+    // `new` followed by inserting `leaves` in order, leaving the same roots in the history as
+    // the inserts would. Too many leaves fail with `MerkleTreeFull` before anything is hashed.
+    // Every leaf is hashed with the tree's own hasher, so nothing is locked per leaf
+    pub fn new_with_leaves(
+        levels: u32,
+        leaves: &[[u8; 32]],
+    ) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        let mut tree = PoseidonMerkleTree::new(levels)?;
+        tree.insert_many(leaves)?;
+        Ok(tree)
+    }
+
+    // Like `new`, but the tree borrows a hasher from `pool` for each insert instead of owning
+    // one
+    #[cfg(feature = "std")]
+    pub fn new_with_pool(
+        levels: u32,
+        pool: &HasherPool,
+    ) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        let mut tree = PoseidonMerkleTree::new(levels)?;
+        tree.hasher = TreeHasher::Pooled(pool.clone());
+        Ok(tree)
+    }
+
+    // Empties the tree in place, leaving it equal to a freshly built one with the same depth,
+    // history size, zero preset, hasher and leaf log setting. Nothing is hashed, so the shared
+    // hasher is never touched. It only fails for `levels` that `validate` would reject
+    pub fn reset(&mut self) -> Result<(), PoseidonMerkleTreeError> {
+        self.reset_with_levels(self.levels)
+    }
+
+    // `reset` to a tree of `levels`. An invalid depth leaves the tree as it was
+    pub fn reset_with_levels(&mut self, levels: u32) -> Result<(), PoseidonMerkleTreeError> {
+        let mut fresh = self.to_builder().levels(levels).build()?;
+        fresh.leaf_log = self.leaf_log.as_ref().map(|_| Vec::new());
+        fresh.reject_duplicates = self.reject_duplicates;
+        fresh.hasher = core::mem::take(&mut self.hasher);
+        *self = fresh;
+        Ok(())
+    }
+
+    // Returns the index the leaf was placed at, the one its proof and circom `pathIndices` use
+    pub fn insert(&mut self, leaf: &[u8; 32]) -> Result<u64, PoseidonMerkleTreeError> {
+        self.insert_with_root(leaf).map(|(index, _)| index)
+    }
+
+    // `insert` that also returns the root it recorded, so a caller sharing the tree does not
+    // have to read `last_root` separately and risk seeing another writer's root
+    pub fn insert_with_root(
+        &mut self,
+        leaf: &[u8; 32],
+    ) -> Result<(u64, [u8; 32]), PoseidonMerkleTreeError> {
+        let index = self.insert_fr(self.read_leaf(leaf)?)?;
+        Ok((index, self.last_root()))
+    }
+
+    // Field-element forms of `insert`, the current root and `is_known_root`. Bytes are read in
+    // the tree's `endianness`, so both forms can be mixed on one tree
+    pub fn insert_fr(&mut self, leaf: Fr) -> Result<u64, PoseidonMerkleTreeError> {
+        let index = self.next_index;
+        let root = self.append_with(leaf, |_| {})?;
+        self.record_root(root);
+        Ok(index)
+    }
+
+    // Inserts the `commitment` of `nullifier` and `secret`, both read in the tree's
+    // `endianness`, and returns its index. The commitment is always hashed on the bundled
+    // circom parameters, however the tree hashes its nodes
+    pub fn insert_commitment(
+        &mut self,
+        nullifier: &[u8; 32],
+        secret: &[u8; 32],
+    ) -> Result<u64, PoseidonMerkleTreeError> {
+        let (nullifier, secret) = (self.read_leaf(nullifier)?, self.read_leaf(secret)?);
+        let leaf = hash_fr(&mut lock_poseidon(), nullifier, secret)?;
+        self.insert_fr(leaf)
+    }
+
+    // The rightmost complete left subtree at each level, from the leaves up
+    pub fn filled_subtrees(&self) -> Vec<[u8; 32]> {
+        self.filled_subtrees.iter().map(fr_to_be_bytes).collect()
+    }
+
+    pub fn root_fr(&self) -> Result<Fr, PoseidonMerkleTreeError> {
+        fr_from_be_bytes(&self.endianness.convert(self.last_root()))
+    }
+
+    pub fn is_known_root_fr(&self, root: Fr) -> bool {
+        self.is_known_root(self.endianness.convert(fr_to_be_bytes(&root)))
+    }
+
+    // A leaf as given to this tree, in its `endianness`, as a field element
+    pub(crate) fn read_leaf(&self, leaf: &[u8; 32]) -> Result<Fr, PoseidonMerkleTreeError> {
+        leaf_fr(&self.endianness.convert(*leaf))
+            .map_err(|_| PoseidonMerkleTreeError::invalid_leaf(leaf))
     }
 
-    pub fn insert(&mut self, leaf: &[u8; 32]) -> Result<u32, PoseidonMerkleTreeError> {
-        if self.next_index == 2u32.pow(self.levels) {
-            return Err(PoseidonMerkleTreeError::MerkleTreeFull);
+    // Inserts every leaf in order, recording a root after each one like `insert`, and returns
+    // the index each leaf was assigned. Nothing is inserted unless the whole batch fits
+    pub fn insert_many(
+        &mut self,
+        leaves: &[[u8; 32]],
+    ) -> Result<Vec<u64>, PoseidonMerkleTreeError> {
+        if self.remaining() < leaves.len() as u64 {
+            return Err(PoseidonMerkleTreeError::MerkleTreeFull {
+                capacity: self.capacity(),
+            });
         }
 
-        let mut current_index = self.next_index;
-        let mut current_level_hash = leaf.clone();
+        let mut indices = Vec::with_capacity(leaves.len());
+        for leaf in leaves {
+            indices.push(self.next_index);
+            self.append(leaf)?;
+        }
+
+        Ok(indices)
+    }
+
+    // Like `insert_many`, but all or nothing: every leaf is checked to be inside the field and
+    // the batch to fit before anything is hashed, and the inserts run on a copy of the tree
+    // that only replaces it once all of them succeeded. On error the tree is exactly as it was
+    pub fn insert_batch_atomic(
+        &mut self,
+        leaves: &[[u8; 32]],
+    ) -> Result<Vec<u64>, PoseidonMerkleTreeError> {
+        if self.remaining() < leaves.len() as u64 {
+            return Err(PoseidonMerkleTreeError::MerkleTreeFull {
+                capacity: self.capacity(),
+            });
+        }
+        for leaf in leaves {
+            self.read_leaf(leaf)?;
+        }
 
-        // Acquire the Poseidon hasher lock
-        let mut poseidon = POSEIDON
-            .lock()
-            .map_err(|_| PoseidonMerkleTreeError::PoseidonLockError)?;
+        let mut staged = self.clone();
+        let indices = staged.insert_many(leaves)?;
+        *self = staged;
+        Ok(indices)
+    }
+
+    // Inserts two sibling leaves with a single walk up the tree and returns the index of `left`.
+    // The pair must start at an even index so both leaves share their level-0 parent
+    pub fn insert_pair(
+        &mut self,
+        left: &[u8; 32],
+        right: &[u8; 32],
+    ) -> Result<u64, PoseidonMerkleTreeError> {
+        if !self.next_index.is_multiple_of(2) {
+            return Err(PoseidonMerkleTreeError::InvalidPairAlignment);
+        }
+        if self.remaining() < 2 {
+            return Err(PoseidonMerkleTreeError::MerkleTreeFull {
+                capacity: self.capacity(),
+            });
+        }
 
-        for i in 0..self.levels {
-            let (left, right) = if current_index % 2 == 0 {
-                (current_level_hash, zeros(i))
+        let (left_fr, right_fr) = (self.read_leaf(left)?, self.read_leaf(right)?);
+        if self.reject_duplicates && (left == right || self.contains_logged(left, right)) {
+            return Err(PoseidonMerkleTreeError::DuplicateLeaf);
+        }
+        let mut poseidon = self.hasher.checkout();
+        let poseidon = &mut *poseidon;
+
+        // As in `append_with`, the frontier is only written once every hash succeeded
+        let mut left_nodes = [Fr::default(); MAX_LEVELS];
+        left_nodes[0] = left_fr;
+        let mut current_index = self.next_index / 2;
+        let mut current_level_hash = hash_fr(poseidon, left_fr, right_fr)?;
+
+        let levels = self.levels as usize;
+        for (i, left_node) in left_nodes.iter_mut().enumerate().take(levels).skip(1) {
+            let (left, right) = if current_index.is_multiple_of(2) {
+                *left_node = current_level_hash;
+                (current_level_hash, self.zeros.fr()[i])
             } else {
-                (self.filled_subtrees[i as usize], current_level_hash)
+                (self.filled_subtrees[i], current_level_hash)
             };
 
-            current_level_hash = poseidon.hash_bytes_be(&[&left, &right]).unwrap();
-            self.filled_subtrees[i as usize] = left;
+            current_level_hash = hash_fr(poseidon, left, right)?;
             current_index /= 2;
         }
 
-        let new_root_index = (self.current_root_index + 1) % MAX_LEVELS as u32;
-        self.current_root_index = new_root_index;
-        self.roots[new_root_index as usize] = current_level_hash;
-        self.next_index += 1;
+        for (i, node) in left_nodes.iter().enumerate().take(self.levels as usize) {
+            if (self.next_index >> i).is_multiple_of(2) {
+                self.filled_subtrees[i] = *node;
+            }
+        }
+        self.next_index += 2;
+        if let Some(leaf_log) = &mut self.leaf_log {
+            leaf_log.extend([*left, *right]);
+            self.leaf_positions.insert(*left, self.next_index - 2);
+            self.leaf_positions.insert(*right, self.next_index - 1);
+        }
+        self.record_root(fr_to_be_bytes(&current_level_hash));
 
-        Ok(self.next_index)
+        Ok(self.next_index - 2)
     }
 
-    pub fn is_known_root(&self, root: [u8; 32]) -> bool {
-        if root == [0; 32] {
-            return false;
-        }
-
-        let mut i = self.current_root_index;
-        for _ in 0..MAX_LEVELS {
-            if self.roots[i as usize] == root {
-                return true;
-            }
-            i = if i == 0 { MAX_LEVELS as u32 - 1 } else { i - 1 };
-        }
-
-        false
-    }
-}
-
-fn zeros(i: u32) -> [u8; 32] {
-    match i {
-        0 => [
-            0x28, 0x94, 0x0d, 0xee, 0xac, 0xd1, 0xca, 0x28, 0x31, 0x33, 0x68, 0x74, 0xe8, 0x74,
-            0x29, 0xdb, 0x0e, 0x72, 0x8a, 0x67, 0xa4, 0x72, 0xb7, 0xac, 0x81, 0x95, 0xc4, 0x3c,
-            0x2f, 0xb1, 0x30, 0x09,
-        ], // sha("voidify")
-        1 => [
-            0x13, 0x8b, 0xfd, 0xb7, 0x91, 0xd8, 0xba, 0xd9, 0x8a, 0x50, 0xc8, 0x2e, 0xa1, 0xef,
-            0x62, 0x4f, 0xeb, 0x03, 0xed, 0x9b, 0x7b, 0xbd, 0xb3, 0x48, 0x55, 0x1a, 0x6b, 0x34,
-            0x7f, 0xfd, 0x56, 0x1c,
-        ],
-        2 => [
-            0x00, 0x5e, 0xf3, 0xbb, 0xa3, 0x6e, 0x2d, 0x71, 0x45, 0x75, 0xef, 0x75, 0xc6, 0xec,
-            0x27, 0xc6, 0x0e, 0x05, 0x93, 0xfb, 0x7b, 0xd4, 0x01, 0x2a, 0x33, 0x0b, 0xc0, 0x65,
-            0xfb, 0x79, 0x08, 0x37,
-        ],
-        3 => [
-            0x10, 0xc7, 0x03, 0x6d, 0x8a, 0x63, 0xd1, 0x40, 0xd7, 0x7c, 0x6a, 0xc1, 0x21, 0xc2,
-            0xef, 0x50, 0x2c, 0xa8, 0x37, 0x03, 0x91, 0x3d, 0x34, 0x97, 0x48, 0x17, 0x54, 0x31,
-            0x1c, 0xf8, 0x12, 0xa1,
-        ],
-        4 => [
-            0x1e, 0x54, 0xdf, 0x31, 0x58, 0xcf, 0x89, 0x80, 0x2f, 0x13, 0xf7, 0x22, 0x65, 0xf2,
-            0x6c, 0x3f, 0x28, 0x13, 0x91, 0x46, 0x57, 0xcc, 0xe8, 0xfe, 0x1c, 0x68, 0xc8, 0x1c,
-            0x6f, 0x84, 0xb5, 0xe3,
-        ],
-        5 => [
-            0x07, 0xf8, 0x79, 0x07, 0xf4, 0x8e, 0x61, 0x7a, 0x18, 0x4d, 0x93, 0x59, 0x64, 0x50,
-            0xb3, 0xa6, 0x8a, 0x30, 0xc0, 0xdf, 0xdf, 0x93, 0x16, 0x4a, 0x0a, 0xf9, 0x63, 0xdd,
-            0xcc, 0xc0, 0x4c, 0xc7,
-        ],
-        6 => [
-            0x1b, 0xca, 0xbd, 0x63, 0x5e, 0x6f, 0x84, 0x5b, 0x50, 0x39, 0xcb, 0xf8, 0x27, 0xb5,
-            0x28, 0x12, 0x1e, 0xc3, 0x4a, 0x2a, 0x3f, 0x68, 0x0f, 0x27, 0xf8, 0x84, 0x56, 0xc4,
-            0x76, 0x62, 0xec, 0x32,
-        ],
-        7 => [
-            0x03, 0x2d, 0x93, 0x0e, 0x15, 0x6c, 0xce, 0x79, 0x7f, 0xcd, 0x3f, 0x4a, 0x11, 0xdc,
-            0x41, 0x70, 0x31, 0x5f, 0x8f, 0x83, 0x0c, 0xa6, 0xb0, 0xf3, 0xbb, 0x71, 0x1e, 0x53,
-            0x37, 0xd6, 0x77, 0x3d,
-        ],
-        8 => [
-            0x17, 0x0a, 0xbe, 0x49, 0x47, 0xc1, 0x19, 0x5a, 0x40, 0xa4, 0x88, 0x11, 0xe6, 0xb3,
-            0x62, 0xa0, 0xa9, 0xc8, 0x68, 0x57, 0x33, 0xc1, 0x7f, 0x61, 0x50, 0xc1, 0x96, 0xb9,
-            0x39, 0xfc, 0x21, 0xf8,
-        ],
-        9 => [
-            0x03, 0xd9, 0xe6, 0x48, 0xd6, 0x74, 0x27, 0xd0, 0xa6, 0xe0, 0xa3, 0x0a, 0xad, 0x5d,
-            0x18, 0xaf, 0x05, 0xb9, 0xe0, 0x4b, 0x41, 0xb4, 0x98, 0x5f, 0xd4, 0x06, 0x2d, 0xe2,
-            0x71, 0x1c, 0xbe, 0xc1,
-        ],
-        10 => [
-            0x04, 0xa4, 0xfe, 0x12, 0x21, 0xc0, 0xd2, 0x1b, 0x27, 0xb4, 0x9a, 0x23, 0xb7, 0x53,
-            0x47, 0xfe, 0xc6, 0x90, 0x3b, 0xba, 0xd2, 0xf6, 0x12, 0x99, 0xb9, 0x36, 0xbf, 0xb7,
-            0xb7, 0x83, 0xfc, 0xd7,
-        ],
-        11 => [
-            0x14, 0x32, 0xaa, 0x33, 0x5f, 0xcc, 0xae, 0xed, 0xed, 0x95, 0x05, 0xa5, 0xa1, 0x42,
-            0xe8, 0x56, 0x8a, 0xf6, 0x2c, 0xcc, 0x90, 0x81, 0x14, 0xbf, 0xdc, 0xbe, 0x95, 0x6e,
-            0x11, 0x72, 0xad, 0x98,
-        ],
-        12 => [
-            0x18, 0x91, 0x90, 0x59, 0xfd, 0x2a, 0x3d, 0x7b, 0xa6, 0xc4, 0x04, 0x9f, 0x42, 0xb7,
-            0x7b, 0x0e, 0xcc, 0x6a, 0x23, 0x01, 0xe6, 0x65, 0x36, 0x38, 0x7f, 0x11, 0xaa, 0x52,
-            0x2b, 0x3e, 0xd2, 0x7b,
-        ],
-        13 => [
-            0x06, 0x96, 0x2f, 0x22, 0x9c, 0x6f, 0x6e, 0x30, 0x7a, 0x60, 0x22, 0x49, 0x33, 0xcb,
-            0x0d, 0x9c, 0x9b, 0x61, 0xcf, 0x44, 0x2e, 0xd5, 0xb0, 0x36, 0xe9, 0xcf, 0x36, 0x70,
-            0xa5, 0xaf, 0xf8, 0xd2,
-        ],
-        14 => [
-            0x01, 0x82, 0x1e, 0x95, 0xe5, 0x34, 0x93, 0x44, 0x8e, 0x2d, 0x59, 0x9c, 0xb0, 0x45,
-            0xcd, 0x8e, 0x8d, 0x21, 0xf3, 0xd2, 0xd7, 0xe8, 0xac, 0xf5, 0xc9, 0x09, 0x68, 0x1e,
-            0xe2, 0x0a, 0x69, 0x26,
-        ],
-        15 => [
-            0x0e, 0xc5, 0xb2, 0x9a, 0xd4, 0x60, 0x9e, 0xfd, 0x69, 0xbd, 0x92, 0x30, 0xc8, 0x9f,
-            0x82, 0xf3, 0xfc, 0x15, 0x03, 0xf3, 0x8c, 0x21, 0x15, 0x07, 0x3e, 0x82, 0x22, 0x61,
-            0x91, 0x92, 0x62, 0x96,
-        ],
-        16 => [
-            0x16, 0x4c, 0x52, 0x2e, 0xc8, 0xd8, 0xd0, 0x64, 0xe9, 0xac, 0x53, 0x5c, 0x6a, 0x1b,
-            0x34, 0xfc, 0x41, 0xa5, 0x05, 0xd8, 0x70, 0xeb, 0xc0, 0xad, 0x55, 0x16, 0x72, 0x17,
-            0x1b, 0x75, 0xf3, 0x4c,
-        ],
-        17 => [
-            0x25, 0x2a, 0x2a, 0xcf, 0xa2, 0x2c, 0xa0, 0x9d, 0x7f, 0x96, 0x5d, 0x01, 0x5b, 0x01,
-            0xcf, 0x3c, 0xd5, 0x9f, 0xf8, 0x9d, 0x5b, 0x4f, 0x22, 0x95, 0x64, 0xc2, 0x28, 0xf2,
-            0x50, 0x20, 0xed, 0xf1,
-        ],
-        18 => [
-            0x2f, 0x72, 0x9a, 0xb9, 0x99, 0x4d, 0x06, 0xf1, 0xe6, 0xc0, 0x77, 0xc5, 0xea, 0xdb,
-            0xc4, 0x51, 0xe7, 0x21, 0xd0, 0x29, 0x15, 0x9a, 0x30, 0xe4, 0x7e, 0x32, 0xb1, 0x5c,
-            0xc6, 0xe2, 0x8a, 0xb7,
-        ],
-        19 => [
-            0x19, 0xbf, 0x0a, 0x91, 0xf2, 0x85, 0x2d, 0x3a, 0x5b, 0xd3, 0x56, 0x5d, 0x9f, 0x77,
-            0xe0, 0x4f, 0xb6, 0xde, 0x7b, 0xc3, 0x18, 0x75, 0x3f, 0xa5, 0x28, 0x17, 0x00, 0xd7,
-            0x86, 0xe8, 0xab, 0xd1,
-        ],
-        20 => [
-            0x28, 0xc6, 0xd1, 0x55, 0xc4, 0xef, 0x4f, 0x87, 0x09, 0x53, 0x23, 0xe8, 0x83, 0x2e,
-            0xc0, 0x54, 0xfa, 0x7d, 0xab, 0x72, 0xa6, 0xfd, 0x22, 0x95, 0x6b, 0x39, 0xe3, 0xdb,
-            0x18, 0x40, 0x29, 0x6f,
-        ],
-        _ => panic!("Index out of bounds"),
+    // Appends a leaf and returns the nodes hashed along its path, from the leaf (level 0)
+    // up to the new root (level `levels`).
+    pub(crate) fn append(
+        &mut self,
+        leaf: &[u8; 32],
+    ) -> Result<Vec<[u8; 32]>, PoseidonMerkleTreeError> {
+        let path = self.append_unrecorded(leaf)?;
+        self.record_root(path[self.levels as usize]);
+        Ok(path)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    // Same as `append`, but leaves the root history untouched
+    pub(crate) fn append_unrecorded(
+        &mut self,
+        leaf: &[u8; 32],
+    ) -> Result<Vec<[u8; 32]>, PoseidonMerkleTreeError> {
+        let mut path = Vec::with_capacity(self.levels as usize + 1);
+        path.push(*leaf);
+        self.append_with(self.read_leaf(leaf)?, |node| {
+            path.push(fr_to_be_bytes(node))
+        })?;
+        Ok(path)
+    }
 
-    #[test]
-    fn test_new_valid_levels() {
-        let tree = PoseidonMerkleTree::new(5).unwrap();
-        assert_eq!(tree.levels, 5);
-        assert_eq!(tree.filled_subtrees.len(), 5);
-        assert_eq!(tree.roots.len(), MAX_LEVELS);
-        assert_eq!(tree.current_root_index, 0);
-        assert_eq!(tree.next_index, 0);
-        assert_eq!(tree.roots[0], zeros(4)); // Root should be zero hash for level 4
-        assert_eq!(tree.filled_subtrees[0], zeros(0));
-        assert_eq!(tree.filled_subtrees[4], zeros(4));
+    // Appends a leaf, handing every node above it to `visit` on the way up, and returns the
+    // new root without recording it. The running hash stays a field element between levels,
+    // so bytes are only produced for what is stored
+    fn append_with(
+        &mut self,
+        leaf: Fr,
+        mut visit: impl FnMut(&Fr),
+    ) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        if self.is_full() {
+            return Err(PoseidonMerkleTreeError::MerkleTreeFull {
+                capacity: self.capacity(),
+            });
+        }
+        if self.reject_duplicates {
+            let bytes = self.endianness.convert(fr_to_be_bytes(&leaf));
+            if self.contains_logged(&bytes, &bytes) {
+                return Err(PoseidonMerkleTreeError::DuplicateLeaf);
+            }
+        }
+
+        let mut poseidon = self.hasher.checkout();
+        let poseidon = &mut *poseidon;
+        let mut current_index = self.next_index;
+        let mut current_level_hash = leaf;
+
+        // A left child's old frontier entry is never read on the way up, so the new ones are
+        // kept aside and only written once every hash succeeded. A failing hasher then leaves
+        // the tree as it was
+        let mut left_nodes = [Fr::default(); MAX_LEVELS];
+        for (i, left_node) in left_nodes.iter_mut().enumerate().take(self.levels as usize) {
+            let (left, right) = if current_index.is_multiple_of(2) {
+                *left_node = current_level_hash;
+                (current_level_hash, self.zeros.fr()[i])
+            } else {
+                (self.filled_subtrees[i], current_level_hash)
+            };
+
+            current_level_hash = hash_fr(poseidon, left, right)?;
+            visit(&current_level_hash);
+            current_index /= 2;
+        }
+
+        for (i, node) in left_nodes.iter().enumerate().take(self.levels as usize) {
+            if (self.next_index >> i).is_multiple_of(2) {
+                self.filled_subtrees[i] = *node;
+            }
+        }
+        self.next_index += 1;
+        if let Some(leaf_log) = &mut self.leaf_log {
+            let leaf = self.endianness.convert(fr_to_be_bytes(&leaf));
+            leaf_log.push(leaf);
+            self.leaf_positions.insert(leaf, self.next_index - 1);
+        }
+
+        Ok(fr_to_be_bytes(&current_level_hash))
     }
 
-    #[test]
-    fn test_new_invalid_levels() {
-        let result = PoseidonMerkleTree::new(MAX_LEVELS as u32 + 1);
-        assert_eq!(result, Err(PoseidonMerkleTreeError::InvalidLevels));
+    // `root` is big-endian, and is stored in the tree's `endianness`
+    pub(crate) fn record_root(&mut self, root: [u8; 32]) {
+        let root = self.endianness.convert(root);
+        let new_root_index = (self.current_root_index + 1) % self.roots.len() as u32;
+        self.current_root_index = new_root_index;
+
+        let evicted = core::mem::replace(&mut self.roots[new_root_index as usize], root);
+        if let Some(count) = self.known_roots.get_mut(&evicted) {
+            *count -= 1;
+            if *count == 0 {
+                self.known_roots.remove(&evicted);
+            }
+        }
+        if root != [0; 32] {
+            *self.known_roots.entry(root).or_insert(0) += 1;
+        }
     }
 
-    #[test]
-    fn test_insert_single_leaf() {
-        let mut tree = PoseidonMerkleTree::new(3).unwrap();
-        let leaf = [1u8; 32];
-        let result = tree.insert(&leaf);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 1);
-        assert_eq!(tree.next_index, 1);
-        assert_eq!(tree.current_root_index, 1);
-        assert_eq!(tree.filled_subtrees[0], leaf);
-        assert_ne!(tree.roots[1], [0; 32]);
+    // Reverses the latest `record_root`, putting back the root it evicted
+    pub(crate) fn unrecord_root(&mut self, evicted: [u8; 32], previous_root_index: u32) {
+        let slot = self.current_root_index as usize;
+        let root = core::mem::replace(&mut self.roots[slot], evicted);
+        if let Some(count) = self.known_roots.get_mut(&root) {
+            *count -= 1;
+            if *count == 0 {
+                self.known_roots.remove(&root);
+            }
+        }
+        if evicted != [0; 32] {
+            *self.known_roots.entry(evicted).or_insert(0) += 1;
+        }
+        self.current_root_index = previous_root_index;
     }
 
-    #[test]
-    fn test_insert_multiple_leaves() {
-        let mut tree = PoseidonMerkleTree::new(3).unwrap();
-        let leaf1 = [1u8; 32];
-        let leaf2 = [2u8; 32];
+    // Rebuilds the lookup index after `roots` was replaced wholesale. Each insert records at
+    // most one root, so only the `next_index + 1` slots walking back from `current_root_index`
+    // can have been written; anything left in the other slots is cleared rather than trusted
+    pub(crate) fn reindex_roots(&mut self) {
+        let len = self.roots.len();
+        let written = self.next_index.saturating_add(1).min(len as u64) as usize;
 
-        tree.insert(&leaf1).unwrap();
-        tree.insert(&leaf2).unwrap();
+        self.known_roots.clear();
+        for age in 0..len {
+            let slot = (self.current_root_index as usize + len - age) % len;
+            if age >= written {
+                self.roots[slot] = [0; 32];
+            } else if self.roots[slot] != [0; 32] {
+                *self.known_roots.entry(self.roots[slot]).or_insert(0) += 1;
+            }
+        }
+    }
 
-        assert_eq!(tree.next_index, 2);
-        assert_eq!(tree.current_root_index, 2);
+    // Leaves inserted so far
+    pub fn len(&self) -> u64 {
+        self.next_index
+    }
 
-        let mut poseidon = Poseidon::<Fr>::new_circom(2).unwrap();
-        let expected_hash = poseidon.hash_bytes_be(&[&leaf1, &leaf2]).unwrap();
-        assert_eq!(tree.filled_subtrees[1], expected_hash);
+    pub fn is_empty(&self) -> bool {
+        self.next_index == 0
     }
 
-    #[test]
-    fn test_merkle_tree_full() {
-        let mut tree = PoseidonMerkleTree::new(2).unwrap();
-        let leaf = [1u8; 32];
+    pub fn capacity(&self) -> u64 {
+        1u64 << self.levels
+    }
 
-        // Insert 4 leaves (2^2 = 4)
-        tree.insert(&leaf).unwrap();
-        tree.insert(&leaf).unwrap();
-        tree.insert(&leaf).unwrap();
-        tree.insert(&leaf).unwrap();
+    // Saturates, so a tree read back with `next_index` past its capacity reports 0 instead of
+    // wrapping
+    pub fn remaining(&self) -> u64 {
+        self.capacity().saturating_sub(self.next_index)
+    }
 
-        // Next insert should fail
-        let result = tree.insert(&leaf);
-        assert_eq!(result, Err(PoseidonMerkleTreeError::MerkleTreeFull));
+    pub fn is_full(&self) -> bool {
+        self.remaining() == 0
     }
 
-    #[test]
-    fn test_is_known_root() {
-        let mut tree = PoseidonMerkleTree::new(3).unwrap();
-        let leaf = [1u8; 32];
-        tree.insert(&leaf).unwrap();
+    // `len() / capacity()`, e.g. for alerting before a tree fills up
+    pub fn fill_ratio(&self) -> f64 {
+        self.next_index as f64 / self.capacity() as f64
+    }
 
-        let current_root = tree.roots[1];
-        assert!(tree.is_known_root(current_root));
-        assert!(!tree.is_known_root([0; 32]));
-        assert!(!tree.is_known_root([2u8; 32]));
+    // How many of the latest roots `is_known_root` accepts
+    pub fn history_size(&self) -> usize {
+        self.roots.len()
     }
 
-    #[test]
-    fn test_root_history() {
-        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+    // The root after the latest insert. Each insert records its root before returning, so under
+    // a lock held across both calls this is the root that insert produced
+    pub fn last_root(&self) -> [u8; 32] {
+        self.roots[self.current_root_index as usize]
+    }
+
+    // How many roots the history holds so far: one per insert plus the initial root, up to
+    // `history_size`
+    pub fn root_history_len(&self) -> usize {
+        self.roots_iter().count()
+    }
+
+    // `(age, root)` for every root in the history, from the current one (age 0) back to the
+    // oldest still kept. Each insert records one root, so only the `next_index + 1` slots
+    // walking back can have been written, and the walk also stops at a slot that is still zero,
+    // as after importing a frontier
+    pub fn roots_iter(&self) -> impl Iterator<Item = (u32, [u8; 32])> + '_ {
+        let len = self.roots.len();
+        let written = self.next_index.saturating_add(1).min(len as u64) as usize;
+        (0..written)
+            .map(move |age| {
+                let slot = (self.current_root_index as usize + len - age) % len;
+                (age as u32, self.roots[slot])
+            })
+            .take_while(|(_, root)| *root != [0; 32])
+    }
+
+    // The newest `n` roots, newest first
+    pub fn recent_roots(&self, n: usize) -> Vec<[u8; 32]> {
+        self.roots_iter().take(n).map(|(_, root)| root).collect()
+    }
+
+    // The roots recorded by inserts that the history still holds, newest first. Unlike
+    // `roots_iter` this leaves out the empty tree's initial root, so it is empty until the
+    // first insert
+    pub fn valid_roots(&self) -> Vec<[u8; 32]> {
+        self.roots_iter()
+            .take(self.next_index.min(self.roots.len() as u64) as usize)
+            .map(|(_, root)| root)
+            .collect()
+    }
+
+    pub fn is_known_root(&self, root: [u8; 32]) -> bool {
+        // Unused history slots are zero, which must never count as a root
+        root != [0; 32]
+            && (self.accept_initial_root || self.next_index > 0)
+            && self.known_roots.contains_key(&root)
+    }
+
+    // The age of `root` as `roots_iter` counts it: 0 for the current root, plus one for every
+    // root recorded since. `None` for the zero root and for roots the history no longer holds.
+    // A root recorded more than once reports its most recent time
+    pub fn find_root(&self, root: &[u8; 32]) -> Option<u32> {
+        if !self.is_known_root(*root) {
+            return None;
+        }
+        let len = self.roots.len();
+        (0..len)
+            .find(|age| self.roots[(self.current_root_index as usize + len - age) % len] == *root)
+            .map(|age| age as u32)
+    }
+
+    // The root in slot `history_index` of the ring, in storage order rather than by age. Slots
+    // not written yet read as zero
+    pub fn root_at(&self, history_index: u32) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        self.roots.get(history_index as usize).copied().ok_or(
+            PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: history_index as u64,
+                len: self.roots.len() as u64,
+            },
+        )
+    }
+
+    // The root that was current `age` roots ago, as `roots_iter` walks them. Fails past the
+    // oldest root the history holds
+    pub fn root_by_age(&self, age: u32) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        self.roots_iter()
+            .nth(age as usize)
+            .map(|(_, root)| root)
+            .ok_or(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: age as u64,
+                len: self.root_history_len() as u64,
+            })
+    }
+}
+
+// The options a tree was built with follow the leaf log behind a tag. Layouts from before they
+// were stored end at the leaf log, or run on into the zeroed rest of an account, so a missing
+// or zero tag reads as the options of `new`. Each tag adds fields to the one before: 1 stores
+// `accept_initial_root`, 2 the zero preset, 3 the endianness and 4 `reject_duplicates`, and
+// fields a tag does not store read as those of `new` too
+const OPTIONS_TAG: u8 = 4;
+// The tag, `accept_initial_root`, the zero preset as its tag and `Custom` leaf, the
+// endianness and `reject_duplicates`
+const OPTIONS_SIZE: usize = 1 + 1 + 1 + 32 + 1 + 1;
+
+struct StoredOptions {
+    accept_initial_root: bool,
+    zero_preset: ZeroPreset,
+    endianness: Endianness,
+    reject_duplicates: bool,
+}
+
+impl Default for StoredOptions {
+    fn default() -> StoredOptions {
+        StoredOptions {
+            accept_initial_root: true,
+            zero_preset: ZeroPreset::Voidify,
+            endianness: Endianness::Big,
+            reject_duplicates: false,
+        }
+    }
+}
+
+impl StoredOptions {
+    fn of(tree: &PoseidonMerkleTree) -> StoredOptions {
+        StoredOptions {
+            accept_initial_root: tree.accept_initial_root,
+            zero_preset: tree.zero_preset(),
+            endianness: tree.endianness,
+            reject_duplicates: tree.reject_duplicates,
+        }
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        OPTIONS_TAG.serialize(writer)?;
+        self.accept_initial_root.serialize(writer)?;
+        let (preset, leaf) = self.zero_preset.to_stored();
+        preset.serialize(writer)?;
+        leaf.serialize(writer)?;
+        (self.endianness == Endianness::Little).serialize(writer)?;
+        self.reject_duplicates.serialize(writer)
+    }
+
+    fn read<R: Read>(reader: &mut R) -> io::Result<StoredOptions> {
+        let tag = read_tag(reader)?;
+        if tag > OPTIONS_TAG {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Unknown tree options tag",
+            ));
+        }
+
+        let mut options = StoredOptions::default();
+        if tag >= 1 {
+            options.accept_initial_root = bool::deserialize_reader(reader)?;
+        }
+        if tag >= 2 {
+            let preset = u8::deserialize_reader(reader)?;
+            let leaf = <[u8; 32]>::deserialize_reader(reader)?;
+            options.zero_preset = ZeroPreset::from_stored(preset, leaf)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Unknown zero preset"))?;
+        }
+        if tag >= 3 && bool::deserialize_reader(reader)? {
+            options.endianness = Endianness::Little;
+        }
+        if tag >= 4 {
+            options.reject_duplicates = bool::deserialize_reader(reader)?;
+        }
+        Ok(options)
+    }
+
+    // Fails with `InvalidLeaf` for a `Custom` leaf outside the field, and as
+    // `set_reject_duplicates` does for a tree without a leaf log that rejects duplicates
+    fn apply(self, tree: &mut PoseidonMerkleTree) -> Result<(), PoseidonMerkleTreeError> {
+        tree.accept_initial_root = self.accept_initial_root;
+        tree.zeros = ZeroChain::new(self.zero_preset)?;
+        tree.endianness = self.endianness;
+        tree.set_reject_duplicates(self.reject_duplicates)
+    }
+}
+
+// Written field by field in declaration order, with `filled_subtrees` as bytes, so the layout
+// is the same as when they were stored that way. Parameters from `new_with_params` are not part
+// of the layout, so such a tree refuses to be written rather than read back as one hashing
+// with the bundled ones
+impl BorshSerialize for PoseidonMerkleTree {
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.serialize_fields(writer)?;
+        self.serialize_options(writer)
+    }
+}
+
+impl BorshDeserialize for PoseidonMerkleTree {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        read_tree(reader)?.map_err(invalid_data)
+    }
+}
+
+// A tree inside another type is written in two parts: its fields where the tree always was,
+// and its options at the end of the outer layout. Outer bytes from before the options were
+// stored then simply end early, and read with the options of `new`
+impl PoseidonMerkleTree {
+    pub(crate) fn serialize_fields<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        if self.has_custom_params() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Trees on custom Poseidon parameters cannot be serialized",
+            ));
+        }
+        self.levels.serialize(writer)?;
+        self.filled_subtrees().serialize(writer)?;
+        self.roots.serialize(writer)?;
+        self.current_root_index.serialize(writer)?;
+        self.next_index.serialize(writer)?;
+        self.leaf_log.serialize(writer)
+    }
+
+    pub(crate) fn serialize_options<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        StoredOptions::of(self).write(writer)
+    }
+
+    pub(crate) fn deserialize_fields<R: Read>(reader: &mut R) -> io::Result<PoseidonMerkleTree> {
+        read_fields(reader)?.map_err(invalid_data)
+    }
+
+    pub(crate) fn deserialize_options<R: Read>(&mut self, reader: &mut R) -> io::Result<()> {
+        StoredOptions::read(reader)?
+            .apply(self)
+            .map_err(invalid_data)
+    }
+}
+
+// Reads the stored fields and rebuilds the tree. The outer error means the bytes are not a tree
+// at all, the inner one that a check on the fields failed, which callers report as its own
+// variant
+fn read_tree<R: Read>(
+    reader: &mut R,
+) -> io::Result<Result<PoseidonMerkleTree, PoseidonMerkleTreeError>> {
+    let tree = read_fields(reader)?;
+    let options = StoredOptions::read(reader)?;
+    Ok(tree.and_then(|mut tree| {
+        options.apply(&mut tree)?;
+        Ok(tree)
+    }))
+}
+
+// `read_tree` without the options
+fn read_fields<R: Read>(
+    reader: &mut R,
+) -> io::Result<Result<PoseidonMerkleTree, PoseidonMerkleTreeError>> {
+    let levels = u32::deserialize_reader(reader)?;
+    let filled_subtrees = Vec::<[u8; 32]>::deserialize_reader(reader)?;
+    let roots = Vec::<[u8; 32]>::deserialize_reader(reader)?;
+    let current_root_index = u32::deserialize_reader(reader)?;
+    let next_index = u64::deserialize_reader(reader)?;
+    let leaf_log = Option::<Vec<[u8; 32]>>::deserialize_reader(reader)?;
+
+    Ok(PoseidonMerkleTree::from_stored(
+        levels,
+        &filled_subtrees,
+        roots,
+        current_root_index,
+        next_index,
+        leaf_log,
+    ))
+}
+
+// The next byte, or 0 at the end of the input
+pub(crate) fn read_tag<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut tag = [0u8; 1];
+    match reader.read(&mut tag)? {
+        0 => Ok(0),
+        _ => Ok(tag[0]),
+    }
+}
+
+// A length-prefixed vector. The prefix is checked with `fits` before any element is read, so
+// bytes claiming more elements than the layout allows are refused with `message` without ever
+// being allocated for
+pub(crate) fn read_bounded<R: Read, T: BorshDeserialize>(
+    reader: &mut R,
+    fits: impl FnOnce(u32) -> bool,
+    message: &'static str,
+) -> io::Result<Vec<T>> {
+    let len = u32::deserialize_reader(reader)?;
+    if !fits(len) {
+        return Err(Error::new(ErrorKind::InvalidData, message));
+    }
+    (0..len).map(|_| T::deserialize_reader(reader)).collect()
+}
+
+// Borsh errors only carry a message without `std`, so the variant is kept as text
+fn invalid_data(error: PoseidonMerkleTreeError) -> Error {
+    Error::new(ErrorKind::InvalidData, error.to_string())
+}
+
+impl PoseidonMerkleTree {
+    // Rebuilds a tree from its stored fields. Only what rebuilding needs is checked here;
+    // `validate` covers the rest. Shared by every format a tree can be read from
+    pub(crate) fn from_stored(
+        levels: u32,
+        filled_subtrees: &[[u8; 32]],
+        roots: Vec<[u8; 32]>,
+        current_root_index: u32,
+        next_index: u64,
+        leaf_log: Option<Vec<[u8; 32]>>,
+    ) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        let filled_subtrees = filled_subtrees
+            .iter()
+            .map(|node| {
+                fr_from_be_bytes(node)
+                    .map_err(|_| PoseidonMerkleTreeError::FilledSubtreeOutsideField)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if current_root_index as usize >= roots.len() {
+            return Err(PoseidonMerkleTreeError::RootIndexOutOfRange);
+        }
+        if leaf_log
+            .as_ref()
+            .is_some_and(|leaf_log| leaf_log.len() as u64 != next_index)
+        {
+            return Err(PoseidonMerkleTreeError::LeafLogMismatch);
+        }
+
+        let leaf_positions = LeafIndex::from_leaves(leaf_log.iter().flatten().copied());
+        let mut tree = PoseidonMerkleTree {
+            levels,
+            filled_subtrees,
+            roots,
+            current_root_index,
+            next_index,
+            leaf_log,
+            leaf_positions,
+            reject_duplicates: false,
+            accept_initial_root: true,
+            known_roots: BTreeMap::new(),
+            hasher: TreeHasher::default(),
+            zeros: ZeroChain::default(),
+            endianness: Endianness::Big,
+        };
+        tree.reindex_roots();
+        Ok(tree)
+    }
+
+    // Like `try_from_slice`, but the tree must also pass `validate`, so inserting into it can
+    // never index out of bounds
+    pub fn try_from_slice_validated(
+        bytes: &[u8],
+    ) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        let mut reader = bytes;
+        let tree =
+            read_tree(&mut reader).map_err(|_| PoseidonMerkleTreeError::InvalidEncoding)??;
+        if !reader.is_empty() {
+            return Err(PoseidonMerkleTreeError::InvalidEncoding);
+        }
+        tree.validate()?;
+        Ok(tree)
+    }
+
+    // Checks that the fields are consistent with each other, as they always are for a tree
+    // built through this API. Worth calling after reading a tree from any untrusted source
+    pub fn validate(&self) -> Result<(), PoseidonMerkleTreeError> {
+        if self.levels == 0 || self.levels as usize > MAX_LEVELS {
+            return Err(PoseidonMerkleTreeError::invalid_levels(self.levels));
+        }
+        if self.filled_subtrees.len() != self.levels as usize {
+            return Err(PoseidonMerkleTreeError::FilledSubtreesMismatch);
+        }
+        if self.roots.is_empty() {
+            return Err(PoseidonMerkleTreeError::InvalidHistorySize);
+        }
+        if self.current_root_index as usize >= self.roots.len() {
+            return Err(PoseidonMerkleTreeError::RootIndexOutOfRange);
+        }
+        if self.next_index > self.capacity() {
+            return Err(PoseidonMerkleTreeError::NextIndexBeyondCapacity);
+        }
+        if self
+            .leaf_log
+            .as_ref()
+            .is_some_and(|leaf_log| leaf_log.len() as u64 != self.next_index)
+        {
+            return Err(PoseidonMerkleTreeError::LeafLogMismatch);
+        }
+        Ok(())
+    }
+
+    // `validate`, and with `deep` also that the frontier hashes up to `last_root` with the
+    // tree's own zero chain and hasher, failing with `FrontierMismatch` otherwise. The deep
+    // check costs a hash per level. An empty tree is instead expected to hold the zero chain
+    // and the initial root `new` sets. A full tree no longer holds its rightmost leaf, so only
+    // the cheap checks run for it
+    pub fn check_consistency(&self, deep: bool) -> Result<(), PoseidonMerkleTreeError> {
+        self.validate()?;
+        if !deep || self.is_full() {
+            return Ok(());
+        }
+
+        if self.next_index == 0 {
+            let levels = self.levels as usize;
+            let pristine = self.filled_subtrees[..] == self.zeros.fr()[..levels]
+                && self.last_root() == self.endianness.convert(self.zeros.bytes(self.levels - 1));
+            if !pristine {
+                return Err(PoseidonMerkleTreeError::FrontierMismatch);
+            }
+            return Ok(());
+        }
+
+        let mut hasher = self.hasher.clone();
+        let mut poseidon = hasher.checkout();
+        let poseidon = &mut *poseidon;
+        // Up the path of the next leaf, which is still empty
+        let mut current_level_hash = self.zeros.fr()[0];
+        for i in 0..self.levels as usize {
+            current_level_hash = if (self.next_index >> i).is_multiple_of(2) {
+                hash_fr(poseidon, current_level_hash, self.zeros.fr()[i])?
+            } else {
+                hash_fr(poseidon, self.filled_subtrees[i], current_level_hash)?
+            };
+        }
+        if self.endianness.convert(fr_to_be_bytes(&current_level_hash)) != self.last_root() {
+            return Err(PoseidonMerkleTreeError::FrontierMismatch);
+        }
+        Ok(())
+    }
+
+    // Reads a tree written before `next_index` became a u64, when it was stored as a u32 and
+    // the layout ended there
+    pub fn try_from_legacy_slice(bytes: &[u8]) -> io::Result<PoseidonMerkleTree> {
+        let mut reader = bytes;
+        let levels = u32::deserialize_reader(&mut reader)?;
+        let filled_subtrees = Vec::<[u8; 32]>::deserialize_reader(&mut reader)?;
+        let roots = Vec::<[u8; 32]>::deserialize_reader(&mut reader)?;
+        let current_root_index = u32::deserialize_reader(&mut reader)?;
+        let next_index = u32::deserialize_reader(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "Not all bytes read"));
+        }
+
+        PoseidonMerkleTree::from_stored(
+            levels,
+            &filled_subtrees,
+            roots,
+            current_root_index,
+            next_index as u64,
+            None,
+        )
+        .map_err(invalid_data)
+    }
+}
+
+fn hash_pair(
+    poseidon: &mut Poseidon<Fr>,
+    left: &[u8; 32],
+    right: &[u8; 32],
+) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+    poseidon
+        .hash_bytes_be(&[left, right])
+        .map_err(PoseidonMerkleTreeError::hash_error)
+}
+
+// Same as the conversion inside `hash_bytes_be`, but without going through a heap-allocated
+// big integer
+fn fr_from_be_bytes(bytes: &[u8; 32]) -> Result<Fr, PoseidonMerkleTreeError> {
+    let mut limbs = [0u64; 4];
+    for (limb, chunk) in limbs.iter_mut().rev().zip(bytes.chunks_exact(8)) {
+        *limb = u64::from_be_bytes(chunk.try_into().unwrap());
+    }
+    Fr::from_bigint(BigInteger256::new(limbs))
+        .ok_or_else(|| PoseidonMerkleTreeError::hash_error("input is outside the field"))
+}
+
+// Poseidon only hashes field elements, so a leaf must be below the BN254 scalar field modulus.
+// Checked before anything is hashed, so a rejected leaf leaves the tree as it was
+pub fn validate_leaf(leaf: &[u8; 32]) -> Result<(), PoseidonMerkleTreeError> {
+    leaf_fr(leaf).map(drop)
+}
+
+fn leaf_fr(leaf: &[u8; 32]) -> Result<Fr, PoseidonMerkleTreeError> {
+    fr_from_be_bytes(leaf).map_err(|_| PoseidonMerkleTreeError::invalid_leaf(leaf))
+}
+
+fn fr_to_be_bytes(value: &Fr) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    for (chunk, limb) in bytes
+        .chunks_exact_mut(8)
+        .zip(value.into_bigint().0.iter().rev())
+    {
+        chunk.copy_from_slice(&limb.to_be_bytes());
+    }
+    bytes
+}
+
+// `ZERO_HASHES` as field elements, so the hashing path does not convert the same bytes on every
+// insert. Debug builds check the constants the first time
+fn zeros_fr() -> &'static [Fr; MAX_LEVELS + 1] {
+    static ZEROS_FR: OnceBox<[Fr; MAX_LEVELS + 1]> = OnceBox::new();
+    ZEROS_FR.get_or_init(|| {
+        debug_assert_eq!(verify_zero_constants(), Ok(()));
+        Box::new(core::array::from_fn(|i| {
+            fr_from_be_bytes(&ZERO_HASHES[i]).expect("zero constants are inside the field")
+        }))
+    })
+}
+
+fn hash_fr(
+    poseidon: &mut Poseidon<Fr>,
+    left: Fr,
+    right: Fr,
+) -> Result<Fr, PoseidonMerkleTreeError> {
+    poseidon
+        .hash(&[left, right])
+        .map_err(PoseidonMerkleTreeError::hash_error)
+}
+
+// Poseidon of two hashes with the same hasher and parameters the trees use, for checking a
+// single step of a path against the tree. Fails with `InvalidLeaf` for an input outside the field
+pub fn hash_left_right(
+    left: &[u8; 32],
+    right: &[u8; 32],
+) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+    let (left, right) = (leaf_fr(left)?, leaf_fr(right)?);
+    let mut poseidon = lock_poseidon();
+    hash_fr(&mut poseidon, left, right).map(|hash| fr_to_be_bytes(&hash))
+}
+
+// The deposit commitment `Poseidon(nullifier, secret)` Tornado-style circuits check a leaf
+// against, hashed and ordered like `hash_left_right`. Fails with `InvalidLeaf` for an input
+// outside the field
+pub fn commitment(
+    nullifier: &[u8; 32],
+    secret: &[u8; 32],
+) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+    hash_left_right(nullifier, secret)
+}
+
+// The withdrawal's `nullifierHash`, circom's one-input Poseidon of `nullifier`. Fails with
+// `InvalidLeaf` for a nullifier outside the field
+pub fn nullifier_hash(nullifier: &[u8; 32]) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+    poseidon_hash(&[nullifier])
+}
+
+// circom's Poseidon of 1 to 12 hashes, on the parameters circomlib uses for that many inputs,
+// as for leaf commitments over several values. Two inputs go through the trees' own hasher like
+// `hash_left_right`. Fails with `InvalidInputCount` for no inputs or more than 12
+pub fn poseidon_hash(inputs: &[&[u8; 32]]) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+    if !(1..=MAX_POSEIDON_INPUTS).contains(&inputs.len()) {
+        return Err(PoseidonMerkleTreeError::InvalidInputCount);
+    }
+    if let [left, right] = inputs {
+        return hash_left_right(left, right);
+    }
+    let inputs = inputs
+        .iter()
+        .map(|input| leaf_fr(input))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let hash = Poseidon::<Fr>::new_circom(inputs.len())
+        .and_then(|mut poseidon| poseidon.hash(&inputs))
+        .map_err(PoseidonMerkleTreeError::hash_error)?;
+    Ok(fr_to_be_bytes(&hash))
+}
+
+// The `Voidify` chain: level 0 is sha("voidify") and every level above hashes two of the one
+// below. Levels above 20 were generated from level 20 the same way; `verify_zero_constants`
+// checks every one
+const ZERO_HASHES: [[u8; 32]; MAX_LEVELS + 1] = [
+    [
+        0x28, 0x94, 0x0d, 0xee, 0xac, 0xd1, 0xca, 0x28, 0x31, 0x33, 0x68, 0x74, 0xe8, 0x74, 0x29,
+        0xdb, 0x0e, 0x72, 0x8a, 0x67, 0xa4, 0x72, 0xb7, 0xac, 0x81, 0x95, 0xc4, 0x3c, 0x2f, 0xb1,
+        0x30, 0x09,
+    ],
+    [
+        0x13, 0x8b, 0xfd, 0xb7, 0x91, 0xd8, 0xba, 0xd9, 0x8a, 0x50, 0xc8, 0x2e, 0xa1, 0xef, 0x62,
+        0x4f, 0xeb, 0x03, 0xed, 0x9b, 0x7b, 0xbd, 0xb3, 0x48, 0x55, 0x1a, 0x6b, 0x34, 0x7f, 0xfd,
+        0x56, 0x1c,
+    ],
+    [
+        0x00, 0x5e, 0xf3, 0xbb, 0xa3, 0x6e, 0x2d, 0x71, 0x45, 0x75, 0xef, 0x75, 0xc6, 0xec, 0x27,
+        0xc6, 0x0e, 0x05, 0x93, 0xfb, 0x7b, 0xd4, 0x01, 0x2a, 0x33, 0x0b, 0xc0, 0x65, 0xfb, 0x79,
+        0x08, 0x37,
+    ],
+    [
+        0x10, 0xc7, 0x03, 0x6d, 0x8a, 0x63, 0xd1, 0x40, 0xd7, 0x7c, 0x6a, 0xc1, 0x21, 0xc2, 0xef,
+        0x50, 0x2c, 0xa8, 0x37, 0x03, 0x91, 0x3d, 0x34, 0x97, 0x48, 0x17, 0x54, 0x31, 0x1c, 0xf8,
+        0x12, 0xa1,
+    ],
+    [
+        0x1e, 0x54, 0xdf, 0x31, 0x58, 0xcf, 0x89, 0x80, 0x2f, 0x13, 0xf7, 0x22, 0x65, 0xf2, 0x6c,
+        0x3f, 0x28, 0x13, 0x91, 0x46, 0x57, 0xcc, 0xe8, 0xfe, 0x1c, 0x68, 0xc8, 0x1c, 0x6f, 0x84,
+        0xb5, 0xe3,
+    ],
+    [
+        0x07, 0xf8, 0x79, 0x07, 0xf4, 0x8e, 0x61, 0x7a, 0x18, 0x4d, 0x93, 0x59, 0x64, 0x50, 0xb3,
+        0xa6, 0x8a, 0x30, 0xc0, 0xdf, 0xdf, 0x93, 0x16, 0x4a, 0x0a, 0xf9, 0x63, 0xdd, 0xcc, 0xc0,
+        0x4c, 0xc7,
+    ],
+    [
+        0x1b, 0xca, 0xbd, 0x63, 0x5e, 0x6f, 0x84, 0x5b, 0x50, 0x39, 0xcb, 0xf8, 0x27, 0xb5, 0x28,
+        0x12, 0x1e, 0xc3, 0x4a, 0x2a, 0x3f, 0x68, 0x0f, 0x27, 0xf8, 0x84, 0x56, 0xc4, 0x76, 0x62,
+        0xec, 0x32,
+    ],
+    [
+        0x03, 0x2d, 0x93, 0x0e, 0x15, 0x6c, 0xce, 0x79, 0x7f, 0xcd, 0x3f, 0x4a, 0x11, 0xdc, 0x41,
+        0x70, 0x31, 0x5f, 0x8f, 0x83, 0x0c, 0xa6, 0xb0, 0xf3, 0xbb, 0x71, 0x1e, 0x53, 0x37, 0xd6,
+        0x77, 0x3d,
+    ],
+    [
+        0x17, 0x0a, 0xbe, 0x49, 0x47, 0xc1, 0x19, 0x5a, 0x40, 0xa4, 0x88, 0x11, 0xe6, 0xb3, 0x62,
+        0xa0, 0xa9, 0xc8, 0x68, 0x57, 0x33, 0xc1, 0x7f, 0x61, 0x50, 0xc1, 0x96, 0xb9, 0x39, 0xfc,
+        0x21, 0xf8,
+    ],
+    [
+        0x03, 0xd9, 0xe6, 0x48, 0xd6, 0x74, 0x27, 0xd0, 0xa6, 0xe0, 0xa3, 0x0a, 0xad, 0x5d, 0x18,
+        0xaf, 0x05, 0xb9, 0xe0, 0x4b, 0x41, 0xb4, 0x98, 0x5f, 0xd4, 0x06, 0x2d, 0xe2, 0x71, 0x1c,
+        0xbe, 0xc1,
+    ],
+    [
+        0x04, 0xa4, 0xfe, 0x12, 0x21, 0xc0, 0xd2, 0x1b, 0x27, 0xb4, 0x9a, 0x23, 0xb7, 0x53, 0x47,
+        0xfe, 0xc6, 0x90, 0x3b, 0xba, 0xd2, 0xf6, 0x12, 0x99, 0xb9, 0x36, 0xbf, 0xb7, 0xb7, 0x83,
+        0xfc, 0xd7,
+    ],
+    [
+        0x14, 0x32, 0xaa, 0x33, 0x5f, 0xcc, 0xae, 0xed, 0xed, 0x95, 0x05, 0xa5, 0xa1, 0x42, 0xe8,
+        0x56, 0x8a, 0xf6, 0x2c, 0xcc, 0x90, 0x81, 0x14, 0xbf, 0xdc, 0xbe, 0x95, 0x6e, 0x11, 0x72,
+        0xad, 0x98,
+    ],
+    [
+        0x18, 0x91, 0x90, 0x59, 0xfd, 0x2a, 0x3d, 0x7b, 0xa6, 0xc4, 0x04, 0x9f, 0x42, 0xb7, 0x7b,
+        0x0e, 0xcc, 0x6a, 0x23, 0x01, 0xe6, 0x65, 0x36, 0x38, 0x7f, 0x11, 0xaa, 0x52, 0x2b, 0x3e,
+        0xd2, 0x7b,
+    ],
+    [
+        0x06, 0x96, 0x2f, 0x22, 0x9c, 0x6f, 0x6e, 0x30, 0x7a, 0x60, 0x22, 0x49, 0x33, 0xcb, 0x0d,
+        0x9c, 0x9b, 0x61, 0xcf, 0x44, 0x2e, 0xd5, 0xb0, 0x36, 0xe9, 0xcf, 0x36, 0x70, 0xa5, 0xaf,
+        0xf8, 0xd2,
+    ],
+    [
+        0x01, 0x82, 0x1e, 0x95, 0xe5, 0x34, 0x93, 0x44, 0x8e, 0x2d, 0x59, 0x9c, 0xb0, 0x45, 0xcd,
+        0x8e, 0x8d, 0x21, 0xf3, 0xd2, 0xd7, 0xe8, 0xac, 0xf5, 0xc9, 0x09, 0x68, 0x1e, 0xe2, 0x0a,
+        0x69, 0x26,
+    ],
+    [
+        0x0e, 0xc5, 0xb2, 0x9a, 0xd4, 0x60, 0x9e, 0xfd, 0x69, 0xbd, 0x92, 0x30, 0xc8, 0x9f, 0x82,
+        0xf3, 0xfc, 0x15, 0x03, 0xf3, 0x8c, 0x21, 0x15, 0x07, 0x3e, 0x82, 0x22, 0x61, 0x91, 0x92,
+        0x62, 0x96,
+    ],
+    [
+        0x16, 0x4c, 0x52, 0x2e, 0xc8, 0xd8, 0xd0, 0x64, 0xe9, 0xac, 0x53, 0x5c, 0x6a, 0x1b, 0x34,
+        0xfc, 0x41, 0xa5, 0x05, 0xd8, 0x70, 0xeb, 0xc0, 0xad, 0x55, 0x16, 0x72, 0x17, 0x1b, 0x75,
+        0xf3, 0x4c,
+    ],
+    [
+        0x25, 0x2a, 0x2a, 0xcf, 0xa2, 0x2c, 0xa0, 0x9d, 0x7f, 0x96, 0x5d, 0x01, 0x5b, 0x01, 0xcf,
+        0x3c, 0xd5, 0x9f, 0xf8, 0x9d, 0x5b, 0x4f, 0x22, 0x95, 0x64, 0xc2, 0x28, 0xf2, 0x50, 0x20,
+        0xed, 0xf1,
+    ],
+    [
+        0x2f, 0x72, 0x9a, 0xb9, 0x99, 0x4d, 0x06, 0xf1, 0xe6, 0xc0, 0x77, 0xc5, 0xea, 0xdb, 0xc4,
+        0x51, 0xe7, 0x21, 0xd0, 0x29, 0x15, 0x9a, 0x30, 0xe4, 0x7e, 0x32, 0xb1, 0x5c, 0xc6, 0xe2,
+        0x8a, 0xb7,
+    ],
+    [
+        0x19, 0xbf, 0x0a, 0x91, 0xf2, 0x85, 0x2d, 0x3a, 0x5b, 0xd3, 0x56, 0x5d, 0x9f, 0x77, 0xe0,
+        0x4f, 0xb6, 0xde, 0x7b, 0xc3, 0x18, 0x75, 0x3f, 0xa5, 0x28, 0x17, 0x00, 0xd7, 0x86, 0xe8,
+        0xab, 0xd1,
+    ],
+    [
+        0x28, 0xc6, 0xd1, 0x55, 0xc4, 0xef, 0x4f, 0x87, 0x09, 0x53, 0x23, 0xe8, 0x83, 0x2e, 0xc0,
+        0x54, 0xfa, 0x7d, 0xab, 0x72, 0xa6, 0xfd, 0x22, 0x95, 0x6b, 0x39, 0xe3, 0xdb, 0x18, 0x40,
+        0x29, 0x6f,
+    ],
+    [
+        0x0f, 0x5c, 0xc4, 0x4a, 0xbf, 0x77, 0xc6, 0x0a, 0x96, 0x13, 0x51, 0x82, 0xa9, 0x79, 0xa1,
+        0x21, 0xbc, 0x83, 0x49, 0xea, 0x3e, 0x77, 0x3b, 0xa8, 0x66, 0xb8, 0x29, 0x6f, 0x45, 0x81,
+        0x15, 0xe2,
+    ],
+    [
+        0x15, 0x92, 0xa8, 0x2a, 0x3b, 0xe3, 0x2f, 0x31, 0x00, 0x76, 0xaa, 0x04, 0x92, 0x4a, 0x69,
+        0x1f, 0x44, 0xd7, 0xdc, 0x8f, 0x44, 0xe3, 0xf6, 0x2e, 0x2a, 0x4a, 0x77, 0x88, 0x7b, 0x31,
+        0x4a, 0xee,
+    ],
+    [
+        0x1f, 0xf7, 0xa9, 0x5f, 0xbd, 0x7f, 0x7f, 0x25, 0xa9, 0xca, 0x82, 0x0d, 0xbc, 0x72, 0x97,
+        0xb0, 0x90, 0xda, 0x04, 0xe3, 0xdb, 0x7d, 0x69, 0x30, 0x93, 0x84, 0xbe, 0x5c, 0xbb, 0x39,
+        0xd4, 0xae,
+    ],
+    [
+        0x04, 0xfd, 0x8c, 0x90, 0xfe, 0x2f, 0x5a, 0x3c, 0xb5, 0x8c, 0xd4, 0x0e, 0xc9, 0x54, 0x4b,
+        0x45, 0x8a, 0xc6, 0x56, 0x4b, 0x02, 0x23, 0x7f, 0x3b, 0x69, 0x93, 0x30, 0xf3, 0xbc, 0xd6,
+        0x87, 0x1b,
+    ],
+    [
+        0x1e, 0x4a, 0x84, 0x48, 0x2b, 0x63, 0x07, 0xc0, 0xb3, 0x3a, 0xd6, 0xe5, 0x41, 0xcb, 0x7c,
+        0xc8, 0x94, 0x6f, 0x20, 0x37, 0xd8, 0x24, 0xb3, 0x0e, 0xe5, 0xc6, 0x99, 0x11, 0xa7, 0x2c,
+        0x66, 0x59,
+    ],
+    [
+        0x22, 0x49, 0xfc, 0xa9, 0xde, 0xc9, 0xcb, 0xa6, 0x9d, 0xe5, 0xc9, 0x8a, 0x46, 0x51, 0x33,
+        0x7d, 0xfa, 0x39, 0xac, 0x82, 0xe2, 0x75, 0x97, 0x1b, 0x29, 0x24, 0x53, 0xa3, 0x32, 0xfe,
+        0x51, 0x55,
+    ],
+    [
+        0x30, 0x56, 0x67, 0xaf, 0x37, 0xbf, 0x24, 0x46, 0x82, 0xb6, 0x88, 0x62, 0x4f, 0x43, 0x34,
+        0x8e, 0x89, 0x05, 0x74, 0x31, 0xcb, 0x57, 0x68, 0xe0, 0xf2, 0x70, 0x5e, 0x53, 0x97, 0xea,
+        0x6a, 0xae,
+    ],
+    [
+        0x07, 0x00, 0x13, 0xd4, 0x8d, 0xbc, 0xee, 0xf5, 0x26, 0x0b, 0xba, 0x3f, 0xc8, 0x61, 0xed,
+        0xe7, 0x63, 0xa1, 0x70, 0xd7, 0x8b, 0x5b, 0xca, 0xbf, 0xff, 0x27, 0x6b, 0x0d, 0x23, 0x15,
+        0x54, 0x04,
+    ],
+    [
+        0x07, 0x50, 0x37, 0x8b, 0x76, 0xe1, 0xe5, 0x07, 0xe0, 0xbe, 0x2a, 0xb8, 0x10, 0x83, 0x19,
+        0x19, 0xa4, 0x61, 0x32, 0x34, 0xff, 0x33, 0x4e, 0x25, 0xb4, 0x9f, 0x80, 0xa9, 0xf8, 0x96,
+        0x7e, 0x66,
+    ],
+    [
+        0x30, 0x26, 0x64, 0xca, 0x7f, 0xac, 0x4c, 0x00, 0xa2, 0x89, 0xb3, 0x68, 0x19, 0xe1, 0xc8,
+        0x0c, 0x22, 0xf1, 0xd8, 0x8a, 0xd5, 0xa3, 0x69, 0x90, 0x74, 0x5b, 0x50, 0x6a, 0x36, 0x7a,
+        0x22, 0x25,
+    ],
+    [
+        0x28, 0xcb, 0x5d, 0x28, 0x44, 0x35, 0x12, 0x11, 0x89, 0x64, 0x1e, 0x42, 0xe8, 0xb1, 0xbe,
+        0x57, 0x30, 0xee, 0x26, 0x5b, 0x37, 0xb2, 0x37, 0x69, 0x56, 0xd8, 0x4a, 0x20, 0x55, 0xd0,
+        0x89, 0x6e,
+    ],
+    [
+        0x0b, 0xa6, 0x5a, 0x91, 0x0c, 0xae, 0x5e, 0x12, 0x9b, 0x49, 0x0d, 0x22, 0xeb, 0xc7, 0xe8,
+        0x4e, 0x53, 0xfb, 0xe8, 0xd5, 0x19, 0xd7, 0x05, 0xb2, 0xf0, 0x93, 0x6f, 0x21, 0x07, 0x30,
+        0x1d, 0xd0,
+    ],
+];
+
+// The zero value of `level` on the default chain, the node a tree holds for an empty subtree
+// of that height. Fails with `InvalidLevels` above `MAX_LEVELS`
+pub fn zero_hash(level: u32) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+    ZERO_HASHES
+        .get(level as usize)
+        .copied()
+        .ok_or(PoseidonMerkleTreeError::invalid_levels(level))
+}
+
+// `zero_hash` of every level from 0 up to and including `level`
+pub fn zero_hashes(level: u32) -> Result<Vec<[u8; 32]>, PoseidonMerkleTreeError> {
+    zero_hash(level)?;
+    Ok(ZERO_HASHES[..=level as usize].to_vec())
+}
+
+#[cfg(test)]
+fn zeros(level: u32) -> [u8; 32] {
+    zero_hash(level).unwrap()
+}
+
+// xorshift64, so tests can use plenty of scattered input that is the same on every run
+#[cfg(test)]
+struct XorShift(u64);
+
+#[cfg(test)]
+impl XorShift {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for chunk in bytes.chunks_exact_mut(8) {
+            chunk.copy_from_slice(&self.next_u64().to_be_bytes());
+        }
+        bytes
+    }
+
+    // `bytes` with the top byte cleared, so it is always inside the field
+    fn leaf(&mut self) -> [u8; 32] {
+        let mut leaf = self.bytes();
+        leaf[0] = 0;
+        leaf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_valid_levels() {
+        let tree = PoseidonMerkleTree::new(5).unwrap();
+        assert_eq!(tree.levels, 5);
+        assert_eq!(tree.filled_subtrees.len(), 5);
+        assert_eq!(tree.roots.len(), ROOT_HISTORY_SIZE);
+        assert_eq!(tree.current_root_index, 0);
+        assert_eq!(tree.next_index, 0);
+        assert_eq!(tree.roots[0], zeros(4)); // Root should be zero hash for level 4
+        assert_eq!(tree.filled_subtrees()[0], zeros(0));
+        assert_eq!(tree.filled_subtrees()[4], zeros(4));
+    }
+
+    #[test]
+    fn test_new_invalid_levels() {
+        let result = PoseidonMerkleTree::new(MAX_LEVELS as u32 + 1);
+        assert_eq!(
+            result,
+            Err(PoseidonMerkleTreeError::invalid_levels(
+                MAX_LEVELS as u32 + 1
+            ))
+        );
+    }
+
+    #[test]
+    fn test_new_levels_boundaries() {
+        assert_eq!(
+            PoseidonMerkleTree::new(0),
+            Err(PoseidonMerkleTreeError::invalid_levels(0))
+        );
+        assert_eq!(
+            FullPoseidonMerkleTree::new(0).unwrap_err(),
+            PoseidonMerkleTreeError::invalid_levels(0)
+        );
+
+        let mut shallow = PoseidonMerkleTree::new(1).unwrap();
+        assert_eq!(shallow.capacity(), 2);
+        assert_eq!(shallow.last_root(), zeros(0));
+        shallow.insert_many(&[[1u8; 32]; 2]).unwrap();
+        assert!(shallow.is_full());
+
+        let mut deep = PoseidonMerkleTree::new(MAX_LEVELS as u32).unwrap();
+        assert_eq!(deep.capacity(), 1u64 << MAX_LEVELS);
+        assert_eq!(deep.last_root(), zeros(MAX_LEVELS as u32 - 1));
+        deep.insert(&[1u8; 32]).unwrap();
+        assert!(deep.is_known_root(deep.last_root()));
+    }
+
+    #[test]
+    fn test_insert_single_leaf() {
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
         let leaf = [1u8; 32];
+        let result = tree.insert(&leaf);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+        assert_eq!(tree.next_index, 1);
+        assert_eq!(tree.current_root_index, 1);
+        assert_eq!(tree.filled_subtrees()[0], leaf);
+        assert_ne!(tree.roots[1], [0; 32]);
+    }
+
+    #[test]
+    fn test_insert_multiple_leaves() {
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        let leaf1 = [1u8; 32];
+        let leaf2 = [2u8; 32];
+
+        tree.insert(&leaf1).unwrap();
+        tree.insert(&leaf2).unwrap();
+
+        assert_eq!(tree.next_index, 2);
+        assert_eq!(tree.current_root_index, 2);
+
+        let mut poseidon = Poseidon::<Fr>::new_circom(2).unwrap();
+        let expected_hash = poseidon.hash_bytes_be(&[&leaf1, &leaf2]).unwrap();
+        assert_eq!(tree.filled_subtrees()[1], expected_hash);
+    }
+
+    #[test]
+    fn test_errors_carry_context() {
+        assert_eq!(
+            PoseidonMerkleTree::new(MAX_LEVELS as u32 + 1)
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "Invalid levels {}, expected 1 to {MAX_LEVELS}",
+                MAX_LEVELS + 1
+            )
+        );
+        let mut tree = PoseidonMerkleTree::new(1).unwrap();
+        tree.insert_many(&[[1u8; 32]; 2]).unwrap();
+        assert_eq!(
+            tree.insert(&[1u8; 32]).unwrap_err().to_string(),
+            "Merkle tree is full at 2 leaves"
+        );
+        assert_eq!(
+            PoseidonMerkleTreeError::invalid_leaf(&[0xff; 32]).to_string(),
+            format!("Leaf 0x{} is not a BN254 field element", "ff".repeat(32))
+        );
+    }
+
+    #[cfg(all(feature = "std", not(feature = "tls-hasher")))]
+    #[test]
+    fn test_poisoned_hasher_lock_is_recovered() {
+        let panicked = std::thread::spawn(|| {
+            let _poseidon = lock_poseidon();
+            panic!("poisoning the shared hasher");
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(POSEIDON.is_poisoned());
+
+        // `FullPoseidonMerkleTree` hashes with the shared hasher
+        let mut full = FullPoseidonMerkleTree::new(3).unwrap();
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        for i in 1..=3u8 {
+            full.insert(&[i; 32]).unwrap();
+            tree.insert(&[i; 32]).unwrap();
+        }
+        assert!(full.is_known_root(tree.last_root()));
+        assert!(verify_frontier(
+            &tree.filled_subtrees(),
+            tree.next_index,
+            &tree.last_root()
+        ));
+    }
+
+    #[test]
+    fn test_check_consistency() {
+        let mut tree = PoseidonMerkleTree::new_with_history(3, 4).unwrap();
+        assert_eq!(tree.check_consistency(true), Ok(()));
+        let mut empty = tree.clone();
+        empty.filled_subtrees[2] = Fr::from(9u64);
+        assert_eq!(
+            empty.check_consistency(true).unwrap_err(),
+            PoseidonMerkleTreeError::FrontierMismatch
+        );
+        for i in 1..=5u8 {
+            tree.insert(&[i; 32]).unwrap();
+        }
+        assert_eq!(tree.check_consistency(true), Ok(()));
+
+        type Corrupt = fn(&mut PoseidonMerkleTree);
+        let corruptions: [(Corrupt, PoseidonMerkleTreeError); 5] = [
+            (
+                |tree| tree.levels = 0,
+                PoseidonMerkleTreeError::invalid_levels(0),
+            ),
+            (
+                |tree| {
+                    tree.filled_subtrees.pop();
+                },
+                PoseidonMerkleTreeError::FilledSubtreesMismatch,
+            ),
+            (
+                |tree| tree.roots.clear(),
+                PoseidonMerkleTreeError::InvalidHistorySize,
+            ),
+            (
+                |tree| tree.current_root_index = 4,
+                PoseidonMerkleTreeError::RootIndexOutOfRange,
+            ),
+            (
+                |tree| tree.next_index = 9,
+                PoseidonMerkleTreeError::NextIndexBeyondCapacity,
+            ),
+        ];
+        for (corrupt, expected) in corruptions {
+            let mut corrupted = tree.clone();
+            corrupt(&mut corrupted);
+            assert_eq!(corrupted.check_consistency(false).unwrap_err(), expected);
+            assert_eq!(corrupted.check_consistency(true).unwrap_err(), expected);
+        }
+
+        // Only the deep check hashes, so only it sees a frontier or root that was changed
+        let mismatched: [Corrupt; 3] = [
+            // Five leaves: level 0 holds the fifth leaf, the left sibling of the next one
+            |tree| tree.filled_subtrees[0] = Fr::from(9u64),
+            |tree| tree.roots[tree.current_root_index as usize] = [7; 32],
+            |tree| tree.next_index = 4,
+        ];
+        for corrupt in mismatched {
+            let mut corrupted = tree.clone();
+            corrupt(&mut corrupted);
+            assert_eq!(corrupted.check_consistency(false), Ok(()));
+            assert_eq!(
+                corrupted.check_consistency(true).unwrap_err(),
+                PoseidonMerkleTreeError::FrontierMismatch
+            );
+        }
+
+        for i in 6..=8u8 {
+            tree.insert(&[i; 32]).unwrap();
+        }
+        assert_eq!(tree.check_consistency(true), Ok(()));
+    }
+
+    #[test]
+    fn test_accept_initial_root() {
+        let mut accepting = PoseidonMerkleTree::new(3).unwrap();
+        let mut refusing = PoseidonMerkleTree::builder()
+            .levels(3)
+            .accept_initial_root(false)
+            .build()
+            .unwrap();
+        let empty_root = accepting.last_root();
+        assert_eq!(refusing.last_root(), empty_root);
+        assert!(accepting.accepts_initial_root());
+        assert!(!refusing.accepts_initial_root());
+        assert!(accepting.is_known_root(empty_root));
+        assert!(!refusing.is_known_root(empty_root));
+        assert_eq!(refusing.find_root(&empty_root), None);
+
+        accepting.insert(&[1u8; 32]).unwrap();
+        refusing.insert(&[1u8; 32]).unwrap();
+        let root = refusing.last_root();
+        assert!(accepting.is_known_root(root) && refusing.is_known_root(root));
+        // The empty root is still in history slot 0, and counts as any older root once the
+        // tree has leaves
+        assert_eq!(refusing.root_at(0), Ok(empty_root));
+        assert!(accepting.is_known_root(empty_root));
+        assert!(refusing.is_known_root(empty_root));
+        assert_eq!(refusing.find_root(&empty_root), Some(1));
+
+        refusing.reset().unwrap();
+        assert!(!refusing.is_known_root(empty_root));
+        let bytes = borsh::to_vec(&refusing).unwrap();
+        assert_eq!(bytes.len(), refusing.serialized_size());
+        let read = PoseidonMerkleTree::try_from_slice(&bytes).unwrap();
+        assert!(!read.accepts_initial_root());
+        assert!(!read.is_known_root(empty_root));
+        assert_eq!(read, refusing);
+
+        // Bytes from before the options were stored, with or without the zeroed rest of an
+        // account after them, accept the initial root
+        let legacy = &bytes[..bytes.len() - OPTIONS_SIZE];
+        assert!(PoseidonMerkleTree::try_from_slice(legacy)
+            .unwrap()
+            .is_known_root(empty_root));
+        let padded = [legacy, &[0; 8]].concat();
+        let mut reader = padded.as_slice();
+        assert!(PoseidonMerkleTree::deserialize_reader(&mut reader)
+            .unwrap()
+            .accepts_initial_root());
+    }
+
+    #[test]
+    fn test_older_options_tags_still_load() {
+        let mut tree = PoseidonMerkleTree::builder()
+            .levels(3)
+            .accept_initial_root(false)
+            .zero_preset(ZeroPreset::Tornado)
+            .endianness(Endianness::Little)
+            .build()
+            .unwrap();
+        tree.insert(&[1; 32]).unwrap();
+        let bytes = borsh::to_vec(&tree).unwrap();
+        let (fields, options) = bytes.split_at(bytes.len() - OPTIONS_SIZE);
+        assert_eq!(options[0], OPTIONS_TAG);
+
+        // Every tag stores a prefix of the fields that follow it, and the rest read as `new`'s
+        let read = |tag: u8, len: usize| {
+            let bytes = [fields, &[tag], &options[1..1 + len]].concat();
+            PoseidonMerkleTree::try_from_slice(&bytes).unwrap()
+        };
+        let first = read(1, 1);
+        assert!(!first.accepts_initial_root());
+        assert_eq!(first.zero_preset(), ZeroPreset::Voidify);
+        let second = read(2, 34);
+        assert_eq!(second.zero_preset(), ZeroPreset::Tornado);
+        assert_eq!(second.endianness(), Endianness::Big);
+        assert_eq!(read(3, 35).endianness(), Endianness::Little);
+        assert_eq!(read(4, 36), tree);
+
+        let unknown = [fields, &[OPTIONS_TAG + 1], &options[1..]].concat();
+        assert!(PoseidonMerkleTree::try_from_slice(&unknown).is_err());
+    }
+
+    #[test]
+    fn test_find_root_counts_inserts_since() {
+        let mut tree = PoseidonMerkleTree::new_with_history(5, 4).unwrap();
+        let empty = tree.last_root();
+        assert_eq!(tree.find_root(&empty), Some(0));
+        assert_eq!(tree.find_root(&[0; 32]), None);
+
+        let mut roots = vec![empty];
+        for i in 1..=6u8 {
+            tree.insert(&[i; 32]).unwrap();
+            roots.push(tree.last_root());
+
+            for (inserted, root) in roots.iter().enumerate() {
+                let age = roots.len() - 1 - inserted;
+                let expected = (age < 4).then_some(age as u32);
+                assert_eq!(
+                    tree.find_root(root),
+                    expected,
+                    "age {age} after {i} inserts"
+                );
+                assert_eq!(tree.is_known_root(*root), expected.is_some());
+            }
+        }
+        assert_eq!(tree.find_root(&[7; 32]), None);
+    }
+
+    #[test]
+    fn test_root_by_age_wraps_the_ring() {
+        let mut tree = PoseidonMerkleTree::new_with_history(6, 5).unwrap();
+        let mut roots = vec![tree.last_root()];
+        for i in 0..23u8 {
+            assert_eq!(tree.root_by_age(0), Ok(tree.last_root()));
+            assert_eq!(tree.root_at(tree.current_root_index), Ok(tree.last_root()));
+            for age in 0..tree.root_history_len() {
+                assert_eq!(
+                    tree.root_by_age(age as u32),
+                    Ok(roots[roots.len() - 1 - age])
+                );
+            }
+            let held = tree.root_history_len() as u64;
+            assert_eq!(
+                tree.root_by_age(held as u32),
+                Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                    index: held,
+                    len: held
+                })
+            );
+
+            tree.insert(&[i + 1; 32]).unwrap();
+            roots.push(tree.last_root());
+        }
+
+        assert_eq!(tree.root_at(4), Ok(tree.roots[4]));
+        assert_eq!(
+            tree.root_at(5),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 5, len: 5 })
+        );
+    }
+
+    #[test]
+    fn test_failing_hasher_leaves_the_tree_unchanged() {
+        let mut tree = PoseidonMerkleTree::with_leaf_log(3).unwrap();
+        tree.insert_many(&[[1u8; 32], [2u8; 32]]).unwrap();
+        // A three-input hasher refuses every pair
+        tree.hasher = TreeHasher::Custom(
+            Poseidon::<Fr>::new_circom(3).unwrap(),
+            Arc::new(circom_params()),
+        );
+        let before = tree.clone();
+        let filled = tree.filled_subtrees();
+
+        for result in [
+            tree.insert(&[3u8; 32]),
+            tree.insert_pair(&[3u8; 32], &[4u8; 32]),
+        ] {
+            assert!(matches!(result, Err(PoseidonMerkleTreeError::HashError(_))));
+            assert_eq!(tree, before);
+            assert_eq!(tree.filled_subtrees(), filled);
+        }
+    }
+
+    #[test]
+    fn test_merkle_tree_full() {
+        let mut tree = PoseidonMerkleTree::new(2).unwrap();
+        let leaf = [1u8; 32];
+
+        // Insert 4 leaves (2^2 = 4)
+        tree.insert(&leaf).unwrap();
+        tree.insert(&leaf).unwrap();
+        tree.insert(&leaf).unwrap();
+        tree.insert(&leaf).unwrap();
+
+        // Next insert should fail
+        let result = tree.insert(&leaf);
+        assert_eq!(
+            result,
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 4 })
+        );
+    }
+
+    #[test]
+    fn test_is_known_root() {
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        let leaf = [1u8; 32];
+        tree.insert(&leaf).unwrap();
+
+        let current_root = tree.roots[1];
+        assert!(tree.is_known_root(current_root));
+        assert!(!tree.is_known_root([0; 32]));
+        assert!(!tree.is_known_root([2u8; 32]));
+    }
+
+    #[test]
+    fn test_root_history() {
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        let leaf = [1u8; 32];
+
+        // Insert enough leaves to fill some root history
+        for i in 0..5 {
+            tree.insert(&leaf).unwrap();
+            let root = tree.roots[(i + 1) as usize];
+            assert!(tree.is_known_root(root));
+        }
+
+        // Check that older roots are still recognized
+        let first_root = tree.roots[1];
+        assert!(tree.is_known_root(first_root));
+    }
+
+    #[test]
+    fn test_default_tree() {
+        assert!(DEFAULT_LEVELS as usize <= MAX_LEVELS);
+        assert_eq!(
+            PoseidonMerkleTree::default(),
+            PoseidonMerkleTree::new(DEFAULT_LEVELS).unwrap()
+        );
+        assert_eq!(PoseidonMerkleTree::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_fill_introspection() {
+        let mut tree = PoseidonMerkleTree::new(2).unwrap();
+        assert_eq!((tree.len(), tree.capacity(), tree.remaining()), (0, 4, 4));
+        assert!(tree.is_empty() && !tree.is_full());
+        assert_eq!(tree.fill_ratio(), 0.0);
+
+        for i in 1..=4u8 {
+            tree.insert(&[i; 32]).unwrap();
+            assert_eq!(tree.len(), i as u64);
+            assert_eq!(tree.remaining(), 4 - i as u64);
+            assert_eq!(tree.fill_ratio(), i as f64 / 4.0);
+        }
+        assert!(tree.is_full() && !tree.is_empty());
+        assert_eq!(
+            tree.insert(&[5; 32]),
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 4 })
+        );
+
+        let deepest = PoseidonMerkleTree::new(MAX_LEVELS as u32).unwrap();
+        assert_eq!(deepest.capacity(), 1 << MAX_LEVELS);
+        assert_eq!(deepest.remaining(), deepest.capacity());
+
+        // A corrupted count past the capacity still reads as full
+        tree.next_index = 10;
+        assert_eq!(tree.remaining(), 0);
+        assert!(tree.is_full());
+        assert_eq!(
+            tree.insert_pair(&[1; 32], &[2; 32]),
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 4 })
+        );
+    }
+
+    #[test]
+    fn test_next_index_past_capacity_is_refused() {
+        let mut tree = PoseidonMerkleTree::new(2).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+
+        // `next_index` follows `current_root_index` in the Borsh layout
+        let mut bytes = tree.try_to_vec().unwrap();
+        let offset = 4 + (4 + 32 * 2) + (4 + 32 * ROOT_HISTORY_SIZE) + 4;
+        bytes[offset..offset + 8].copy_from_slice(&9u64.to_le_bytes());
+        assert_eq!(
+            PoseidonMerkleTree::try_from_slice_validated(&bytes),
+            Err(PoseidonMerkleTreeError::NextIndexBeyondCapacity)
+        );
+
+        tree.next_index = 9;
+        let before = tree.clone();
+        let full = PoseidonMerkleTreeError::MerkleTreeFull { capacity: 4 };
+        assert_eq!(tree.insert(&[2u8; 32]).unwrap_err(), full);
+        assert_eq!(tree.insert_many(&[[2u8; 32]]).unwrap_err(), full);
+        assert_eq!(tree.insert_batch_atomic(&[[2u8; 32]]).unwrap_err(), full);
+        assert_eq!(tree.try_extend([[2u8; 32]]).unwrap_err(), (0, full));
+        assert_eq!(tree, before);
+        assert_eq!(tree.filled_subtrees(), before.filled_subtrees());
+    }
+
+    #[test]
+    fn test_reset_matches_a_fresh_tree() {
+        let mut tree = PoseidonMerkleTree::new(4).unwrap();
+        for i in 0..9u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        tree.reset().unwrap();
+        assert_eq!(tree, PoseidonMerkleTree::new(4).unwrap());
+        assert_eq!(tree.roots[0], zeros(3));
+        assert!(!tree.is_known_root(
+            PoseidonMerkleTree::from_leaves(4, &[[1; 32]])
+                .unwrap()
+                .last_root()
+        ));
+
+        let mut logged = PoseidonMerkleTree::with_leaf_log(3).unwrap();
+        logged.insert(&[1u8; 32]).unwrap();
+        logged.reset_with_levels(5).unwrap();
+        assert_eq!(logged, PoseidonMerkleTree::with_leaf_log(5).unwrap());
+
+        let mut sized = PoseidonMerkleTree::new_with_history(3, 7).unwrap();
+        sized.insert(&[1u8; 32]).unwrap();
+        sized.reset().unwrap();
+        assert_eq!(sized, PoseidonMerkleTree::new_with_history(3, 7).unwrap());
+
+        let mut tornado = PoseidonMerkleTree::new_with_zero_preset(3, ZeroPreset::Tornado).unwrap();
+        tornado.insert(&[1u8; 32]).unwrap();
+        tornado.reset().unwrap();
+        assert_eq!(
+            tornado,
+            PoseidonMerkleTree::new_with_zero_preset(3, ZeroPreset::Tornado).unwrap()
+        );
+
+        tree.insert(&[1u8; 32]).unwrap();
+        let before = tree.clone();
+        for levels in [0, MAX_LEVELS as u32 + 1] {
+            assert_eq!(
+                tree.reset_with_levels(levels),
+                Err(PoseidonMerkleTreeError::invalid_levels(levels))
+            );
+            assert_eq!(tree, before);
+        }
+    }
+
+    #[test]
+    fn test_last_root_follows_inserts() {
+        let mut tree = PoseidonMerkleTree::new_with_history(4, 5).unwrap();
+        assert_eq!(tree.last_root(), zeros(3));
+        assert_eq!(tree.root_history_len(), 1);
+
+        for i in 0..12u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+            assert_eq!(
+                tree.last_root(),
+                tree.roots[tree.current_root_index as usize]
+            );
+            assert!(tree.is_known_root(tree.last_root()));
+            assert_eq!(tree.root_history_len(), (i as usize + 2).min(5));
+        }
+    }
+
+    #[test]
+    fn test_roots_iter_newest_first() {
+        let mut tree = PoseidonMerkleTree::new_with_history(4, 4).unwrap();
+        assert_eq!(tree.roots_iter().collect::<Vec<_>>(), [(0, zeros(3))]);
+        assert_eq!(tree.recent_roots(3), [zeros(3)]);
+
+        let mut recorded = vec![zeros(3)];
+        for i in 0..11u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+            recorded.insert(0, tree.last_root());
+
+            let expected = recorded
+                .iter()
+                .take(4)
+                .enumerate()
+                .map(|(age, root)| (age as u32, *root))
+                .collect::<Vec<_>>();
+            assert_eq!(tree.roots_iter().collect::<Vec<_>>(), expected);
+            assert!(expected.iter().all(|(_, root)| tree.is_known_root(*root)));
+
+            // Exactly at the wrap boundary the initial root is still the oldest one
+            if i == 2 {
+                assert_eq!(tree.current_root_index, 3);
+                assert_eq!(tree.roots_iter().last(), Some((3, zeros(3))));
+            }
+        }
+        assert_eq!(tree.recent_roots(2), recorded[..2]);
+        assert_eq!(tree.recent_roots(10), recorded[..4]);
+        assert_eq!(tree.recent_roots(0), Vec::<[u8; 32]>::new());
+    }
+
+    #[test]
+    fn test_valid_roots() {
+        let mut tree = PoseidonMerkleTree::new_with_history(4, 3).unwrap();
+        assert!(tree.valid_roots().is_empty());
+
+        let mut recorded = Vec::new();
+        for i in 0..2u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+            recorded.insert(0, tree.last_root());
+        }
+        assert_eq!(tree.valid_roots(), recorded);
+
+        // Wrapped: the oldest slot now holds the newest root
+        for i in 2..7u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+            recorded.insert(0, tree.last_root());
+        }
+        assert_eq!(tree.current_root_index, 1);
+        assert_eq!(tree.valid_roots(), recorded[..3]);
+        assert!(tree
+            .valid_roots()
+            .iter()
+            .all(|root| tree.is_known_root(*root)));
+
+        let bytes = tree.try_to_vec().unwrap();
+        let decoded = PoseidonMerkleTree::try_from_slice(&bytes).unwrap();
+        assert_eq!(decoded.valid_roots(), recorded[..3]);
+    }
+
+    #[test]
+    fn test_evicted_roots_are_forgotten() {
+        let mut tree = PoseidonMerkleTree::new(6).unwrap();
+        let mut roots = vec![tree.roots[0]];
+        for i in 0..30u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+            roots.push(tree.last_root());
+        }
+
+        // Only the newest ROOT_HISTORY_SIZE roots are still in the ring
+        let (evicted, kept) = roots.split_at(roots.len() - ROOT_HISTORY_SIZE);
+        assert!(evicted.iter().all(|root| !tree.is_known_root(*root)));
+        assert!(kept.iter().all(|root| tree.is_known_root(*root)));
+        assert!(!tree.is_known_root([0; 32]));
+
+        let bytes = tree.try_to_vec().unwrap();
+        let mut decoded = PoseidonMerkleTree::try_from_slice(&bytes).unwrap();
+        assert_eq!(decoded, tree);
+        assert!(kept.iter().all(|root| decoded.is_known_root(*root)));
+
+        decoded.insert(&[0x20; 32]).unwrap();
+        assert!(!decoded.is_known_root(kept[0]));
+        assert!(decoded.is_known_root(kept[1]));
+    }
+
+    #[test]
+    fn test_history_size_wraps_around() {
+        for history_size in [1, ROOT_HISTORY_SIZE, 100] {
+            let mut tree = PoseidonMerkleTree::new_with_history(8, history_size).unwrap();
+            assert_eq!(tree.history_size(), history_size);
+
+            let mut roots = vec![tree.roots[0]];
+            for i in 0..history_size as u64 + 5 {
+                let mut leaf = [0u8; 32];
+                leaf[24..].copy_from_slice(&(i + 1).to_be_bytes());
+                tree.insert(&leaf).unwrap();
+                roots.push(tree.last_root());
+
+                // The ring keeps exactly the newest `history_size` roots
+                let (evicted, kept) = roots.split_at(roots.len().saturating_sub(history_size));
+                assert!(evicted.iter().all(|root| !tree.is_known_root(*root)));
+                assert!(kept.iter().all(|root| tree.is_known_root(*root)));
+            }
+            assert_eq!(
+                tree.current_root_index as usize,
+                (history_size + 5) % history_size
+            );
+
+            let bytes = tree.try_to_vec().unwrap();
+            let decoded = PoseidonMerkleTree::try_from_slice(&bytes).unwrap();
+            assert_eq!(decoded.history_size(), history_size);
+            assert_eq!(decoded, tree);
+        }
+    }
+
+    #[test]
+    fn test_size_fits_history() {
+        for history_size in [1, ROOT_HISTORY_SIZE, 100] {
+            let tree =
+                PoseidonMerkleTree::new_with_history(MAX_LEVELS as u32, history_size).unwrap();
+            assert_eq!(
+                tree.try_to_vec().unwrap().len(),
+                PoseidonMerkleTree::size(history_size)
+            );
+        }
+        assert_eq!(
+            PoseidonMerkleTree::SIZE,
+            PoseidonMerkleTree::size(ROOT_HISTORY_SIZE)
+        );
+        assert_eq!(
+            PoseidonMerkleTree::new_with_history(3, 0),
+            Err(PoseidonMerkleTreeError::InvalidHistorySize)
+        );
+    }
+
+    #[test]
+    fn test_history_does_not_follow_depth() {
+        for levels in [5, 12, MAX_LEVELS as u32] {
+            let mut tree = PoseidonMerkleTree::new(levels).unwrap();
+            assert_eq!(tree.history_size(), ROOT_HISTORY_SIZE);
+
+            let mut roots = Vec::new();
+            for i in 0..ROOT_HISTORY_SIZE as u8 + 3 {
+                tree.insert(&[i + 1; 32]).unwrap();
+                roots.push(tree.last_root());
+            }
+            assert_eq!(tree.valid_roots().len(), ROOT_HISTORY_SIZE);
+            assert!(roots[..3].iter().all(|root| !tree.is_known_root(*root)));
+            assert!(roots[3..].iter().all(|root| tree.is_known_root(*root)));
+        }
+
+        // The default tree's layout: depth 20 with 20 roots
+        assert_eq!(PoseidonMerkleTree::max_serialized_size(20), 1342);
+        assert_eq!(
+            PoseidonMerkleTree::new(20)
+                .unwrap()
+                .try_to_vec()
+                .unwrap()
+                .len(),
+            1342
+        );
+    }
+
+    #[test]
+    fn test_depth_32_matches_reference() {
+        use ark_ff::PrimeField;
+        use sha2::{Digest, Sha256};
+
+        // Everything from scratch: the seed, the zero chain and the hasher
+        let mut poseidon = Poseidon::<Fr>::new_circom(2).unwrap();
+        let mut zeros = vec![Fr::from_be_bytes_mod_order(&Sha256::digest(b"voidify"))];
+        for level in 0..32 {
+            let below = zeros[level];
+            zeros.push(poseidon.hash(&[below, below]).unwrap());
+        }
+
+        let leaves: Vec<Fr> = (1..=3u64).map(Fr::from).collect();
+        let left = poseidon.hash(&[leaves[0], leaves[1]]).unwrap();
+        let right = poseidon.hash(&[leaves[2], zeros[0]]).unwrap();
+        let mut expected = poseidon.hash(&[left, right]).unwrap();
+        for zero in &zeros[2..32] {
+            expected = poseidon.hash(&[expected, *zero]).unwrap();
+        }
+
+        assert_eq!(MAX_LEVELS, 32);
+        let mut tree = PoseidonMerkleTree::new(32).unwrap();
+        assert_eq!(tree.capacity(), 1u64 << 32);
+        assert_eq!(tree.last_root(), fr_to_be_bytes(&zeros[31]));
+        for leaf in &leaves {
+            tree.insert(&fr_to_be_bytes(leaf)).unwrap();
+        }
+        assert_eq!(tree.last_root(), fr_to_be_bytes(&expected));
+        assert_eq!(tree.check_consistency(true), Ok(()));
+
+        let bytes = tree.try_to_vec().unwrap();
+        assert_eq!(bytes.len(), PoseidonMerkleTree::max_serialized_size(32));
+        assert_eq!(PoseidonMerkleTree::SIZE, bytes.len());
+        assert_eq!(PoseidonMerkleTree::try_from_slice(&bytes).unwrap(), tree);
+
+        let mut full = FullPoseidonMerkleTree::new(32).unwrap();
+        for leaf in &leaves {
+            full.insert(&fr_to_be_bytes(leaf)).unwrap();
+        }
+        let proof = full.get_proof(2).unwrap();
+        assert_eq!(proof.siblings.len(), 32);
+        assert_eq!(proof.verify(&tree.last_root()), Ok(true));
+        let last = MerkleProof {
+            index: u32::MAX,
+            ..proof
+        };
+        let bytes = last.try_to_vec().unwrap();
+        assert_eq!(MerkleProof::try_from_slice(&bytes).unwrap(), last);
+
+        // The other trees write a node at the root level as well
+        let mut sparse = SparsePoseidonMerkleTree::new(32).unwrap();
+        sparse.insert(u32::MAX, &[1u8; 32]).unwrap();
+        let proof = sparse.prove_inclusion(u32::MAX).unwrap();
+        assert_eq!(proof.verify(&sparse.root()), Ok(true));
+        let mut indexed = IndexedMerkleTree::new(32).unwrap();
+        indexed.insert(&[1u8; 32]).unwrap();
+        let proof = indexed.get_proof(1).unwrap();
+        assert_eq!(proof.verify(&indexed.root()), Ok(true));
+    }
+
+    #[test]
+    fn test_serialized_size_matches_borsh() {
+        for levels in [1, 3, 8, MAX_LEVELS as u32] {
+            let mut tree = PoseidonMerkleTree::new(levels).unwrap();
+            let mut logged = PoseidonMerkleTree::with_leaf_log(levels).unwrap();
+            for i in 0..2u8 {
+                tree.insert(&[i + 1; 32]).unwrap();
+                logged.insert(&[i + 1; 32]).unwrap();
+            }
+
+            let len = tree.try_to_vec().unwrap().len();
+            assert_eq!(len, tree.serialized_size());
+            assert_eq!(len, PoseidonMerkleTree::max_serialized_size(levels));
+            assert!(len <= PoseidonMerkleTree::SIZE);
+            assert_eq!(logged.try_to_vec().unwrap().len(), logged.serialized_size());
+        }
+
+        let tree = PoseidonMerkleTree::new_with_history(4, 50).unwrap();
+        assert_eq!(tree.try_to_vec().unwrap().len(), tree.serialized_size());
+    }
+
+    #[test]
+    fn test_unwritten_root_slots_never_match() {
+        let mut tree = PoseidonMerkleTree::new(4).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+
+        // Slot 5 has not been written after a single insert
+        let stale = [7u8; 32];
+        let mut forged = tree.clone();
+        forged.roots[5] = stale;
+        let bytes = forged.try_to_vec().unwrap();
+        let decoded = PoseidonMerkleTree::try_from_slice(&bytes).unwrap();
+        assert!(!decoded.is_known_root(stale));
+        assert_eq!(decoded, tree);
+
+        let mut fixed = PoseidonMerkleTreeConst::<4>::new();
+        fixed.insert(&[1u8; 32]).unwrap();
+        fixed.roots[5] = stale;
+        assert!(!fixed.is_known_root(stale));
+        assert!(!PoseidonMerkleTree::from(fixed).is_known_root(stale));
+
+        let mut shifted = tree.clone();
+        shifted.current_root_index = ROOT_HISTORY_SIZE as u32;
+        let bytes = shifted.try_to_vec().unwrap();
+        assert!(PoseidonMerkleTree::try_from_slice(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_zeros_fr_round_trip() {
+        for i in 0..=MAX_LEVELS as u32 {
+            assert_eq!(fr_to_be_bytes(&zeros_fr()[i as usize]), zeros(i));
+        }
+    }
+
+    #[test]
+    fn test_hash_left_right_rebuilds_a_root() {
+        let leaves: Vec<[u8; 32]> = (1..=5u8).map(|i| [i; 32]).collect();
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        for (count, leaf) in leaves.iter().enumerate() {
+            tree.insert(leaf).unwrap();
+
+            let mut nodes = leaves[..=count].to_vec();
+            for level in 0..3 {
+                if !nodes.len().is_multiple_of(2) {
+                    nodes.push(zero_hash(level).unwrap());
+                }
+                nodes = nodes
+                    .chunks(2)
+                    .map(|pair| hash_left_right(&pair[0], &pair[1]).unwrap())
+                    .collect();
+            }
+            assert_eq!(nodes, [tree.last_root()], "after {} leaves", count + 1);
+        }
+
+        let mut outside = [0xffu8; 32];
+        assert_eq!(
+            hash_left_right(&outside, &leaves[0]),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&outside))
+        );
+        outside[0] = 0;
+        assert_eq!(
+            hash_left_right(&leaves[0], &outside),
+            hash_pair(&mut lock_poseidon(), &leaves[0], &outside)
+        );
+    }
+
+    #[test]
+    fn test_commitment_matches_circomlibjs() {
+        let (nullifier, secret) = (
+            fr_to_be_bytes(&Fr::from(1u64)),
+            fr_to_be_bytes(&Fr::from(2u64)),
+        );
+        // circomlibjs `poseidon([1, 2])` and `poseidon([0, 0])`
+        let expected =
+            decode_hash("0x115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a")
+                .unwrap();
+        assert_eq!(commitment(&nullifier, &secret), Ok(expected));
+        assert_eq!(
+            commitment(&[0; 32], &[0; 32]),
+            Ok(
+                decode_hash("0x2098f5fb9e239eab3ceac3f27b81e481dc3124d55ffed523a839ee8446b64864")
+                    .unwrap()
+            )
+        );
+        assert_ne!(commitment(&secret, &nullifier), Ok(expected));
+
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        let mut check = PoseidonMerkleTree::new(3).unwrap();
+        tree.insert(&[7u8; 32]).unwrap();
+        check.insert(&[7u8; 32]).unwrap();
+        assert_eq!(tree.insert_commitment(&nullifier, &secret), Ok(1));
+        check.insert(&expected).unwrap();
+        assert_eq!(tree, check);
+
+        let mut little = PoseidonMerkleTree::builder()
+            .levels(3)
+            .endianness(Endianness::Little)
+            .build()
+            .unwrap();
+        let mut little_check = little.clone();
+        let le = |bytes: [u8; 32]| Endianness::Little.convert(bytes);
+        little
+            .insert_commitment(&le(nullifier), &le(secret))
+            .unwrap();
+        little_check.insert(&le(expected)).unwrap();
+        assert_eq!(little, little_check);
+
+        let outside = [0xffu8; 32];
+        assert_eq!(
+            commitment(&nullifier, &outside),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&outside))
+        );
+        assert_eq!(
+            tree.insert_commitment(&outside, &secret),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&outside))
+        );
+        assert_eq!(tree.next_index, 2);
+    }
+
+    #[test]
+    fn test_nullifier_hash_matches_circomlibjs() {
+        // circomlibjs `poseidon([1])` and `poseidon([0])`
+        assert_eq!(
+            nullifier_hash(&fr_to_be_bytes(&Fr::from(1u64))),
+            decode_hash("0x29176100eaa962bdc1fe6c654d6a3c130e96a4d1168b33848b897dc502820133")
+        );
+        assert_eq!(
+            nullifier_hash(&[0; 32]),
+            decode_hash("0x2a09a9fd93c590c26b91effbb2499f07e8f7aa12e2b4940a3aed2411cb65e11c")
+        );
+
+        let outside = [0xffu8; 32];
+        assert_eq!(
+            nullifier_hash(&outside),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&outside))
+        );
+    }
+
+    #[test]
+    fn test_poseidon_hash_matches_circomlibjs() {
+        let inputs: Vec<[u8; 32]> = (1..=3)
+            .map(|i| fr_to_be_bytes(&Fr::from(i as u64)))
+            .collect();
+        let inputs: Vec<&[u8; 32]> = inputs.iter().collect();
+        // circomlibjs `poseidon([1])`, `poseidon([1, 2])` and `poseidon([1, 2, 3])`
+        for (count, expected) in [
+            "0x29176100eaa962bdc1fe6c654d6a3c130e96a4d1168b33848b897dc502820133",
+            "0x115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a",
+            "0x0e7732d89e6939c0ff03d5e58dab6302f3230e269dc5b968f725df34ab36d732",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            assert_eq!(
+                poseidon_hash(&inputs[..=count]),
+                Ok(decode_hash(expected).unwrap()),
+                "{} inputs",
+                count + 1
+            );
+        }
+        assert_eq!(
+            poseidon_hash(&inputs[..2]),
+            hash_left_right(inputs[0], inputs[1])
+        );
+
+        let many = [inputs[0]; MAX_POSEIDON_INPUTS + 1];
+        assert!(poseidon_hash(&many[..MAX_POSEIDON_INPUTS]).is_ok());
+        for count in [0, MAX_POSEIDON_INPUTS + 1] {
+            assert_eq!(
+                poseidon_hash(&many[..count]),
+                Err(PoseidonMerkleTreeError::InvalidInputCount)
+            );
+        }
+        assert_eq!(
+            poseidon_hash(&[inputs[0], &[0xff; 32], inputs[1]]),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&[0xff; 32]))
+        );
+    }
+
+    #[test]
+    fn test_zero_hash_bounds() {
+        assert_eq!(
+            zero_hash(0),
+            Ok(PoseidonMerkleTree::new(1).unwrap().last_root())
+        );
+        let top = zero_hash(MAX_LEVELS as u32).unwrap();
+        let mut poseidon = lock_poseidon();
+        let below = zero_hash(MAX_LEVELS as u32 - 1).unwrap();
+        assert_eq!(hash_pair(&mut poseidon, &below, &below), Ok(top));
+        assert_eq!(
+            zero_hash(MAX_LEVELS as u32 + 1),
+            Err(PoseidonMerkleTreeError::invalid_levels(
+                MAX_LEVELS as u32 + 1
+            ))
+        );
+
+        assert_eq!(zero_hashes(0), Ok(vec![zero_hash(0).unwrap()]));
+        let chain = zero_hashes(MAX_LEVELS as u32).unwrap();
+        assert_eq!(chain.len(), MAX_LEVELS + 1);
+        assert_eq!(chain[MAX_LEVELS], top);
+        assert_eq!(
+            zero_hashes(MAX_LEVELS as u32 + 1),
+            Err(PoseidonMerkleTreeError::invalid_levels(
+                MAX_LEVELS as u32 + 1
+            ))
+        );
+    }
+
+    #[test]
+    fn test_insert_matches_byte_hashing() {
+        // The insert path as it was before the running hash became a field element
+        let mut poseidon = new_poseidon();
+        let mut filled_subtrees: Vec<[u8; 32]> = (0..8).map(zeros).collect();
+
+        let mut tree = PoseidonMerkleTree::new(8).unwrap();
+        for index in 0..100u32 {
+            let mut leaf = [0u8; 32];
+            leaf[1..5].copy_from_slice(&index.wrapping_mul(0x9e37_79b9).to_be_bytes());
+            leaf[31] = index as u8;
+
+            let mut current_index = index;
+            let mut current_level_hash = leaf;
+            for i in 0..8 {
+                let (left, right) = if current_index.is_multiple_of(2) {
+                    (current_level_hash, zeros(i))
+                } else {
+                    (filled_subtrees[i as usize], current_level_hash)
+                };
+                current_level_hash = poseidon.hash_bytes_be(&[&left, &right]).unwrap();
+                filled_subtrees[i as usize] = left;
+                current_index /= 2;
+            }
 
-        // Insert enough leaves to fill some root history
-        for i in 0..5 {
             tree.insert(&leaf).unwrap();
-            let root = tree.roots[(i + 1) as usize];
+            assert_eq!(tree.last_root(), current_level_hash);
+            assert_eq!(tree.filled_subtrees(), filled_subtrees);
+        }
+    }
+
+    #[test]
+    fn test_insert_rejects_leaf_outside_field() {
+        let mut tree = PoseidonMerkleTree::new(4).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        tree.insert(&[2u8; 32]).unwrap();
+        let before = tree.clone();
+
+        assert_eq!(
+            tree.insert(&[0xff; 32]),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&[0xff; 32]))
+        );
+        assert_eq!(tree, before);
+        assert_eq!(
+            tree.insert_pair(&[3u8; 32], &[0xff; 32]),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&[0xff; 32]))
+        );
+        assert_eq!(tree, before);
+
+        assert_eq!(validate_leaf(&[1u8; 32]), Ok(()));
+        assert_eq!(validate_leaf(&zeros(0)), Ok(()));
+        assert_eq!(
+            validate_leaf(&[0xff; 32]),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&[0xff; 32]))
+        );
+    }
+
+    #[test]
+    fn test_wire_format_unchanged() {
+        // Captured while `filled_subtrees` was still stored as bytes
+        let fixtures: [(&[u8], PoseidonMerkleTree); 2] = [
+            (
+                include_bytes!("testdata/tree_levels3_5_leaves.bin"),
+                PoseidonMerkleTree::new(3).unwrap(),
+            ),
+            (
+                include_bytes!("testdata/tree_levels3_5_leaves_log.bin"),
+                PoseidonMerkleTree::with_leaf_log(3).unwrap(),
+            ),
+        ];
+
+        for (bytes, mut expected) in fixtures {
+            for i in 1..=5u8 {
+                expected.insert(&[i; 32]).unwrap();
+            }
+
+            let tree = PoseidonMerkleTree::try_from_slice(bytes).unwrap();
+            assert_eq!(tree, expected);
+            // Written again with the options after the leaf log
+            let written = tree.try_to_vec().unwrap();
+            assert_eq!(&written[..bytes.len()], bytes);
+            assert_eq!(written.len(), bytes.len() + OPTIONS_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_read_rejects_subtree_outside_field() {
+        let mut bytes = PoseidonMerkleTree::new(3).unwrap().try_to_vec().unwrap();
+        // The first filled subtree follows the levels and the vector length
+        bytes[8..40].copy_from_slice(&[0xff; 32]);
+        assert!(PoseidonMerkleTree::try_from_slice(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_validated_read_rejects_inconsistent_state() {
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        assert_eq!(
+            PoseidonMerkleTree::try_from_slice_validated(&tree.try_to_vec().unwrap()),
+            Ok(tree.clone())
+        );
+
+        let corrupt = |change: fn(&mut PoseidonMerkleTree)| {
+            let mut corrupted = tree.clone();
+            change(&mut corrupted);
+            PoseidonMerkleTree::try_from_slice_validated(&corrupted.try_to_vec().unwrap())
+        };
+        assert_eq!(
+            corrupt(|tree| tree.levels = 99),
+            Err(PoseidonMerkleTreeError::invalid_levels(99))
+        );
+        assert_eq!(
+            corrupt(|tree| tree.levels = 4),
+            Err(PoseidonMerkleTreeError::FilledSubtreesMismatch)
+        );
+        assert_eq!(
+            corrupt(|tree| tree.roots.clear()),
+            Err(PoseidonMerkleTreeError::RootIndexOutOfRange)
+        );
+        assert_eq!(
+            corrupt(|tree| tree.current_root_index = ROOT_HISTORY_SIZE as u32),
+            Err(PoseidonMerkleTreeError::RootIndexOutOfRange)
+        );
+        assert_eq!(
+            corrupt(|tree| tree.next_index = 9),
+            Err(PoseidonMerkleTreeError::NextIndexBeyondCapacity)
+        );
+
+        let bytes = tree.try_to_vec().unwrap();
+        assert_eq!(
+            PoseidonMerkleTree::try_from_slice_validated(&bytes[..bytes.len() - 1]),
+            Err(PoseidonMerkleTreeError::InvalidEncoding)
+        );
+        let mut outside_field = bytes.clone();
+        outside_field[8..40].copy_from_slice(&[0xff; 32]);
+        assert_eq!(
+            PoseidonMerkleTree::try_from_slice_validated(&outside_field),
+            Err(PoseidonMerkleTreeError::FilledSubtreeOutsideField)
+        );
+    }
+
+    #[test]
+    fn test_corrupted_buffers_never_panic() {
+        let mut tree = PoseidonMerkleTree::with_leaf_log(4).unwrap();
+        for i in 0..5u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let bytes = tree.try_to_vec().unwrap();
+
+        // Cut right before the options, the bytes are a valid tree in the older layout
+        for len in 0..bytes.len() {
+            assert_eq!(
+                PoseidonMerkleTree::try_from_slice_validated(&bytes[..len]).is_ok(),
+                len == bytes.len() - OPTIONS_SIZE
+            );
+        }
+
+        // A few random bytes of a valid encoding are overwritten per round
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        let mut next = || rng.next_u64();
+        for _ in 0..500 {
+            let mut corrupted = bytes.clone();
+            for _ in 0..1 + next() % 3 {
+                let position = next() as usize % corrupted.len();
+                corrupted[position] = next() as u8;
+            }
+
+            let _ = PoseidonMerkleTree::try_from_slice(&corrupted);
+            if let Ok(mut decoded) = PoseidonMerkleTree::try_from_slice_validated(&corrupted) {
+                let _ = decoded.insert(&[0x20; 32]);
+                let _ = decoded.is_known_root(decoded.last_root());
+                assert_eq!(
+                    decoded.try_to_vec().unwrap().len(),
+                    decoded.serialized_size()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_insert_fr_matches_byte_insert() {
+        let mut bytes_tree = PoseidonMerkleTree::with_leaf_log(5).unwrap();
+        let mut fr_tree = PoseidonMerkleTree::with_leaf_log(5).unwrap();
+
+        for i in 0..12u64 {
+            let value = Fr::from(i * 1_000_003 + 7);
+            bytes_tree.insert(&fr_to_be_bytes(&value)).unwrap();
+            // Mixing both forms on one tree gives the same result
+            if i % 2 == 0 {
+                fr_tree.insert_fr(value).unwrap();
+            } else {
+                fr_tree.insert(&fr_to_be_bytes(&value)).unwrap();
+            }
+        }
+        assert_eq!(fr_tree, bytes_tree);
+
+        let root = bytes_tree.root_fr().unwrap();
+        assert_eq!(fr_to_be_bytes(&root), bytes_tree.last_root());
+        assert!(fr_tree.is_known_root_fr(root));
+        assert!(!fr_tree.is_known_root_fr(root + Fr::from(1u64)));
+    }
+
+    #[test]
+    fn test_insert_many_matches_single_inserts() {
+        let leaves: Vec<[u8; 32]> = (0..12u8).map(|i| [i + 1; 32]).collect();
+
+        let mut batched = PoseidonMerkleTree::new(4).unwrap();
+        assert_eq!(
+            batched.insert_many(&leaves[..5]).unwrap(),
+            vec![0, 1, 2, 3, 4]
+        );
+        assert_eq!(
+            batched.insert_many(&leaves[5..]).unwrap(),
+            (5..12).collect::<Vec<u64>>()
+        );
+        assert!(batched.insert_many(&[]).unwrap().is_empty());
+
+        let mut single = PoseidonMerkleTree::new(4).unwrap();
+        for leaf in &leaves {
+            single.insert(leaf).unwrap();
+        }
+        assert_eq!(batched, single);
+    }
+
+    #[test]
+    fn test_insert_many_rejects_oversized_batch() {
+        let mut tree = PoseidonMerkleTree::new(2).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        let before = tree.clone();
+
+        assert_eq!(
+            tree.insert_many(&[[2u8; 32]; 4]),
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 4 })
+        );
+        assert_eq!(tree, before);
+        assert_eq!(tree.insert_many(&[[2u8; 32]; 3]).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_insert_with_root() {
+        let mut tree = PoseidonMerkleTree::new(4).unwrap();
+        let mut expected = tree.clone();
+        for i in 0..10u64 {
+            let (index, root) = tree.insert_with_root(&[i as u8 + 1; 32]).unwrap();
+            assert_eq!(index, i);
+            assert_eq!(root, tree.last_root());
             assert!(tree.is_known_root(root));
+
+            expected.insert(&[i as u8 + 1; 32]).unwrap();
+            assert_eq!(tree, expected);
         }
+        assert!(PoseidonMerkleTree::new(1)
+            .unwrap()
+            .insert_with_root(&[0xff; 32])
+            .is_err());
+    }
 
-        // Check that older roots are still recognized
-        let first_root = tree.roots[1];
-        assert!(tree.is_known_root(first_root));
+    #[test]
+    fn test_insert_batch_atomic() {
+        let mut tree = PoseidonMerkleTree::with_leaf_log(3).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        let before = tree.clone();
+        let bytes = tree.try_to_vec().unwrap();
+
+        let poisoned = [[2u8; 32], [3u8; 32], [0xff; 32], [4u8; 32]];
+        assert_eq!(
+            tree.insert_batch_atomic(&poisoned),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&[0xff; 32]))
+        );
+        assert_eq!(tree, before);
+        assert_eq!(tree.try_to_vec().unwrap(), bytes);
+
+        assert_eq!(
+            tree.insert_batch_atomic(&[[2u8; 32]; 8]),
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 8 })
+        );
+        assert_eq!(tree.try_to_vec().unwrap(), bytes);
+
+        let mut expected = before.clone();
+        let leaves = [[2u8; 32], [3u8; 32], [4u8; 32]];
+        assert_eq!(tree.insert_batch_atomic(&leaves), Ok(vec![1, 2, 3]));
+        assert_eq!(expected.insert_many(&leaves), Ok(vec![1, 2, 3]));
+        assert_eq!(tree, expected);
+        assert_eq!(tree.insert_batch_atomic(&[]), Ok(vec![]));
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn test_new_with_leaves_matches_an_insert_loop() {
+        // Past the root history, so seeded roots are evicted like inserted ones
+        for count in [0, 1, 7, ROOT_HISTORY_SIZE + 5, 32] {
+            let leaves: Vec<[u8; 32]> = (0..count as u8).map(|i| [i; 32]).collect();
+            let mut expected = PoseidonMerkleTree::new(5).unwrap();
+            for leaf in &leaves {
+                expected.insert(leaf).unwrap();
+            }
+
+            let tree = PoseidonMerkleTree::new_with_leaves(5, &leaves).unwrap();
+            assert_eq!(tree, expected, "{count} leaves");
+            assert_eq!(tree.try_to_vec().unwrap(), expected.try_to_vec().unwrap());
+            assert_eq!(tree.valid_roots(), expected.valid_roots());
+        }
+        assert_eq!(
+            PoseidonMerkleTree::new_with_leaves(5, &[]).unwrap(),
+            PoseidonMerkleTree::new(5).unwrap()
+        );
+
+        assert_eq!(
+            PoseidonMerkleTree::new_with_leaves(2, &[[1u8; 32]; 5]),
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 4 })
+        );
+        assert_eq!(
+            PoseidonMerkleTree::new_with_leaves(2, &[[1u8; 32], [0xff; 32]]),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&[0xff; 32]))
+        );
+        assert_eq!(
+            PoseidonMerkleTree::new_with_leaves(0, &[]),
+            Err(PoseidonMerkleTreeError::invalid_levels(0))
+        );
+    }
+
+    #[test]
+    fn test_insert_pair_matches_two_inserts() {
+        let mut paired = PoseidonMerkleTree::with_leaf_log(4).unwrap();
+        let mut single = PoseidonMerkleTree::with_leaf_log(4).unwrap();
+
+        for i in (0..16u8).step_by(2) {
+            let (a, b) = ([i + 1; 32], [i + 2; 32]);
+            assert_eq!(paired.insert_pair(&a, &b).unwrap(), i as u64);
+            single.insert(&a).unwrap();
+            single.insert(&b).unwrap();
+
+            assert_eq!(paired.last_root(), single.last_root());
+            assert_eq!(paired.filled_subtrees, single.filled_subtrees);
+        }
+        assert_eq!(paired.leaves(), single.leaves());
+        // One root per pair instead of one per leaf
+        assert_eq!(paired.current_root_index, 8);
+
+        assert_eq!(
+            paired.insert_pair(&[1u8; 32], &[2u8; 32]),
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 16 })
+        );
+    }
+
+    #[test]
+    fn test_insert_pair_rejects_odd_index() {
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        let before = tree.clone();
+
+        assert_eq!(
+            tree.insert_pair(&[2u8; 32], &[3u8; 32]),
+            Err(PoseidonMerkleTreeError::InvalidPairAlignment)
+        );
+        assert_eq!(tree, before);
+    }
+
+    #[test]
+    fn test_trees_insert_concurrently() {
+        let leaves = |tree: u8| {
+            (0..32u8).map(move |i| {
+                let mut leaf = [0u8; 32];
+                leaf[30] = tree;
+                leaf[31] = i + 1;
+                leaf
+            })
+        };
+
+        let expected: Vec<PoseidonMerkleTree> = (0..8u8)
+            .map(|t| {
+                let mut tree = PoseidonMerkleTree::new(6).unwrap();
+                leaves(t).for_each(|leaf| {
+                    tree.insert(&leaf).unwrap();
+                });
+                tree
+            })
+            .collect();
+
+        let mut trees: Vec<PoseidonMerkleTree> = (0..8)
+            .map(|_| PoseidonMerkleTree::new(6).unwrap())
+            .collect();
+        std::thread::scope(|scope| {
+            for (t, tree) in trees.iter_mut().enumerate() {
+                scope.spawn(move || {
+                    leaves(t as u8).for_each(|leaf| {
+                        tree.insert(&leaf).unwrap();
+                    });
+                });
+            }
+        });
+
+        assert_eq!(trees, expected);
+    }
+
+    #[test]
+    fn test_capacity_beyond_u32() {
+        // The capacity check runs before any hashing, so deep trees can be exercised by
+        // placing `next_index` right at the boundary
+        for levels in [31, 32] {
+            let mut tree = PoseidonMerkleTree::new(2).unwrap();
+            tree.levels = levels;
+            tree.next_index = 1u64 << levels;
+            assert_eq!(tree.capacity(), 1u64 << levels);
+            assert_eq!(
+                tree.insert(&[1u8; 32]),
+                Err(PoseidonMerkleTreeError::MerkleTreeFull {
+                    capacity: 1u64 << levels
+                })
+            );
+        }
+
+        let mut tree = PoseidonMerkleTree::new(2).unwrap();
+        for i in 0..4u8 {
+            assert_eq!(tree.insert(&[i + 1; 32]), Ok(i as u64));
+        }
+        assert_eq!(
+            tree.insert(&[5u8; 32]),
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 4 })
+        );
+    }
+
+    #[test]
+    fn test_legacy_layout_migration() {
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        tree.insert(&[2u8; 32]).unwrap();
+
+        // The layout before the move to a u64 `next_index`
+        let mut legacy = Vec::new();
+        legacy.extend(tree.levels.try_to_vec().unwrap());
+        legacy.extend(tree.filled_subtrees().try_to_vec().unwrap());
+        legacy.extend(tree.roots.try_to_vec().unwrap());
+        legacy.extend(tree.current_root_index.try_to_vec().unwrap());
+        legacy.extend((tree.next_index as u32).try_to_vec().unwrap());
+
+        assert!(PoseidonMerkleTree::try_from_slice(&legacy).is_err());
+        let migrated = PoseidonMerkleTree::try_from_legacy_slice(&legacy).unwrap();
+        assert_eq!(migrated, tree);
+        assert_eq!(
+            PoseidonMerkleTree::try_from_slice(&migrated.try_to_vec().unwrap()).unwrap(),
+            tree
+        );
+
+        legacy.push(0);
+        assert!(PoseidonMerkleTree::try_from_legacy_slice(&legacy).is_err());
+    }
+
+    // Only built with `default-features = false`, where every shared hash goes through a
+    // hasher made for that call instead of the global one
+    #[cfg(not(feature = "std"))]
+    #[test]
+    fn test_no_std_public_api() {
+        let mut tree = PoseidonMerkleTree::new(4).unwrap();
+        let mut full = FullPoseidonMerkleTree::new(4).unwrap();
+        for i in 1..=5u8 {
+            assert_eq!(tree.insert(&[i; 32]), full.insert(&[i; 32]));
+        }
+
+        let root = tree.last_root();
+        assert!(tree.is_known_root(root));
+        assert!(full.is_known_root(root));
+
+        let proof = full.get_proof(3).unwrap();
+        assert!(proof.verify(&root).unwrap());
+        assert!(verify_merkle_proof(&proof.leaf, proof.index, &proof.siblings, &root).unwrap());
+
+        let bytes = tree.serialize_versioned().unwrap();
+        assert_eq!(PoseidonMerkleTree::deserialize_versioned(&bytes), Ok(tree));
+    }
+
+    #[cfg(not(feature = "std"))]
+    #[test]
+    fn test_no_std_reports_check_failures() {
+        let mut bytes = PoseidonMerkleTree::new(3).unwrap().try_to_vec().unwrap();
+        // The first filled subtree starts right after `levels` and the vector length
+        bytes[8..40].copy_from_slice(&[0xff; 32]);
+        assert_eq!(
+            PoseidonMerkleTree::try_from_slice_validated(&bytes),
+            Err(PoseidonMerkleTreeError::FilledSubtreeOutsideField)
+        );
+        assert_eq!(
+            PoseidonMerkleTree::try_from_slice(&bytes)
+                .unwrap_err()
+                .to_string(),
+            "Filled subtree is outside the field"
+        );
     }
 }
@@ -0,0 +1,304 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use borsh::maybestd::io::{self, Read};
+use borsh::{BorshDeserialize, BorshSerialize};
+use once_cell::race::OnceBox;
+
+use crate::{
+    hash_pair, lock_poseidon, new_poseidon, read_bounded, PoseidonMerkleTreeError, ZERO_HASHES,
+};
+
+// Every bit of a 32-byte key selects one level of the path
+const KEY_BITS: u32 = 256;
+
+// The `zero_hash` chain continued up to the root of a 256-level tree. Built with its own hasher so
+// it can be forced while the shared hasher is locked
+fn map_zeros() -> &'static [[u8; 32]] {
+    static MAP_ZEROS: OnceBox<Vec<[u8; 32]>> = OnceBox::new();
+    MAP_ZEROS.get_or_init(|| {
+        let mut poseidon = new_poseidon();
+        let mut chain = ZERO_HASHES.to_vec();
+        while chain.len() <= KEY_BITS as usize {
+            let below = chain[chain.len() - 1];
+            chain.push(
+                hash_pair(&mut poseidon, &below, &below).expect("zero chain is in the field"),
+            );
+        }
+        Box::new(chain)
+    })
+}
+
+// Authenticated key/value map over 32-byte keys, where bit `level` of the big-endian key picks
+// the side at that level. Absent keys hold the `zero_hash(0)` leaf, so an empty map always has the
+// same root
+#[derive(Clone, Default, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct PoseidonSparseMap {
+    // Only nodes that differ from the zero chain are stored, keyed by level and the key with
+    // the bits below that level cleared
+    nodes: BTreeMap<(u16, [u8; 32]), [u8; 32]>,
+}
+
+// Path for one key, with siblings equal to the zero chain left out as in `CompressedProof`
+#[derive(Clone, BorshSerialize, Debug, PartialEq)]
+pub struct SparseMapProof {
+    pub key: [u8; 32],
+    pub value: [u8; 32],
+    // Bit `level` is set when the sibling at that level is stored explicitly, numbered like the
+    // key bits
+    pub bitmap: [u8; 32],
+    // The explicit siblings, in ascending level order
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl PoseidonSparseMap {
+    pub fn new() -> PoseidonSparseMap {
+        PoseidonSparseMap::default()
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.node(KEY_BITS, &[0u8; 32])
+    }
+
+    pub fn get(&self, key: &[u8; 32]) -> Option<[u8; 32]> {
+        self.nodes.get(&(0, *key)).copied()
+    }
+
+    // Writing the default `zeros(0)` leaf removes the key
+    pub fn set(&mut self, key: &[u8; 32], value: &[u8; 32]) -> Result<(), PoseidonMerkleTreeError> {
+        let mut poseidon = lock_poseidon();
+
+        // Hash the whole path before writing anything so a failure leaves the map untouched
+        let mut updates = Vec::with_capacity(KEY_BITS as usize + 1);
+        let mut current_level_hash = *value;
+        updates.push(current_level_hash);
+        for level in 0..KEY_BITS {
+            let sibling = self.node(level, &sibling_prefix(key, level));
+            current_level_hash = if key_bit(key, level) == 0 {
+                hash_pair(&mut poseidon, &current_level_hash, &sibling)?
+            } else {
+                hash_pair(&mut poseidon, &sibling, &current_level_hash)?
+            };
+            updates.push(current_level_hash);
+        }
+
+        for (level, node) in updates.into_iter().enumerate() {
+            let slot = (level as u16, prefix(key, level as u32));
+            if node == map_zeros()[level] {
+                self.nodes.remove(&slot);
+            } else {
+                self.nodes.insert(slot, node);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &[u8; 32]) -> Result<(), PoseidonMerkleTreeError> {
+        self.set(key, &ZERO_HASHES[0])
+    }
+
+    // Proves the current value of `key`; for an absent key the proof carries the default
+    // `zero_hash(0)` value and so doubles as an exclusion proof
+    pub fn prove(&self, key: &[u8; 32]) -> SparseMapProof {
+        let mut bitmap = [0u8; 32];
+        let mut siblings = Vec::new();
+        for level in 0..KEY_BITS {
+            let sibling = self.node(level, &sibling_prefix(key, level));
+            if sibling != map_zeros()[level as usize] {
+                bitmap[31 - level as usize / 8] |= 1 << (level % 8);
+                siblings.push(sibling);
+            }
+        }
+
+        SparseMapProof {
+            key: *key,
+            value: self.get(key).unwrap_or(ZERO_HASHES[0]),
+            bitmap,
+            siblings,
+        }
+    }
+
+    fn node(&self, level: u32, prefix: &[u8; 32]) -> [u8; 32] {
+        self.nodes
+            .get(&(level as u16, *prefix))
+            .copied()
+            .unwrap_or(map_zeros()[level as usize])
+    }
+}
+
+impl SparseMapProof {
+    pub fn root(&self) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        let explicit = self
+            .bitmap
+            .iter()
+            .map(|byte| byte.count_ones())
+            .sum::<u32>();
+        if explicit as usize != self.siblings.len() {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+
+        let mut poseidon = lock_poseidon();
+
+        let mut explicit = self.siblings.iter();
+        let mut current_level_hash = self.value;
+        for level in 0..KEY_BITS {
+            let sibling = match key_bit(&self.bitmap, level) {
+                1 => explicit.next().unwrap(),
+                _ => &map_zeros()[level as usize],
+            };
+
+            current_level_hash = if key_bit(&self.key, level) == 0 {
+                hash_pair(&mut poseidon, &current_level_hash, sibling)?
+            } else {
+                hash_pair(&mut poseidon, sibling, &current_level_hash)?
+            };
+        }
+
+        Ok(current_level_hash)
+    }
+
+    pub fn verify(&self, root: &[u8; 32]) -> Result<bool, PoseidonMerkleTreeError> {
+        Ok(self.root()? == *root)
+    }
+}
+
+impl BorshDeserialize for SparseMapProof {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let key = <[u8; 32]>::deserialize_reader(reader)?;
+        let value = <[u8; 32]>::deserialize_reader(reader)?;
+        let bitmap = <[u8; 32]>::deserialize_reader(reader)?;
+
+        let siblings = read_bounded(
+            reader,
+            |len| len == bitmap.iter().map(|byte| byte.count_ones()).sum::<u32>(),
+            "Sparse map proof sibling count does not match its bitmap",
+        )?;
+
+        Ok(SparseMapProof {
+            key,
+            value,
+            bitmap,
+            siblings,
+        })
+    }
+}
+
+fn key_bit(key: &[u8; 32], level: u32) -> u8 {
+    key[31 - level as usize / 8] >> (level % 8) & 1
+}
+
+// The key with every bit below `level` cleared, identifying its ancestor at that level
+fn prefix(key: &[u8; 32], level: u32) -> [u8; 32] {
+    let mut prefix = *key;
+    for bit in 0..level {
+        prefix[31 - bit as usize / 8] &= !(1 << (bit % 8));
+    }
+    prefix
+}
+
+fn sibling_prefix(key: &[u8; 32], level: u32) -> [u8; 32] {
+    let mut sibling = prefix(key, level);
+    sibling[31 - level as usize / 8] ^= 1 << (level % 8);
+    sibling
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{zeros, XorShift};
+
+    fn scattered_keys(count: usize) -> Vec<[u8; 32]> {
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        (0..count).map(|_| rng.bytes()).collect()
+    }
+
+    #[test]
+    fn test_map_zero_chain_extends_zeros() {
+        assert_eq!(map_zeros().len(), KEY_BITS as usize + 1);
+        let mut poseidon = new_poseidon();
+        assert_eq!(
+            hash_pair(&mut poseidon, &zeros(19), &zeros(19)).unwrap(),
+            zeros(20)
+        );
+        assert_eq!(
+            PoseidonSparseMap::new().root(),
+            map_zeros()[KEY_BITS as usize]
+        );
+    }
+
+    #[test]
+    fn test_map_overwrite_and_delete() {
+        let mut map = PoseidonSparseMap::new();
+        let empty_root = map.root();
+        let key = [0xabu8; 32];
+
+        map.set(&key, &[1u8; 32]).unwrap();
+        let first_root = map.root();
+        map.set(&key, &[2u8; 32]).unwrap();
+        assert_eq!(map.get(&key), Some([2u8; 32]));
+        assert_ne!(map.root(), first_root);
+
+        let other = [0x01u8; 32];
+        map.set(&other, &[3u8; 32]).unwrap();
+        map.remove(&key).unwrap();
+        assert_eq!(map.get(&key), None);
+
+        let mut expected = PoseidonSparseMap::new();
+        expected.set(&other, &[3u8; 32]).unwrap();
+        assert_eq!(map, expected);
+
+        map.set(&other, &zeros(0)).unwrap();
+        assert_eq!(map.root(), empty_root);
+        assert_eq!(map, PoseidonSparseMap::new());
+    }
+
+    #[test]
+    fn test_map_proofs_after_scattered_writes() {
+        let keys = scattered_keys(17);
+        let mut map = PoseidonSparseMap::new();
+        for (i, key) in keys[..16].iter().enumerate() {
+            map.set(key, &[i as u8 + 1; 32]).unwrap();
+        }
+        let root = map.root();
+
+        for (i, key) in keys[..16].iter().enumerate() {
+            let proof = map.prove(key);
+            assert_eq!(proof.value, [i as u8 + 1; 32]);
+            assert!(proof.verify(&root).unwrap());
+            // A handful of keys share only short prefixes, so almost every sibling is implied
+            assert!(proof.siblings.len() < 16);
+        }
+
+        let exclusion = map.prove(&keys[16]);
+        assert_eq!(exclusion.value, zeros(0));
+        assert!(exclusion.verify(&root).unwrap());
+
+        let mut forged = map.prove(&keys[3]);
+        forged.value = [42u8; 32];
+        assert!(!forged.verify(&root).unwrap());
+    }
+
+    #[test]
+    fn test_map_proof_borsh_round_trip() {
+        let mut map = PoseidonSparseMap::new();
+        map.set(&[1u8; 32], &[5u8; 32]).unwrap();
+        map.set(&[2u8; 32], &[6u8; 32]).unwrap();
+
+        let proof = map.prove(&[1u8; 32]);
+        let bytes = proof.try_to_vec().unwrap();
+        assert_eq!(SparseMapProof::try_from_slice(&bytes).unwrap(), proof);
+
+        let mut inconsistent = proof.clone();
+        inconsistent.bitmap[0] ^= 0x80;
+        assert_eq!(
+            inconsistent.verify(&map.root()),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+        assert!(SparseMapProof::try_from_slice(&inconsistent.try_to_vec().unwrap()).is_err());
+
+        let bytes = map.try_to_vec().unwrap();
+        assert_eq!(PoseidonSparseMap::try_from_slice(&bytes).unwrap(), map);
+    }
+}
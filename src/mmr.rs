@@ -0,0 +1,345 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use ark_bn254::Fr;
+use borsh::maybestd::io::{self, Error, ErrorKind, Read};
+use borsh::{BorshDeserialize, BorshSerialize};
+use light_poseidon::Poseidon;
+
+use crate::{hash_pair, lock_poseidon, read_bounded, PoseidonMerkleTreeError, ZERO_HASHES};
+
+// Append-only log without a fixed capacity. Leaves are grouped into perfect subtrees, one per
+// set bit of the leaf count, and the root bags their peaks from right to left
+#[derive(Clone, BorshSerialize, Debug, Default, PartialEq)]
+pub struct PoseidonMmr {
+    leaf_count: u64,
+    // nodes[height] holds the roots of every complete subtree of that height, in order
+    nodes: Vec<Vec<[u8; 32]>>,
+}
+
+// Path from a leaf to the peak that covers it, plus every peak of the range it was taken from
+#[derive(Clone, BorshSerialize, Debug, PartialEq)]
+pub struct MmrProof {
+    pub leaf_index: u64,
+    pub leaf: [u8; 32],
+    pub leaf_count: u64,
+    pub siblings: Vec<[u8; 32]>,
+    pub peaks: Vec<[u8; 32]>,
+}
+
+impl PoseidonMmr {
+    pub fn new() -> PoseidonMmr {
+        PoseidonMmr::default()
+    }
+
+    pub fn leaf_count(&self) -> u64 {
+        self.leaf_count
+    }
+
+    // Peaks from the tallest (leftmost) subtree to the shortest
+    pub fn peaks(&self) -> Vec<[u8; 32]> {
+        peak_heights(self.leaf_count)
+            .map(|height| *self.nodes[height as usize].last().unwrap())
+            .collect()
+    }
+
+    // An empty range has no peaks and reports the `zero_hash(0)` leaf
+    pub fn root(&self) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        let mut poseidon = lock_poseidon();
+
+        bag_peaks(&mut poseidon, &self.peaks())
+    }
+
+    pub fn append(&mut self, leaf: &[u8; 32]) -> Result<u64, PoseidonMerkleTreeError> {
+        let mut poseidon = lock_poseidon();
+
+        // Merge equal-height peaks before writing anything so a failure leaves the range untouched
+        let mut merged = vec![*leaf];
+        let mut height = 0;
+        while self.leaf_count >> height & 1 == 1 {
+            let left = self.nodes[height].last().unwrap();
+            let right = merged[height];
+            merged.push(hash_pair(&mut poseidon, left, &right)?);
+            height += 1;
+        }
+
+        for (height, node) in merged.into_iter().enumerate() {
+            match self.nodes.get_mut(height) {
+                Some(level_nodes) => level_nodes.push(node),
+                None => self.nodes.push(vec![node]),
+            }
+        }
+        self.leaf_count += 1;
+
+        Ok(self.leaf_count)
+    }
+
+    pub fn get_proof(&self, leaf_index: u64) -> Result<MmrProof, PoseidonMerkleTreeError> {
+        if leaf_index >= self.leaf_count {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: leaf_index,
+                len: self.leaf_count,
+            });
+        }
+
+        let (_, height) = peak_of(self.leaf_count, leaf_index);
+        let siblings = (0..height)
+            .map(|level| self.nodes[level as usize][((leaf_index >> level) ^ 1) as usize])
+            .collect();
+
+        Ok(MmrProof {
+            leaf_index,
+            leaf: self.nodes[0][leaf_index as usize],
+            leaf_count: self.leaf_count,
+            siblings,
+            peaks: self.peaks(),
+        })
+    }
+
+    // Brings a proof taken from an earlier state up to date. Appends only ever extend the path
+    // to the covering peak, so the stored siblings are kept and checked against the log before
+    // the new ones and the current peaks are added
+    pub fn extend_proof(&self, proof: &MmrProof) -> Result<MmrProof, PoseidonMerkleTreeError> {
+        if proof.leaf_count > self.leaf_count {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: proof.leaf_count,
+                len: self.leaf_count,
+            });
+        }
+
+        let current = self.get_proof(proof.leaf_index)?;
+        if proof.leaf_index >= proof.leaf_count
+            || current.leaf != proof.leaf
+            || !current.siblings.starts_with(&proof.siblings)
+        {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+
+        Ok(current)
+    }
+}
+
+impl MmrProof {
+    // The proof commits to its peaks, so it checks both the path to the covering peak and that
+    // the bagged peaks equal `root`
+    pub fn verify(&self, root: &[u8; 32]) -> Result<bool, PoseidonMerkleTreeError> {
+        if self.leaf_index >= self.leaf_count
+            || self.peaks.len() != self.leaf_count.count_ones() as usize
+        {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+        let (peak, height) = peak_of(self.leaf_count, self.leaf_index);
+        if self.siblings.len() != height as usize {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+
+        let mut poseidon = lock_poseidon();
+
+        let mut current_level_hash = self.leaf;
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            current_level_hash = if (self.leaf_index >> level).is_multiple_of(2) {
+                hash_pair(&mut poseidon, &current_level_hash, sibling)?
+            } else {
+                hash_pair(&mut poseidon, sibling, &current_level_hash)?
+            };
+        }
+
+        Ok(current_level_hash == self.peaks[peak]
+            && bag_peaks(&mut poseidon, &self.peaks)? == *root)
+    }
+}
+
+impl BorshDeserialize for PoseidonMmr {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let leaf_count = u64::deserialize_reader(reader)?;
+        let nodes = Vec::<Vec<[u8; 32]>>::deserialize_reader(reader)?;
+
+        // Each height must hold exactly the complete subtrees formed by `leaf_count` appends
+        let heights = (u64::BITS - leaf_count.leading_zeros()) as usize;
+        let consistent = nodes.len() == heights
+            && nodes
+                .iter()
+                .enumerate()
+                .all(|(height, level_nodes)| level_nodes.len() as u64 == leaf_count >> height);
+        if !consistent {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Stored nodes do not match the leaf count",
+            ));
+        }
+
+        Ok(PoseidonMmr { leaf_count, nodes })
+    }
+}
+
+impl BorshDeserialize for MmrProof {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let leaf_index = u64::deserialize_reader(reader)?;
+        let leaf = <[u8; 32]>::deserialize_reader(reader)?;
+        let leaf_count = u64::deserialize_reader(reader)?;
+
+        let too_long = "MMR proof is longer than any range can produce";
+        let siblings = read_bounded(reader, |len| len <= u64::BITS, too_long)?;
+        let peaks = read_bounded(reader, |len| len <= u64::BITS, too_long)?;
+
+        Ok(MmrProof {
+            leaf_index,
+            leaf,
+            leaf_count,
+            siblings,
+            peaks,
+        })
+    }
+}
+
+// Heights of the peaks for `leaf_count` leaves, tallest first
+fn peak_heights(leaf_count: u64) -> impl Iterator<Item = u32> {
+    (0..u64::BITS)
+        .rev()
+        .filter(move |height| leaf_count >> height & 1 == 1)
+}
+
+// Position among the peaks and height of the subtree covering `leaf_index`
+fn peak_of(leaf_count: u64, leaf_index: u64) -> (usize, u32) {
+    let mut start = 0;
+    for (peak, height) in peak_heights(leaf_count).enumerate() {
+        start += 1 << height;
+        if leaf_index < start {
+            return (peak, height);
+        }
+    }
+    unreachable!("leaf index is below the leaf count")
+}
+
+fn bag_peaks(
+    poseidon: &mut Poseidon<Fr>,
+    peaks: &[[u8; 32]],
+) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+    let Some((last, rest)) = peaks.split_last() else {
+        return Ok(ZERO_HASHES[0]);
+    };
+
+    rest.iter()
+        .rev()
+        .try_fold(*last, |bagged, peak| hash_pair(poseidon, peak, &bagged))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zeros;
+
+    fn leaf(i: u64) -> [u8; 32] {
+        let mut leaf = [0u8; 32];
+        leaf[24..].copy_from_slice(&(i + 1).to_be_bytes());
+        leaf
+    }
+
+    #[test]
+    fn test_mmr_peaks_follow_leaf_count() {
+        let mut mmr = PoseidonMmr::new();
+        assert_eq!(mmr.root(), Ok(zeros(0)));
+
+        mmr.append(&leaf(0)).unwrap();
+        assert_eq!(mmr.root(), Ok(leaf(0)));
+
+        for i in 1..11 {
+            mmr.append(&leaf(i)).unwrap();
+        }
+        // 11 = 8 + 2 + 1
+        assert_eq!(mmr.leaf_count(), 11);
+        assert_eq!(mmr.peaks().len(), 3);
+        assert_eq!(mmr.peaks()[2], leaf(10));
+
+        let mut poseidon = lock_poseidon();
+        let peaks = mmr.peaks();
+        let inner = hash_pair(&mut poseidon, &peaks[1], &peaks[2]).unwrap();
+        let expected = hash_pair(&mut poseidon, &peaks[0], &inner).unwrap();
+        drop(poseidon);
+        assert_eq!(mmr.root(), Ok(expected));
+    }
+
+    #[test]
+    fn test_mmr_old_proofs_verify_against_new_roots() {
+        let mut mmr = PoseidonMmr::new();
+        for i in 0..37 {
+            mmr.append(&leaf(i)).unwrap();
+        }
+        let old_root = mmr.root().unwrap();
+        let old_proofs = [0, 20, 33, 36]
+            .map(|index| mmr.get_proof(index).unwrap())
+            .to_vec();
+        for proof in &old_proofs {
+            assert!(proof.verify(&old_root).unwrap());
+        }
+
+        for i in 37..3000 {
+            mmr.append(&leaf(i)).unwrap();
+        }
+        let new_root = mmr.root().unwrap();
+
+        for proof in &old_proofs {
+            assert!(!proof.verify(&new_root).unwrap());
+            let extended = mmr.extend_proof(proof).unwrap();
+            assert_eq!(
+                extended.siblings[..proof.siblings.len()],
+                proof.siblings[..]
+            );
+            assert!(extended.verify(&new_root).unwrap());
+        }
+
+        for index in (0..3000).step_by(271) {
+            assert!(mmr.get_proof(index).unwrap().verify(&new_root).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_mmr_rejects_bad_proofs() {
+        let mut mmr = PoseidonMmr::new();
+        for i in 0..6 {
+            mmr.append(&leaf(i)).unwrap();
+        }
+        let root = mmr.root().unwrap();
+        assert_eq!(
+            mmr.get_proof(6),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 6, len: 6 })
+        );
+
+        let mut forged = mmr.get_proof(2).unwrap();
+        forged.leaf = leaf(9);
+        assert!(!forged.verify(&root).unwrap());
+        assert_eq!(
+            mmr.extend_proof(&forged),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+
+        let mut truncated = mmr.get_proof(2).unwrap();
+        truncated.peaks.pop();
+        assert_eq!(
+            truncated.verify(&root),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+    }
+
+    #[test]
+    fn test_mmr_borsh_round_trip() {
+        let mut mmr = PoseidonMmr::new();
+        for i in 0..13 {
+            mmr.append(&leaf(i)).unwrap();
+        }
+
+        let bytes = mmr.try_to_vec().unwrap();
+        let decoded = PoseidonMmr::try_from_slice(&bytes).unwrap();
+        assert_eq!(decoded, mmr);
+        assert_eq!(decoded.root(), mmr.root());
+
+        let proof = mmr.get_proof(5).unwrap();
+        let bytes = proof.try_to_vec().unwrap();
+        assert_eq!(MmrProof::try_from_slice(&bytes).unwrap(), proof);
+
+        let mut inconsistent = mmr.clone();
+        inconsistent.leaf_count += 1;
+        let bytes = inconsistent.try_to_vec().unwrap();
+        assert!(PoseidonMmr::try_from_slice(&bytes).is_err());
+    }
+}
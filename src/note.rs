@@ -0,0 +1,252 @@
+use alloc::string::{String, ToString};
+use core::fmt;
+use core::str::FromStr;
+
+#[cfg(feature = "rand")]
+use rand_core::{CryptoRng, RngCore};
+
+use crate::{
+    commitment, decode_hash, encode_hash, nullifier_hash, validate_leaf, PoseidonMerkleTreeError,
+};
+
+// The secrets behind one deposit and the hashes derived from them: `commitment` is the leaf
+// inserted into the tree, `nullifier_hash` what the withdrawal reveals. Written as a note like
+// `voidify-0.1-1-0x<secret>0x<nullifier>`: the protocol, the denomination, the network id and
+// both secrets as big-endian hex
+#[derive(Clone, PartialEq, Eq)]
+pub struct DepositNote {
+    pub protocol: String,
+    pub denomination: String,
+    pub net_id: u64,
+    pub secret: [u8; 32],
+    pub nullifier: [u8; 32],
+    pub commitment: [u8; 32],
+    pub nullifier_hash: [u8; 32],
+}
+
+impl DepositNote {
+    // Derives both hashes from the secrets. Fails with `InvalidLeaf` for a secret outside the
+    // field
+    pub fn from_secrets(
+        protocol: &str,
+        denomination: &str,
+        net_id: u64,
+        secret: [u8; 32],
+        nullifier: [u8; 32],
+    ) -> Result<DepositNote, PoseidonMerkleTreeError> {
+        validate_leaf(&secret)?;
+        Ok(DepositNote {
+            protocol: protocol.to_string(),
+            denomination: denomination.to_string(),
+            net_id,
+            secret,
+            nullifier,
+            commitment: commitment(&nullifier, &secret)?,
+            nullifier_hash: nullifier_hash(&nullifier)?,
+        })
+    }
+
+    // Fresh secrets drawn uniformly from the field, so they never need to be reduced or
+    // rejected later. The denomination must not contain `-` for the note to parse back
+    #[cfg(feature = "rand")]
+    pub fn generate<R: RngCore + CryptoRng>(
+        protocol: &str,
+        denomination: &str,
+        net_id: u64,
+        rng: &mut R,
+    ) -> DepositNote {
+        let secret = random_field_element(rng);
+        let nullifier = random_field_element(rng);
+        DepositNote::from_secrets(protocol, denomination, net_id, secret, nullifier)
+            .expect("sampled secrets are inside the field")
+    }
+}
+
+// Rejection sampling below the modulus. The modulus takes 254 bits, so masking the top two
+// keeps three draws in four
+#[cfg(feature = "rand")]
+fn random_field_element<R: RngCore + CryptoRng>(rng: &mut R) -> [u8; 32] {
+    loop {
+        let mut bytes = [0u8; 32];
+        rng.fill_bytes(&mut bytes);
+        bytes[0] &= 0x3f;
+        if validate_leaf(&bytes).is_ok() {
+            return bytes;
+        }
+    }
+}
+
+impl fmt::Display for DepositNote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}-{}-{}{}",
+            self.protocol,
+            self.denomination,
+            self.net_id,
+            encode_hash(&self.secret),
+            encode_hash(&self.nullifier)
+        )
+    }
+}
+
+// The secrets stay out of logs; the hashes are public anyway
+impl fmt::Debug for DepositNote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DepositNote")
+            .field("protocol", &self.protocol)
+            .field("denomination", &self.denomination)
+            .field("net_id", &self.net_id)
+            .field("commitment", &encode_hash(&self.commitment))
+            .field("nullifier_hash", &encode_hash(&self.nullifier_hash))
+            .finish_non_exhaustive()
+    }
+}
+
+// The protocol may contain `-`, as the fields after it are found from the end. Fails with
+// `InvalidNote` for anything but two `0x`-prefixed 64-digit secrets after a protocol, a
+// denomination and a decimal network id, and with `InvalidLeaf` for a secret outside the field
+impl FromStr for DepositNote {
+    type Err = PoseidonMerkleTreeError;
+
+    fn from_str(note: &str) -> Result<DepositNote, PoseidonMerkleTreeError> {
+        let mut fields = note.rsplitn(4, '-');
+        let (Some(secrets), Some(net_id), Some(denomination), Some(protocol)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(PoseidonMerkleTreeError::InvalidNote);
+        };
+        // `parse` alone would take a leading `+`
+        if protocol.is_empty()
+            || denomination.is_empty()
+            || !net_id.bytes().all(|digit| digit.is_ascii_digit())
+        {
+            return Err(PoseidonMerkleTreeError::InvalidNote);
+        }
+        let net_id = net_id
+            .parse()
+            .map_err(|_| PoseidonMerkleTreeError::InvalidNote)?;
+
+        // `get` rather than slicing, which panics inside a multi-byte character
+        let (Some(secret), Some(nullifier)) = (secrets.get(..66), secrets.get(66..)) else {
+            return Err(PoseidonMerkleTreeError::InvalidNote);
+        };
+        if secrets.len() != 132 || !secret.starts_with("0x") || !nullifier.starts_with("0x") {
+            return Err(PoseidonMerkleTreeError::InvalidNote);
+        }
+        let decode = |hex| decode_hash(hex).map_err(|_| PoseidonMerkleTreeError::InvalidNote);
+
+        DepositNote::from_secrets(
+            protocol,
+            denomination,
+            net_id,
+            decode(secret)?,
+            decode(nullifier)?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note() -> DepositNote {
+        let mut secret = [0u8; 32];
+        secret[31] = 2;
+        let mut nullifier = [0u8; 32];
+        nullifier[31] = 1;
+        DepositNote::from_secrets("voidify", "0.1", 1, secret, nullifier).unwrap()
+    }
+
+    #[test]
+    fn test_note_round_trip() {
+        let note = note();
+        let written = note.to_string();
+        assert_eq!(written.len(), "voidify-0.1-1-".len() + 132);
+        assert!(written.starts_with("voidify-0.1-1-0x00"));
+        assert_eq!(written.parse::<DepositNote>(), Ok(note.clone()));
+        // circomlibjs poseidon([1, 2]), the nullifier on the left
+        assert_eq!(
+            encode_hash(&note.commitment),
+            "0x115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a"
+        );
+        assert_eq!(
+            note.nullifier_hash,
+            nullifier_hash(&note.nullifier).unwrap()
+        );
+
+        let debug = format!("{note:?}");
+        assert!(debug.contains("commitment") && debug.ends_with(", .. }"));
+        assert!(!debug.contains(&encode_hash(&note.secret)[2..]));
+
+        let dashed = DepositNote {
+            protocol: "tornado-eth".to_string(),
+            ..note
+        };
+        assert_eq!(dashed.to_string().parse::<DepositNote>(), Ok(dashed));
+    }
+
+    #[test]
+    fn test_rejects_malformed_notes() {
+        let written = note().to_string();
+        let (head, secrets) = written.split_at(written.len() - 132);
+        let invalid = Err(PoseidonMerkleTreeError::InvalidNote);
+        let malformed = [
+            String::new(),
+            secrets.to_string(),
+            format!("0.1-1-{secrets}"),
+            format!("voidify--1-{secrets}"),
+            format!("voidify-0.1--{secrets}"),
+            format!("voidify-0.1-+1-{secrets}"),
+            format!("voidify-0.1-x-{secrets}"),
+            format!("{written}0"),
+            written[..written.len() - 1].to_string(),
+            format!("{head}{}", secrets.replacen("0x", "", 1)),
+            format!("{head}{}", secrets.replace("0x", "0X")),
+            format!("{head}{}g", &secrets[..131]),
+            format!("{head}{}é", &secrets[..130]),
+            format!("{head}{}é{}", &secrets[..65], &secrets[66..131]),
+        ];
+        for note in malformed {
+            assert_eq!(note.parse::<DepositNote>(), invalid, "{note}");
+        }
+
+        let outside = [0xff; 32];
+        let note = format!("{head}{}{}", encode_hash(&outside), &secrets[66..]);
+        assert_eq!(
+            note.parse::<DepositNote>(),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&outside))
+        );
+        let note = format!("{head}{}{}", &secrets[..66], encode_hash(&outside));
+        assert_eq!(
+            note.parse::<DepositNote>(),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&outside))
+        );
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_generate() {
+        use rand_chacha::rand_core::SeedableRng;
+        use rand_chacha::ChaCha20Rng;
+
+        let mut rng = ChaCha20Rng::seed_from_u64(7);
+        let first = DepositNote::generate("voidify", "1", 5, &mut rng);
+        assert_eq!(first.to_string().parse::<DepositNote>(), Ok(first.clone()));
+        assert_eq!(
+            first,
+            DepositNote::generate("voidify", "1", 5, &mut ChaCha20Rng::seed_from_u64(7))
+        );
+        assert_ne!(first.secret, first.nullifier);
+
+        for _ in 0..200 {
+            let note = DepositNote::generate("voidify", "1", 5, &mut rng);
+            assert_eq!(validate_leaf(&note.secret), Ok(()));
+            assert_eq!(validate_leaf(&note.nullifier), Ok(()));
+            assert_eq!(
+                note.commitment,
+                commitment(&note.nullifier, &note.secret).unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use borsh::maybestd::io::{self, Error, ErrorKind, Read};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{nullifier_hash, validate_leaf, PoseidonMerkleTreeError};
+
+// The nullifier hashes already withdrawn against a commitment tree. Kept sorted, so the Borsh
+// encoding of a set does not depend on the order its nullifiers were spent in
+#[derive(Clone, BorshSerialize, Debug, Default, PartialEq)]
+pub struct NullifierSet {
+    // Most nullifiers the set takes; `None` for no limit
+    capacity: Option<u64>,
+    nullifiers: BTreeSet<[u8; 32]>,
+}
+
+impl NullifierSet {
+    pub fn new() -> NullifierSet {
+        NullifierSet::default()
+    }
+
+    // A set that refuses further nullifiers with `NullifierSetFull` once it holds `capacity`
+    pub fn new_with_capacity(capacity: u64) -> NullifierSet {
+        NullifierSet {
+            capacity: Some(capacity),
+            nullifiers: BTreeSet::new(),
+        }
+    }
+
+    // Marks `nullifier_hash` as spent. Fails with `DoubleSpend` if it already was, with
+    // `NullifierSetFull` for a full set and with `InvalidLeaf` for a hash outside the field,
+    // leaving the set as it was
+    pub fn insert(&mut self, nullifier_hash: &[u8; 32]) -> Result<(), PoseidonMerkleTreeError> {
+        validate_leaf(nullifier_hash)?;
+        if self.contains(nullifier_hash) {
+            return Err(PoseidonMerkleTreeError::DoubleSpend);
+        }
+        if let Some(capacity) = self
+            .capacity
+            .filter(|&capacity| self.len() as u64 >= capacity)
+        {
+            return Err(PoseidonMerkleTreeError::NullifierSetFull { capacity });
+        }
+        self.nullifiers.insert(*nullifier_hash);
+        Ok(())
+    }
+
+    // `insert` of `nullifier_hash(nullifier)`, returning the hash it marked as spent
+    pub fn insert_nullifier(
+        &mut self,
+        nullifier: &[u8; 32],
+    ) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        let hash = nullifier_hash(nullifier)?;
+        self.insert(&hash)?;
+        Ok(hash)
+    }
+
+    pub fn contains(&self, nullifier_hash: &[u8; 32]) -> bool {
+        self.nullifiers.contains(nullifier_hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nullifiers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nullifiers.is_empty()
+    }
+
+    pub fn capacity(&self) -> Option<u64> {
+        self.capacity
+    }
+
+    // In ascending byte order
+    pub fn iter(&self) -> impl Iterator<Item = &[u8; 32]> {
+        self.nullifiers.iter()
+    }
+}
+
+// Only the encoding `serialize` writes is accepted: strictly ascending field elements, no more
+// than the capacity allows
+impl BorshDeserialize for NullifierSet {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let capacity = Option::<u64>::deserialize_reader(reader)?;
+        let nullifiers = Vec::<[u8; 32]>::deserialize_reader(reader)?;
+
+        let canonical = nullifiers.windows(2).all(|pair| pair[0] < pair[1])
+            && capacity.is_none_or(|capacity| nullifiers.len() as u64 <= capacity)
+            && nullifiers.iter().all(|hash| validate_leaf(hash).is_ok());
+        if !canonical {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Nullifiers are not a sorted set of field elements within capacity",
+            ));
+        }
+
+        Ok(NullifierSet {
+            capacity,
+            nullifiers: nullifiers.into_iter().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(i: u8) -> [u8; 32] {
+        nullifier_hash(&[i; 32]).unwrap()
+    }
+
+    #[test]
+    fn test_double_spend_is_refused() {
+        let mut set = NullifierSet::new();
+        assert!(set.is_empty());
+        assert_eq!(set.insert(&hash(1)), Ok(()));
+        assert_eq!(
+            set.insert(&hash(1)),
+            Err(PoseidonMerkleTreeError::DoubleSpend)
+        );
+        assert_eq!(set.insert_nullifier(&[2; 32]), Ok(hash(2)));
+        assert_eq!(
+            set.insert_nullifier(&[2; 32]),
+            Err(PoseidonMerkleTreeError::DoubleSpend)
+        );
+        assert!(set.contains(&hash(1)) && set.contains(&hash(2)));
+        assert!(!set.contains(&hash(3)));
+        assert_eq!(set.len(), 2);
+
+        let outside = [0xff; 32];
+        assert_eq!(
+            set.insert(&outside),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&outside))
+        );
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut set = NullifierSet::new_with_capacity(2);
+        set.insert(&hash(1)).unwrap();
+        set.insert(&hash(2)).unwrap();
+        assert_eq!(
+            set.insert(&hash(3)),
+            Err(PoseidonMerkleTreeError::NullifierSetFull { capacity: 2 })
+        );
+        // A spent nullifier is still reported as such once the set is full
+        assert_eq!(
+            set.insert(&hash(1)),
+            Err(PoseidonMerkleTreeError::DoubleSpend)
+        );
+        assert_eq!(set.capacity(), Some(2));
+        assert_eq!(NullifierSet::new().capacity(), None);
+    }
+
+    #[test]
+    fn test_borsh_is_deterministic() {
+        let mut forward = NullifierSet::new_with_capacity(10);
+        let mut backward = NullifierSet::new_with_capacity(10);
+        for i in 1..=5 {
+            forward.insert(&hash(i)).unwrap();
+            backward.insert(&hash(6 - i)).unwrap();
+        }
+        let bytes = forward.try_to_vec().unwrap();
+        assert_eq!(bytes, backward.try_to_vec().unwrap());
+        assert_eq!(bytes.len(), 9 + 4 + 32 * 5);
+
+        let read = NullifierSet::try_from_slice(&bytes).unwrap();
+        assert_eq!(read, forward);
+        assert!(read.iter().zip(read.iter().skip(1)).all(|(a, b)| a < b));
+        assert_eq!(read.iter().count(), 5);
+    }
+
+    #[test]
+    fn test_rejects_non_canonical_bytes() {
+        let mut set = NullifierSet::new_with_capacity(3);
+        for i in 1..=3 {
+            set.insert(&hash(i)).unwrap();
+        }
+        let bytes = set.try_to_vec().unwrap();
+        // After the capacity and the length prefix
+        let slot = |i: usize| 13 + 32 * i..13 + 32 * (i + 1);
+
+        let mut swapped = bytes.clone();
+        swapped[slot(0)].copy_from_slice(&bytes[slot(1)]);
+        swapped[slot(1)].copy_from_slice(&bytes[slot(0)]);
+
+        let mut duplicated = bytes.clone();
+        duplicated.copy_within(slot(0), slot(1).start);
+
+        let mut over_capacity = bytes.clone();
+        over_capacity[1..9].copy_from_slice(&2u64.to_le_bytes());
+
+        let mut outside = bytes;
+        outside[slot(2)].fill(0xff);
+
+        for corrupted in [swapped, duplicated, over_capacity, outside] {
+            assert!(NullifierSet::try_from_slice(&corrupted).is_err());
+        }
+    }
+}
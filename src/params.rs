@@ -0,0 +1,200 @@
+use alloc::sync::Arc;
+
+use ark_bn254::Fr;
+use light_poseidon::{Poseidon, PoseidonParameters};
+
+use crate::zero_preset::{ZeroChain, ZeroPreset, ZeroValues};
+use crate::{PoseidonMerkleTree, PoseidonMerkleTreeError, TreeHasher};
+
+impl PoseidonMerkleTree {
+    // A tree that hashes with `params` instead of the bundled circom constants, through a hasher
+    // of its own rather than the shared one. The `Voidify` zero chain is hashed up again with
+    // them, so the empty root follows the parameters too. Fails with `InvalidPoseidonParameters`
+    // for anything that is not a two-input Poseidon the round constants fully cover
+    pub fn new_with_params(
+        levels: u32,
+        params: PoseidonParameters<Fr>,
+    ) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        validate_params(&params)?;
+        let params = Arc::new(params);
+        let mut hasher = Poseidon::new(copy_params(&params));
+        let zeros = ZeroChain::with_hasher(ZeroPreset::Voidify, &mut hasher)?;
+
+        let mut tree = PoseidonMerkleTree::builder()
+            .levels(levels)
+            .zero_values(&ZeroValues::from_chain(zeros))
+            .build()?;
+        tree.hasher = TreeHasher::Custom(hasher, params);
+        Ok(tree)
+    }
+
+    // Whether the tree hashes with parameters from `new_with_params`. Those are not stored, so
+    // such a tree cannot be serialized
+    pub fn has_custom_params(&self) -> bool {
+        matches!(self.hasher, TreeHasher::Custom(..))
+    }
+}
+
+// Width 3 is two inputs and the capacity element. Every round reads `width` round constants
+// and half the full rounds come before the partial ones, so anything else would make the
+// hasher index past the constants or hash a different function
+fn validate_params(params: &PoseidonParameters<Fr>) -> Result<(), PoseidonMerkleTreeError> {
+    let rounds = params.full_rounds + params.partial_rounds;
+    if params.width != 3
+        || params.full_rounds == 0
+        || !params.full_rounds.is_multiple_of(2)
+        || params.partial_rounds == 0
+        || params.alpha < 3
+        || params.ark.len() != rounds * params.width
+        || params.mds.len() != params.width
+        || params.mds.iter().any(|row| row.len() != params.width)
+    {
+        return Err(PoseidonMerkleTreeError::InvalidPoseidonParameters);
+    }
+    Ok(())
+}
+
+// `PoseidonParameters` is not `Clone`, and a custom hasher is rebuilt from them on tree clone
+pub(crate) fn copy_params(params: &PoseidonParameters<Fr>) -> PoseidonParameters<Fr> {
+    PoseidonParameters {
+        ark: params.ark.clone(),
+        mds: params.mds.clone(),
+        full_rounds: params.full_rounds,
+        partial_rounds: params.partial_rounds,
+        width: params.width,
+        alpha: params.alpha,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use light_poseidon::PoseidonHasher;
+
+    use super::*;
+    use crate::{circom_params, fr_to_be_bytes, leaf_fr};
+
+    fn leaf(i: u8) -> [u8; 32] {
+        let mut leaf = [0u8; 32];
+        leaf[31] = i;
+        leaf
+    }
+
+    #[test]
+    fn test_bundled_params_match_the_default_tree() {
+        let mut custom = PoseidonMerkleTree::new_with_params(5, circom_params()).unwrap();
+        let mut default = PoseidonMerkleTree::new(5).unwrap();
+        assert_eq!(custom.last_root(), default.last_root());
+        assert_eq!(format!("{:?}", custom.hasher), "Custom");
+
+        for i in 0..6 {
+            custom.insert(&leaf(i)).unwrap();
+            default.insert(&leaf(i)).unwrap();
+            assert_eq!(custom.last_root(), default.last_root());
+        }
+
+        let mut cloned = custom.clone();
+        cloned.insert(&leaf(9)).unwrap();
+        default.insert(&leaf(9)).unwrap();
+        assert_eq!(cloned.last_root(), default.last_root());
+        assert_eq!(format!("{:?}", cloned.hasher), "Custom");
+
+        custom.reset().unwrap();
+        assert_eq!(
+            custom.last_root(),
+            PoseidonMerkleTree::new(5).unwrap().last_root()
+        );
+    }
+
+    #[test]
+    fn test_other_params_change_every_root() {
+        let mut params = circom_params();
+        params.ark[0] += Fr::from(1u64);
+        let mut check = Poseidon::new(copy_params(&params));
+
+        let mut tree = PoseidonMerkleTree::new_with_params(3, params).unwrap();
+        // The empty root of a depth 3 tree is the zero value of level 2
+        let mut zero = leaf_fr(&crate::zeros(0)).unwrap();
+        for _ in 0..2 {
+            zero = check.hash(&[zero, zero]).unwrap();
+        }
+        let root = fr_to_be_bytes(&zero);
+        assert_eq!(tree.last_root(), root);
+        assert_ne!(root, PoseidonMerkleTree::new(3).unwrap().last_root());
+
+        let mut default = PoseidonMerkleTree::new(3).unwrap();
+        tree.insert(&leaf(1)).unwrap();
+        default.insert(&leaf(1)).unwrap();
+        assert_ne!(tree.last_root(), default.last_root());
+    }
+
+    #[test]
+    fn test_custom_params_are_not_serialized() {
+        use borsh::BorshSerialize;
+
+        let mut params = circom_params();
+        params.ark[0] += Fr::from(1u64);
+        let tree = PoseidonMerkleTree::new_with_params(3, params).unwrap();
+        assert!(tree.has_custom_params());
+        assert!(!PoseidonMerkleTree::new(3).unwrap().has_custom_params());
+
+        assert!(tree.try_to_vec().is_err());
+        assert!(tree.serialize_versioned().is_err());
+        let full = crate::FullPoseidonMerkleTree::from_parts(tree, crate::MemoryNodeStore::new());
+        assert!(full.try_to_vec().is_err());
+        // The bundled parameters through `new_with_params` are still custom ones to the tree
+        let bundled = PoseidonMerkleTree::new_with_params(3, circom_params()).unwrap();
+        assert!(bundled.try_to_vec().is_err());
+    }
+
+    #[test]
+    fn test_proof_at_version_hashes_with_the_tree_params() {
+        let mut params = circom_params();
+        params.ark[0] += Fr::from(1u64);
+        let custom = || {
+            let tree = PoseidonMerkleTree::new_with_params(3, copy_params(&params)).unwrap();
+            crate::FullPoseidonMerkleTree::from_parts(tree, crate::MemoryNodeStore::new())
+        };
+
+        let mut tree = custom();
+        let mut at_three = custom();
+        for i in 1..=5u8 {
+            tree.insert(&leaf(i)).unwrap();
+            if i <= 3 {
+                at_three.insert(&leaf(i)).unwrap();
+            }
+        }
+        for index in 0..3 {
+            assert_eq!(
+                tree.get_proof_at_version(index, 3),
+                at_three.get_proof(index)
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid_params() {
+        let invalid = PoseidonMerkleTreeError::InvalidPoseidonParameters;
+        let breaks: [fn(&mut PoseidonParameters<Fr>); 6] = [
+            |params| params.width = 4,
+            |params| params.full_rounds = 7,
+            |params| params.partial_rounds = 0,
+            |params| params.alpha = 1,
+            |params| {
+                params.ark.pop();
+            },
+            |params| params.mds[1].truncate(2),
+        ];
+        for break_params in breaks {
+            let mut params = circom_params();
+            break_params(&mut params);
+            assert_eq!(
+                PoseidonMerkleTree::new_with_params(3, params).unwrap_err(),
+                invalid
+            );
+        }
+        assert_eq!(
+            PoseidonMerkleTree::new_with_params(0, circom_params()).unwrap_err(),
+            PoseidonMerkleTreeError::invalid_levels(0)
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use core::mem::size_of;
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::{
+    hash_pair, lock_poseidon, zero_hash, PoseidonMerkleTree, PoseidonMerkleTreeError,
+    ROOT_HISTORY_SIZE,
+};
+
+// The depth accounts were laid out for before `MAX_LEVELS` grew. Kept so accounts created
+// since stay readable; deeper trees do not fit
+const POD_MAX_LEVELS: usize = 20;
+
+// `PoseidonMerkleTree` laid out as plain fixed-size data, so a program can read and update a
+// tree stored in an account in place, without deserializing or allocating. Fields are stored
+// in this order with no padding:
+//
+// | field                | size                       |
+// |----------------------|----------------------------|
+// | `levels`             | 4                          |
+// | `filled_subtrees`    | 32 * 20                    |
+// | `roots`              | 32 * ROOT_HISTORY_SIZE     |
+// | `current_root_index` | 4                          |
+// | `next_index`         | 8                          |
+//
+// Only the first `levels` filled subtrees are used; the rest stay zero
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct PodTree {
+    pub levels: u32,
+    pub filled_subtrees: [[u8; 32]; POD_MAX_LEVELS],
+    pub roots: [[u8; 32]; ROOT_HISTORY_SIZE],
+    pub current_root_index: u32,
+    pub next_index: u64,
+}
+
+// A layout change that adds padding fails to compile here rather than corrupting accounts
+const _: () = assert!(PodTree::SIZE == size_of::<PodTree>());
+
+impl PodTree {
+    pub const MAX_LEVELS: usize = POD_MAX_LEVELS;
+    pub const SIZE: usize = 4 + 32 * POD_MAX_LEVELS + 32 * ROOT_HISTORY_SIZE + 4 + 8;
+
+    pub fn new(levels: u32) -> Result<PodTree, PoseidonMerkleTreeError> {
+        PodTree::try_from(&PoseidonMerkleTree::new(levels)?)
+    }
+
+    // Views `bytes` as a tree. They must be exactly `SIZE` bytes, aligned for a `u64`, and
+    // describe a tree the owned API could have produced
+    pub fn from_bytes(bytes: &[u8]) -> Result<&PodTree, PoseidonMerkleTreeError> {
+        let tree: &PodTree = bytemuck::try_from_bytes(bytes)
+            .map_err(|_| PoseidonMerkleTreeError::InvalidAccountData)?;
+        tree.check()?;
+        Ok(tree)
+    }
+
+    pub fn from_bytes_mut(bytes: &mut [u8]) -> Result<&mut PodTree, PoseidonMerkleTreeError> {
+        let tree: &mut PodTree = bytemuck::try_from_bytes_mut(bytes)
+            .map_err(|_| PoseidonMerkleTreeError::InvalidAccountData)?;
+        tree.check()?;
+        Ok(tree)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(self)
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.roots[self.current_root_index as usize]
+    }
+
+    pub fn insert(&mut self, leaf: &[u8; 32]) -> Result<u64, PoseidonMerkleTreeError> {
+        if self.next_index >= 1u64 << self.levels {
+            return Err(PoseidonMerkleTreeError::MerkleTreeFull {
+                capacity: 1u64 << self.levels,
+            });
+        }
+
+        let mut poseidon = lock_poseidon();
+
+        // Hash into a copy so a failure leaves the account untouched
+        let mut filled_subtrees = self.filled_subtrees;
+        let mut current_index = self.next_index;
+        let mut current_level_hash = *leaf;
+        for (level, filled) in filled_subtrees[..self.levels as usize]
+            .iter_mut()
+            .enumerate()
+        {
+            let (left, right) = if current_index.is_multiple_of(2) {
+                (current_level_hash, zero_hash(level as u32)?)
+            } else {
+                (*filled, current_level_hash)
+            };
+
+            current_level_hash = hash_pair(&mut poseidon, &left, &right)?;
+            *filled = left;
+            current_index /= 2;
+        }
+
+        let new_root_index = (self.current_root_index + 1) % ROOT_HISTORY_SIZE as u32;
+        self.filled_subtrees = filled_subtrees;
+        self.current_root_index = new_root_index;
+        self.roots[new_root_index as usize] = current_level_hash;
+        self.next_index += 1;
+
+        Ok(self.next_index - 1)
+    }
+
+    pub fn is_known_root(&self, root: [u8; 32]) -> bool {
+        if root == [0; 32] {
+            return false;
+        }
+
+        // Slots older than the number of inserts were never written
+        let written = (self.next_index + 1).min(ROOT_HISTORY_SIZE as u64);
+        let mut i = self.current_root_index;
+        for _ in 0..written {
+            if self.roots[i as usize] == root {
+                return true;
+            }
+            i = if i == 0 {
+                ROOT_HISTORY_SIZE as u32 - 1
+            } else {
+                i - 1
+            };
+        }
+
+        false
+    }
+
+    // Any bit pattern is a valid `PodTree`, so account data is checked before it is indexed
+    // with
+    fn check(&self) -> Result<(), PoseidonMerkleTreeError> {
+        if self.levels == 0
+            || self.levels as usize > POD_MAX_LEVELS
+            || self.current_root_index as usize >= ROOT_HISTORY_SIZE
+            || self.next_index > 1u64 << self.levels
+        {
+            return Err(PoseidonMerkleTreeError::InvalidAccountData);
+        }
+        Ok(())
+    }
+}
+
+// Fails with `InvalidHistorySize` unless the tree keeps the default `ROOT_HISTORY_SIZE` roots,
+// with `LeafLogUnsupported` if it has a leaf log, which has no fixed size, and with
+// `InvalidLevels` for a tree deeper than `PodTree::MAX_LEVELS`
+impl TryFrom<&PoseidonMerkleTree> for PodTree {
+    type Error = PoseidonMerkleTreeError;
+
+    fn try_from(tree: &PoseidonMerkleTree) -> Result<PodTree, PoseidonMerkleTreeError> {
+        if tree.leaves().is_some() {
+            return Err(PoseidonMerkleTreeError::LeafLogUnsupported);
+        }
+        if tree.levels as usize > POD_MAX_LEVELS {
+            return Err(PoseidonMerkleTreeError::InvalidLevels {
+                got: tree.levels,
+                max: POD_MAX_LEVELS as u32,
+            });
+        }
+
+        let mut filled_subtrees = [[0; 32]; POD_MAX_LEVELS];
+        filled_subtrees[..tree.levels as usize].copy_from_slice(&tree.filled_subtrees());
+
+        Ok(PodTree {
+            levels: tree.levels,
+            filled_subtrees,
+            roots: tree
+                .roots
+                .as_slice()
+                .try_into()
+                .map_err(|_| PoseidonMerkleTreeError::InvalidHistorySize)?,
+            current_root_index: tree.current_root_index,
+            next_index: tree.next_index,
+        })
+    }
+}
+
+impl TryFrom<&PodTree> for PoseidonMerkleTree {
+    type Error = PoseidonMerkleTreeError;
+
+    fn try_from(tree: &PodTree) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        tree.check()?;
+        PoseidonMerkleTree::from_stored(
+            tree.levels,
+            &tree.filled_subtrees[..tree.levels as usize],
+            tree.roots.to_vec(),
+            tree.current_root_index,
+            tree.next_index,
+            None,
+        )
+        .map_err(|_| PoseidonMerkleTreeError::InvalidAccountData)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Account data is 8-byte aligned; a `u64` buffer gives the tests the same guarantee
+    fn account(tree: &PodTree) -> Vec<u64> {
+        let mut words = vec![0u64; PodTree::SIZE / 8];
+        bytemuck::cast_slice_mut(&mut words).copy_from_slice(tree.as_bytes());
+        words
+    }
+
+    #[test]
+    fn test_size_matches_layout() {
+        assert_eq!(PodTree::SIZE, size_of::<PodTree>());
+        assert_eq!(PodTree::SIZE, 4 + 32 * 20 + 32 * 20 + 4 + 8);
+    }
+
+    #[test]
+    fn test_insert_through_view_matches_owned_tree() {
+        let mut owned = PoseidonMerkleTree::new(5).unwrap();
+        let mut words = account(&PodTree::new(5).unwrap());
+
+        for i in 0..25u8 {
+            let bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut words);
+            let view = PodTree::from_bytes_mut(bytes).unwrap();
+            assert_eq!(view.insert(&[i + 1; 32]), owned.insert(&[i + 1; 32]));
+        }
+
+        let view = PodTree::from_bytes(bytemuck::cast_slice(&words)).unwrap();
+        assert_eq!(view.root(), owned.last_root());
+
+        let mut corrupted = *view;
+        corrupted.next_index = 40;
+        assert_eq!(
+            corrupted.insert(&[1u8; 32]),
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 32 })
+        );
+        assert_eq!(corrupted.filled_subtrees, view.filled_subtrees);
+        assert!(view.is_known_root(owned.roots[(owned.current_root_index as usize + 1) % 20]));
+        assert_eq!(PoseidonMerkleTree::try_from(view).unwrap(), owned);
+        assert_eq!(&PodTree::try_from(&owned).unwrap(), view);
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_bad_accounts() {
+        let tree = PodTree::new(3).unwrap();
+        let mut words = account(&tree);
+        let bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut words);
+
+        assert_eq!(
+            PodTree::from_bytes(&bytes[..PodTree::SIZE - 8]),
+            Err(PoseidonMerkleTreeError::InvalidAccountData)
+        );
+        assert_eq!(
+            PodTree::from_bytes(&bytes[1..PodTree::SIZE - 7]),
+            Err(PoseidonMerkleTreeError::InvalidAccountData)
+        );
+
+        // `levels` is the first field
+        bytes[..4].copy_from_slice(&(PodTree::MAX_LEVELS as u32 + 1).to_le_bytes());
+        assert_eq!(
+            PodTree::from_bytes_mut(bytes),
+            Err(PoseidonMerkleTreeError::InvalidAccountData)
+        );
+
+        let mut logged = PoseidonMerkleTree::with_leaf_log(3).unwrap();
+        logged.insert(&[1u8; 32]).unwrap();
+        assert_eq!(
+            PodTree::try_from(&logged),
+            Err(PoseidonMerkleTreeError::LeafLogUnsupported)
+        );
+        assert_eq!(
+            PodTree::try_from(&PoseidonMerkleTree::new_with_history(3, 5).unwrap()),
+            Err(PoseidonMerkleTreeError::InvalidHistorySize)
+        );
+        assert_eq!(
+            PodTree::new(21),
+            Err(PoseidonMerkleTreeError::InvalidLevels { got: 21, max: 20 })
+        );
+        assert!(PodTree::new(20).is_ok());
+    }
+}
@@ -0,0 +1,154 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use ark_bn254::Fr;
+use light_poseidon::Poseidon;
+
+use crate::new_poseidon;
+
+// Hashers shared by many trees, so a service with hundreds of trees only keeps as many
+// hashers as it has concurrent inserts. Clones share the same pool
+#[derive(Clone)]
+pub struct HasherPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    // Most hashers kept around between checkouts; more are built under load and dropped
+    // on checkin
+    capacity: usize,
+    idle: Mutex<Vec<Poseidon<Fr>>>,
+}
+
+// `Poseidon` has no `Debug` of its own
+impl fmt::Debug for HasherPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HasherPool")
+            .field("capacity", &self.inner.capacity)
+            .field("idle", &self.idle())
+            .finish()
+    }
+}
+
+impl HasherPool {
+    pub fn new(capacity: usize) -> HasherPool {
+        HasherPool {
+            inner: Arc::new(PoolInner {
+                capacity,
+                idle: Mutex::new(Vec::with_capacity(capacity)),
+            }),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity
+    }
+
+    // Hashers currently waiting in the pool
+    pub fn idle(&self) -> usize {
+        self.lock_idle().len()
+    }
+
+    // Hands out an idle hasher, or a new one when all are in use. It returns to the pool when
+    // the guard is dropped
+    pub fn checkout(&self) -> PooledHasher {
+        let poseidon = self.lock_idle().pop().unwrap_or_else(new_poseidon);
+
+        PooledHasher {
+            pool: self.clone(),
+            poseidon: Some(poseidon),
+        }
+    }
+
+    // A hasher keeps no state between hashes, so one dropped by a panicking thread is still
+    // fine to reuse
+    fn lock_idle(&self) -> MutexGuard<'_, Vec<Poseidon<Fr>>> {
+        self.inner
+            .idle
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+pub struct PooledHasher {
+    pool: HasherPool,
+    poseidon: Option<Poseidon<Fr>>,
+}
+
+impl Deref for PooledHasher {
+    type Target = Poseidon<Fr>;
+
+    fn deref(&self) -> &Poseidon<Fr> {
+        self.poseidon.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledHasher {
+    fn deref_mut(&mut self) -> &mut Poseidon<Fr> {
+        self.poseidon.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledHasher {
+    fn drop(&mut self) {
+        let mut idle = self.pool.lock_idle();
+        if idle.len() < self.pool.inner.capacity {
+            idle.push(self.poseidon.take().unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rayon::prelude::*;
+
+    use super::*;
+    use crate::PoseidonMerkleTree;
+
+    #[test]
+    fn test_pool_reuses_up_to_capacity() {
+        let pool = HasherPool::new(2);
+        let first = pool.checkout();
+        let second = pool.checkout();
+        let third = pool.checkout();
+        assert_eq!(pool.idle(), 0);
+
+        drop(first);
+        drop(second);
+        drop(third);
+        assert_eq!(pool.idle(), 2);
+
+        let _reused = pool.checkout();
+        assert_eq!(pool.clone().idle(), 1);
+    }
+
+    #[test]
+    fn test_pooled_trees_insert_in_parallel() {
+        let pool = HasherPool::new(8);
+        let leaf = |tree: usize, i: u8| {
+            let mut leaf = [0u8; 32];
+            leaf[30] = tree as u8;
+            leaf[31] = i + 1;
+            leaf
+        };
+
+        let mut pooled: Vec<PoseidonMerkleTree> = (0..64)
+            .map(|_| PoseidonMerkleTree::new_with_pool(5, &pool).unwrap())
+            .collect();
+        pooled.par_iter_mut().enumerate().for_each(|(t, tree)| {
+            for i in 0..20 {
+                tree.insert(&leaf(t, i)).unwrap();
+            }
+        });
+
+        for (t, tree) in pooled.iter().enumerate() {
+            let mut expected = PoseidonMerkleTree::new(5).unwrap();
+            for i in 0..20 {
+                expected.insert(&leaf(t, i)).unwrap();
+            }
+            assert_eq!(tree, &expected);
+        }
+        assert!(pool.idle() <= pool.capacity());
+    }
+}
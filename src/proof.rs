@@ -0,0 +1,543 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use ark_bn254::Fr;
+use borsh::maybestd::io::{self, Error, ErrorKind, Read};
+use borsh::{BorshDeserialize, BorshSerialize};
+use light_poseidon::Poseidon;
+
+use crate::{
+    hash_pair, lock_poseidon, read_bounded, zero_hash, PoseidonMerkleTreeError, MAX_LEVELS,
+};
+
+#[derive(Clone, BorshSerialize, Debug, Default, PartialEq)]
+pub struct MerkleProof {
+    pub leaf: [u8; 32],
+    // The leaf index doubles as the packed path: bit `level` is 0 when the path node at that
+    // level is a left child and 1 when it is a right child
+    pub index: u32,
+    // Sibling hashes ordered from the leaf level up to the level just below the root
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl MerkleProof {
+    pub fn path_bit(&self, level: u32) -> u8 {
+        path_bit(self.index, level)
+    }
+
+    pub fn path_indices(&self) -> u32 {
+        self.index
+    }
+
+    // Expanded one-entry-per-level form, as used by circom's `pathIndices`
+    pub fn path_indices_vec(&self) -> Vec<u8> {
+        (0..self.siblings.len() as u32)
+            .map(|level| self.path_bit(level))
+            .collect()
+    }
+
+    // Packs an expanded `pathIndices` vector back into the index bitfield
+    pub fn path_indices_from_vec(bits: &[u8]) -> Result<u32, PoseidonMerkleTreeError> {
+        if bits.len() > MAX_LEVELS {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+        bits.iter()
+            .enumerate()
+            .try_fold(0u32, |packed, (level, bit)| match bit {
+                0 => Ok(packed),
+                1 => Ok(packed | 1 << level),
+                _ => Err(PoseidonMerkleTreeError::InvalidProof),
+            })
+    }
+
+    pub fn root(&self) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        compute_root_from_proof(&self.leaf, self.index, &self.siblings)
+    }
+
+    pub fn verify(&self, root: &[u8; 32]) -> Result<bool, PoseidonMerkleTreeError> {
+        verify_merkle_proof(&self.leaf, self.index, &self.siblings, root)
+    }
+}
+
+impl BorshDeserialize for MerkleProof {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let leaf = <[u8; 32]>::deserialize_reader(reader)?;
+        let index = u32::deserialize_reader(reader)?;
+
+        let siblings: Vec<[u8; 32]> = read_bounded(
+            reader,
+            |len| len as usize <= MAX_LEVELS,
+            "Merkle proof has more siblings than MAX_LEVELS",
+        )?;
+        if !index_fits(index, siblings.len()) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Merkle proof path bits exceed its depth",
+            ));
+        }
+
+        Ok(MerkleProof {
+            leaf,
+            index,
+            siblings,
+        })
+    }
+}
+
+// Authenticates several leaves at once; siblings shared between their paths, or
+// recomputable from the leaves themselves, are only included once
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, Default, PartialEq)]
+pub struct MultiProof {
+    pub levels: u32,
+    // Proven leaves sorted by index, without duplicates
+    pub leaves: Vec<(u32, [u8; 32])>,
+    // Sibling hashes not derivable from the leaves, level by level in ascending index order
+    pub nodes: Vec<[u8; 32]>,
+}
+
+impl MultiProof {
+    pub fn root(&self) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        if self.levels as usize > MAX_LEVELS
+            || self.leaves.is_empty()
+            || self.leaves.windows(2).any(|pair| pair[0].0 >= pair[1].0)
+            || self
+                .leaves
+                .iter()
+                .any(|(index, _)| !index_fits(*index, self.levels as usize))
+        {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+
+        let mut poseidon = lock_poseidon();
+
+        let mut nodes = self.nodes.iter();
+        let mut current = self.leaves.clone();
+        for _ in 0..self.levels {
+            let mut parents = Vec::with_capacity(current.len());
+            let mut known = current.iter().peekable();
+            while let Some(&(position, hash)) = known.next() {
+                let sibling = match known.peek() {
+                    Some(&&(next, next_hash)) if position % 2 == 0 && next == position + 1 => {
+                        known.next();
+                        next_hash
+                    }
+                    _ => *nodes.next().ok_or(PoseidonMerkleTreeError::InvalidProof)?,
+                };
+
+                let parent = if position.is_multiple_of(2) {
+                    hash_pair(&mut poseidon, &hash, &sibling)?
+                } else {
+                    hash_pair(&mut poseidon, &sibling, &hash)?
+                };
+                parents.push((position / 2, parent));
+            }
+            current = parents;
+        }
+
+        if nodes.next().is_some() {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+
+        Ok(current[0].1)
+    }
+
+    pub fn verify(&self, root: &[u8; 32]) -> Result<bool, PoseidonMerkleTreeError> {
+        Ok(self.root()? == *root)
+    }
+}
+
+// Recomputes the root for `leaf` at `index` from its authentication path, using the same
+// left/right ordering as `PoseidonMerkleTree::insert`, and compares it against `root`
+pub fn verify_merkle_proof(
+    leaf: &[u8; 32],
+    index: u32,
+    siblings: &[[u8; 32]],
+    root: &[u8; 32],
+) -> Result<bool, PoseidonMerkleTreeError> {
+    Ok(compute_root_from_proof(leaf, index, siblings)? == *root)
+}
+
+// Verifies a proof given only the siblings that differ from the zero chain, as (level, node)
+// pairs in any order. Every level not listed is filled with `zero_hash(level)`
+pub fn verify_partial_proof(
+    leaf: &[u8; 32],
+    index: u32,
+    known_siblings: &[(u32, [u8; 32])],
+    levels: u32,
+    root: &[u8; 32],
+) -> Result<bool, PoseidonMerkleTreeError> {
+    if levels as usize > MAX_LEVELS {
+        return Err(PoseidonMerkleTreeError::InvalidProof);
+    }
+
+    let mut siblings: Vec<Option<[u8; 32]>> = vec![None; levels as usize];
+    for (level, sibling) in known_siblings {
+        match siblings.get_mut(*level as usize) {
+            Some(slot @ None) => *slot = Some(*sibling),
+            _ => return Err(PoseidonMerkleTreeError::InvalidProof),
+        }
+    }
+    let siblings = siblings
+        .into_iter()
+        .enumerate()
+        .map(|(level, sibling)| sibling.map_or_else(|| zero_hash(level as u32), Ok))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    verify_merkle_proof(leaf, index, &siblings, root)
+}
+
+// Hashes `leaf` up its authentication path and returns the resulting root, which can then be
+// checked with `PoseidonMerkleTree::is_known_root`
+pub fn compute_root_from_proof(
+    leaf: &[u8; 32],
+    index: u32,
+    siblings: &[[u8; 32]],
+) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+    let mut poseidon = lock_poseidon();
+
+    compute_root_with(&mut poseidon, leaf, index, siblings)
+}
+
+// Verifies every proof against `root` under a single acquisition of the hasher lock. Proofs
+// that are malformed or contain values outside the field are reported as `false` rather
+// than aborting the batch
+pub fn verify_proofs(
+    proofs: &[MerkleProof],
+    root: &[u8; 32],
+) -> Result<Vec<bool>, PoseidonMerkleTreeError> {
+    let mut results = Vec::with_capacity(proofs.len());
+
+    let mut poseidon = lock_poseidon();
+
+    for proof in proofs {
+        let computed = compute_root_with(&mut poseidon, &proof.leaf, proof.index, &proof.siblings);
+        results.push(computed.is_ok_and(|computed| computed == *root));
+    }
+
+    Ok(results)
+}
+
+fn compute_root_with(
+    poseidon: &mut Poseidon<Fr>,
+    leaf: &[u8; 32],
+    index: u32,
+    siblings: &[[u8; 32]],
+) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+    if siblings.len() > MAX_LEVELS {
+        return Err(PoseidonMerkleTreeError::InvalidProof);
+    }
+    if !index_fits(index, siblings.len()) {
+        return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+            index: index as u64,
+            len: 1u64 << siblings.len(),
+        });
+    }
+
+    let mut current_level_hash = *leaf;
+    for (level, sibling) in siblings.iter().enumerate() {
+        let (left, right) = if path_bit(index, level as u32) == 0 {
+            (&current_level_hash, sibling)
+        } else {
+            (sibling, &current_level_hash)
+        };
+
+        current_level_hash = hash_pair(poseidon, left, right)?;
+    }
+
+    Ok(current_level_hash)
+}
+
+// Whether `index` addresses a leaf of a tree `depth` levels deep
+pub(crate) fn index_fits(index: u32, depth: usize) -> bool {
+    position_at(index, depth) == 0
+}
+
+// `index >> level`, the position of a leaf's ancestor at `level`. A plain shift overflows at
+// the root of a 32-level tree, where every leaf's ancestor is position 0
+pub(crate) fn position_at(index: u32, level: usize) -> u32 {
+    u32::try_from(level)
+        .ok()
+        .and_then(|level| index.checked_shr(level))
+        .unwrap_or(0)
+}
+
+fn path_bit(path_indices: u32, level: u32) -> u8 {
+    ((path_indices >> level) & 1) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zeros;
+    use crate::FullPoseidonMerkleTree;
+
+    #[test]
+    fn test_verify_merkle_proof_round_trip() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        for i in 0..6u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let root = tree.tree().last_root();
+
+        for index in 0..6 {
+            let proof = tree.get_proof(index).unwrap();
+            assert!(verify_merkle_proof(&proof.leaf, proof.index, &proof.siblings, &root).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_verify_merkle_proof_flipped_sibling() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        for i in 0..3u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let root = tree.tree().last_root();
+
+        let mut proof = tree.get_proof(2).unwrap();
+        proof.siblings[1][31] ^= 1;
+        assert!(!verify_merkle_proof(&proof.leaf, proof.index, &proof.siblings, &root).unwrap());
+
+        // The same path is not valid for a different position either
+        let proof = tree.get_proof(2).unwrap();
+        assert!(!verify_merkle_proof(&proof.leaf, 3, &proof.siblings, &root).unwrap());
+    }
+
+    #[test]
+    fn test_verify_merkle_proof_index_beyond_path() {
+        let result = verify_merkle_proof(&[1u8; 32], 8, &[[0u8; 32]; 3], &[0u8; 32]);
+        assert_eq!(
+            result,
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 8, len: 8 })
+        );
+    }
+
+    #[test]
+    fn test_compute_root_from_proof() {
+        let mut tree = FullPoseidonMerkleTree::new(4).unwrap();
+        for i in 0..7u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+
+        let proof = tree.get_proof(5).unwrap();
+        let root = compute_root_from_proof(&proof.leaf, proof.index, &proof.siblings).unwrap();
+        assert!(tree.tree().is_known_root(root));
+
+        let proof = tree.get_proof_at_version(2, 4).unwrap();
+        let root = compute_root_from_proof(&proof.leaf, proof.index, &proof.siblings).unwrap();
+        assert!(tree.tree().is_known_root(root));
+    }
+
+    #[test]
+    fn test_compute_root_from_proof_errors() {
+        let result = compute_root_from_proof(&[1u8; 32], 0, &[[0u8; 32]; MAX_LEVELS + 1]);
+        assert_eq!(result, Err(PoseidonMerkleTreeError::InvalidProof));
+
+        // Siblings outside the field surface as an error instead of a panic
+        let result = compute_root_from_proof(&[1u8; 32], 0, &[[0xff; 32]]);
+        assert_eq!(
+            result,
+            Err(PoseidonMerkleTreeError::HashError(
+                "Input is larger than the modulus of the prime field.".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_verify_proofs_matches_single_verification() {
+        let mut tree = FullPoseidonMerkleTree::new(6).unwrap();
+        for i in 0..40u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let root = tree.tree().last_root();
+
+        let mut proofs: Vec<MerkleProof> = (0..40).map(|i| tree.get_proof(i).unwrap()).collect();
+        proofs[3].siblings[2] = [9u8; 32];
+        proofs[17].leaf = [0xff; 32];
+        proofs[21].siblings.push([0u8; 32]);
+        proofs[30].index = 31;
+
+        let batch = verify_proofs(&proofs, &root).unwrap();
+        let single: Vec<bool> = proofs
+            .iter()
+            .map(|proof| proof.verify(&root).unwrap_or(false))
+            .collect();
+        assert_eq!(batch, single);
+        assert_eq!(batch.iter().filter(|valid| !**valid).count(), 4);
+        assert!(!batch[3] && !batch[17] && !batch[21] && !batch[30]);
+
+        assert_eq!(verify_proofs(&[], &root), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_verify_from_many_threads() {
+        let mut tree = FullPoseidonMerkleTree::new(6).unwrap();
+        for i in 0..32u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let root = tree.tree().last_root();
+        let proofs: Vec<MerkleProof> = (0..32).map(|i| tree.get_proof(i).unwrap()).collect();
+
+        std::thread::scope(|scope| {
+            for t in 0..8 {
+                let proofs = &proofs;
+                scope.spawn(move || {
+                    for round in 0..4 {
+                        let proof = &proofs[(t * 4 + round) % proofs.len()];
+                        assert!(verify_merkle_proof(
+                            &proof.leaf,
+                            proof.index,
+                            &proof.siblings,
+                            &root
+                        )
+                        .unwrap());
+                        assert!(!verify_merkle_proof(
+                            &[0xaa; 32],
+                            proof.index,
+                            &proof.siblings,
+                            &root
+                        )
+                        .unwrap_or(false));
+                        assert!(verify_proofs(proofs, &root).unwrap().into_iter().all(|v| v));
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_proof_verify_and_root() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        for i in 0..5u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let root = tree.tree().last_root();
+
+        let proof = tree.get_proof(4).unwrap();
+        assert_eq!(proof.root().unwrap(), root);
+        assert!(proof.verify(&root).unwrap());
+        assert!(!proof.verify(&[2u8; 32]).unwrap());
+    }
+
+    #[test]
+    fn test_proof_borsh_round_trip() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        tree.insert(&[2u8; 32]).unwrap();
+
+        let proof = tree.get_proof(1).unwrap();
+        let bytes = proof.try_to_vec().unwrap();
+        assert_eq!(bytes.len(), 32 + 4 + 4 + 32 * 3);
+        assert_eq!(MerkleProof::try_from_slice(&bytes).unwrap(), proof);
+    }
+
+    #[test]
+    fn test_proof_path_bits() {
+        let proof = MerkleProof {
+            leaf: [1u8; 32],
+            index: 0b1101,
+            siblings: vec![[0u8; 32]; 5],
+        };
+        assert_eq!(proof.path_indices(), 13);
+        assert_eq!(proof.path_bit(0), 1);
+        assert_eq!(proof.path_bit(1), 0);
+        assert_eq!(proof.path_bit(4), 0);
+
+        let expanded = proof.path_indices_vec();
+        assert_eq!(expanded, vec![1, 0, 1, 1, 0]);
+        assert_eq!(MerkleProof::path_indices_from_vec(&expanded), Ok(13));
+        assert_eq!(
+            MerkleProof::path_indices_from_vec(&[0, 2]),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+    }
+
+    #[test]
+    fn test_proof_borsh_layout() {
+        let proof = MerkleProof {
+            leaf: [1u8; 32],
+            index: 0b101,
+            siblings: vec![[2u8; 32]; 3],
+        };
+        let bytes = proof.try_to_vec().unwrap();
+
+        // leaf | packed path (u32 LE) | sibling count (u32 LE) | siblings
+        assert_eq!(bytes.len(), 32 + 4 + 4 + 32 * 3);
+        assert_eq!(&bytes[32..36], &5u32.to_le_bytes());
+        assert_eq!(&bytes[36..40], &3u32.to_le_bytes());
+
+        // Path bits above the proof depth are not a canonical encoding
+        let mut non_canonical = bytes.clone();
+        non_canonical[32] = 0b1101;
+        assert!(MerkleProof::try_from_slice(&non_canonical).is_err());
+    }
+
+    #[test]
+    fn test_proof_deserialize_rejects_long_path() {
+        let proof = MerkleProof {
+            leaf: [1u8; 32],
+            index: 0,
+            siblings: vec![[0u8; 32]; MAX_LEVELS + 1],
+        };
+        let bytes = proof.try_to_vec().unwrap();
+        assert!(MerkleProof::try_from_slice(&bytes).is_err());
+
+        // An oversized length prefix is rejected without any sibling data behind it
+        let mut truncated = bytes[..36].to_vec();
+        truncated.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(MerkleProof::try_from_slice(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_verify_partial_proof_fills_zero_siblings() {
+        let mut tree = FullPoseidonMerkleTree::new(10).unwrap();
+        for i in 0..3u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        let root = tree.tree().last_root();
+        let proof = tree.get_proof(2).unwrap();
+
+        let known = proof
+            .siblings
+            .iter()
+            .enumerate()
+            .filter(|(level, sibling)| **sibling != zeros(*level as u32))
+            .map(|(level, sibling)| (level as u32, *sibling))
+            .rev()
+            .collect::<Vec<_>>();
+        assert_eq!(known.len(), 1);
+        assert!(verify_partial_proof(&proof.leaf, 2, &known, 10, &root).unwrap());
+
+        // Supplying every sibling behaves exactly like the full verifier
+        let all = proof
+            .siblings
+            .iter()
+            .enumerate()
+            .map(|(level, sibling)| (level as u32, *sibling))
+            .collect::<Vec<_>>();
+        assert!(verify_partial_proof(&proof.leaf, 2, &all, 10, &root).unwrap());
+        assert!(!verify_partial_proof(&proof.leaf, 3, &all, 10, &root).unwrap());
+        assert_eq!(
+            verify_partial_proof(&proof.leaf, 1 << 10, &all, 10, &root),
+            verify_merkle_proof(&proof.leaf, 1 << 10, &proof.siblings, &root)
+        );
+    }
+
+    #[test]
+    fn test_verify_partial_proof_rejects_bad_levels() {
+        let leaf = [1u8; 32];
+        let root = [0u8; 32];
+
+        assert_eq!(
+            verify_partial_proof(&leaf, 0, &[(1, [2u8; 32]), (1, [2u8; 32])], 4, &root),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+        assert_eq!(
+            verify_partial_proof(&leaf, 0, &[(4, [2u8; 32])], 4, &root),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+        assert_eq!(
+            verify_partial_proof(&leaf, 0, &[], MAX_LEVELS as u32 + 1, &root),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{PoseidonMerkleTree, PoseidonMerkleTreeError};
+
+impl PoseidonMerkleTree {
+    // Rebuilds a tree from `(leaf_index, leaf)` events such as Tornado's
+    // `Deposit(commitment, leafIndex)`, which must cover every index from 0 in order
+    pub fn replay(
+        levels: u32,
+        events: impl IntoIterator<Item = (u32, [u8; 32])>,
+    ) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        let mut tree = PoseidonMerkleTree::new(levels)?;
+        tree.replay_events(events)?;
+        Ok(tree)
+    }
+
+    // Continues a replay from `next_index` and returns the number of leaves after it. Stops at
+    // the first event that does not carry the next index: `DuplicateEvent` for an index already
+    // in the tree, `MissingEvent` with the first index nothing was seen for otherwise. Every
+    // event before it stays applied, so the replay can resume once the missing one is fetched
+    pub fn replay_events(
+        &mut self,
+        events: impl IntoIterator<Item = (u32, [u8; 32])>,
+    ) -> Result<u64, PoseidonMerkleTreeError> {
+        for (index, leaf) in events {
+            let index = index as u64;
+            if index < self.next_index {
+                return Err(PoseidonMerkleTreeError::DuplicateEvent(index));
+            }
+            if index > self.next_index {
+                return Err(PoseidonMerkleTreeError::MissingEvent(self.next_index));
+            }
+            self.insert(&leaf)?;
+        }
+        Ok(self.next_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    fn events(count: u32) -> Vec<(u32, [u8; 32])> {
+        (0..count).map(|i| (i, [i as u8 + 1; 32])).collect()
+    }
+
+    fn reference(levels: u32, events: &[(u32, [u8; 32])]) -> PoseidonMerkleTree {
+        let mut tree = PoseidonMerkleTree::new(levels).unwrap();
+        for (_, leaf) in events {
+            tree.insert(leaf).unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn test_replay_matches_sequential_inserts() {
+        let events = events(10);
+        assert_eq!(
+            PoseidonMerkleTree::replay(5, events.clone()),
+            Ok(reference(5, &events))
+        );
+        assert_eq!(
+            PoseidonMerkleTree::replay(5, []),
+            Ok(PoseidonMerkleTree::new(5).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_replay_resumes_on_an_existing_tree() {
+        let events = events(10);
+        let mut tree = PoseidonMerkleTree::replay(5, events[..4].to_vec()).unwrap();
+        assert_eq!(tree.replay_events(events[4..].to_vec()), Ok(10));
+        assert_eq!(tree, reference(5, &events));
+
+        // Starting over from 0 on a tree that already holds those leaves
+        assert_eq!(
+            tree.replay_events(events[..1].to_vec()),
+            Err(PoseidonMerkleTreeError::DuplicateEvent(0))
+        );
+    }
+
+    #[test]
+    fn test_replay_reports_gaps_and_duplicates() {
+        let events = events(6);
+
+        let mut gap = events.clone();
+        gap.remove(3);
+        assert_eq!(
+            PoseidonMerkleTree::replay(5, gap.clone()),
+            Err(PoseidonMerkleTreeError::MissingEvent(3))
+        );
+        let mut tree = PoseidonMerkleTree::new(5).unwrap();
+        assert_eq!(
+            tree.replay_events(gap.clone()),
+            Err(PoseidonMerkleTreeError::MissingEvent(3))
+        );
+        assert_eq!(tree, reference(5, &events[..3]));
+        assert_eq!(tree.replay_events(events[3..].to_vec()), Ok(6));
+        assert_eq!(tree, reference(5, &events));
+
+        let mut duplicate = events.clone();
+        duplicate.insert(2, events[1]);
+        assert_eq!(
+            PoseidonMerkleTree::replay(5, duplicate),
+            Err(PoseidonMerkleTreeError::DuplicateEvent(1))
+        );
+
+        let mut out_of_order = events.clone();
+        out_of_order.swap(1, 2);
+        assert_eq!(
+            PoseidonMerkleTree::replay(5, out_of_order),
+            Err(PoseidonMerkleTreeError::MissingEvent(1))
+        );
+    }
+
+    #[test]
+    fn test_replay_stops_at_a_failing_insert() {
+        let mut tree = PoseidonMerkleTree::new(1).unwrap();
+        assert_eq!(
+            tree.replay_events([(0, [1; 32]), (1, [0xff; 32])]),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&[0xff; 32]))
+        );
+        assert_eq!(tree.next_index, 1);
+        assert_eq!(
+            tree.replay_events([(1, [2; 32]), (2, [3; 32])]),
+            Err(PoseidonMerkleTreeError::MerkleTreeFull { capacity: 2 })
+        );
+    }
+}
@@ -0,0 +1,514 @@
+use std::fmt;
+
+use serde::de::{self, SeqAccess, Visitor};
+use serde::ser::SerializeSeq;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::proof::index_fits;
+use crate::zero_preset::ZeroChain;
+use crate::{
+    decode_hash, encode_hash, Endianness, MerkleProof, PoseidonMerkleTree, TreeInfo, ZeroPreset,
+    MAX_LEVELS,
+};
+
+// Serde support for the `serde` feature. In human-readable formats such as JSON every 32-byte
+// value is written as a `0x`-prefixed lowercase hex string, and reading also accepts the plain
+// 32-element arrays a derived impl would have produced. Binary formats keep the raw bytes
+
+// A single 32-byte value, as stored in the fields below
+struct Hex32([u8; 32]);
+
+impl Serialize for Hex32 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return self.0.serialize(serializer);
+        }
+
+        serializer.serialize_str(&encode_hash(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Hex32 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Hex32, D::Error> {
+        if !deserializer.is_human_readable() {
+            return <[u8; 32]>::deserialize(deserializer).map(Hex32);
+        }
+        deserializer.deserialize_any(Hex32Visitor)
+    }
+}
+
+struct Hex32Visitor;
+
+impl<'de> Visitor<'de> for Hex32Visitor {
+    type Value = Hex32;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a string of 64 hex digits or an array of 32 bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Hex32, E> {
+        decode_hash(value)
+            .map(Hex32)
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Hex32, A::Error> {
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+        }
+        if seq.next_element::<de::IgnoredAny>()?.is_some() {
+            return Err(de::Error::invalid_length(33, &self));
+        }
+        Ok(Hex32(bytes))
+    }
+}
+
+// `#[serde(with)]` helpers for the field shapes used in this crate
+mod hex {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+        Hex32(*bytes).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+        Ok(Hex32::deserialize(deserializer)?.0)
+    }
+}
+
+mod hex_vec {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(values: &[[u8; 32]], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(values.len()))?;
+        for value in values {
+            seq.serialize_element(&Hex32(*value))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<[u8; 32]>, D::Error> {
+        Ok(Vec::<Hex32>::deserialize(deserializer)?
+            .into_iter()
+            .map(|value| value.0)
+            .collect())
+    }
+}
+
+mod hex_vec_option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        values: &Option<Vec<[u8; 32]>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match values {
+            Some(values) => super::hex_vec::serialize(values, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<[u8; 32]>>, D::Error> {
+        Ok(Option::<Vec<Hex32>>::deserialize(deserializer)?
+            .map(|values| values.into_iter().map(|value| value.0).collect()))
+    }
+}
+
+// `"voidify"`, `"tornado"` or the hex of a `Custom` leaf
+mod preset {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(preset: &ZeroPreset, serializer: S) -> Result<S::Ok, S::Error> {
+        match preset {
+            ZeroPreset::Voidify => serializer.serialize_str("voidify"),
+            ZeroPreset::Tornado => serializer.serialize_str("tornado"),
+            ZeroPreset::Custom(leaf) => serializer.serialize_str(&encode_hash(leaf)),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ZeroPreset, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        match value.as_str() {
+            "voidify" => Ok(ZeroPreset::Voidify),
+            "tornado" => Ok(ZeroPreset::Tornado),
+            hex => decode_hash(hex).map(ZeroPreset::Custom).map_err(|_| {
+                de::Error::invalid_value(
+                    de::Unexpected::Str(hex),
+                    &"\"voidify\", \"tornado\" or 64 hex digits",
+                )
+            }),
+        }
+    }
+}
+
+// `"big"` or `"little"`
+mod endianness {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        endianness: &Endianness,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match endianness {
+            Endianness::Big => "big",
+            Endianness::Little => "little",
+        })
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Endianness, D::Error> {
+        match String::deserialize(deserializer)?.as_str() {
+            "big" => Ok(Endianness::Big),
+            "little" => Ok(Endianness::Little),
+            other => Err(de::Error::unknown_variant(other, &["big", "little"])),
+        }
+    }
+}
+
+// The stored fields of a tree, in the same order as its Borsh layout
+#[derive(Serialize, Deserialize)]
+struct TreeFields {
+    levels: u32,
+    #[serde(with = "hex_vec")]
+    filled_subtrees: Vec<[u8; 32]>,
+    #[serde(with = "hex_vec")]
+    roots: Vec<[u8; 32]>,
+    current_root_index: u32,
+    next_index: u64,
+    #[serde(with = "hex_vec_option", default)]
+    leaf_log: Option<Vec<[u8; 32]>>,
+    // Only written when off, so the JSON of other trees stays as it was
+    #[serde(
+        default = "accept_by_default",
+        skip_serializing_if = "is_default_accept"
+    )]
+    accept_initial_root: bool,
+    #[serde(with = "preset", default, skip_serializing_if = "is_voidify")]
+    zero_preset: ZeroPreset,
+    #[serde(with = "endianness", default, skip_serializing_if = "is_big_endian")]
+    endianness: Endianness,
+    #[serde(default, skip_serializing_if = "is_false")]
+    reject_duplicates: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn is_big_endian(endianness: &Endianness) -> bool {
+    *endianness == Endianness::Big
+}
+
+fn is_voidify(preset: &ZeroPreset) -> bool {
+    *preset == ZeroPreset::Voidify
+}
+
+fn accept_by_default() -> bool {
+    true
+}
+
+fn is_default_accept(accept: &bool) -> bool {
+    *accept
+}
+
+// Refused for custom Poseidon parameters, as with Borsh
+impl Serialize for PoseidonMerkleTree {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.has_custom_params() {
+            return Err(serde::ser::Error::custom(
+                "Trees on custom Poseidon parameters cannot be serialized",
+            ));
+        }
+        TreeFields {
+            levels: self.levels,
+            filled_subtrees: self.filled_subtrees(),
+            roots: self.roots.clone(),
+            current_root_index: self.current_root_index,
+            next_index: self.next_index,
+            leaf_log: self.leaf_log.clone(),
+            accept_initial_root: self.accept_initial_root,
+            zero_preset: self.zero_preset(),
+            endianness: self.endianness,
+            reject_duplicates: self.rejects_duplicates(),
+        }
+        .serialize(serializer)
+    }
+}
+
+// Runs the same checks as the Borsh reader
+impl<'de> Deserialize<'de> for PoseidonMerkleTree {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<PoseidonMerkleTree, D::Error> {
+        let fields = TreeFields::deserialize(deserializer)?;
+        let mut tree = PoseidonMerkleTree::from_stored(
+            fields.levels,
+            &fields.filled_subtrees,
+            fields.roots,
+            fields.current_root_index,
+            fields.next_index,
+            fields.leaf_log,
+        )
+        .map_err(de::Error::custom)?;
+        tree.accept_initial_root = fields.accept_initial_root;
+        tree.zeros = ZeroChain::new(fields.zero_preset).map_err(de::Error::custom)?;
+        tree.endianness = fields.endianness;
+        tree.set_reject_duplicates(fields.reject_duplicates)
+            .map_err(de::Error::custom)?;
+        Ok(tree)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProofFields {
+    #[serde(with = "hex")]
+    leaf: [u8; 32],
+    index: u32,
+    #[serde(with = "hex_vec")]
+    siblings: Vec<[u8; 32]>,
+}
+
+impl Serialize for MerkleProof {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ProofFields {
+            leaf: self.leaf,
+            index: self.index,
+            siblings: self.siblings.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+// Same limits as the Borsh reader
+impl<'de> Deserialize<'de> for MerkleProof {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<MerkleProof, D::Error> {
+        let fields = ProofFields::deserialize(deserializer)?;
+        if fields.siblings.len() > MAX_LEVELS {
+            return Err(de::Error::custom(
+                "Merkle proof has more siblings than MAX_LEVELS",
+            ));
+        }
+        if !index_fits(fields.index, fields.siblings.len()) {
+            return Err(de::Error::custom("Merkle proof path bits exceed its depth"));
+        }
+
+        Ok(MerkleProof {
+            leaf: fields.leaf,
+            index: fields.index,
+            siblings: fields.siblings,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct InfoFields {
+    levels: u32,
+    next_index: u64,
+    capacity: u64,
+    fill_ratio: f64,
+    #[serde(with = "hex")]
+    current_root: [u8; 32],
+    root_history_size: u32,
+    roots_recorded: u32,
+}
+
+impl Serialize for TreeInfo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        InfoFields {
+            levels: self.levels,
+            next_index: self.next_index,
+            capacity: self.capacity,
+            fill_ratio: self.fill_ratio,
+            current_root: self.current_root,
+            root_history_size: self.root_history_size,
+            roots_recorded: self.roots_recorded,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TreeInfo {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<TreeInfo, D::Error> {
+        let fields = InfoFields::deserialize(deserializer)?;
+        Ok(TreeInfo {
+            levels: fields.levels,
+            next_index: fields.next_index,
+            capacity: fields.capacity,
+            fill_ratio: fields.fill_ratio,
+            current_root: fields.current_root,
+            root_history_size: fields.root_history_size,
+            roots_recorded: fields.roots_recorded,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_tree() -> PoseidonMerkleTree {
+        let mut tree = PoseidonMerkleTree::with_leaf_log(3).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        tree.insert(&[2u8; 32]).unwrap();
+        tree
+    }
+
+    #[test]
+    fn test_tree_json_matches_fixture() {
+        let tree = sample_tree();
+        let json = serde_json::to_string_pretty(&tree).unwrap();
+        assert_eq!(
+            json,
+            include_str!("testdata/tree_levels3_2_leaves.json").trim_end()
+        );
+
+        let decoded: PoseidonMerkleTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, tree);
+        assert!(decoded.is_known_root(tree.last_root()));
+    }
+
+    #[test]
+    fn test_tree_json_keeps_accept_initial_root() {
+        let tree = PoseidonMerkleTree::builder()
+            .levels(3)
+            .accept_initial_root(false)
+            .build()
+            .unwrap();
+        let json = serde_json::to_string(&tree).unwrap();
+        assert!(json.ends_with(",\"accept_initial_root\":false}"));
+
+        let decoded: PoseidonMerkleTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, tree);
+        assert!(!decoded.is_known_root(tree.last_root()));
+    }
+
+    #[test]
+    fn test_tree_json_keeps_zero_preset() {
+        let custom = ZeroPreset::Custom([7; 32]);
+        for (preset, written) in [
+            (ZeroPreset::Tornado, "\"tornado\"".to_string()),
+            (custom, format!("\"0x{}\"", "07".repeat(32))),
+        ] {
+            let mut tree = PoseidonMerkleTree::new_with_zero_preset(3, preset).unwrap();
+            tree.insert(&[1u8; 32]).unwrap();
+            let json = serde_json::to_string(&tree).unwrap();
+            assert!(json.ends_with(&format!(",\"zero_preset\":{written}}}")));
+
+            let decoded: PoseidonMerkleTree = serde_json::from_str(&json).unwrap();
+            assert_eq!(decoded.zero_preset(), preset);
+            assert_eq!(decoded, tree);
+        }
+
+        let tree = PoseidonMerkleTree::new(3).unwrap();
+        let json = serde_json::to_string(&tree).unwrap();
+        for preset in ["\"other\"", "\"0xff\""] {
+            let invalid = json.replace('}', &format!(",\"zero_preset\":{preset}}}"));
+            assert!(serde_json::from_str::<PoseidonMerkleTree>(&invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_tree_json_keeps_endianness() {
+        let mut tree = PoseidonMerkleTree::builder()
+            .levels(3)
+            .endianness(Endianness::Little)
+            .build()
+            .unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        let json = serde_json::to_string(&tree).unwrap();
+        assert!(json.ends_with(",\"endianness\":\"little\"}"));
+
+        let decoded: PoseidonMerkleTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.endianness(), Endianness::Little);
+        assert!(decoded.is_known_root(tree.last_root()));
+        assert!(
+            serde_json::from_str::<PoseidonMerkleTree>(&json.replace("little", "middle")).is_err()
+        );
+    }
+
+    #[test]
+    fn test_tree_json_keeps_reject_duplicates() {
+        let mut tree = sample_tree();
+        tree.set_reject_duplicates(true).unwrap();
+        let json = serde_json::to_string(&tree).unwrap();
+        assert!(json.ends_with(",\"reject_duplicates\":true}"));
+
+        let decoded: PoseidonMerkleTree = serde_json::from_str(&json).unwrap();
+        assert!(decoded.rejects_duplicates());
+        assert_eq!(decoded, tree);
+
+        let unlogged = serde_json::to_string(&PoseidonMerkleTree::new(3).unwrap()).unwrap();
+        let invalid = unlogged.replace('}', ",\"reject_duplicates\":true}");
+        assert!(serde_json::from_str::<PoseidonMerkleTree>(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_custom_params_are_not_written() {
+        let tree = PoseidonMerkleTree::new_with_params(3, crate::circom_params()).unwrap();
+        assert!(serde_json::to_string(&tree).is_err());
+    }
+
+    #[test]
+    fn test_proof_json_round_trip() {
+        let proof = MerkleProof {
+            leaf: [0xab; 32],
+            index: 5,
+            siblings: vec![[1u8; 32], [2u8; 32], [3u8; 32]],
+        };
+        let json = serde_json::to_string(&proof).unwrap();
+        assert!(json.starts_with(&format!("{{\"leaf\":\"0x{}\"", "ab".repeat(32))));
+        assert_eq!(serde_json::from_str::<MerkleProof>(&json).unwrap(), proof);
+
+        let too_deep = MerkleProof { index: 8, ..proof };
+        let json = serde_json::to_string(&too_deep).unwrap();
+        assert!(serde_json::from_str::<MerkleProof>(&json).is_err());
+    }
+
+    #[test]
+    fn test_raw_arrays_still_load() {
+        let tree = sample_tree();
+        let raw = serde_json::json!({
+            "levels": tree.levels,
+            "filled_subtrees": tree.filled_subtrees(),
+            "roots": tree.roots,
+            "current_root_index": tree.current_root_index,
+            "next_index": tree.next_index,
+            "leaf_log": tree.leaves(),
+        });
+        let decoded: PoseidonMerkleTree = serde_json::from_value(raw).unwrap();
+        assert_eq!(decoded, tree);
+    }
+
+    #[test]
+    fn test_info_json_round_trip() {
+        let info = sample_tree().info();
+        let json = serde_json::to_value(info).unwrap();
+        assert_eq!(json["current_root"], encode_hash(&info.current_root));
+        assert_eq!(json["fill_ratio"], 0.25);
+        assert_eq!(serde_json::from_value::<TreeInfo>(json).unwrap(), info);
+    }
+
+    #[test]
+    fn test_rejects_malformed_hex() {
+        let valid = format!("\"0x{}\"", "0".repeat(64));
+        assert!(serde_json::from_str::<Hex32>(&valid).is_ok());
+        assert!(serde_json::from_str::<Hex32>(&valid.replace("0x", "")).is_ok());
+
+        for invalid in [
+            format!("\"0x{}\"", "0".repeat(63)),
+            format!("\"0x{}\"", "0".repeat(65)),
+            format!("\"0x{}g\"", "0".repeat(63)),
+            format!("\"0x0x{}\"", "0".repeat(62)),
+            "[1, 2, 3]".to_string(),
+        ] {
+            assert!(
+                serde_json::from_str::<Hex32>(&invalid).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+}
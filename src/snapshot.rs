@@ -0,0 +1,168 @@
+use alloc::vec::Vec;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{PoseidonMerkleTree, PoseidonMerkleTreeError};
+
+// The mutable state of a tree, for rolling back any number of inserts, e.g. when the block
+// they were made for is abandoned. The leaf log is only copied for trees that keep one
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct TreeSnapshot {
+    pub levels: u32,
+    pub filled_subtrees: Vec<[u8; 32]>,
+    pub roots: Vec<[u8; 32]>,
+    pub current_root_index: u32,
+    pub next_index: u64,
+    pub leaf_log: Option<Vec<[u8; 32]>>,
+}
+
+// The state of `PoseidonMerkleTree::default()`
+impl Default for TreeSnapshot {
+    fn default() -> TreeSnapshot {
+        PoseidonMerkleTree::default().snapshot()
+    }
+}
+
+impl PoseidonMerkleTree {
+    pub fn snapshot(&self) -> TreeSnapshot {
+        TreeSnapshot {
+            levels: self.levels,
+            filled_subtrees: self.filled_subtrees(),
+            roots: self.roots.clone(),
+            current_root_index: self.current_root_index,
+            next_index: self.next_index,
+            leaf_log: self.leaf_log.clone(),
+        }
+    }
+
+    // Puts the tree back in the state `snapshot` was taken in. A snapshot can come back from
+    // storage, so it is checked like a deserialized tree first: one from a tree of another
+    // depth is refused with `SnapshotMismatch`, an inconsistent one with the error `validate`
+    // reports, and either way the tree is left as it is. The hasher, zero preset, endianness
+    // and `accept_initial_root` are kept, and so is `reject_duplicates` if the snapshot has a
+    // leaf log
+    pub fn restore(&mut self, snapshot: &TreeSnapshot) -> Result<(), PoseidonMerkleTreeError> {
+        if snapshot.levels != self.levels {
+            return Err(PoseidonMerkleTreeError::SnapshotMismatch);
+        }
+
+        let mut restored = PoseidonMerkleTree::from_stored(
+            snapshot.levels,
+            &snapshot.filled_subtrees,
+            snapshot.roots.clone(),
+            snapshot.current_root_index,
+            snapshot.next_index,
+            snapshot.leaf_log.clone(),
+        )?;
+        restored.validate()?;
+        restored.hasher = core::mem::take(&mut self.hasher);
+        restored.zeros = self.zeros.clone();
+        restored.endianness = self.endianness;
+        restored.accept_initial_root = self.accept_initial_root;
+        restored.reject_duplicates = self.reject_duplicates && restored.leaf_log.is_some();
+        *self = restored;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(batch: u8, i: u8) -> [u8; 32] {
+        let mut leaf = [batch; 32];
+        leaf[31] = i;
+        leaf
+    }
+
+    #[test]
+    fn test_restore_discards_speculative_inserts() {
+        let mut tree = PoseidonMerkleTree::new(7).unwrap();
+        let snapshot = tree.snapshot();
+        for i in 0..50 {
+            tree.insert(&leaf(1, i)).unwrap();
+        }
+        tree.restore(&snapshot).unwrap();
+        assert_eq!(tree, PoseidonMerkleTree::new(7).unwrap());
+
+        let mut expected = PoseidonMerkleTree::new(7).unwrap();
+        for i in 0..50 {
+            tree.insert(&leaf(2, i)).unwrap();
+            expected.insert(&leaf(2, i)).unwrap();
+        }
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn test_restore_to_empty_follows_accept_initial_root() {
+        for accept in [true, false] {
+            let mut tree = PoseidonMerkleTree::builder()
+                .levels(3)
+                .accept_initial_root(accept)
+                .build()
+                .unwrap();
+            let empty_root = tree.last_root();
+            let snapshot = tree.snapshot();
+            tree.insert(&leaf(1, 0)).unwrap();
+            assert!(tree.is_known_root(empty_root));
+
+            tree.restore(&snapshot).unwrap();
+            assert_eq!(tree.accepts_initial_root(), accept);
+            assert_eq!(tree.is_known_root(empty_root), accept);
+        }
+    }
+
+    #[test]
+    fn test_snapshot_survives_borsh() {
+        let mut tree = PoseidonMerkleTree::with_leaf_log(5).unwrap();
+        for i in 0..30 {
+            tree.insert(&leaf(1, i)).unwrap();
+        }
+        let before = tree.clone();
+        let bytes = tree.snapshot().try_to_vec().unwrap();
+
+        tree.insert(&leaf(2, 0)).unwrap();
+        tree.restore(&TreeSnapshot::try_from_slice(&bytes).unwrap())
+            .unwrap();
+        assert_eq!(tree, before);
+        assert!(before
+            .valid_roots()
+            .iter()
+            .all(|root| tree.is_known_root(*root)));
+    }
+
+    #[test]
+    fn test_default_snapshot_restores_an_empty_tree() {
+        let mut tree = PoseidonMerkleTree::default();
+        tree.insert(&leaf(1, 0)).unwrap();
+        tree.restore(&TreeSnapshot::default()).unwrap();
+        assert_eq!(tree, PoseidonMerkleTree::default());
+    }
+
+    #[test]
+    fn test_restore_refuses_foreign_snapshots() {
+        let mut tree = PoseidonMerkleTree::new(4).unwrap();
+        tree.insert(&leaf(1, 0)).unwrap();
+        let before = tree.clone();
+
+        let other = PoseidonMerkleTree::new(5).unwrap().snapshot();
+        assert_eq!(
+            tree.restore(&other),
+            Err(PoseidonMerkleTreeError::SnapshotMismatch)
+        );
+
+        let mut corrupted = before.snapshot();
+        corrupted.next_index = 17;
+        assert_eq!(
+            tree.restore(&corrupted),
+            Err(PoseidonMerkleTreeError::NextIndexBeyondCapacity)
+        );
+        let mut corrupted = before.snapshot();
+        corrupted.filled_subtrees.pop();
+        assert_eq!(
+            tree.restore(&corrupted),
+            Err(PoseidonMerkleTreeError::FilledSubtreesMismatch)
+        );
+        assert_eq!(tree, before);
+    }
+}
@@ -0,0 +1,140 @@
+use alloc::vec::Vec;
+
+use crate::{MerkleProof, PoseidonMerkleTreeError, MAX_LEVELS};
+
+const WORD: usize = 32;
+
+impl MerkleProof {
+    // `abi.encode(bytes32[] pathElements, uint256 pathIndices)`, where bit `level` of
+    // pathIndices is the side of the path node at that level, as in `insert`
+    pub fn to_solidity_calldata(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(WORD * (3 + self.siblings.len()));
+
+        // Head: offset of the dynamic array, then the static index word
+        data.extend_from_slice(&word(2 * WORD as u32));
+        data.extend_from_slice(&word(self.index));
+
+        // Tail: array length followed by its elements
+        data.extend_from_slice(&word(self.siblings.len() as u32));
+        for sibling in &self.siblings {
+            data.extend_from_slice(sibling);
+        }
+
+        data
+    }
+
+    // The calldata only carries the path, so the proven leaf is supplied by the caller
+    pub fn from_solidity_calldata(
+        leaf: &[u8; 32],
+        data: &[u8],
+    ) -> Result<MerkleProof, PoseidonMerkleTreeError> {
+        if data.len() < 3 * WORD || read_word(&data[..WORD])? != 2 * WORD as u32 {
+            return Err(PoseidonMerkleTreeError::InvalidCalldata);
+        }
+        let index = read_word(&data[WORD..2 * WORD])?;
+        let len = read_word(&data[2 * WORD..3 * WORD])? as usize;
+
+        if len > MAX_LEVELS || data.len() != WORD * (3 + len) || (index as u64) >> len != 0 {
+            return Err(PoseidonMerkleTreeError::InvalidCalldata);
+        }
+
+        let siblings = data[3 * WORD..]
+            .chunks_exact(WORD)
+            .map(|chunk| {
+                let mut sibling = [0u8; 32];
+                sibling.copy_from_slice(chunk);
+                sibling
+            })
+            .collect();
+
+        Ok(MerkleProof {
+            leaf: *leaf,
+            index,
+            siblings,
+        })
+    }
+}
+
+fn word(value: u32) -> [u8; WORD] {
+    let mut word = [0u8; WORD];
+    word[WORD - 4..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+// Reads a big-endian uint256 word that is expected to fit in a u32
+fn read_word(word: &[u8]) -> Result<u32, PoseidonMerkleTreeError> {
+    if word[..WORD - 4].iter().any(|byte| *byte != 0) {
+        return Err(PoseidonMerkleTreeError::InvalidCalldata);
+    }
+    let mut value = [0u8; 4];
+    value.copy_from_slice(&word[WORD - 4..]);
+    Ok(u32::from_be_bytes(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FullPoseidonMerkleTree;
+
+    fn sample_proof() -> MerkleProof {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        for i in 0..6u8 {
+            tree.insert(&[i + 1; 32]).unwrap();
+        }
+        tree.get_proof(5).unwrap()
+    }
+
+    #[test]
+    fn test_solidity_calldata_layout() {
+        let proof = sample_proof();
+        let data = proof.to_solidity_calldata();
+
+        assert_eq!(data.len(), 32 * 6);
+        assert_eq!(data[31], 0x40);
+        assert_eq!(data[63], 5);
+        assert_eq!(data[95], 3);
+        assert_eq!(&data[96..128], &proof.siblings[0]);
+        assert_eq!(&data[160..192], &proof.siblings[2]);
+    }
+
+    #[test]
+    fn test_solidity_calldata_round_trip() {
+        let proof = sample_proof();
+        let data = proof.to_solidity_calldata();
+
+        let decoded = MerkleProof::from_solidity_calldata(&proof.leaf, &data).unwrap();
+        assert_eq!(decoded, proof);
+        assert_eq!(decoded.to_solidity_calldata(), data);
+    }
+
+    #[test]
+    fn test_solidity_calldata_rejects_malformed_input() {
+        let proof = sample_proof();
+        let data = proof.to_solidity_calldata();
+
+        let truncated = &data[..data.len() - 1];
+        assert_eq!(
+            MerkleProof::from_solidity_calldata(&proof.leaf, truncated),
+            Err(PoseidonMerkleTreeError::InvalidCalldata)
+        );
+        assert_eq!(
+            MerkleProof::from_solidity_calldata(&proof.leaf, &data[..40]),
+            Err(PoseidonMerkleTreeError::InvalidCalldata)
+        );
+
+        let mut too_long = data.clone();
+        too_long[95] = MAX_LEVELS as u8 + 1;
+        too_long.resize(32 * (3 + MAX_LEVELS + 1), 0);
+        assert_eq!(
+            MerkleProof::from_solidity_calldata(&proof.leaf, &too_long),
+            Err(PoseidonMerkleTreeError::InvalidCalldata)
+        );
+
+        let mut huge_index = data.clone();
+        huge_index[32] = 1;
+        assert_eq!(
+            MerkleProof::from_solidity_calldata(&proof.leaf, &huge_index),
+            Err(PoseidonMerkleTreeError::InvalidCalldata)
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::proof::position_at;
+use crate::{
+    hash_pair, lock_poseidon, zero_hash, MerkleProof, PoseidonMerkleTreeError, MAX_LEVELS,
+    ZERO_HASHES,
+};
+
+// Fixed-depth tree addressed by key where every absent key holds the `zero_hash(0)` leaf, so
+// untouched subtrees hash to the same zero chain as the incremental tree
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct SparsePoseidonMerkleTree {
+    pub levels: u32,
+    // Only nodes that differ from the zero chain are stored, keyed by (level, position)
+    nodes: BTreeMap<(u32, u32), [u8; 32]>,
+}
+
+impl SparsePoseidonMerkleTree {
+    pub fn new(levels: u32) -> Result<SparsePoseidonMerkleTree, PoseidonMerkleTreeError> {
+        if levels == 0 || levels > MAX_LEVELS as u32 {
+            return Err(PoseidonMerkleTreeError::invalid_levels(levels));
+        }
+
+        Ok(SparsePoseidonMerkleTree {
+            levels,
+            nodes: BTreeMap::new(),
+        })
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.node(self.levels, 0)
+    }
+
+    pub fn get(&self, key: u32) -> Option<[u8; 32]> {
+        self.nodes.get(&(0, key)).copied()
+    }
+
+    // Writing the default `zero_hash(0)` leaf removes the key
+    pub fn insert(&mut self, key: u32, value: &[u8; 32]) -> Result<(), PoseidonMerkleTreeError> {
+        self.check_key(key)?;
+
+        let mut poseidon = lock_poseidon();
+
+        // Hash the whole path before writing anything so a failure leaves the tree untouched
+        let mut updates = Vec::with_capacity(self.levels as usize + 1);
+        let mut current_level_hash = *value;
+        updates.push(current_level_hash);
+        for level in 0..self.levels {
+            let position = key >> level;
+            let sibling = self.node(level, position ^ 1);
+            current_level_hash = if position.is_multiple_of(2) {
+                hash_pair(&mut poseidon, &current_level_hash, &sibling)?
+            } else {
+                hash_pair(&mut poseidon, &sibling, &current_level_hash)?
+            };
+            updates.push(current_level_hash);
+        }
+
+        for (level, node) in updates.into_iter().enumerate() {
+            let slot = (level as u32, position_at(key, level));
+            if zero_hash(level as u32) == Ok(node) {
+                self.nodes.remove(&slot);
+            } else {
+                self.nodes.insert(slot, node);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn prove_inclusion(&self, key: u32) -> Result<MerkleProof, PoseidonMerkleTreeError> {
+        self.check_key(key)?;
+        let leaf = self.get(key).ok_or(PoseidonMerkleTreeError::KeyNotFound)?;
+        Ok(self.proof(key, leaf))
+    }
+
+    // The returned proof carries the default `zero_hash(0)` leaf and verifies against `root()`
+    // only while the key is absent
+    pub fn prove_exclusion(&self, key: u32) -> Result<MerkleProof, PoseidonMerkleTreeError> {
+        self.check_key(key)?;
+        if self.get(key).is_some() {
+            return Err(PoseidonMerkleTreeError::KeyAlreadyPresent);
+        }
+        Ok(self.proof(key, ZERO_HASHES[0]))
+    }
+
+    fn proof(&self, key: u32, leaf: [u8; 32]) -> MerkleProof {
+        let siblings = (0..self.levels)
+            .map(|level| self.node(level, (key >> level) ^ 1))
+            .collect();
+
+        MerkleProof {
+            leaf,
+            index: key,
+            siblings,
+        }
+    }
+
+    fn node(&self, level: u32, position: u32) -> [u8; 32] {
+        self.nodes
+            .get(&(level, position))
+            .copied()
+            .unwrap_or(ZERO_HASHES[level as usize])
+    }
+
+    fn check_key(&self, key: u32) -> Result<(), PoseidonMerkleTreeError> {
+        if key as u64 >= 1u64 << self.levels {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: key as u64,
+                len: 1u64 << self.levels,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zeros;
+    use crate::FullPoseidonMerkleTree;
+
+    #[test]
+    fn test_sparse_empty_root() {
+        let tree = SparsePoseidonMerkleTree::new(4).unwrap();
+        assert_eq!(tree.root(), zeros(4));
+        assert_eq!(
+            SparsePoseidonMerkleTree::new(0),
+            Err(PoseidonMerkleTreeError::invalid_levels(0))
+        );
+        assert_eq!(
+            SparsePoseidonMerkleTree::new(MAX_LEVELS as u32 + 1),
+            Err(PoseidonMerkleTreeError::invalid_levels(
+                MAX_LEVELS as u32 + 1
+            ))
+        );
+    }
+
+    #[test]
+    fn test_sparse_matches_full_tree() {
+        let mut sparse = SparsePoseidonMerkleTree::new(3).unwrap();
+        let mut full = FullPoseidonMerkleTree::new(3).unwrap();
+        for i in 0..8u8 {
+            sparse.insert(i as u32, &[i + 1; 32]).unwrap();
+            full.insert(&[i + 1; 32]).unwrap();
+        }
+
+        // Once every slot is written both trees describe exactly the same leaves
+        assert_eq!(sparse.root(), full.tree().last_root());
+        assert_eq!(
+            sparse.prove_inclusion(5).unwrap(),
+            full.get_proof(5).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_sparse_inclusion_and_exclusion() {
+        let mut tree = SparsePoseidonMerkleTree::new(5).unwrap();
+        tree.insert(3, &[7u8; 32]).unwrap();
+        tree.insert(20, &[9u8; 32]).unwrap();
+        let root = tree.root();
+
+        let inclusion = tree.prove_inclusion(20).unwrap();
+        assert_eq!(inclusion.leaf, [9u8; 32]);
+        assert!(inclusion.verify(&root).unwrap());
+
+        let exclusion = tree.prove_exclusion(4).unwrap();
+        assert_eq!(exclusion.leaf, zeros(0));
+        assert!(exclusion.verify(&root).unwrap());
+
+        assert_eq!(
+            tree.prove_inclusion(4),
+            Err(PoseidonMerkleTreeError::KeyNotFound)
+        );
+        assert_eq!(
+            tree.prove_exclusion(3),
+            Err(PoseidonMerkleTreeError::KeyAlreadyPresent)
+        );
+        assert_eq!(
+            tree.insert(32, &[1u8; 32]),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 32, len: 32 })
+        );
+    }
+
+    #[test]
+    fn test_sparse_delete_restores_root() {
+        let mut tree = SparsePoseidonMerkleTree::new(4).unwrap();
+        tree.insert(1, &[1u8; 32]).unwrap();
+        let root = tree.root();
+
+        tree.insert(9, &[2u8; 32]).unwrap();
+        assert_ne!(tree.root(), root);
+        tree.insert(9, &zeros(0)).unwrap();
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.get(9), None);
+
+        let mut expected = SparsePoseidonMerkleTree::new(4).unwrap();
+        expected.insert(1, &[1u8; 32]).unwrap();
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn test_sparse_borsh_round_trip() {
+        let mut tree = SparsePoseidonMerkleTree::new(4).unwrap();
+        tree.insert(2, &[3u8; 32]).unwrap();
+        tree.insert(11, &[4u8; 32]).unwrap();
+
+        let bytes = tree.try_to_vec().unwrap();
+        let decoded = SparsePoseidonMerkleTree::try_from_slice(&bytes).unwrap();
+        assert_eq!(decoded, tree);
+        assert_eq!(decoded.root(), tree.root());
+    }
+}
@@ -0,0 +1,181 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::zero_hash;
+
+// Where a `FullPoseidonMerkleTree` keeps its nodes. Positions that were never written report
+// `None` and are treated as empty subtrees, so a store only has to hold what was touched
+pub trait NodeStore {
+    fn get(&self, level: u32, index: u32) -> Option<[u8; 32]>;
+
+    fn put(&mut self, level: u32, index: u32, hash: [u8; 32]);
+}
+
+// Keeps every node in memory, one vector per level
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, Default, PartialEq)]
+pub struct MemoryNodeStore {
+    // nodes[0] holds the leaves; a level grows as far as its rightmost written position
+    pub(crate) nodes: Vec<Vec<[u8; 32]>>,
+}
+
+impl MemoryNodeStore {
+    pub fn new() -> MemoryNodeStore {
+        MemoryNodeStore::default()
+    }
+}
+
+impl NodeStore for MemoryNodeStore {
+    fn get(&self, level: u32, index: u32) -> Option<[u8; 32]> {
+        self.nodes.get(level as usize)?.get(index as usize).copied()
+    }
+
+    fn put(&mut self, level: u32, index: u32, hash: [u8; 32]) {
+        if self.nodes.len() <= level as usize {
+            self.nodes.resize(level as usize + 1, Vec::new());
+        }
+        let level_nodes = &mut self.nodes[level as usize];
+        if level_nodes.len() <= index as usize {
+            // No tree reaches past the zero chain, so such levels are padded with zero bytes
+            level_nodes.resize(index as usize + 1, zero_hash(level).unwrap_or_default());
+        }
+        level_nodes[index as usize] = hash;
+    }
+}
+
+// Write-through cache in front of a slower store, holding at most `capacity` recently used
+// nodes. Reads are served from the cache when possible and loaded from the backing store
+// otherwise
+#[derive(Debug)]
+pub struct CachedNodeStore<S: NodeStore> {
+    backing: S,
+    capacity: usize,
+    cache: RefCell<NodeCache>,
+}
+
+#[derive(Debug, Default)]
+struct NodeCache {
+    entries: BTreeMap<(u32, u32), ([u8; 32], u64)>,
+    // Last use of every cached node, oldest first
+    recency: BTreeMap<u64, (u32, u32)>,
+    clock: u64,
+    misses: u64,
+}
+
+impl<S: NodeStore> CachedNodeStore<S> {
+    pub fn new(backing: S, capacity: usize) -> CachedNodeStore<S> {
+        CachedNodeStore {
+            backing,
+            capacity,
+            cache: RefCell::new(NodeCache::default()),
+        }
+    }
+
+    pub fn backing(&self) -> &S {
+        &self.backing
+    }
+
+    pub fn cached_len(&self) -> usize {
+        self.cache.borrow().entries.len()
+    }
+
+    // Reads that had to go to the backing store
+    pub fn misses(&self) -> u64 {
+        self.cache.borrow().misses
+    }
+}
+
+impl NodeCache {
+    fn touch(&mut self, key: (u32, u32), hash: [u8; 32], capacity: usize) {
+        self.clock += 1;
+        if let Some((_, last_used)) = self.entries.insert(key, (hash, self.clock)) {
+            self.recency.remove(&last_used);
+        }
+        self.recency.insert(self.clock, key);
+
+        while self.entries.len() > capacity {
+            let (_, evicted) = self.recency.pop_first().unwrap();
+            self.entries.remove(&evicted);
+        }
+    }
+}
+
+impl<S: NodeStore> NodeStore for CachedNodeStore<S> {
+    fn get(&self, level: u32, index: u32) -> Option<[u8; 32]> {
+        let mut cache = self.cache.borrow_mut();
+        let hash = match cache.entries.get(&(level, index)) {
+            Some((hash, _)) => *hash,
+            None => {
+                cache.misses += 1;
+                self.backing.get(level, index)?
+            }
+        };
+        cache.touch((level, index), hash, self.capacity);
+        Some(hash)
+    }
+
+    fn put(&mut self, level: u32, index: u32, hash: [u8; 32]) {
+        self.backing.put(level, index, hash);
+        self.cache
+            .get_mut()
+            .touch((level, index), hash, self.capacity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FullPoseidonMerkleTree;
+
+    #[test]
+    fn test_memory_store_reports_unwritten_nodes() {
+        let mut store = MemoryNodeStore::new();
+        assert_eq!(store.get(0, 0), None);
+
+        store.put(2, 1, [7u8; 32]);
+        assert_eq!(store.get(2, 1), Some([7u8; 32]));
+        assert_eq!(store.get(2, 2), None);
+        assert_eq!(store.get(3, 0), None);
+    }
+
+    #[test]
+    fn test_cached_store_matches_memory_store() {
+        let mut memory = FullPoseidonMerkleTree::new(8).unwrap();
+        let store = CachedNodeStore::new(MemoryNodeStore::new(), 16);
+        let mut cached = FullPoseidonMerkleTree::with_store(8, store).unwrap();
+
+        for i in 0..40u8 {
+            memory.insert(&[i + 1; 32]).unwrap();
+            cached.insert(&[i + 1; 32]).unwrap();
+            assert_eq!(cached.get_node(8, 0), memory.get_node(8, 0));
+        }
+        assert_eq!(cached.tree(), memory.tree());
+        assert!(cached.store().cached_len() <= 16);
+
+        for index in [0, 17, 39] {
+            assert_eq!(cached.get_proof(index), memory.get_proof(index));
+        }
+        // Old leaves have long been evicted and were loaded from the backing store
+        assert!(cached.store().misses() > 0);
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let mut store = CachedNodeStore::new(MemoryNodeStore::new(), 2);
+        store.put(0, 0, [1u8; 32]);
+        store.put(0, 1, [2u8; 32]);
+
+        // Reading (0, 0) makes (0, 1) the eviction candidate
+        assert_eq!(store.get(0, 0), Some([1u8; 32]));
+        store.put(0, 2, [3u8; 32]);
+        assert_eq!(store.misses(), 0);
+
+        assert_eq!(store.get(0, 0), Some([1u8; 32]));
+        assert_eq!(store.misses(), 0);
+        assert_eq!(store.get(0, 1), Some([2u8; 32]));
+        assert_eq!(store.misses(), 1);
+        assert_eq!(store.cached_len(), 2);
+    }
+}
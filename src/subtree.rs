@@ -0,0 +1,149 @@
+use crate::{
+    fr_from_be_bytes, fr_to_be_bytes, hash_fr, hash_pair, lock_poseidon, PoseidonMerkleTree,
+    PoseidonMerkleTreeError,
+};
+
+// Root of a complete subtree over `leaves`, for use with `append_subtree`. The number of leaves
+// must be a power of two; the subtree then has `leaves.len().ilog2()` levels
+pub fn compute_subtree_root(leaves: &[[u8; 32]]) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+    if !leaves.len().is_power_of_two() {
+        // Reports the depth the leaves would have filled so far
+        let got = leaves.len().checked_ilog2().unwrap_or(0);
+        return Err(PoseidonMerkleTreeError::invalid_levels(got));
+    }
+
+    let mut poseidon = lock_poseidon();
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks_exact(2)
+            .map(|pair| hash_pair(&mut poseidon, &pair[0], &pair[1]))
+            .collect::<Result<_, _>>()?;
+    }
+
+    Ok(level[0])
+}
+
+impl PoseidonMerkleTree {
+    // Grafts a complete subtree of 2^k leaves, given only its root, onto the next 2^k slots.
+    // Only the `levels - k` nodes above the graft are hashed. The subtree's own leaves are never
+    // seen, so trees keeping a leaf log refuse the graft rather than lose track of them. Returns
+    // the index of the subtree's first leaf
+    pub fn append_subtree(
+        &mut self,
+        subtree_root: [u8; 32],
+        k: u32,
+    ) -> Result<u64, PoseidonMerkleTreeError> {
+        if k >= self.levels {
+            return Err(PoseidonMerkleTreeError::InvalidLevels {
+                got: k,
+                max: self.levels - 1,
+            });
+        }
+        if !self.next_index.is_multiple_of(1 << k) {
+            return Err(PoseidonMerkleTreeError::MisalignedSubtree);
+        }
+        if self.remaining() < 1 << k {
+            return Err(PoseidonMerkleTreeError::MerkleTreeFull {
+                capacity: self.capacity(),
+            });
+        }
+        if self.leaf_log.is_some() {
+            return Err(PoseidonMerkleTreeError::LeafLogUnsupported);
+        }
+
+        let mut current_level_hash = fr_from_be_bytes(&subtree_root)?;
+        let mut poseidon = self.hasher.checkout();
+        let poseidon = &mut *poseidon;
+
+        // The levels below `k` are not needed again: the next leaf starts a fresh subtree there
+        let mut current_index = self.next_index >> k;
+        for i in k as usize..self.levels as usize {
+            let (left, right) = if current_index.is_multiple_of(2) {
+                self.filled_subtrees[i] = current_level_hash;
+                (current_level_hash, self.zeros.fr()[i])
+            } else {
+                (self.filled_subtrees[i], current_level_hash)
+            };
+
+            current_level_hash = hash_fr(poseidon, left, right)?;
+            current_index /= 2;
+        }
+
+        self.next_index += 1 << k;
+        self.record_root(fr_to_be_bytes(&current_level_hash));
+
+        Ok(self.next_index - (1 << k))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current_root(tree: &PoseidonMerkleTree) -> [u8; 32] {
+        tree.last_root()
+    }
+
+    #[test]
+    fn test_append_subtree_matches_single_inserts() {
+        let mut grafted = PoseidonMerkleTree::new(6).unwrap();
+        let mut single = PoseidonMerkleTree::new(6).unwrap();
+
+        let leaves: Vec<[u8; 32]> = (0..16u8).map(|i| [i + 1; 32]).collect();
+        for leaf in &leaves {
+            single.insert(leaf).unwrap();
+        }
+
+        grafted
+            .append_subtree(compute_subtree_root(&leaves[..8]).unwrap(), 3)
+            .unwrap();
+        grafted
+            .append_subtree(compute_subtree_root(&leaves[8..12]).unwrap(), 2)
+            .unwrap();
+        grafted.insert(&leaves[12]).unwrap();
+        grafted.insert(&leaves[13]).unwrap();
+        assert_eq!(
+            grafted
+                .append_subtree(compute_subtree_root(&leaves[14..]).unwrap(), 1)
+                .unwrap(),
+            14
+        );
+        assert_eq!(current_root(&grafted), current_root(&single));
+
+        // Later inserts do not depend on the skipped lower levels
+        for i in 16..24u8 {
+            grafted.insert(&[i + 1; 32]).unwrap();
+            single.insert(&[i + 1; 32]).unwrap();
+            assert_eq!(current_root(&grafted), current_root(&single));
+        }
+    }
+
+    #[test]
+    fn test_append_subtree_rejects_bad_placement() {
+        let mut tree = PoseidonMerkleTree::new(4).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        let before = tree.clone();
+
+        assert_eq!(
+            tree.append_subtree([2u8; 32], 1),
+            Err(PoseidonMerkleTreeError::MisalignedSubtree)
+        );
+        assert_eq!(
+            tree.append_subtree([2u8; 32], 4),
+            Err(PoseidonMerkleTreeError::InvalidLevels { got: 4, max: 3 })
+        );
+        assert_eq!(tree, before);
+
+        let mut logged = PoseidonMerkleTree::with_leaf_log(4).unwrap();
+        assert_eq!(
+            logged.append_subtree([2u8; 32], 1),
+            Err(PoseidonMerkleTreeError::LeafLogUnsupported)
+        );
+        assert_eq!(
+            compute_subtree_root(&[[1u8; 32]; 3]),
+            Err(PoseidonMerkleTreeError::invalid_levels(1))
+        );
+    }
+}
@@ -0,0 +1,91 @@
+# Regenerates merkle_vectors.json with a standalone Python port of circomlibjs's
+# `poseidon([a, b])`, so the vectors do not depend on the Rust hasher they check. The round
+# constants are read from src/circom_t3.rs and the zero leaf from `zeros` in src/lib.rs;
+# every other zero is recomputed here. Run from the repository root:
+#
+#     python3 src/testdata/merkle_vectors.py
+import json
+import re
+
+P = 21888242871839275222246405745257275088548364400416034343698204186575808495617
+
+constants = [
+    sum(int(limb) << (64 * i) for i, limb in enumerate(re.findall(r"\d+", limbs)))
+    for limbs in re.findall(
+        r"BigInteger256::new\(\[([^\]]*)\]\)", open("src/circom_t3.rs").read()
+    )
+]
+MDS = [constants[0:3], constants[3:6], constants[6:9]]
+ARK = constants[9:]
+assert len(ARK) == 65 * 3
+
+
+def poseidon(left, right):
+    state = [0, left, right]
+    for round in range(65):
+        state = [(x + ARK[round * 3 + i]) % P for i, x in enumerate(state)]
+        if round < 4 or round >= 61:
+            state = [pow(x, 5, P) for x in state]
+        else:
+            state[0] = pow(state[0], 5, P)
+        state = [sum(MDS[i][j] * state[j] for j in range(3)) % P for i in range(3)]
+    return state[0]
+
+
+# The value circomlibjs's own tests expect
+assert poseidon(1, 2) == 7853200120776062878684798364095072458815029376092732009249414926327459813530
+
+lib = open("src/lib.rs").read()
+zero_leaf = lib[lib.index("fn zeros(i: u32)") :].split("1 => [")[0]
+zero_leaf = int.from_bytes(bytes(int(b, 16) for b in re.findall(r"0x([0-9a-f]{2})", zero_leaf)), "big")
+zeros = [zero_leaf]
+for _ in range(20):
+    zeros.append(poseidon(zeros[-1], zeros[-1]))
+
+
+def roots_after_each_insert(levels, leaves):
+    filled = zeros[:levels]
+    roots = []
+    for index, leaf in enumerate(leaves):
+        node = leaf
+        for level in range(levels):
+            if (index >> level) % 2 == 0:
+                filled[level] = node
+                node = poseidon(node, zeros[level])
+            else:
+                node = poseidon(filled[level], node)
+        roots.append(node)
+    return roots
+
+
+def hex32(value):
+    return "0x%064x" % value
+
+
+# Tornado Cash's ZERO_VALUE, keccak256("tornado") reduced into the field
+TORNADO_ZERO = 21663839004416932945382355908790599225266501822907911457504978515578255421292
+
+sequences = {
+    2: [1, 2, 3, 4],
+    5: list(range(1, 32)) + [P - 1],
+    20: [TORNADO_ZERO, P - 1, 0] + [(i * 0x9E3779B97F4A7C15) % P for i in range(1, 38)],
+}
+pairs = [(1, 2), (0, 0), (P - 1, TORNADO_ZERO)]
+
+vectors = {
+    "hashes": [
+        {"left": hex32(left), "right": hex32(right), "hash": hex32(poseidon(left, right))}
+        for left, right in pairs
+    ],
+    "trees": [
+        {
+            "levels": levels,
+            "leaves": [hex32(leaf) for leaf in leaves],
+            "roots": [hex32(root) for root in roots_after_each_insert(levels, leaves)],
+        }
+        for levels, leaves in sequences.items()
+    ],
+}
+with open("src/testdata/merkle_vectors.json", "w") as out:
+    json.dump(vectors, out, indent=2)
+    out.write("\n")
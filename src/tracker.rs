@@ -0,0 +1,210 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+
+use crate::{fr_to_be_bytes, MerkleProof, PoseidonMerkleTree, PoseidonMerkleTreeError};
+
+// Keeps the authentication paths of a few watched leaves current as the tree they belong to
+// grows, by patching only the siblings each new leaf's path touches
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProofTracker {
+    // Shadow copy of the tracked tree, used to hash the path of every inserted leaf
+    tree: PoseidonMerkleTree,
+    proofs: BTreeMap<u32, MerkleProof>,
+    // Indices registered before their leaf has been inserted
+    pending: BTreeSet<u32>,
+}
+
+impl ProofTracker {
+    pub fn new(tree: &PoseidonMerkleTree) -> ProofTracker {
+        ProofTracker {
+            tree: tree.clone(),
+            proofs: BTreeMap::new(),
+            pending: BTreeSet::new(),
+        }
+    }
+
+    pub fn tree(&self) -> &PoseidonMerkleTree {
+        &self.tree
+    }
+
+    // Registers a leaf that has not been inserted yet; its proof is built when it arrives
+    pub fn watch(&mut self, index: u32) -> Result<(), PoseidonMerkleTreeError> {
+        if (index as u64) < self.tree.next_index || index as u64 >= self.tree.capacity() {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: index as u64,
+                len: self.tree.capacity(),
+            });
+        }
+        self.pending.insert(index);
+        Ok(())
+    }
+
+    // Registers an already inserted leaf from a proof against the current root
+    pub fn track(&mut self, proof: MerkleProof) -> Result<(), PoseidonMerkleTreeError> {
+        if proof.index as u64 >= self.tree.next_index {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: proof.index as u64,
+                len: self.tree.next_index,
+            });
+        }
+        let root = self.tree.last_root();
+        if proof.siblings.len() != self.tree.levels as usize || !proof.verify(&root)? {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+        self.proofs.insert(proof.index, proof);
+        Ok(())
+    }
+
+    pub fn untrack(&mut self, index: u32) {
+        self.proofs.remove(&index);
+        self.pending.remove(&index);
+    }
+
+    // Must be called with every leaf inserted into the tracked tree, in order
+    pub fn on_insert(
+        &mut self,
+        leaf: &[u8; 32],
+        index: u32,
+    ) -> Result<(), PoseidonMerkleTreeError> {
+        if index as u64 != self.tree.next_index {
+            return Err(PoseidonMerkleTreeError::IndexOutOfBounds {
+                index: index as u64,
+                len: self.tree.next_index,
+            });
+        }
+        let path = self.tree.append(leaf)?;
+
+        // The new node at `level` is the sibling of every tracked path whose own node
+        // at that level sits right next to it
+        for (tracked, proof) in self.proofs.iter_mut() {
+            for (level, sibling) in proof.siblings.iter_mut().enumerate() {
+                if (tracked >> level) ^ 1 == index >> level {
+                    *sibling = path[level];
+                }
+            }
+        }
+
+        if self.pending.remove(&index) {
+            // Left siblings of the newest leaf are the frontier, right siblings are still empty
+            let siblings = (0..self.tree.levels)
+                .map(|level| {
+                    if (index >> level) % 2 == 1 {
+                        fr_to_be_bytes(&self.tree.filled_subtrees[level as usize])
+                    } else {
+                        self.tree.zeros.bytes(level)
+                    }
+                })
+                .collect();
+            self.proofs.insert(
+                index,
+                MerkleProof {
+                    leaf: *leaf,
+                    index,
+                    siblings,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn proof_for(&self, index: u32) -> Option<&MerkleProof> {
+        self.proofs.get(&index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FullPoseidonMerkleTree, ZeroPreset};
+
+    fn leaf(i: u32) -> [u8; 32] {
+        let mut leaf = [0u8; 32];
+        leaf[28..].copy_from_slice(&(i + 1).to_be_bytes());
+        leaf
+    }
+
+    #[test]
+    fn test_tracker_follows_full_tree() {
+        let mut tree = FullPoseidonMerkleTree::new(5).unwrap();
+        let mut tracker = ProofTracker::new(tree.tree());
+        tracker.watch(1).unwrap();
+        tracker.watch(6).unwrap();
+
+        for i in 0..20 {
+            tree.insert(&leaf(i)).unwrap();
+            tracker.on_insert(&leaf(i), i).unwrap();
+            assert_eq!(tracker.tree(), tree.tree());
+
+            let root = tree.tree().last_root();
+            for index in [1, 6] {
+                if index <= i {
+                    let proof = tracker.proof_for(index).unwrap();
+                    assert_eq!(proof, &tree.get_proof(index).unwrap());
+                    assert!(proof.verify(&root).unwrap());
+                } else {
+                    assert!(tracker.proof_for(index).is_none());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_tracker_most_recent_leaf() {
+        let mut tree = FullPoseidonMerkleTree::new(4).unwrap();
+        for i in 0..5 {
+            tree.insert(&leaf(i)).unwrap();
+        }
+
+        let mut tracker = ProofTracker::new(tree.tree());
+        tracker.watch(5).unwrap();
+        tree.insert(&leaf(5)).unwrap();
+        tracker.on_insert(&leaf(5), 5).unwrap();
+        assert_eq!(tracker.proof_for(5), Some(&tree.get_proof(5).unwrap()));
+
+        // Proofs registered after the fact keep up as well
+        tracker.track(tree.get_proof(2).unwrap()).unwrap();
+        tree.insert(&leaf(6)).unwrap();
+        tracker.on_insert(&leaf(6), 6).unwrap();
+        assert_eq!(tracker.proof_for(2), Some(&tree.get_proof(2).unwrap()));
+        assert_eq!(tracker.proof_for(5), Some(&tree.get_proof(5).unwrap()));
+    }
+
+    #[test]
+    fn test_tracker_follows_the_zero_preset() {
+        let mut tree =
+            FullPoseidonMerkleTree::new_with_zero_preset(4, ZeroPreset::Tornado).unwrap();
+        let mut tracker = ProofTracker::new(tree.tree());
+        tracker.watch(2).unwrap();
+        for i in 0..3 {
+            tree.insert(&leaf(i)).unwrap();
+            tracker.on_insert(&leaf(i), i).unwrap();
+        }
+
+        let proof = tracker.proof_for(2).unwrap();
+        assert_eq!(proof, &tree.get_proof(2).unwrap());
+        assert!(proof.verify(&tree.tree().last_root()).unwrap());
+    }
+
+    #[test]
+    fn test_tracker_rejects_inconsistent_input() {
+        let mut tree = FullPoseidonMerkleTree::new(3).unwrap();
+        tree.insert(&leaf(0)).unwrap();
+        let mut tracker = ProofTracker::new(tree.tree());
+
+        assert_eq!(
+            tracker.on_insert(&leaf(1), 2),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 2, len: 1 })
+        );
+        assert_eq!(
+            tracker.watch(0),
+            Err(PoseidonMerkleTreeError::IndexOutOfBounds { index: 0, len: 8 })
+        );
+
+        let mut proof = tree.get_proof(0).unwrap();
+        proof.leaf = leaf(7);
+        assert_eq!(
+            tracker.track(proof),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+    }
+}
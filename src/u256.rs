@@ -0,0 +1,151 @@
+use alloc::vec::Vec;
+
+use primitive_types::U256;
+
+use crate::proof::index_fits;
+use crate::{validate_leaf, MerkleProof, PoseidonMerkleTreeError, MAX_LEVELS};
+
+// Conversions for the `u256` feature, for tooling that passes hashes around as `U256`. Hashes
+// are the big-endian bytes of the number, as Solidity and circom use them
+
+// Fails with `InvalidLeaf` for values at or above the BN254 scalar field modulus, which a
+// contract would otherwise reduce into a different leaf
+pub fn leaf_from_u256(value: U256) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+    let bytes = value.to_big_endian();
+    validate_leaf(&bytes)?;
+    Ok(bytes)
+}
+
+pub fn root_to_u256(root: &[u8; 32]) -> U256 {
+    U256::from_big_endian(root)
+}
+
+// `MerkleProof` with every hash as a `U256`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct U256Proof {
+    pub leaf: U256,
+    pub index: u32,
+    pub siblings: Vec<U256>,
+}
+
+impl From<&MerkleProof> for U256Proof {
+    fn from(proof: &MerkleProof) -> U256Proof {
+        U256Proof {
+            leaf: root_to_u256(&proof.leaf),
+            index: proof.index,
+            siblings: proof.siblings.iter().map(root_to_u256).collect(),
+        }
+    }
+}
+
+// Siblings are field elements too, so they are checked like the leaf. The shape is checked
+// as when reading a proof with Borsh
+impl TryFrom<&U256Proof> for MerkleProof {
+    type Error = PoseidonMerkleTreeError;
+
+    fn try_from(proof: &U256Proof) -> Result<MerkleProof, PoseidonMerkleTreeError> {
+        if proof.siblings.len() > MAX_LEVELS || !index_fits(proof.index, proof.siblings.len()) {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+
+        Ok(MerkleProof {
+            leaf: leaf_from_u256(proof.leaf)?,
+            index: proof.index,
+            siblings: proof
+                .siblings
+                .iter()
+                .map(|sibling| leaf_from_u256(*sibling))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_bn254::Fr;
+    use ark_ff::PrimeField;
+
+    use super::*;
+    use crate::{FullPoseidonMerkleTree, PoseidonMerkleTree};
+
+    // Tornado Cash's `FIELD_SIZE` and `ZERO_VALUE`, keccak256("tornado") reduced into the field
+    fn field_size() -> U256 {
+        U256::from_dec_str(
+            "21888242871839275222246405745257275088548364400416034343698204186575808495617",
+        )
+        .unwrap()
+    }
+
+    fn zero_value() -> U256 {
+        U256::from_dec_str(
+            "21663839004416932945382355908790599225266501822907911457504978515578255421292",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_leaf_from_u256_is_big_endian() {
+        let leaf = leaf_from_u256(zero_value()).unwrap();
+        assert_eq!(leaf[..4], [0x2f, 0xe5, 0x4c, 0x60]);
+        assert_eq!(leaf[28..], [0x48, 0x99, 0xaf, 0x6c]);
+        assert_eq!(root_to_u256(&leaf), zero_value());
+
+        assert_eq!(leaf_from_u256(U256::from(1u8)).unwrap()[31], 1);
+        assert_eq!(leaf_from_u256(U256::zero()), Ok([0; 32]));
+
+        let mut tree = PoseidonMerkleTree::new(3).unwrap();
+        tree.insert(&leaf).unwrap();
+        assert!(tree.is_known_root(leaf_from_u256(root_to_u256(&tree.roots[1])).unwrap()));
+    }
+
+    #[test]
+    fn test_rejects_values_outside_the_field() {
+        assert_eq!(
+            field_size().to_little_endian()[..],
+            Fr::MODULUS
+                .0
+                .iter()
+                .flat_map(|limb| limb.to_le_bytes())
+                .collect::<Vec<_>>()
+        );
+
+        let below = field_size() - 1;
+        assert_eq!(root_to_u256(&leaf_from_u256(below).unwrap()), below);
+        for outside in [field_size(), field_size() + 1, U256::MAX] {
+            assert_eq!(
+                leaf_from_u256(outside),
+                Err(PoseidonMerkleTreeError::invalid_leaf(
+                    &outside.to_big_endian()
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn test_proof_round_trip() {
+        let mut tree = FullPoseidonMerkleTree::new(4).unwrap();
+        tree.insert(&leaf_from_u256(zero_value()).unwrap()).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        let proof = tree.get_proof(0).unwrap();
+
+        let converted = U256Proof::from(&proof);
+        assert_eq!(converted.leaf, zero_value());
+        assert_eq!(converted.siblings.len(), 4);
+        assert_eq!(MerkleProof::try_from(&converted), Ok(proof));
+
+        let mut outside = converted.clone();
+        outside.siblings[2] = field_size();
+        assert_eq!(
+            MerkleProof::try_from(&outside),
+            Err(PoseidonMerkleTreeError::invalid_leaf(
+                &field_size().to_big_endian()
+            ))
+        );
+        let mut too_deep = converted;
+        too_deep.index = 16;
+        assert_eq!(
+            MerkleProof::try_from(&too_deep),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+    }
+}
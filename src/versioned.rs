@@ -0,0 +1,187 @@
+use borsh::BorshSerialize;
+
+use alloc::vec::Vec;
+
+use crate::{PoseidonMerkleTree, PoseidonMerkleTreeError};
+
+// Starts every versioned payload. Read as the `levels` of an unversioned tree it would be far
+// beyond MAX_LEVELS, so the two can never be confused
+pub const VERSION_MAGIC: [u8; 4] = *b"PMTv";
+
+// Version written by `serialize_versioned`. Version 0 is the unversioned layout that came
+// before the envelope, version 1 the Borsh encoding alone and version 2 adds a byte for
+// `accept_initial_root` in front of it. Version 3 is the Borsh encoding again, which now
+// stores the options itself
+pub const CURRENT_VERSION: u8 = 3;
+
+impl PoseidonMerkleTree {
+    // The Borsh encoding behind `VERSION_MAGIC` and `CURRENT_VERSION`, so later layout
+    // changes can still tell old payloads apart
+    pub fn serialize_versioned(&self) -> borsh::maybestd::io::Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(VERSION_MAGIC.len() + 1 + self.serialized_size());
+        bytes.extend_from_slice(&VERSION_MAGIC);
+        bytes.push(CURRENT_VERSION);
+        self.serialize(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    // Reads any payload this crate has ever written. Bytes without the magic are version 0,
+    // either the raw Borsh layout or the older one with a u32 `next_index`. Payloads before
+    // version 2 accept the initial root. The tree must also pass `validate`
+    pub fn deserialize_versioned(
+        bytes: &[u8],
+    ) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        match bytes.strip_prefix(&VERSION_MAGIC) {
+            None => read_unversioned(bytes),
+            Some([1 | 3, payload @ ..]) => PoseidonMerkleTree::try_from_slice_validated(payload),
+            Some([2, accept @ (0 | 1), payload @ ..]) => {
+                let mut tree = PoseidonMerkleTree::try_from_slice_validated(payload)?;
+                tree.accept_initial_root = *accept == 1;
+                Ok(tree)
+            }
+            Some([2, ..]) => Err(PoseidonMerkleTreeError::InvalidEncoding),
+            Some([version, ..]) => Err(PoseidonMerkleTreeError::UnsupportedVersion(*version)),
+            Some([]) => Err(PoseidonMerkleTreeError::InvalidEncoding),
+        }
+    }
+}
+
+// The legacy layout ends four bytes short of a u64 `next_index` and has no leaf log, so bytes
+// in one layout never read as the other. Errors are reported for the current layout
+fn read_unversioned(bytes: &[u8]) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+    PoseidonMerkleTree::try_from_slice_validated(bytes).or_else(|error| {
+        let tree = PoseidonMerkleTree::try_from_legacy_slice(bytes).map_err(|_| error)?;
+        tree.validate()?;
+        Ok(tree)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn five_leaves(tree: &mut PoseidonMerkleTree) {
+        for i in 1..=5u8 {
+            tree.insert(&[i; 32]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_golden_payloads_still_load() {
+        let mut plain = PoseidonMerkleTree::new(3).unwrap();
+        five_leaves(&mut plain);
+        let mut logged = PoseidonMerkleTree::with_leaf_log(3).unwrap();
+        five_leaves(&mut logged);
+
+        let fixtures: [(&[u8], &PoseidonMerkleTree); 8] = [
+            // Version 0, before `next_index` became a u64
+            (
+                include_bytes!("testdata/tree_levels3_5_leaves_legacy.bin"),
+                &plain,
+            ),
+            // Version 0
+            (include_bytes!("testdata/tree_levels3_5_leaves.bin"), &plain),
+            (
+                include_bytes!("testdata/tree_levels3_5_leaves_log.bin"),
+                &logged,
+            ),
+            // Version 1
+            (
+                include_bytes!("testdata/tree_levels3_5_leaves_v1.bin"),
+                &plain,
+            ),
+            (
+                include_bytes!("testdata/tree_levels3_5_leaves_log_v1.bin"),
+                &logged,
+            ),
+            // Version 2
+            (
+                include_bytes!("testdata/tree_levels3_5_leaves_v2.bin"),
+                &plain,
+            ),
+            // Version 3, with the options of each tag
+            (
+                include_bytes!("testdata/tree_levels3_5_leaves_v3.bin"),
+                &plain,
+            ),
+            (
+                include_bytes!("testdata/tree_levels3_5_leaves_v3_tag4.bin"),
+                &plain,
+            ),
+        ];
+        for (bytes, expected) in fixtures {
+            assert_eq!(
+                &PoseidonMerkleTree::deserialize_versioned(bytes).unwrap(),
+                expected
+            );
+        }
+
+        assert_eq!(
+            plain.serialize_versioned().unwrap(),
+            include_bytes!("testdata/tree_levels3_5_leaves_v3_tag4.bin")
+        );
+    }
+
+    #[test]
+    fn test_versioned_round_trip() {
+        let mut tree = PoseidonMerkleTree::new_with_history(4, 7).unwrap();
+        five_leaves(&mut tree);
+
+        let bytes = tree.serialize_versioned().unwrap();
+        assert_eq!(&bytes[..5], b"PMTv\x03");
+        assert_eq!(bytes.len(), 5 + tree.serialized_size());
+        assert_eq!(PoseidonMerkleTree::deserialize_versioned(&bytes), Ok(tree));
+    }
+
+    #[test]
+    fn test_keeps_accept_initial_root() {
+        let tree = PoseidonMerkleTree::builder()
+            .levels(3)
+            .accept_initial_root(false)
+            .build()
+            .unwrap();
+        let bytes = tree.serialize_versioned().unwrap();
+        assert_eq!(bytes.last(), Some(&0));
+        let read = PoseidonMerkleTree::deserialize_versioned(&bytes).unwrap();
+        assert!(!read.accepts_initial_root());
+        assert!(!read.is_known_root(read.last_root()));
+        assert_eq!(read, tree);
+
+        // Version 2 kept the setting in front of a Borsh encoding without options
+        let legacy = &bytes[5..bytes.len() - crate::OPTIONS_SIZE];
+        let mut v2 = b"PMTv\x02\x00".to_vec();
+        v2.extend_from_slice(legacy);
+        assert_eq!(PoseidonMerkleTree::deserialize_versioned(&v2), Ok(tree));
+        v2[5] = 2;
+        assert_eq!(
+            PoseidonMerkleTree::deserialize_versioned(&v2),
+            Err(PoseidonMerkleTreeError::InvalidEncoding)
+        );
+    }
+
+    #[test]
+    fn test_rejects_unknown_versions() {
+        let mut bytes = PoseidonMerkleTree::new(3)
+            .unwrap()
+            .serialize_versioned()
+            .unwrap();
+        bytes[4] = 4;
+        assert_eq!(
+            PoseidonMerkleTree::deserialize_versioned(&bytes),
+            Err(PoseidonMerkleTreeError::UnsupportedVersion(4))
+        );
+
+        for truncated in [
+            &b"PMTv"[..],
+            &b"PMTv\x01"[..],
+            &b"PMTv\x02"[..],
+            &b"PM"[..],
+            &[],
+        ] {
+            assert_eq!(
+                PoseidonMerkleTree::deserialize_versioned(truncated),
+                Err(PoseidonMerkleTreeError::InvalidEncoding)
+            );
+        }
+    }
+}
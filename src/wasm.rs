@@ -0,0 +1,132 @@
+use js_sys::{Array, Object, Reflect};
+use wasm_bindgen::prelude::*;
+
+use crate::{encode_hash, FullPoseidonMerkleTree, MerkleProof, PoseidonMerkleTreeError};
+
+// A tree for JavaScript, e.g. a wallet rebuilding it from deposit events, that keeps every
+// node so it can prove any leaf. Every fallible method throws a `JsError` rather than
+// panicking, since a panic aborts the whole wasm instance
+#[wasm_bindgen]
+pub struct WasmMerkleTree {
+    tree: FullPoseidonMerkleTree,
+}
+
+#[wasm_bindgen]
+impl WasmMerkleTree {
+    #[wasm_bindgen(constructor)]
+    pub fn new(levels: u32) -> Result<WasmMerkleTree, JsError> {
+        let tree = FullPoseidonMerkleTree::new(levels).map_err(js_error)?;
+        Ok(WasmMerkleTree { tree })
+    }
+
+    // Returns the index the leaf was placed at
+    pub fn insert(&mut self, leaf: &[u8]) -> Result<u64, JsError> {
+        let leaf = bytes32(leaf, "leaf")?;
+        self.tree.insert(&leaf).map_err(js_error)
+    }
+
+    pub fn root(&self) -> Vec<u8> {
+        let tree = self.tree.tree();
+        tree.last_root().to_vec()
+    }
+
+    #[wasm_bindgen(js_name = isKnownRoot)]
+    pub fn is_known_root(&self, root: &[u8]) -> Result<bool, JsError> {
+        let root = bytes32(root, "root")?;
+        Ok(self.tree.is_known_root(root))
+    }
+
+    // `{ leaf, index, siblings }`, with every hash as a `0x`-prefixed hex string and the
+    // siblings ordered from the leaf level up
+    #[wasm_bindgen(js_name = getProof)]
+    pub fn get_proof(&self, index: u32) -> Result<Object, JsError> {
+        let proof = self.tree.get_proof(index).map_err(js_error)?;
+        proof_object(&proof).map_err(|_| JsError::new("Failed to build the proof object"))
+    }
+}
+
+fn proof_object(proof: &MerkleProof) -> Result<Object, JsValue> {
+    let siblings = proof
+        .siblings
+        .iter()
+        .map(|sibling| JsValue::from(encode_hash(sibling)))
+        .collect::<Array>();
+
+    let object = Object::new();
+    Reflect::set(&object, &"leaf".into(), &encode_hash(&proof.leaf).into())?;
+    Reflect::set(&object, &"index".into(), &proof.index.into())?;
+    Reflect::set(&object, &"siblings".into(), &siblings)?;
+    Ok(object)
+}
+
+fn bytes32(bytes: &[u8], name: &str) -> Result<[u8; 32], JsError> {
+    bytes.try_into().map_err(|_| {
+        JsError::new(&format!(
+            "Expected a 32-byte {name}, got {} bytes",
+            bytes.len()
+        ))
+    })
+}
+
+fn js_error(error: PoseidonMerkleTreeError) -> JsError {
+    JsError::new(&error.to_string())
+}
+
+// Run with `wasm-pack test --node --features wasm`; `JsError` and `JsValue` only work inside a
+// JavaScript host
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+    use crate::verify_merkle_proof;
+
+    fn field(object: &Object, name: &str) -> JsValue {
+        Reflect::get(object, &name.into()).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_insert_and_proof_round_trip() {
+        let mut tree = WasmMerkleTree::new(4).unwrap();
+        for i in 1..=5u8 {
+            assert_eq!(tree.insert(&[i; 32]).unwrap(), i as u64 - 1);
+        }
+
+        let root: [u8; 32] = tree.root().try_into().unwrap();
+        assert!(tree.is_known_root(&root).unwrap());
+        assert!(!tree.is_known_root(&[9; 32]).unwrap());
+
+        let proof = tree.get_proof(2).unwrap();
+        assert_eq!(
+            field(&proof, "leaf").as_string().unwrap(),
+            encode_hash(&[3; 32])
+        );
+        assert_eq!(field(&proof, "index").as_f64(), Some(2.0));
+
+        let expected = tree.tree.get_proof(2).unwrap();
+        let siblings = Array::from(&field(&proof, "siblings"));
+        assert_eq!(siblings.length(), 4);
+        for (sibling, expected) in siblings.iter().zip(&expected.siblings) {
+            assert_eq!(sibling.as_string().unwrap(), encode_hash(expected));
+        }
+        assert!(
+            verify_merkle_proof(&expected.leaf, expected.index, &expected.siblings, &root).unwrap()
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_rejects_bad_input_without_panicking() {
+        assert!(WasmMerkleTree::new(0).is_err());
+
+        let mut tree = WasmMerkleTree::new(1).unwrap();
+        assert!(tree.insert(&[1; 31]).is_err());
+        assert!(tree.insert(&[0xff; 32]).is_err());
+        assert!(tree.is_known_root(&[1; 33]).is_err());
+        assert!(tree.get_proof(0).is_err());
+
+        tree.insert(&[1; 32]).unwrap();
+        tree.insert(&[2; 32]).unwrap();
+        assert!(tree.insert(&[3; 32]).is_err());
+        assert!(tree.get_proof(1).is_ok());
+    }
+}
@@ -0,0 +1,98 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{encode_hash, DepositNote, MerkleProof, PoseidonMerkleTreeError};
+
+// Everything the Tornado-style withdraw circuit takes for one note: the public root,
+// nullifier hash, recipient, relayer, fee and refund, then the private nullifier, secret and
+// path. The path is the proof's own, siblings from the leaf up with one direction bit each
+#[derive(Clone, PartialEq)]
+pub struct WithdrawInputs {
+    pub root: [u8; 32],
+    pub nullifier_hash: [u8; 32],
+    pub recipient: [u8; 20],
+    pub relayer: [u8; 20],
+    pub fee: u64,
+    pub refund: u64,
+    pub nullifier: [u8; 32],
+    pub secret: [u8; 32],
+    pub path_elements: Vec<[u8; 32]>,
+    pub path_indices: Vec<u8>,
+}
+
+impl WithdrawInputs {
+    // Addresses are the 20 bytes an EVM address is written as. Fails with `InvalidProof` when
+    // `proof` is not for the note's commitment, and with `InvalidLeaf` for a sibling outside the
+    // field
+    pub fn build(
+        proof: &MerkleProof,
+        note: &DepositNote,
+        recipient: [u8; 20],
+        relayer: [u8; 20],
+        fee: u64,
+        refund: u64,
+    ) -> Result<WithdrawInputs, PoseidonMerkleTreeError> {
+        if proof.leaf != note.commitment {
+            return Err(PoseidonMerkleTreeError::InvalidProof);
+        }
+
+        Ok(WithdrawInputs {
+            root: proof.root()?,
+            nullifier_hash: note.nullifier_hash,
+            recipient,
+            relayer,
+            fee,
+            refund,
+            nullifier: note.nullifier,
+            secret: note.secret,
+            path_elements: proof.siblings.clone(),
+            path_indices: proof.path_indices_vec(),
+        })
+    }
+}
+
+// Leaves the note's secrets out, as `DepositNote` does
+impl fmt::Debug for WithdrawInputs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithdrawInputs")
+            .field("root", &encode_hash(&self.root))
+            .field("nullifier_hash", &encode_hash(&self.nullifier_hash))
+            .field("recipient", &self.recipient)
+            .field("relayer", &self.relayer)
+            .field("fee", &self.fee)
+            .field("refund", &self.refund)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FullPoseidonMerkleTree;
+
+    #[test]
+    fn test_build_follows_the_proof() {
+        let mut secret = [0u8; 32];
+        secret[31] = 2;
+        let note = DepositNote::from_secrets("voidify", "0.1", 1, secret, [7; 32]).unwrap();
+        let mut tree = FullPoseidonMerkleTree::new(4).unwrap();
+        for i in 1..=5u8 {
+            tree.insert(&[i; 32]).unwrap();
+        }
+        tree.insert(&note.commitment).unwrap();
+        let proof = tree.get_proof(5).unwrap();
+
+        let inputs = WithdrawInputs::build(&proof, &note, [0x11; 20], [0x22; 20], 3, 4).unwrap();
+        assert_eq!(inputs.root, tree.tree().last_root());
+        assert_eq!(inputs.path_indices, [1, 0, 1, 0]);
+        assert_eq!(inputs.path_elements, proof.siblings);
+        assert_eq!((inputs.nullifier, inputs.secret), ([7; 32], secret));
+        assert!(!format!("{inputs:?}").contains(&encode_hash(&[7; 32])));
+
+        let other = tree.get_proof(4).unwrap();
+        assert_eq!(
+            WithdrawInputs::build(&other, &note, [0x11; 20], [0x22; 20], 3, 4),
+            Err(PoseidonMerkleTreeError::InvalidProof)
+        );
+    }
+}
@@ -0,0 +1,521 @@
+use alloc::boxed::Box;
+use core::fmt;
+
+use ark_bn254::Fr;
+use ark_ff::PrimeField;
+use light_poseidon::Poseidon;
+use once_cell::race::OnceBox;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    fr_to_be_bytes, hash_fr, leaf_fr, new_poseidon, zeros_fr, PoseidonMerkleTree,
+    PoseidonMerkleTreeError, MAX_LEVELS, ZERO_HASHES,
+};
+
+// Tornado Cash's `ZERO_VALUE`, keccak256("tornado") reduced into the field
+const TORNADO_SEED: [u8; 32] = [
+    0x2f, 0xe5, 0x4c, 0x60, 0xd3, 0xac, 0xab, 0xf3, 0x34, 0x3a, 0x35, 0xb6, 0xeb, 0xa1, 0x5d, 0xb4,
+    0x82, 0x1b, 0x34, 0x0f, 0x76, 0xe7, 0x41, 0xe2, 0x24, 0x96, 0x85, 0xed, 0x48, 0x99, 0xaf, 0x6c,
+];
+
+// The empty leaf a tree is built on. Every empty subtree hashes up from it, so the preset
+// decides the initial root and every zero sibling in a proof
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ZeroPreset {
+    // This crate's own chain, seeded with sha("voidify")
+    #[default]
+    Voidify,
+    // The chain the Poseidon deployments of Tornado Cash hardcode, so roots match theirs
+    Tornado,
+    // Any field element as the empty leaf
+    Custom([u8; 32]),
+}
+
+impl ZeroPreset {
+    // The tag and leaf a tree stores the preset as. The leaf is zero but for `Custom`
+    pub(crate) fn to_stored(self) -> (u8, [u8; 32]) {
+        match self {
+            ZeroPreset::Voidify => (0, [0; 32]),
+            ZeroPreset::Tornado => (1, [0; 32]),
+            ZeroPreset::Custom(leaf) => (2, leaf),
+        }
+    }
+
+    // `None` for an unknown tag, or a leaf next to one of the fixed presets
+    pub(crate) fn from_stored(tag: u8, leaf: [u8; 32]) -> Option<ZeroPreset> {
+        match tag {
+            0 if leaf == [0; 32] => Some(ZeroPreset::Voidify),
+            1 if leaf == [0; 32] => Some(ZeroPreset::Tornado),
+            2 => Some(ZeroPreset::Custom(leaf)),
+            _ => None,
+        }
+    }
+}
+
+// The zero value of every level for one preset. The fixed presets share a table built once;
+// a custom one is derived for each tree that uses it
+#[derive(Clone)]
+pub(crate) struct ZeroChain {
+    preset: ZeroPreset,
+    table: ZeroTable,
+}
+
+#[derive(Clone)]
+enum ZeroTable {
+    Shared(&'static [Fr; MAX_LEVELS + 1]),
+    Owned(Box<[Fr; MAX_LEVELS + 1]>),
+}
+
+impl ZeroChain {
+    pub(crate) fn new(preset: ZeroPreset) -> Result<ZeroChain, PoseidonMerkleTreeError> {
+        let table = match preset {
+            ZeroPreset::Voidify => ZeroTable::Shared(zeros_fr()),
+            ZeroPreset::Tornado => ZeroTable::Shared(tornado_zeros()),
+            ZeroPreset::Custom(seed) => ZeroTable::Owned(Box::new(derive_chain(leaf_fr(&seed)?))),
+        };
+        Ok(ZeroChain { preset, table })
+    }
+
+    pub(crate) fn preset(&self) -> ZeroPreset {
+        self.preset
+    }
+
+    pub(crate) fn fr(&self) -> &[Fr; MAX_LEVELS + 1] {
+        match &self.table {
+            ZeroTable::Shared(table) => table,
+            ZeroTable::Owned(table) => table,
+        }
+    }
+
+    pub(crate) fn bytes(&self, level: u32) -> [u8; 32] {
+        fr_to_be_bytes(&self.fr()[level as usize])
+    }
+}
+
+// The zero chain of a deployment's own domain seed, derived once and handed to
+// `PoseidonMerkleTreeBuilder::zero_values` for every tree built on it
+#[derive(Clone, Debug, PartialEq)]
+pub struct ZeroValues {
+    levels: u32,
+    chain: ZeroChain,
+}
+
+impl ZeroValues {
+    // Level 0 is the SHA-256 of `seed` reduced into the field, as the `Voidify` chain is
+    // made from "voidify", and each level above hashes two of the one below. Covers trees up
+    // to `levels` deep, which fails with `InvalidLevels` above `MAX_LEVELS`
+    pub fn derive(seed: &[u8], levels: u32) -> Result<ZeroValues, PoseidonMerkleTreeError> {
+        if levels as usize > MAX_LEVELS {
+            return Err(PoseidonMerkleTreeError::invalid_levels(levels));
+        }
+        let chain = ZeroChain::new(ZeroPreset::Custom(seed_leaf(seed)))?;
+        Ok(ZeroValues { levels, chain })
+    }
+
+    pub fn levels(&self) -> u32 {
+        self.levels
+    }
+
+    // The zero value of `level`, up to and including `levels`
+    pub fn get(&self, level: u32) -> Result<[u8; 32], PoseidonMerkleTreeError> {
+        if level > self.levels {
+            return Err(PoseidonMerkleTreeError::InvalidLevels {
+                got: level,
+                max: self.levels,
+            });
+        }
+        Ok(self.chain.bytes(level))
+    }
+
+    // The preset a tree built on these values reports, and can be rebuilt from
+    pub fn preset(&self) -> ZeroPreset {
+        self.chain.preset()
+    }
+
+    // Values behind an already built chain, covering every depth
+    pub(crate) fn from_chain(chain: ZeroChain) -> ZeroValues {
+        ZeroValues {
+            levels: MAX_LEVELS as u32,
+            chain,
+        }
+    }
+
+    pub(crate) fn chain(&self) -> &ZeroChain {
+        &self.chain
+    }
+}
+
+// SHA-256 of `seed` reduced into the field, big-endian
+pub(crate) fn seed_leaf(seed: &[u8]) -> [u8; 32] {
+    fr_to_be_bytes(&Fr::from_be_bytes_mod_order(&Sha256::digest(seed)))
+}
+
+impl ZeroChain {
+    // `preset` hashed up with `poseidon` instead of the bundled hash
+    pub(crate) fn with_hasher(
+        preset: ZeroPreset,
+        poseidon: &mut Poseidon<Fr>,
+    ) -> Result<ZeroChain, PoseidonMerkleTreeError> {
+        let seed = leaf_fr(&ZeroChain::new(preset)?.bytes(0))?;
+        let table = ZeroTable::Owned(Box::new(derive_chain_with(poseidon, seed)));
+        Ok(ZeroChain { preset, table })
+    }
+}
+
+impl Default for ZeroChain {
+    fn default() -> ZeroChain {
+        ZeroChain {
+            preset: ZeroPreset::Voidify,
+            table: ZeroTable::Shared(zeros_fr()),
+        }
+    }
+}
+
+// The table follows from the preset and the hash, so only the preset is shown
+impl fmt::Debug for ZeroChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.preset.fmt(f)
+    }
+}
+
+impl PartialEq for ZeroChain {
+    fn eq(&self, other: &ZeroChain) -> bool {
+        self.preset == other.preset && self.fr() == other.fr()
+    }
+}
+
+fn tornado_zeros() -> &'static [Fr; MAX_LEVELS + 1] {
+    static TORNADO_ZEROS: OnceBox<[Fr; MAX_LEVELS + 1]> = OnceBox::new();
+    TORNADO_ZEROS.get_or_init(|| {
+        Box::new(derive_chain(
+            leaf_fr(&TORNADO_SEED).expect("Tornado seed is inside the field"),
+        ))
+    })
+}
+
+// `zeros(i + 1) = Poseidon(zeros(i), zeros(i))`. Built with its own hasher so it can run while
+// the shared hasher is locked
+fn derive_chain(seed: Fr) -> [Fr; MAX_LEVELS + 1] {
+    derive_chain_with(&mut new_poseidon(), seed)
+}
+
+fn derive_chain_with(poseidon: &mut Poseidon<Fr>, seed: Fr) -> [Fr; MAX_LEVELS + 1] {
+    let mut chain = [seed; MAX_LEVELS + 1];
+    for level in 1..=MAX_LEVELS {
+        let below = chain[level - 1];
+        chain[level] = hash_fr(poseidon, below, below).expect("zero chain is in the field");
+    }
+    chain
+}
+
+// Checks the bundled `Voidify` constants against the bundled Poseidon parameters, so a typo in
+// one of them cannot go unnoticed. Fails with `ZeroChainMismatch` at the first level that is
+// not the hash of two of the one below, or that is outside the field
+pub fn verify_zero_constants() -> Result<(), PoseidonMerkleTreeError> {
+    check_chain(&mut new_poseidon(), &ZERO_HASHES)
+}
+
+// Any zero chain against `poseidon`. Reads bytes rather than `zeros_fr`, which checks the
+// bundled constants with this before it first converts them
+pub(crate) fn check_chain(
+    poseidon: &mut Poseidon<Fr>,
+    chain: &[[u8; 32]],
+) -> Result<(), PoseidonMerkleTreeError> {
+    let mismatch = |level: usize| PoseidonMerkleTreeError::ZeroChainMismatch {
+        level: level as u32,
+    };
+    let Some(first) = chain.first() else {
+        return Ok(());
+    };
+
+    let mut below = leaf_fr(first).map_err(|_| mismatch(0))?;
+    for (level, bytes) in chain.iter().enumerate().skip(1) {
+        let value = leaf_fr(bytes).map_err(|_| mismatch(level))?;
+        if hash_fr(poseidon, below, below)? != value {
+            return Err(mismatch(level));
+        }
+        below = value;
+    }
+    Ok(())
+}
+
+impl PoseidonMerkleTree {
+    // Like `new`, but empty subtrees hash up from `preset` instead of the `Voidify` chain. A
+    // `Custom` leaf must be inside the field
+    pub fn new_with_zero_preset(
+        levels: u32,
+        preset: ZeroPreset,
+    ) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        PoseidonMerkleTree::builder()
+            .levels(levels)
+            .zero_preset(preset)
+            .build()
+    }
+
+    pub fn zero_preset(&self) -> ZeroPreset {
+        self.zeros.preset()
+    }
+
+    // The stored layout keeps the preset, but bytes written before it did read back on the
+    // `Voidify` chain. This puts such a tree back on the one it was built with
+    pub fn with_zero_preset(
+        mut self,
+        preset: ZeroPreset,
+    ) -> Result<PoseidonMerkleTree, PoseidonMerkleTreeError> {
+        self.zeros = ZeroChain::new(preset)?;
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use borsh::{BorshDeserialize, BorshSerialize};
+
+    use super::*;
+    use crate::{
+        compute_root_from_proof, decode_hash, verify_merkle_proof, zero_hash, zeros,
+        FullPoseidonMerkleTree,
+    };
+
+    // The first values of the chain from Tornado Cash's `ZERO_VALUE`, as hardcoded in the
+    // `zeros(i)` of its Poseidon `MerkleTreeWithHistory`
+    const TORNADO_ZEROS: [&str; 5] = [
+        "0x2fe54c60d3acabf3343a35b6eba15db4821b340f76e741e2249685ed4899af6c",
+        "0x13e37f2d6cb86c78ccc1788607c2b199788c6bb0a615a21f2e7a8e88384222f8",
+        "0x217126fa352c326896e8c2803eec8fd63ad50cf65edfef27a41a9e32dc622765",
+        "0x0e28a61a9b3e91007d5a9e3ada18e1b24d6d230c618388ee5df34cacd7397eee",
+        "0x27953447a6979839536badc5425ed15fadb0e292e9bc36f92f0aa5cfa5013587",
+    ];
+
+    fn current_root(tree: &PoseidonMerkleTree) -> [u8; 32] {
+        tree.last_root()
+    }
+
+    #[test]
+    fn test_verify_zero_constants() {
+        assert_eq!(verify_zero_constants(), Ok(()));
+        let tornado: Vec<_> = (0..=MAX_LEVELS as u32)
+            .map(|level| ZeroChain::new(ZeroPreset::Tornado).unwrap().bytes(level))
+            .collect();
+        assert_eq!(check_chain(&mut new_poseidon(), &tornado), Ok(()));
+
+        // A typo is reported at its own level even though the next one no longer matches it
+        let mut typo = ZERO_HASHES;
+        typo[7][31] ^= 1;
+        assert_eq!(
+            check_chain(&mut new_poseidon(), &typo),
+            Err(PoseidonMerkleTreeError::ZeroChainMismatch { level: 7 })
+        );
+        let mut typo = ZERO_HASHES;
+        typo[0][31] ^= 1;
+        assert_eq!(
+            check_chain(&mut new_poseidon(), &typo),
+            Err(PoseidonMerkleTreeError::ZeroChainMismatch { level: 1 })
+        );
+        let mut outside = ZERO_HASHES;
+        outside[3] = [0xff; 32];
+        assert_eq!(
+            check_chain(&mut new_poseidon(), &outside),
+            Err(PoseidonMerkleTreeError::ZeroChainMismatch { level: 3 })
+        );
+    }
+
+    #[test]
+    fn test_tornado_zeros_match_on_chain_constants() {
+        let chain = ZeroChain::new(ZeroPreset::Tornado).unwrap();
+        for (level, expected) in TORNADO_ZEROS.iter().enumerate() {
+            assert_eq!(
+                chain.bytes(level as u32),
+                decode_hash(expected).unwrap(),
+                "level {level}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_voidify_preset_is_the_default_chain() {
+        let chain = ZeroChain::new(ZeroPreset::Voidify).unwrap();
+        for level in 0..=MAX_LEVELS as u32 {
+            assert_eq!(chain.bytes(level), zeros(level));
+        }
+        assert_eq!(
+            PoseidonMerkleTree::new_with_zero_preset(5, ZeroPreset::Voidify).unwrap(),
+            PoseidonMerkleTree::new(5).unwrap()
+        );
+        assert_eq!(
+            PoseidonMerkleTree::new(5).unwrap().zero_preset(),
+            ZeroPreset::Voidify
+        );
+    }
+
+    #[test]
+    fn test_custom_preset_derives_its_chain() {
+        let seed = decode_hash(TORNADO_ZEROS[0]).unwrap();
+        let custom = ZeroChain::new(ZeroPreset::Custom(seed)).unwrap();
+        let tornado = ZeroChain::new(ZeroPreset::Tornado).unwrap();
+        assert_eq!(custom.fr(), tornado.fr());
+        assert_ne!(custom, tornado);
+
+        assert_eq!(
+            PoseidonMerkleTree::new_with_zero_preset(3, ZeroPreset::Custom([0xff; 32])),
+            Err(PoseidonMerkleTreeError::invalid_leaf(&[0xff; 32]))
+        );
+    }
+
+    #[test]
+    fn test_voidify_seed_derives_the_bundled_table() {
+        let values = ZeroValues::derive(b"voidify", MAX_LEVELS as u32).unwrap();
+        for level in 0..=MAX_LEVELS as u32 {
+            assert_eq!(values.get(level), zero_hash(level), "level {level}");
+        }
+        assert_eq!(values.preset(), ZeroPreset::Custom(zeros(0)));
+        assert_eq!(
+            ZeroValues::derive(b"voidify", MAX_LEVELS as u32 + 1),
+            Err(PoseidonMerkleTreeError::invalid_levels(
+                MAX_LEVELS as u32 + 1
+            ))
+        );
+    }
+
+    #[test]
+    fn test_tree_built_on_derived_values() {
+        let values = ZeroValues::derive(b"my deployment", 4).unwrap();
+        assert_eq!(values.levels(), 4);
+        assert_eq!(
+            values.get(5),
+            Err(PoseidonMerkleTreeError::InvalidLevels { got: 5, max: 4 })
+        );
+
+        let tree = PoseidonMerkleTree::builder()
+            .levels(4)
+            .zero_values(&values)
+            .build()
+            .unwrap();
+        assert_eq!(tree.last_root(), values.get(3).unwrap());
+        assert_eq!(
+            tree,
+            PoseidonMerkleTree::new_with_zero_preset(4, values.preset()).unwrap()
+        );
+        assert_eq!(
+            PoseidonMerkleTree::builder()
+                .levels(5)
+                .zero_values(&values)
+                .build(),
+            Err(PoseidonMerkleTreeError::InvalidLevels { got: 5, max: 4 })
+        );
+    }
+
+    #[test]
+    fn test_tornado_tree_respects_preset() {
+        let levels = 4;
+        let mut tree =
+            PoseidonMerkleTree::new_with_zero_preset(levels, ZeroPreset::Tornado).unwrap();
+        let initial = decode_hash(TORNADO_ZEROS[levels as usize - 1]).unwrap();
+        assert_eq!(current_root(&tree), initial);
+        assert!(tree.is_known_root(initial));
+        assert!(!PoseidonMerkleTree::new(levels)
+            .unwrap()
+            .is_known_root(initial));
+
+        // A single leaf next to empty subtrees: each level hashes with the Tornado zero there
+        let leaf = [1u8; 32];
+        tree.insert(&leaf).unwrap();
+        let chain = ZeroChain::new(ZeroPreset::Tornado).unwrap();
+        let mut poseidon = new_poseidon();
+        let mut expected = leaf_fr(&leaf).unwrap();
+        for level in 0..levels as usize {
+            expected = hash_fr(&mut poseidon, expected, chain.fr()[level]).unwrap();
+        }
+        assert_eq!(current_root(&tree), fr_to_be_bytes(&expected));
+        assert!(tree.is_known_root(fr_to_be_bytes(&expected)));
+    }
+
+    #[test]
+    fn test_full_tree_proofs_use_preset_zeros() {
+        let mut tree =
+            FullPoseidonMerkleTree::new_with_zero_preset(3, ZeroPreset::Tornado).unwrap();
+        for i in 1..=3u8 {
+            tree.insert(&[i; 32]).unwrap();
+        }
+        let root = current_root(tree.tree());
+        let proof = tree.get_proof(2).unwrap();
+        assert_eq!(proof.siblings[0], decode_hash(TORNADO_ZEROS[0]).unwrap());
+        assert!(verify_merkle_proof(&proof.leaf, proof.index, &proof.siblings, &root).unwrap());
+        assert_eq!(
+            tree.get_node(1, 3),
+            Ok(decode_hash(TORNADO_ZEROS[1]).unwrap())
+        );
+
+        let version = tree.get_proof_at_version(0, 1).unwrap();
+        assert_eq!(version.siblings[0], decode_hash(TORNADO_ZEROS[0]).unwrap());
+        assert!(tree.is_known_root(
+            compute_root_from_proof(&version.leaf, version.index, &version.siblings).unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_preset_kept_across_round_trips() {
+        let custom = ZeroPreset::Custom(seed_leaf(b"my deployment"));
+        for preset in [ZeroPreset::Tornado, custom] {
+            let mut tree = PoseidonMerkleTree::new_with_zero_preset(4, preset).unwrap();
+            tree.insert(&[1u8; 32]).unwrap();
+            let mut full = FullPoseidonMerkleTree::new_with_zero_preset(4, preset).unwrap();
+            full.insert(&[1u8; 32]).unwrap();
+
+            let mut read = PoseidonMerkleTree::try_from_slice(&tree.try_to_vec().unwrap()).unwrap();
+            assert_eq!(read.zero_preset(), preset);
+            assert_eq!(read, tree);
+            let versioned = tree.serialize_versioned().unwrap();
+            assert_eq!(
+                PoseidonMerkleTree::deserialize_versioned(&versioned),
+                Ok(tree.clone())
+            );
+
+            let mut full_read =
+                FullPoseidonMerkleTree::try_from_slice(&full.try_to_vec().unwrap()).unwrap();
+            assert_eq!(full_read.tree().zero_preset(), preset);
+            assert_eq!(full_read, full);
+
+            read.insert(&[2u8; 32]).unwrap();
+            tree.insert(&[2u8; 32]).unwrap();
+            full_read.insert(&[2u8; 32]).unwrap();
+            assert_eq!(current_root(&read), current_root(&tree));
+            assert_eq!(current_root(full_read.tree()), current_root(&tree));
+            assert_eq!(
+                full_read.get_proof(1).unwrap().root(),
+                Ok(current_root(&tree))
+            );
+        }
+    }
+
+    #[test]
+    fn test_preset_of_older_bytes_is_restored() {
+        let mut tree = PoseidonMerkleTree::new_with_zero_preset(4, ZeroPreset::Tornado).unwrap();
+        tree.insert(&[1u8; 32]).unwrap();
+        let bytes = tree.try_to_vec().unwrap();
+
+        // Without the options the tree reads back on the `Voidify` chain
+        let legacy = &bytes[..bytes.len() - crate::OPTIONS_SIZE];
+        let read = PoseidonMerkleTree::try_from_slice(legacy).unwrap();
+        assert_eq!(read.zero_preset(), ZeroPreset::Voidify);
+        let mut restored = read.with_zero_preset(ZeroPreset::Tornado).unwrap();
+        assert_eq!(restored, tree);
+
+        restored.insert(&[2u8; 32]).unwrap();
+        tree.insert(&[2u8; 32]).unwrap();
+        assert_eq!(current_root(&restored), current_root(&tree));
+
+        // The preset's tag and leaf follow the options tag and `accept_initial_root`
+        let preset = bytes.len() - crate::OPTIONS_SIZE + 2;
+        let mut unknown = bytes.clone();
+        unknown[preset] = 3;
+        let mut stray_leaf = bytes.clone();
+        stray_leaf[preset + 1] = 1;
+        let mut outside = bytes;
+        outside[preset] = 2;
+        outside[preset + 1..].fill(0xff);
+        for corrupted in [unknown, stray_leaf, outside] {
+            assert!(PoseidonMerkleTree::try_from_slice(&corrupted).is_err());
+        }
+    }
+}